@@ -1,5 +1,98 @@
-pub const MAX_MESSAGE_SIZE: usize = 1024;
+pub const MAX_MESSAGE_SIZE: usize = 100 * 1024 * 1024; // produce requests carry whole record batches
 pub const SUPPORTED_VERSION_MIN: i16 = 0;
 pub const SUPPORTED_VERSION_MAX: i16 = 4;
 pub const API_KEY_API_VERSIONS: i16 = 18;
-pub const API_KEY_FETCH: i16 = 1;
\ No newline at end of file
+pub const API_KEY_PRODUCE: i16 = 0;
+pub const API_KEY_FETCH: i16 = 1;
+pub const API_KEY_CONSUMER_GROUP_HEARTBEAT: i16 = 68;
+pub const API_KEY_DESCRIBE_GROUPS: i16 = 15;
+pub const API_KEY_LIST_GROUPS: i16 = 16;
+pub const API_KEY_DELETE_GROUPS: i16 = 42;
+pub const API_KEY_OFFSET_DELETE: i16 = 47;
+pub const API_KEY_OFFSET_FOR_LEADER_EPOCH: i16 = 23;
+pub const API_KEY_LEADER_AND_ISR: i16 = 4;
+pub const API_KEY_ALTER_PARTITION: i16 = 56;
+pub const API_KEY_BROKER_REGISTRATION: i16 = 62;
+pub const API_KEY_BROKER_HEARTBEAT: i16 = 63;
+pub const API_KEY_ALTER_PARTITION_REASSIGNMENTS: i16 = 45;
+pub const API_KEY_LIST_PARTITION_REASSIGNMENTS: i16 = 46;
+pub const API_KEY_ELECT_LEADERS: i16 = 43;
+pub const API_KEY_INIT_PRODUCER_ID: i16 = 22;
+pub const API_KEY_ALLOCATE_PRODUCER_IDS: i16 = 67;
+pub const API_KEY_ADD_PARTITIONS_TO_TXN: i16 = 24;
+pub const API_KEY_ADD_OFFSETS_TO_TXN: i16 = 25;
+pub const API_KEY_END_TXN: i16 = 26;
+pub const API_KEY_WRITE_TXN_MARKERS: i16 = 27;
+pub const API_KEY_TXN_OFFSET_COMMIT: i16 = 28;
+pub const API_KEY_CREATE_TOPICS: i16 = 19;
+pub const API_KEY_DELETE_TOPICS: i16 = 20;
+pub const API_KEY_CREATE_PARTITIONS: i16 = 37;
+
+// consumer group (KIP-848) defaults
+pub const CONSUMER_GROUP_SESSION_TIMEOUT_MS: i32 = 45_000;
+pub const CONSUMER_GROUP_HEARTBEAT_INTERVAL_MS: i32 = 5_000;
+pub const DEFAULT_SERVER_ASSIGNOR: &str = "uniform";
+
+// ListOffsets sentinel timestamps
+pub const EARLIEST_TIMESTAMP: i64 = -2;
+pub const LATEST_TIMESTAMP: i64 = -1;
+
+// Fetch isolation_level of consumers that skip open and aborted transactions
+pub const READ_COMMITTED: i8 = 1;
+
+pub const LAG_REPORT_INTERVAL_MS: u64 = 10_000;
+
+pub const LOG_SEGMENT_BYTES: u64 = 1024 * 1024 * 1024;
+// index.interval.bytes: how much log data a segment's position index entry covers
+pub const LOG_INDEX_INTERVAL_BYTES: u64 = 4096;
+
+// auto.create.topics.enable: producing to a missing topic creates it with the defaults below
+pub const AUTO_CREATE_TOPICS_ENABLE: bool = true;
+// num.partitions and default.replication.factor, for topics created without them
+pub const DEFAULT_NUM_PARTITIONS: i32 = 1;
+pub const DEFAULT_REPLICATION_FACTOR: i32 = 1;
+
+// follower fetching, see replica.fetch.* in Kafka
+pub const REPLICA_FETCH_WAIT_MAX_MS: i32 = 500;
+pub const REPLICA_FETCH_MIN_BYTES: i32 = 1;
+pub const REPLICA_FETCH_MAX_BYTES: i32 = 10 * 1024 * 1024;
+pub const REPLICA_FETCH_PARTITION_MAX_BYTES: i32 = 1024 * 1024;
+pub const REPLICA_FETCH_BACKOFF_MS: u64 = 1_000;
+pub const REPLICA_FETCH_BACKOFF_MAX_MS: u64 = 30_000;
+pub const REPLICA_FETCHER_CHECK_INTERVAL_MS: u64 = 1_000;
+// replica.lag.time.max.ms, followers that haven't caught up for this long leave the ISR
+pub const REPLICA_LAG_TIME_MAX_MS: i64 = 30_000;
+// wait before retrying controller <-> broker requests that failed to send
+pub const CONTROLLER_REQUEST_BACKOFF_MS: u64 = 1_000;
+// broker.heartbeat.interval.ms and broker.session.timeout.ms: the controller fences
+// brokers it hasn't heard from within the session timeout
+pub const BROKER_HEARTBEAT_INTERVAL_MS: u64 = 2_000;
+pub const BROKER_SESSION_TIMEOUT_MS: u64 = 9_000;
+// how long a stopping broker waits for the controller to move its leaders away
+pub const CONTROLLED_SHUTDOWN_TIMEOUT_MS: u64 = 30_000;
+// auto.leader.rebalance.enable, leader.imbalance.check.interval.seconds and
+// leader.imbalance.per.broker.percentage: leadership goes back to preferred replicas
+// once more than this share of a broker's preferred partitions is led elsewhere
+pub const AUTO_LEADER_REBALANCE_ENABLE: bool = true;
+pub const LEADER_IMBALANCE_CHECK_INTERVAL_MS: u64 = 300_000;
+pub const LEADER_IMBALANCE_PER_BROKER_PERCENTAGE: usize = 10;
+// producer ids the controller hands a broker at a time for InitProducerId
+pub const PRODUCER_ID_BLOCK_SIZE: i32 = 1_000;
+// transaction.state.log.* and transaction.max.timeout.ms: the internal topic the
+// transaction coordinators keep their state in, and the longest transaction allowed
+pub const TRANSACTION_STATE_TOPIC: &str = "__transaction_state";
+pub const TRANSACTION_STATE_NUM_PARTITIONS: i32 = 50;
+pub const TRANSACTION_STATE_REPLICATION_FACTOR: i32 = 3;
+pub const TRANSACTION_MAX_TIMEOUT_MS: i32 = 900_000;
+// transaction.abort.timed.out.transaction.cleanup.interval.ms
+pub const TRANSACTION_ABORT_TIMED_OUT_CLEANUP_INTERVAL_MS: u64 = 10_000;
+// how long transaction state and marker writes wait for the ISR
+pub const TRANSACTION_WRITE_TIMEOUT_MS: u64 = 30_000;
+
+// metadata quorum, see controller.quorum.* in Kafka
+pub const QUORUM_ELECTION_TIMEOUT_MS: u64 = 1_000; // randomised up to twice this
+pub const QUORUM_HEARTBEAT_INTERVAL_MS: u64 = 200;
+pub const QUORUM_REQUEST_TIMEOUT_MS: u64 = 2_000;
+pub const QUORUM_MAX_BATCHES_PER_APPEND: usize = 64;
+// records applied since the last snapshot before a new one is taken
+pub const METADATA_SNAPSHOT_MAX_RECORDS: i64 = 1_000;
@@ -0,0 +1,411 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::core::consumer_group::TopicPartition;
+
+// broker-side partition assignment. every assignor is deterministic for a given input:
+// members, topics and partitions are always visited in sorted order.
+pub trait PartitionAssignor {
+    fn name(&self) -> &'static str;
+
+    /// maps every member in `subscriptions` to its partitions. `current` is the
+    /// previous assignment, only sticky assignors look at it.
+    fn assign(
+        &self,
+        partitions_per_topic: &HashMap<String, i32>,
+        subscriptions: &HashMap<String, Vec<String>>,
+        current: &HashMap<String, Vec<TopicPartition>>,
+    ) -> HashMap<String, Vec<TopicPartition>>;
+}
+
+pub struct RangeAssignor;
+pub struct RoundRobinAssignor;
+pub struct StickyAssignor;
+pub struct CooperativeStickyAssignor;
+
+pub fn assignor_by_name(name: &str) -> Option<Box<dyn PartitionAssignor + Send + Sync>> {
+    match name {
+        "range" => Some(Box::new(RangeAssignor)),
+        "roundrobin" => Some(Box::new(RoundRobinAssignor)),
+        "sticky" => Some(Box::new(StickyAssignor)),
+        "cooperative-sticky" => Some(Box::new(CooperativeStickyAssignor)),
+        _ => None,
+    }
+}
+
+// assignors selectable through ConsumerGroupHeartbeat's server_assignor (KIP-848)
+pub fn server_assignor_by_name(name: &str) -> Option<Box<dyn PartitionAssignor + Send + Sync>> {
+    match name {
+        "uniform" => Some(Box::new(StickyAssignor)),
+        "range" => Some(Box::new(RangeAssignor)),
+        _ => None,
+    }
+}
+
+// member id -> subscribed topics, sorted
+fn sorted_subscriptions(subscriptions: &HashMap<String, Vec<String>>) -> BTreeMap<&str, BTreeSet<&str>> {
+    subscriptions
+        .iter()
+        .map(|(member, topics)| (member.as_str(), topics.iter().map(String::as_str).collect()))
+        .collect()
+}
+
+// every partition of every topic that at least one member subscribes to and that exists
+fn subscribed_partitions(
+    partitions_per_topic: &HashMap<String, i32>,
+    members: &BTreeMap<&str, BTreeSet<&str>>,
+) -> Vec<TopicPartition> {
+    let topics: BTreeSet<&str> = members.values().flatten().copied().collect();
+    let mut partitions = Vec::new();
+    for topic in topics {
+        if let Some(&count) = partitions_per_topic.get(topic) {
+            for partition in 0..count {
+                partitions.push(TopicPartition::new(topic.to_string(), partition));
+            }
+        }
+    }
+    partitions
+}
+
+fn into_assignment(assignment: BTreeMap<&str, BTreeSet<TopicPartition>>) -> HashMap<String, Vec<TopicPartition>> {
+    assignment
+        .into_iter()
+        .map(|(member, partitions)| (member.to_string(), partitions.into_iter().collect()))
+        .collect()
+}
+
+impl PartitionAssignor for RangeAssignor {
+    fn name(&self) -> &'static str {
+        "range"
+    }
+
+    fn assign(
+        &self,
+        partitions_per_topic: &HashMap<String, i32>,
+        subscriptions: &HashMap<String, Vec<String>>,
+        _current: &HashMap<String, Vec<TopicPartition>>,
+    ) -> HashMap<String, Vec<TopicPartition>> {
+        let members = sorted_subscriptions(subscriptions);
+        let mut assignment: BTreeMap<&str, BTreeSet<TopicPartition>> =
+            members.keys().map(|m| (*m, BTreeSet::new())).collect();
+
+        let topics: BTreeSet<&str> = members.values().flatten().copied().collect();
+        for topic in topics {
+            let Some(&num_partitions) = partitions_per_topic.get(topic) else {
+                continue;
+            };
+            let consumers: Vec<&str> = members
+                .iter()
+                .filter(|(_, topics)| topics.contains(topic))
+                .map(|(member, _)| *member)
+                .collect();
+
+            // first (partitions % consumers) members get one extra partition
+            let per_consumer = num_partitions / consumers.len() as i32;
+            let extra = num_partitions % consumers.len() as i32;
+            for (i, member) in consumers.iter().enumerate() {
+                let i = i as i32;
+                let start = per_consumer * i + i.min(extra);
+                let len = per_consumer + if i < extra { 1 } else { 0 };
+                let owned = assignment.entry(member).or_default();
+                for partition in start..start + len {
+                    owned.insert(TopicPartition::new(topic.to_string(), partition));
+                }
+            }
+        }
+
+        into_assignment(assignment)
+    }
+}
+
+impl PartitionAssignor for RoundRobinAssignor {
+    fn name(&self) -> &'static str {
+        "roundrobin"
+    }
+
+    fn assign(
+        &self,
+        partitions_per_topic: &HashMap<String, i32>,
+        subscriptions: &HashMap<String, Vec<String>>,
+        _current: &HashMap<String, Vec<TopicPartition>>,
+    ) -> HashMap<String, Vec<TopicPartition>> {
+        let members = sorted_subscriptions(subscriptions);
+        let mut assignment: BTreeMap<&str, BTreeSet<TopicPartition>> =
+            members.keys().map(|m| (*m, BTreeSet::new())).collect();
+        let ring: Vec<&str> = members.keys().copied().collect();
+        if ring.is_empty() {
+            return HashMap::new();
+        }
+
+        // walk the member ring, skipping members that don't subscribe to the partition's topic
+        let mut cursor = 0;
+        for tp in subscribed_partitions(partitions_per_topic, &members) {
+            for _ in 0..ring.len() {
+                let member = ring[cursor % ring.len()];
+                cursor += 1;
+                if members[member].contains(tp.topic()) {
+                    assignment.entry(member).or_default().insert(tp);
+                    break;
+                }
+            }
+        }
+
+        into_assignment(assignment)
+    }
+}
+
+// keeps as much of `current` as possible, then hands out the rest and evens out the counts.
+fn sticky_assign<'a>(
+    partitions_per_topic: &HashMap<String, i32>,
+    members: &BTreeMap<&'a str, BTreeSet<&str>>,
+    current: &HashMap<String, Vec<TopicPartition>>,
+) -> BTreeMap<&'a str, BTreeSet<TopicPartition>> {
+    let mut assignment: BTreeMap<&str, BTreeSet<TopicPartition>> =
+        members.keys().map(|m| (*m, BTreeSet::new())).collect();
+    let all_partitions = subscribed_partitions(partitions_per_topic, members);
+    let valid: HashSet<&TopicPartition> = all_partitions.iter().collect();
+
+    // keep previously owned partitions the member still subscribes to. a partition
+    // claimed by two members (stale generation) stays with the first one in id order.
+    let mut claimed: HashSet<TopicPartition> = HashSet::new();
+    let owners: BTreeMap<&String, &Vec<TopicPartition>> = current.iter().collect();
+    for (member, owned) in owners {
+        let Some((&member, topics)) = members.get_key_value(member.as_str()) else {
+            continue;
+        };
+        let mut owned: Vec<&TopicPartition> = owned.iter().collect();
+        owned.sort();
+        for tp in owned {
+            if valid.contains(tp) && topics.contains(tp.topic()) && claimed.insert(tp.clone()) {
+                assignment.entry(member).or_default().insert(tp.clone());
+            }
+        }
+    }
+
+    // hand out unowned partitions to the least loaded eligible member
+    for tp in all_partitions.iter().filter(|tp| !claimed.contains(*tp)) {
+        let target = members
+            .iter()
+            .filter(|(_, topics)| topics.contains(tp.topic()))
+            .map(|(member, _)| *member)
+            .min_by_key(|member| (assignment[member].len(), *member));
+        if let Some(member) = target {
+            assignment.entry(member).or_default().insert(tp.clone());
+        }
+    }
+
+    // move one partition at a time from overloaded to underloaded members. every move
+    // shrinks the sum of squared counts, so this terminates.
+    loop {
+        let mut by_load: Vec<&str> = assignment.keys().copied().collect();
+        by_load.sort_by_key(|member| (std::cmp::Reverse(assignment[member].len()), *member));
+
+        let mut next_move = None;
+        'search: for donor in &by_load {
+            for receiver in by_load.iter().rev() {
+                if assignment[donor].len() <= assignment[receiver].len() + 1 {
+                    continue;
+                }
+                let movable = assignment[donor]
+                    .iter()
+                    .rev()
+                    .find(|tp| members[receiver].contains(tp.topic()));
+                if let Some(tp) = movable {
+                    next_move = Some((*donor, *receiver, tp.clone()));
+                    break 'search;
+                }
+            }
+        }
+
+        match next_move {
+            Some((donor, receiver, tp)) => {
+                assignment.entry(donor).or_default().remove(&tp);
+                assignment.entry(receiver).or_default().insert(tp);
+            }
+            None => break,
+        }
+    }
+
+    assignment
+}
+
+impl PartitionAssignor for StickyAssignor {
+    fn name(&self) -> &'static str {
+        "sticky"
+    }
+
+    fn assign(
+        &self,
+        partitions_per_topic: &HashMap<String, i32>,
+        subscriptions: &HashMap<String, Vec<String>>,
+        current: &HashMap<String, Vec<TopicPartition>>,
+    ) -> HashMap<String, Vec<TopicPartition>> {
+        let members = sorted_subscriptions(subscriptions);
+        into_assignment(sticky_assign(partitions_per_topic, &members, current))
+    }
+}
+
+impl PartitionAssignor for CooperativeStickyAssignor {
+    fn name(&self) -> &'static str {
+        "cooperative-sticky"
+    }
+
+    // same target as sticky, but a partition moving between two live members is left out
+    // until its previous owner has revoked it, which takes a follow-up rebalance.
+    fn assign(
+        &self,
+        partitions_per_topic: &HashMap<String, i32>,
+        subscriptions: &HashMap<String, Vec<String>>,
+        current: &HashMap<String, Vec<TopicPartition>>,
+    ) -> HashMap<String, Vec<TopicPartition>> {
+        let members = sorted_subscriptions(subscriptions);
+        let mut assignment = sticky_assign(partitions_per_topic, &members, current);
+
+        let mut previous_owner: HashMap<&TopicPartition, &str> = HashMap::new();
+        let owners: BTreeMap<&String, &Vec<TopicPartition>> = current.iter().collect();
+        for (member, owned) in owners {
+            if members.contains_key(member.as_str()) {
+                for tp in owned {
+                    previous_owner.entry(tp).or_insert(member.as_str());
+                }
+            }
+        }
+
+        for (member, partitions) in assignment.iter_mut() {
+            partitions.retain(|tp| previous_owner.get(tp).is_none_or(|owner| owner == member));
+        }
+
+        into_assignment(assignment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASSIGNORS: [&str; 4] = ["range", "roundrobin", "sticky", "cooperative-sticky"];
+
+    fn members(count: usize) -> Vec<String> {
+        (0..count).map(|member| format!("member-{}", member)).collect()
+    }
+
+    fn subscribe(members: &[String], topics: &[&str]) -> HashMap<String, Vec<String>> {
+        members.iter().map(|member| (member.clone(), topics.iter().map(|topic| topic.to_string()).collect())).collect()
+    }
+
+    fn sizes(assignment: &HashMap<String, Vec<TopicPartition>>, topic: Option<&str>) -> Vec<usize> {
+        assignment
+            .values()
+            .map(|partitions| partitions.iter().filter(|tp| topic.is_none_or(|topic| tp.topic() == topic)).count())
+            .collect()
+    }
+
+    fn spread(sizes: &[usize]) -> usize {
+        sizes.iter().max().unwrap() - sizes.iter().min().unwrap()
+    }
+
+    // every subscribed partition goes to exactly one member
+    fn assert_complete(assignment: &HashMap<String, Vec<TopicPartition>>, partitions_per_topic: &HashMap<String, i32>) {
+        let mut assigned: Vec<&TopicPartition> = assignment.values().flatten().collect();
+        assigned.sort();
+        let mut expected: Vec<TopicPartition> = partitions_per_topic
+            .iter()
+            .flat_map(|(topic, count)| (0..*count).map(|partition| TopicPartition::new(topic.clone(), partition)))
+            .collect();
+        expected.sort();
+        assert_eq!(assigned, expected.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn every_assignor_spreads_partitions_evenly() {
+        for name in ASSIGNORS {
+            let assignor = assignor_by_name(name).unwrap();
+            for member_count in 1..=5 {
+                let members = members(member_count);
+                let subscriptions = subscribe(&members, &["orders", "payments"]);
+                for orders in 0..=9 {
+                    let partitions_per_topic = HashMap::from([("orders".to_string(), orders), ("payments".to_string(), 3)]);
+                    let assignment = assignor.assign(&partitions_per_topic, &subscriptions, &HashMap::new());
+
+                    assert_eq!(assignment.len(), member_count, "{} leaves out a member", name);
+                    assert_complete(&assignment, &partitions_per_topic);
+                    // range balances each topic on its own, the others the whole group
+                    let balanced = match name {
+                        "range" => ["orders", "payments"].iter().all(|topic| spread(&sizes(&assignment, Some(topic))) <= 1),
+                        _ => spread(&sizes(&assignment, None)) <= 1,
+                    };
+                    assert!(balanced, "{} with {} members and {} partitions: {:?}", name, member_count, orders, assignment);
+                    // deterministic for the same input
+                    assert_eq!(assignor.assign(&partitions_per_topic, &subscriptions, &HashMap::new()), assignment);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn partitions_only_go_to_members_subscribed_to_their_topic() {
+        let partitions_per_topic = HashMap::from([("orders".to_string(), 4), ("payments".to_string(), 2)]);
+        let subscriptions = HashMap::from([
+            ("billing".to_string(), vec!["orders".to_string(), "payments".to_string()]),
+            ("shipping".to_string(), vec!["orders".to_string()]),
+            ("audit".to_string(), vec!["refunds".to_string()]),
+        ]);
+        for name in ASSIGNORS {
+            let assignment = assignor_by_name(name).unwrap().assign(&partitions_per_topic, &subscriptions, &HashMap::new());
+            assert_complete(&assignment, &partitions_per_topic);
+            assert!(assignment["audit"].is_empty(), "{}", name);
+            assert!(assignment["shipping"].iter().all(|tp| tp.topic() == "orders"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn sticky_assignors_only_move_the_partitions_of_a_member_that_leaves() {
+        for name in ["sticky", "cooperative-sticky"] {
+            let assignor = assignor_by_name(name).unwrap();
+            for member_count in 2..=5 {
+                for orders in 1..=12 {
+                    let partitions_per_topic = HashMap::from([("orders".to_string(), orders)]);
+                    let members = members(member_count);
+                    let before = assignor.assign(&partitions_per_topic, &subscribe(&members, &["orders"]), &HashMap::new());
+
+                    for leaving in &members {
+                        let remaining: Vec<String> = members.iter().filter(|member| *member != leaving).cloned().collect();
+                        let mut current = before.clone();
+                        current.remove(leaving);
+                        let after = assignor.assign(&partitions_per_topic, &subscribe(&remaining, &["orders"]), &current);
+
+                        assert_complete(&after, &partitions_per_topic);
+                        assert!(spread(&sizes(&after, None)) <= 1, "{}: {:?}", name, after);
+                        // only the leaver's partitions move, the others keep all of theirs
+                        for member in &remaining {
+                            assert!(before[member].iter().all(|tp| after[member].contains(tp)), "{} moved {}'s partitions", name, member);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cooperative_sticky_waits_for_partitions_to_be_revoked_before_moving_them() {
+        let assignor = CooperativeStickyAssignor;
+        let partitions_per_topic = HashMap::from([("orders".to_string(), 4)]);
+        let subscriptions = subscribe(&members(2), &["orders"]);
+        let owned: Vec<TopicPartition> = (0..4).map(|partition| TopicPartition::new("orders".to_string(), partition)).collect();
+
+        // member-1 joins while member-0 still owns everything
+        let current = HashMap::from([("member-0".to_string(), owned.clone())]);
+        let first = assignor.assign(&partitions_per_topic, &subscriptions, &current);
+        assert_eq!(first["member-0"].len(), 2);
+        assert!(first["member-1"].is_empty());
+
+        // once member-0 has let go of the other two they go to member-1
+        let second = assignor.assign(&partitions_per_topic, &subscriptions, &first);
+        assert_eq!(second["member-0"], first["member-0"]);
+        assert_eq!(second["member-1"].len(), 2);
+        assert_complete(&second, &partitions_per_topic);
+
+        // the plain sticky assignor moves them straight away
+        let sticky = StickyAssignor.assign(&partitions_per_topic, &subscriptions, &current);
+        assert_eq!(sticky, second);
+    }
+}
@@ -0,0 +1,1281 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::sync::{Mutex, Notify, RwLock};
+use uuid::Uuid;
+
+use crate::constants::{
+    EARLIEST_TIMESTAMP, LATEST_TIMESTAMP, REPLICA_FETCH_PARTITION_MAX_BYTES, TRANSACTION_STATE_NUM_PARTITIONS,
+    TRANSACTION_STATE_REPLICATION_FACTOR, TRANSACTION_STATE_TOPIC, TRANSACTION_WRITE_TIMEOUT_MS,
+};
+use crate::core::consumer_group::{GroupError, TopicPartition};
+use crate::core::controller::{Controller, NewTopic, PartitionState, LEADER_DURING_DELETE, NO_LEADER};
+use crate::core::delayed_fetch::{DelayedFetch, FetchIsolation, FetchParams, FetchPartitionData, FetchPartitionStatus};
+use crate::core::delayed_produce::{DelayedProduce, ProducePartitionResult};
+use crate::core::group_coordinator::{GroupCoordinator, OffsetResetStrategy};
+use crate::core::lag::{partition_lag, GroupLag};
+use crate::core::metadata::MetadataImage;
+use crate::core::metrics::Metrics;
+use crate::core::partition::Partition;
+use crate::core::producer_id::{ProducerIdBlock, ProducerIdPool};
+use crate::core::purgatory::DelayedOperationPurgatory;
+use crate::core::replica_selector::ReplicaSelector;
+use crate::core::replication::{IsrChange, ReplicaManager, ReplicationError};
+use crate::core::topic::{Topic, TopicConfig};
+use crate::core::topic_manager::TopicManager;
+use crate::core::transaction_coordinator::{
+    partition_for, TransactionCoordinator, TransactionError, TransactionMetadata, TransactionState, TxnMarker,
+};
+use crate::error::KafkaErrorCode;
+use crate::raft::node::RaftNode;
+use crate::storage::record_batch;
+
+/// where appended records come from. Only the broker's own coordinators may write
+/// control batches or internal topics.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AppendOrigin {
+    Client,
+    Coordinator,
+}
+
+// state shared by every connection of a single broker
+#[derive(Debug)]
+pub struct Broker {
+    broker_id: i32,
+    rack: Option<String>, // broker.rack
+    topic_manager: TopicManager,
+    broker_endpoints: RwLock<HashMap<i32, String>>, // broker id -> host:port of its Kafka listener
+    broker_racks: RwLock<HashMap<i32, String>>, // racks of the followers fetching from this broker
+    replica_selector: OnceLock<Box<dyn ReplicaSelector>>, // picks replicas for consumers to read from
+    controller: OnceLock<Arc<Controller>>, // set when this broker also runs the controller
+    metadata_log: OnceLock<Arc<RaftNode>>, // quorum node replaying the metadata log into the cache
+    known_controller: RwLock<Option<(i32, i32)>>, // (controller id, controller epoch) from the last LeaderAndIsr
+    pending_isr_changes: Mutex<Vec<IsrChange>>, // waiting to be sent to the controller in AlterPartition
+    isr_changes_queued: Notify,
+    producer_ids: Mutex<ProducerIdPool>, // handed out in InitProducerId
+    producer_ids_wanted: Notify,
+    group_coordinator: GroupCoordinator,
+    transaction_coordinator: TransactionCoordinator,
+    replica_manager: ReplicaManager,
+    produce_purgatory: DelayedOperationPurgatory<TopicPartition>, // acks=all produces waiting on the HW
+    fetch_purgatory: DelayedOperationPurgatory<TopicPartition>, // fetches waiting for min_bytes
+    metrics: Metrics,
+}
+
+impl From<ReplicationError> for GroupError {
+    fn from(error: ReplicationError) -> Self {
+        match error {
+            ReplicationError::UnknownTopicOrPartition(topic, partition) => GroupError::UnknownTopicOrPartition(topic, partition),
+            other => GroupError::KafkaStorageError(other.to_string()),
+        }
+    }
+}
+
+impl Broker {
+    pub fn new(broker_id: i32) -> Self {
+        Self::with_replica_manager(broker_id, ReplicaManager::new(broker_id))
+    }
+
+    pub fn with_log_dir(broker_id: i32, log_dir: PathBuf) -> Self {
+        Self::with_replica_manager(broker_id, ReplicaManager::with_log_dir(broker_id, log_dir))
+    }
+
+    fn with_replica_manager(broker_id: i32, replica_manager: ReplicaManager) -> Self {
+        Broker {
+            broker_id,
+            rack: None,
+            topic_manager: TopicManager::load(replica_manager.log_dir().to_path_buf()),
+            broker_endpoints: RwLock::new(HashMap::new()),
+            broker_racks: RwLock::new(HashMap::new()),
+            replica_selector: OnceLock::new(),
+            controller: OnceLock::new(),
+            metadata_log: OnceLock::new(),
+            known_controller: RwLock::new(None),
+            pending_isr_changes: Mutex::new(Vec::new()),
+            isr_changes_queued: Notify::new(),
+            producer_ids: Mutex::new(ProducerIdPool::default()),
+            producer_ids_wanted: Notify::new(),
+            group_coordinator: GroupCoordinator::new(),
+            transaction_coordinator: TransactionCoordinator::new(),
+            replica_manager,
+            produce_purgatory: DelayedOperationPurgatory::new(),
+            fetch_purgatory: DelayedOperationPurgatory::new(),
+            metrics: Metrics::new(),
+        }
+    }
+
+    /// places this broker in a rack, used for spreading replicas and for follower fetching
+    pub fn with_rack(mut self, rack: String) -> Self {
+        self.rack = Some(rack);
+        self
+    }
+
+    pub fn broker_id(&self) -> i32 {
+        self.broker_id
+    }
+
+    pub fn rack(&self) -> Option<&str> {
+        self.rack.as_deref()
+    }
+
+    pub fn group_coordinator(&self) -> &GroupCoordinator {
+        &self.group_coordinator
+    }
+
+    pub fn transaction_coordinator(&self) -> &TransactionCoordinator {
+        &self.transaction_coordinator
+    }
+
+    pub fn topic_manager(&self) -> &TopicManager {
+        &self.topic_manager
+    }
+
+    pub fn replica_manager(&self) -> &ReplicaManager {
+        &self.replica_manager
+    }
+
+    pub fn produce_purgatory(&self) -> &DelayedOperationPurgatory<TopicPartition> {
+        &self.produce_purgatory
+    }
+
+    pub fn fetch_purgatory(&self) -> &DelayedOperationPurgatory<TopicPartition> {
+        &self.fetch_purgatory
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub async fn register_broker_endpoint(&self, broker_id: i32, address: String) {
+        let mut endpoints = self.broker_endpoints.write().await;
+        endpoints.insert(broker_id, address);
+    }
+
+    pub async fn broker_endpoint(&self, broker_id: i32) -> Option<String> {
+        let endpoints = self.broker_endpoints.read().await;
+        if let Some(address) = endpoints.get(&broker_id) {
+            return Some(address.clone());
+        }
+        let image = self.metadata_log.get()?.image();
+        let image = image.read().await;
+        image.brokers.get(&broker_id)?.address()
+    }
+
+    pub async fn register_broker_rack(&self, broker_id: i32, rack: String) {
+        let mut racks = self.broker_racks.write().await;
+        racks.insert(broker_id, rack);
+    }
+
+    pub async fn broker_rack(&self, broker_id: i32) -> Option<String> {
+        if broker_id == self.broker_id {
+            return self.rack.clone();
+        }
+        if let Some(rack) = self.broker_racks.read().await.get(&broker_id) {
+            return Some(rack.clone());
+        }
+        let image = self.metadata_log.get()?.image();
+        let image = image.read().await;
+        image.brokers.get(&broker_id)?.rack.clone()
+    }
+
+    /// the controller running inside this broker, if any
+    pub fn controller(&self) -> Option<&Arc<Controller>> {
+        self.controller.get()
+    }
+
+    /// runs the cluster controller inside this broker; can only happen once
+    pub fn attach_controller(&self, controller: Arc<Controller>) -> bool {
+        self.controller.set(controller).is_ok()
+    }
+
+    /// lets consumers read from replicas other than the leader; can only happen once
+    pub fn set_replica_selector(&self, selector: Box<dyn ReplicaSelector>) -> bool {
+        self.replica_selector.set(selector).is_ok()
+    }
+
+    /// ids of the log directories this broker stores partitions in
+    pub fn log_dir_ids(&self) -> std::io::Result<Vec<Uuid>> {
+        Ok(vec![self.replica_manager.directory_id()?])
+    }
+
+    /// builds the metadata cache by replaying the quorum's log; can only happen once
+    pub fn attach_metadata_log(&self, raft: Arc<RaftNode>) -> bool {
+        self.metadata_log.set(raft).is_ok()
+    }
+
+    /// cluster metadata as of the last record this broker replayed, if it follows the quorum
+    pub async fn metadata_image(&self) -> Option<MetadataImage> {
+        let image = self.metadata_log.get()?.image();
+        let image = image.read().await.clone();
+        Some(image)
+    }
+
+    /// offset of the last metadata record this broker replayed, -1 if it doesn't follow the quorum
+    pub fn metadata_offset(&self) -> i64 {
+        self.metadata_log.get().map_or(-1, |raft| raft.applied_offset())
+    }
+
+    /// id of the controller that last sent this broker partition state, or else the
+    /// quorum leader
+    pub async fn known_controller_id(&self) -> Option<i32> {
+        let known = self.known_controller.read().await.map(|(controller_id, _)| controller_id);
+        known.or_else(|| self.metadata_log.get()?.status().leader_id)
+    }
+
+    /// applies LeaderAndIsr from the controller: opens topics and partitions this broker
+    /// hasn't seen, then leads, follows or stops replicating each partition. Requests from
+    /// an older controller epoch are rejected as a whole.
+    pub async fn apply_leader_and_isr(
+        &self,
+        controller_id: i32,
+        controller_epoch: i32,
+        states: &[PartitionState],
+        live_leaders: &[(i32, String)],
+    ) -> Result<Vec<(TopicPartition, KafkaErrorCode)>, KafkaErrorCode> {
+        {
+            let mut known = self.known_controller.write().await;
+            if known.is_some_and(|(_, epoch)| epoch > controller_epoch) {
+                return Err(KafkaErrorCode::StaleControllerEpoch);
+            }
+            *known = Some((controller_id, controller_epoch));
+        }
+        for (broker_id, address) in live_leaders {
+            self.register_broker_endpoint(*broker_id, address.clone()).await;
+        }
+
+        self.create_pushed_topics(states).await;
+        let mut results = Vec::with_capacity(states.len());
+        for state in states {
+            let tp = TopicPartition::new(state.topic.clone(), state.partition);
+            if state.leader == LEADER_DURING_DELETE {
+                let error = self.delete_partition(state).await;
+                results.push((tp, error));
+                continue;
+            }
+            let partition = match self.topic_manager.get(&state.topic).await {
+                Some(topic) => topic.get_partition(state.partition).await,
+                None => None,
+            };
+            let error = match partition {
+                Some(partition) => match self.apply_partition_state(&partition, state).await {
+                    Ok(()) => KafkaErrorCode::None,
+                    Err(error) => error,
+                },
+                None => KafkaErrorCode::UnknownTopicOrPartition,
+            };
+            if error != KafkaErrorCode::None {
+                println!("LeaderAndIsr for {}-{} failed: {:?}", state.topic, state.partition, error);
+            }
+            results.push((tp, error));
+        }
+        Ok(results)
+    }
+
+    // creates the topics and partitions the request names that are new to this broker,
+    // such as those added by CreatePartitions. New topics are configured with their
+    // overrides when this broker follows the metadata log or runs the controller.
+    async fn create_pushed_topics(&self, states: &[PartitionState]) {
+        let image = self.metadata_image().await;
+        // the partitions named for each topic, in request order
+        let mut pushed: Vec<(&PartitionState, Vec<i32>)> = Vec::new();
+        for state in states.iter().filter(|state| state.leader != LEADER_DURING_DELETE) {
+            match pushed.iter_mut().find(|(first, _)| first.topic == state.topic) {
+                Some((_, partitions)) => partitions.push(state.partition),
+                None => pushed.push((state, vec![state.partition])),
+            }
+        }
+
+        for (state, partitions) in pushed {
+            let existing = self.topic_manager.get(&state.topic).await;
+            if let Some(topic) = existing.as_ref().filter(|topic| topic.topic_id() == state.topic_id) {
+                self.topic_manager.add_partitions(topic, &partitions).await;
+                continue;
+            }
+            if let Some(existing) = existing {
+                // deleted and created again before this broker heard of the deletion
+                for partition in existing.all_partitions().await {
+                    self.delete_partition(&PartitionState { partition, topic_id: existing.topic_id(), ..state.clone() }).await;
+                }
+            }
+            let overrides = match (&image, self.controller()) {
+                (Some(image), _) => image.configs.get(&state.topic).cloned(),
+                (None, Some(controller)) => controller.topic_configs(&state.topic).await,
+                (None, None) => None,
+            };
+            let config = match overrides.as_ref().map(TopicConfig::from_overrides) {
+                Some(Ok(config)) => config,
+                Some(Err(e)) => {
+                    eprintln!("Ignoring config overrides of {}: {}", state.topic, e);
+                    TopicConfig::default()
+                }
+                None => TopicConfig::default(),
+            };
+            let mut topic = Topic::with_id(state.topic.clone(), state.topic_id, state.replicas.len() as i32, config);
+            for partition_id in partitions {
+                topic.insert_partition(partition_id, Partition::new(partition_id));
+            }
+            if let Err(e) = self.topic_manager.create(topic).await {
+                eprintln!("Failed to create topic {}: {}", state.topic, e);
+            }
+        }
+    }
+
+    async fn apply_partition_state(&self, partition: &Partition, state: &PartitionState) -> Result<(), KafkaErrorCode> {
+        let (topic, partition_id) = (state.topic.as_str(), state.partition);
+        let current_epoch = partition.leader_epoch().await;
+        if state.leader_epoch < current_epoch {
+            return Err(KafkaErrorCode::FencedLeaderEpoch);
+        }
+        let was_replica = partition.replicas().await.contains(&self.broker_id);
+        partition.set_replicas(state.replicas.clone()).await;
+        let leader = (state.leader != NO_LEADER).then_some(state.leader);
+
+        // same leadership, only the ISR may have moved. A reassignment adding or
+        // removing this broker keeps the leader epoch but still changes its role.
+        let same_role = was_replica == state.replicas.contains(&self.broker_id);
+        if state.leader_epoch == current_epoch && partition.leader().await == leader && same_role {
+            partition.update_isr(state.isr.clone()).await;
+            if state.leader == self.broker_id {
+                self.replica_manager.set_replicas(topic, partition_id, state.replicas.clone()).await;
+            }
+            if state.leader == self.broker_id && self.replica_manager.set_isr(topic, partition_id, state.isr.clone()).await {
+                self.replica_manager.flush_partition_state(topic, partition_id).await;
+                self.on_partition_changed(&TopicPartition::new(topic.to_string(), partition_id)).await;
+            }
+            return Ok(());
+        }
+
+        let role_change = if state.leader == self.broker_id {
+            self.become_leader(topic, partition_id, state.leader_epoch, state.replicas.clone(), state.isr.clone()).await
+        } else if state.leader != NO_LEADER && state.replicas.contains(&self.broker_id) {
+            self.become_follower(topic, partition_id, state.leader, state.leader_epoch).await
+        } else {
+            self.stop_replica(topic, partition_id).await;
+            Ok(())
+        };
+        role_change.map_err(|e| e.error_code())?;
+
+        match leader {
+            Some(leader) => partition.set_leader(leader).await,
+            None => partition.clear_leader().await,
+        }
+        partition.set_leader_epoch(state.leader_epoch).await;
+        partition.update_isr(state.isr.clone()).await;
+        Ok(())
+    }
+
+    // makes this broker the leader of a partition with the given replicas and ISR,
+    // which always include this broker
+    async fn become_leader(
+        &self,
+        topic: &str,
+        partition_id: i32,
+        leader_epoch: i32,
+        replicas: Vec<i32>,
+        isr: Vec<i32>,
+    ) -> Result<(), ReplicationError> {
+        self.replica_manager.add_leader_partition(topic.to_string(), partition_id, leader_epoch, replicas, isr).await?;
+        self.replica_manager.remove_follower_partition(topic.to_string(), partition_id).await;
+        self.replica_manager.flush_partition_state(topic, partition_id).await;
+        // waiting operations have to notice the leadership change
+        self.on_partition_changed(&TopicPartition::new(topic.to_string(), partition_id)).await;
+        if topic == TRANSACTION_STATE_TOPIC {
+            self.load_transactions(partition_id, leader_epoch).await;
+        }
+        Ok(())
+    }
+
+    // makes this broker a follower of `leader_id`, fetching from its local log end
+    async fn become_follower(&self, topic: &str, partition_id: i32, leader_id: i32, leader_epoch: i32) -> Result<(), ReplicationError> {
+        self.replica_manager.remove_leader_partition(topic.to_string(), partition_id).await;
+        // a log that isn't open yet starts out empty
+        let log_end_offset = self.replica_manager.list_offset(topic, partition_id, LATEST_TIMESTAMP).await.ok().flatten().unwrap_or(0);
+        self.replica_manager.add_follower_partition(topic.to_string(), partition_id, leader_id, leader_epoch, self.broker_id, log_end_offset).await?;
+        println!("Following broker {} for {}-{} in epoch {}", leader_id, topic, partition_id, leader_epoch);
+        self.on_partition_changed(&TopicPartition::new(topic.to_string(), partition_id)).await;
+        if topic == TRANSACTION_STATE_TOPIC {
+            self.transaction_coordinator.unload(partition_id).await;
+        }
+        Ok(())
+    }
+
+    // neither leads nor follows the partition any more; its log stays on disk
+    async fn stop_replica(&self, topic: &str, partition_id: i32) {
+        self.replica_manager.remove_leader_partition(topic.to_string(), partition_id).await;
+        self.replica_manager.remove_follower_partition(topic.to_string(), partition_id).await;
+        self.on_partition_changed(&TopicPartition::new(topic.to_string(), partition_id)).await;
+        if topic == TRANSACTION_STATE_TOPIC {
+            self.transaction_coordinator.unload(partition_id).await;
+        }
+    }
+
+    // deletes a partition of a deleted topic: its replica, log and the offsets groups
+    // committed for it. The topic goes with its last partition. Partitions of another
+    // topic of the same name are left alone.
+    async fn delete_partition(&self, state: &PartitionState) -> KafkaErrorCode {
+        let (name, partition_id) = (state.topic.as_str(), state.partition);
+        let Some(topic) = self.topic_manager.get(name).await.filter(|topic| topic.topic_id() == state.topic_id) else {
+            return KafkaErrorCode::None;
+        };
+        self.stop_replica(name, partition_id).await;
+        if let Err(e) = self.replica_manager.delete_partition(name, partition_id).await {
+            eprintln!("Failed to delete {}-{}: {}", name, partition_id, e);
+            return e.error_code();
+        }
+        self.topic_manager.remove_partition(&topic, partition_id).await;
+        let tp = TopicPartition::new(name.to_string(), partition_id);
+        self.group_coordinator.on_partitions_deleted(std::slice::from_ref(&tp)).await;
+        println!("Deleted partition {}-{}", name, partition_id);
+        KafkaErrorCode::None
+    }
+
+    // reads a `__transaction_state` partition this broker now leads back into the
+    // transaction coordinator, the latest state of every transactional id winning
+    async fn load_transactions(&self, partition_id: i32, coordinator_epoch: i32) {
+        self.transaction_coordinator.begin_loading(partition_id).await;
+        let mut transactions: HashMap<String, TransactionMetadata> = HashMap::new();
+        let mut offset = self
+            .replica_manager
+            .list_offset(TRANSACTION_STATE_TOPIC, partition_id, EARLIEST_TIMESTAMP)
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or(0);
+        loop {
+            let fetched = match self
+                .replica_manager
+                .read_records(
+                    TRANSACTION_STATE_TOPIC,
+                    partition_id,
+                    offset,
+                    REPLICA_FETCH_PARTITION_MAX_BYTES as usize,
+                    FetchIsolation::LogEnd,
+                )
+                .await
+            {
+                Ok(fetched) if !fetched.records.is_empty() => fetched,
+                Ok(_) => break,
+                Err(e) => {
+                    eprintln!("Loading {}-{} stopped at offset {}: {}", TRANSACTION_STATE_TOPIC, partition_id, offset, e);
+                    break;
+                }
+            };
+            for batch in record_batch::split_batches(&fetched.records) {
+                let (Some(base_offset), Some(count)) = (record_batch::base_offset(batch), record_batch::record_count(batch)) else {
+                    break;
+                };
+                offset = base_offset + count;
+                for value in record_batch::record_values(batch).into_iter().flatten().flatten() {
+                    match serde_json::from_slice::<TransactionMetadata>(&value) {
+                        Ok(metadata) => {
+                            transactions.insert(metadata.transactional_id.clone(), metadata);
+                        }
+                        Err(e) => eprintln!("Skipping malformed transaction state at offset {}: {}", base_offset, e),
+                    }
+                }
+            }
+        }
+        self.transaction_coordinator
+            .complete_loading(partition_id, coordinator_epoch, transactions.into_values().collect())
+            .await;
+    }
+
+    /// appends produced batches to partitions this broker leads. With acks=-1 the
+    /// call returns once every ISR member has the data or `timeout` passes.
+    pub async fn append_records(
+        &self,
+        origin: AppendOrigin,
+        acks: i16,
+        timeout: Duration,
+        entries: Vec<(TopicPartition, Vec<u8>)>,
+    ) -> Vec<ProducePartitionResult> {
+        let mut results = Vec::with_capacity(entries.len());
+        for (tp, records) in entries {
+            let mut result = ProducePartitionResult {
+                tp,
+                error: KafkaErrorCode::None,
+                base_offset: -1,
+                log_start_offset: -1,
+                required_offset: -1,
+            };
+            match self.append_to_partition(origin, acks, &result.tp, &records).await {
+                Ok((base_offset, last_offset)) => {
+                    result.base_offset = base_offset;
+                    result.required_offset = last_offset + 1;
+                    result.log_start_offset = self
+                        .replica_manager
+                        .list_offset(result.tp.topic(), result.tp.partition(), EARLIEST_TIMESTAMP)
+                        .await
+                        .ok()
+                        .flatten()
+                        .unwrap_or(-1);
+                }
+                Err(error) => result.error = error,
+            }
+            results.push(result);
+        }
+
+        let waiting: Vec<TopicPartition> = results
+            .iter()
+            .filter(|result| result.error == KafkaErrorCode::None)
+            .map(|result| result.tp.clone())
+            .collect();
+        if acks != -1 || waiting.is_empty() {
+            return results;
+        }
+
+        let delayed = DelayedProduce::new(&self.replica_manager, results);
+        self.produce_purgatory.try_complete_else_watch(&delayed, &waiting, timeout).await
+    }
+
+    async fn append_to_partition(
+        &self,
+        origin: AppendOrigin,
+        acks: i16,
+        tp: &TopicPartition,
+        records: &[u8],
+    ) -> Result<(i64, i64), KafkaErrorCode> {
+        let (topic_name, partition_id) = (tp.topic(), tp.partition());
+        // a client could otherwise forge coordinator state or end its own transaction
+        if origin == AppendOrigin::Client {
+            if topic_name == TRANSACTION_STATE_TOPIC {
+                return Err(KafkaErrorCode::InvalidTopicException);
+            }
+            if record_batch::split_batches(records).into_iter().any(record_batch::is_control) {
+                return Err(KafkaErrorCode::InvalidRecord);
+            }
+        }
+        let topic = self.topic_manager.get_or_auto_create(topic_name, self.controller()).await?;
+        if topic.get_partition(partition_id).await.is_none() {
+            return Err(KafkaErrorCode::UnknownTopicOrPartition);
+        }
+        if records.len() > topic.max_message_bytes().max(0) as usize {
+            return Err(KafkaErrorCode::MessageTooLarge);
+        }
+        if acks == -1 && !topic.has_enough_replicas(partition_id).await {
+            return Err(KafkaErrorCode::NotEnoughReplicas);
+        }
+
+        let appended = self.replica_manager.append_as_leader(topic_name, partition_id, records).await;
+        // replica fetches wait on the log end; alone in the ISR, the HW moved too
+        self.on_partition_changed(tp).await;
+        appended.map_err(|e| {
+            println!("Produce to {}-{} failed: {}", topic_name, partition_id, e);
+            e.error_code()
+        })
+    }
+
+    /// wakes delayed operations after a partition's log, high watermark or leader
+    /// changed, and brings the Partition's last stable offset up to date
+    pub async fn on_partition_changed(&self, tp: &TopicPartition) {
+        let partition = match self.topic_manager.get(tp.topic()).await {
+            Some(topic) => topic.get_partition(tp.partition()).await,
+            None => None,
+        };
+        if let Some(partition) = partition {
+            let last_stable_offset = self.replica_manager.last_stable_offset(tp.topic(), tp.partition()).await;
+            partition.set_last_stable_offset(last_stable_offset.unwrap_or(-1)).await;
+        }
+        self.produce_purgatory.check_and_complete(tp);
+        self.fetch_purgatory.check_and_complete(tp);
+    }
+
+    /// serves a Fetch, waiting up to max_wait_ms for min_bytes to show up.
+    /// Replica fetches record the follower's progress first.
+    pub async fn fetch_messages(&self, params: FetchParams, partitions: Vec<FetchPartitionStatus>) -> Vec<FetchPartitionData> {
+        if params.is_from_follower() {
+            for status in &partitions {
+                self.record_replica_fetch(status.tp.topic(), status.tp.partition(), params.replica_id, status.fetch_offset).await;
+            }
+        }
+
+        if partitions.is_empty() {
+            return Vec::new();
+        }
+
+        let mut preferred = Vec::with_capacity(partitions.len());
+        for status in &partitions {
+            preferred.push(self.preferred_read_replica(&params, status).await);
+        }
+        if preferred.iter().any(Option::is_some) {
+            // answered right away without records so the consumer moves to its replica
+            let partitions: Vec<FetchPartitionStatus> = partitions
+                .into_iter()
+                .zip(&preferred)
+                .map(|(status, replica)| FetchPartitionStatus { max_bytes: if replica.is_some() { 0 } else { status.max_bytes }, ..status })
+                .collect();
+            let mut data = self.replica_manager.read_from_local_log(&params, &partitions).await;
+            for (partition, replica) in data.iter_mut().zip(preferred) {
+                if replica.is_some() && partition.error == KafkaErrorCode::None {
+                    partition.records.clear();
+                    partition.preferred_read_replica = replica;
+                }
+            }
+            return data;
+        }
+
+        let timeout = Duration::from_millis(params.max_wait_ms.max(0) as u64);
+        let keys: Vec<TopicPartition> = partitions.iter().map(|status| status.tp.clone()).collect();
+        let delayed = DelayedFetch::new(&self.replica_manager, params, partitions);
+        self.fetch_purgatory.try_complete_else_watch(&delayed, &keys, timeout).await
+    }
+
+    // the replica the selector sends a consumer to when it isn't this broker. Only
+    // leaders pick one, out of the in-sync replicas that have the fetch offset.
+    async fn preferred_read_replica(&self, params: &FetchParams, status: &FetchPartitionStatus) -> Option<i32> {
+        let selector = self.replica_selector.get()?;
+        let client = params.client_metadata.as_ref()?;
+        let (topic, partition_id) = (status.tp.topic(), status.tp.partition());
+        let mut view = self
+            .replica_manager
+            .partition_view(topic, partition_id, Utc::now().timestamp_millis())
+            .await?;
+        view.replicas
+            .retain(|replica| replica.broker_id == self.broker_id || replica.log_end_offset >= status.fetch_offset);
+        view.leader.rack = self.rack.clone();
+        for replica in &mut view.replicas {
+            replica.rack = self.broker_rack(replica.broker_id).await;
+        }
+
+        let selected = selector.select(&status.tp, client, &view)?;
+        (selected != self.broker_id).then_some(selected)
+    }
+
+    /// leader side of a replica fetch: tracks the follower and applies any ISR expansion
+    pub async fn record_replica_fetch(&self, topic: &str, partition_id: i32, replica_id: i32, fetch_offset: i64) {
+        let outcome = self
+            .replica_manager
+            .update_follower_progress(topic.to_string(), partition_id, replica_id, fetch_offset)
+            .await;
+        if let Some(change) = outcome.isr_change {
+            self.apply_isr_change(&change).await;
+        }
+        if outcome.high_watermark_advanced {
+            self.on_partition_changed(&TopicPartition::new(topic.to_string(), partition_id)).await;
+        }
+    }
+
+    /// removes followers that fell behind from every ISR this broker leads
+    pub async fn maybe_shrink_isrs(&self) {
+        for change in self.replica_manager.maybe_shrink_isr(Utc::now().timestamp_millis()).await {
+            self.apply_isr_change(&change).await;
+            // a smaller ISR can let the HW catch up with waiting produces
+            self.on_partition_changed(&TopicPartition::new(change.topic.clone(), change.partition_id)).await;
+        }
+    }
+
+    // keeps the topic's Partition and the persisted partition metadata in line with the
+    // leader state, and queues the change for the controller if there is one
+    async fn apply_isr_change(&self, change: &IsrChange) {
+        if let Some(topic) = self.topic_manager.get(&change.topic).await {
+            if let Some(partition) = topic.get_partition(change.partition_id).await {
+                partition.update_isr(change.isr.clone()).await;
+            }
+        }
+        self.replica_manager.flush_partition_state(&change.topic, change.partition_id).await;
+
+        if self.known_controller.read().await.is_some() {
+            let mut pending = self.pending_isr_changes.lock().await;
+            // only the latest ISR of a partition matters
+            pending.retain(|queued| queued.topic != change.topic || queued.partition_id != change.partition_id);
+            pending.push(change.clone());
+            self.isr_changes_queued.notify_one();
+        }
+    }
+
+    /// waits for ISR changes the controller hasn't been told about and takes them
+    pub async fn take_pending_isr_changes(&self) -> Vec<IsrChange> {
+        loop {
+            let notified = self.isr_changes_queued.notified();
+            {
+                let mut pending = self.pending_isr_changes.lock().await;
+                if !pending.is_empty() {
+                    return std::mem::take(&mut *pending);
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// puts back changes that couldn't be sent, unless a newer one was queued meanwhile
+    pub async fn requeue_isr_changes(&self, changes: Vec<IsrChange>) {
+        let mut pending = self.pending_isr_changes.lock().await;
+        for change in changes {
+            if !pending.iter().any(|queued| queued.topic == change.topic && queued.partition_id == change.partition_id) {
+                pending.push(change);
+            }
+        }
+        if !pending.is_empty() {
+            self.isr_changes_queued.notify_one();
+        }
+    }
+
+    /// a new producer id for an idempotent producer, or CoordinatorLoadInProgress
+    /// while the controller hasn't handed this broker a block yet
+    pub async fn generate_producer_id(&self) -> Result<i64, KafkaErrorCode> {
+        let mut pool = self.producer_ids.lock().await;
+        let producer_id = pool.next_id();
+        if pool.wants_block() {
+            self.producer_ids_wanted.notify_one();
+        }
+        producer_id.ok_or(KafkaErrorCode::CoordinatorLoadInProgress)
+    }
+
+    /// waits until the producer id pool needs another block from the controller
+    pub async fn wait_for_producer_id_demand(&self) {
+        loop {
+            let notified = self.producer_ids_wanted.notified();
+            if self.producer_ids.lock().await.wants_block() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    pub async fn add_producer_id_block(&self, block: ProducerIdBlock) {
+        self.producer_ids.lock().await.add_block(block);
+    }
+
+    /// InitProducerId for a transactional producer: its producer id and the next
+    /// epoch, which fences off older instances of it
+    pub async fn init_transactional_producer(&self, transactional_id: &str, timeout_ms: i32) -> Result<(i64, i16), KafkaErrorCode> {
+        self.ensure_transaction_state_topic().await?;
+        let new_producer_id = if self.transaction_coordinator.needs_producer_id(transactional_id).await {
+            Some(self.generate_producer_id().await?)
+        } else {
+            None
+        };
+        let target = self
+            .transaction_coordinator
+            .prepare_init_producer_id(transactional_id, timeout_ms, new_producer_id, Utc::now().timestamp_millis())
+            .await
+            .map_err(|e| e.error_code())?;
+        let (producer_id, producer_epoch, state) = (target.producer_id, target.producer_epoch, target.state);
+        self.write_transaction_transition(target).await?;
+        // the previous instance's transaction is being aborted; the producer retries
+        if state == TransactionState::PrepareAbort {
+            return Err(KafkaErrorCode::ConcurrentTransactions);
+        }
+        Ok((producer_id, producer_epoch))
+    }
+
+    // transactions need `__transaction_state`; the controller running here creates it
+    // the first time a transactional producer shows up
+    async fn ensure_transaction_state_topic(&self) -> Result<(), KafkaErrorCode> {
+        if self.topic_manager.get(TRANSACTION_STATE_TOPIC).await.is_some() {
+            return Ok(());
+        }
+        if let Some(controller) = self.controller() {
+            if controller.topic_id(TRANSACTION_STATE_TOPIC).await.is_none() {
+                let live_brokers = controller.live_brokers().await.len() as i32;
+                let replication_factor = TRANSACTION_STATE_REPLICATION_FACTOR.min(live_brokers).max(1);
+                let topic = NewTopic {
+                    name: TRANSACTION_STATE_TOPIC.to_string(),
+                    num_partitions: TRANSACTION_STATE_NUM_PARTITIONS,
+                    replication_factor,
+                    ..NewTopic::default()
+                };
+                match controller.create_topic(&topic, false).await {
+                    Ok(_) => println!("Created {} with replication factor {}", TRANSACTION_STATE_TOPIC, replication_factor),
+                    Err(e) => eprintln!("Failed to create {}: {}", TRANSACTION_STATE_TOPIC, e),
+                }
+            }
+        }
+        Err(KafkaErrorCode::CoordinatorNotAvailable)
+    }
+
+    /// AddPartitionsToTxn: the error of every partition. None of them is added when
+    /// one doesn't exist.
+    pub async fn add_partitions_to_txn(
+        &self,
+        transactional_id: &str,
+        producer_id: i64,
+        producer_epoch: i16,
+        partitions: Vec<TopicPartition>,
+    ) -> Vec<(TopicPartition, KafkaErrorCode)> {
+        let mut unknown = Vec::new();
+        for tp in &partitions {
+            if !self.partition_exists(tp).await {
+                unknown.push(tp.clone());
+            }
+        }
+        if !unknown.is_empty() {
+            return partitions
+                .into_iter()
+                .map(|tp| {
+                    let error = if unknown.contains(&tp) { KafkaErrorCode::UnknownTopicOrPartition } else { KafkaErrorCode::OperationNotAttempted };
+                    (tp, error)
+                })
+                .collect();
+        }
+        let prepared = self
+            .transaction_coordinator
+            .prepare_add_partitions(transactional_id, producer_id, producer_epoch, &partitions, Utc::now().timestamp_millis())
+            .await;
+        let error = self.apply_prepared_transition(prepared).await;
+        partitions.into_iter().map(|tp| (tp, error)).collect()
+    }
+
+    /// AddOffsetsToTxn: the group's offsets commit or abort with the transaction
+    pub async fn add_offsets_to_txn(&self, transactional_id: &str, producer_id: i64, producer_epoch: i16, group_id: &str) -> KafkaErrorCode {
+        let prepared = self
+            .transaction_coordinator
+            .prepare_add_group(transactional_id, producer_id, producer_epoch, group_id, Utc::now().timestamp_millis())
+            .await;
+        self.apply_prepared_transition(prepared).await
+    }
+
+    /// EndTxn: starts writing the COMMIT or ABORT markers of the ongoing transaction.
+    /// Done once the decision is written, the markers follow in the background.
+    pub async fn end_txn(&self, transactional_id: &str, producer_id: i64, producer_epoch: i16, commit: bool) -> KafkaErrorCode {
+        let prepared = self
+            .transaction_coordinator
+            .prepare_end_txn(transactional_id, producer_id, producer_epoch, commit, Utc::now().timestamp_millis())
+            .await;
+        self.apply_prepared_transition(prepared).await
+    }
+
+    // writes a change a transaction request prepared, if it needs one
+    async fn apply_prepared_transition(
+        &self,
+        prepared: Result<Option<TransactionMetadata>, TransactionError>,
+    ) -> KafkaErrorCode {
+        match prepared {
+            Ok(Some(target)) => self.write_transaction_transition(target).await.err().unwrap_or(KafkaErrorCode::None),
+            Ok(None) => KafkaErrorCode::None,
+            Err(e) => e.error_code(),
+        }
+    }
+
+    /// aborts every transaction that ran past its timeout
+    pub async fn abort_timed_out_transactions(&self) {
+        for target in self.transaction_coordinator.prepare_timed_out_aborts(Utc::now().timestamp_millis()).await {
+            println!("Aborting the transaction of {} after {} ms", target.transactional_id, target.timeout_ms);
+            let transactional_id = target.transactional_id.clone();
+            if let Err(error) = self.write_transaction_transition(target).await {
+                eprintln!("Failed to abort the transaction of {}: {:?}", transactional_id, error);
+            }
+        }
+    }
+
+    /// WriteTxnMarkers for partitions this broker leads: appends the COMMIT or ABORT
+    /// control batch to each, waiting for the ISR
+    pub async fn write_txn_markers(
+        &self,
+        producer_id: i64,
+        producer_epoch: i16,
+        commit: bool,
+        coordinator_epoch: i32,
+        partitions: Vec<TopicPartition>,
+    ) -> Vec<(TopicPartition, KafkaErrorCode)> {
+        let batch = record_batch::build_control_batch(producer_id, producer_epoch, commit, coordinator_epoch, Utc::now().timestamp_millis());
+        let entries = partitions.into_iter().map(|tp| (tp, batch.clone())).collect();
+        self.append_records(AppendOrigin::Coordinator, -1, Duration::from_millis(TRANSACTION_WRITE_TIMEOUT_MS), entries)
+            .await
+            .into_iter()
+            .map(|result| (result.tp, result.error))
+            .collect()
+    }
+
+    /// finishes a transaction whose markers are all written: settles its groups'
+    /// offsets and records it as complete
+    pub async fn complete_transaction(&self, marker: TxnMarker) {
+        self.group_coordinator
+            .complete_transactional_offsets(&marker.groups, marker.producer_id, marker.commit)
+            .await;
+        let error = match self.transaction_coordinator.prepare_complete(&marker, Utc::now().timestamp_millis()).await {
+            Ok(Some(target)) => match self.write_transaction_transition(target).await {
+                Ok(()) => return,
+                Err(error) => error,
+            },
+            Ok(None) => return,
+            Err(e) => e.error_code(),
+        };
+        // a coordinator that moved has the new leader finish the transaction
+        if error != KafkaErrorCode::NotCoordinator {
+            eprintln!("Failed to complete the transaction of {}, retrying: {:?}", marker.transactional_id, error);
+            self.transaction_coordinator
+                .requeue_markers(vec![TxnMarker { partitions: Vec::new(), ..marker }])
+                .await;
+        }
+    }
+
+    // writes a prepared transaction change to `__transaction_state` and applies it
+    async fn write_transaction_transition(&self, target: TransactionMetadata) -> Result<(), KafkaErrorCode> {
+        let tp = TopicPartition::new(TRANSACTION_STATE_TOPIC.to_string(), partition_for(&target.transactional_id));
+        let value = serde_json::to_vec(&target).expect("transaction metadata serializes");
+        let batch = record_batch::build_batch(0, -1, Utc::now().timestamp_millis(), &[value]);
+        let written = self
+            .append_records(AppendOrigin::Coordinator, -1, Duration::from_millis(TRANSACTION_WRITE_TIMEOUT_MS), vec![(tp, batch)])
+            .await;
+        let error = written.first().map_or(KafkaErrorCode::UnknownServerError, |result| result.error);
+        if error == KafkaErrorCode::None {
+            self.transaction_coordinator.complete_transition(target).await;
+            return Ok(());
+        }
+        self.transaction_coordinator.abort_transition(&target.transactional_id).await;
+        Err(match error {
+            KafkaErrorCode::NotLeaderOrFollower | KafkaErrorCode::UnknownTopicOrPartition => KafkaErrorCode::NotCoordinator,
+            _ => KafkaErrorCode::CoordinatorNotAvailable,
+        })
+    }
+
+    async fn partition_exists(&self, tp: &TopicPartition) -> bool {
+        if let Some(image) = self.metadata_image().await {
+            if let Some(topic) = image.topics.get(tp.topic()) {
+                return topic.partitions.contains_key(&tp.partition());
+            }
+        }
+        match self.topic_manager.get(tp.topic()).await {
+            Some(topic) => topic.get_partition(tp.partition()).await.is_some(),
+            None => false,
+        }
+    }
+
+    /// current leader of a partition as far as this broker knows
+    pub async fn partition_leader(&self, tp: &TopicPartition) -> Option<i32> {
+        if let Some(image) = self.metadata_image().await {
+            if let Some(topic) = image.topics.get(tp.topic()) {
+                let state = topic.partitions.get(&tp.partition())?;
+                return (state.leader != NO_LEADER).then_some(state.leader);
+            }
+        }
+        self.topic_manager.get(tp.topic()).await?.get_partition(tp.partition()).await?.leader().await
+    }
+
+    /// lag of every partition the group has committed an offset for and this broker
+    /// has the log of, with time-lag read from the log's time index
+    pub async fn consumer_lag(&self, group_id: &str) -> Option<GroupLag> {
+        let offsets = self.group_coordinator.committed_offsets(group_id).await?;
+        let now_ms = Utc::now().timestamp_millis();
+
+        let mut committed: Vec<_> = offsets.into_iter().collect();
+        committed.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut partitions = Vec::with_capacity(committed.len());
+        for (tp, offset) in committed {
+            let (topic, partition) = (tp.topic(), tp.partition());
+            let Some(high_watermark) = self.replica_manager.high_watermark(topic, partition).await else {
+                continue;
+            };
+            // committed offsets point at the next message to consume, so the oldest
+            // unconsumed message is the one at the committed offset, or the log start
+            // if retention already deleted that
+            let oldest_timestamp = async {
+                let log_start = self.replica_manager.list_offset(topic, partition, EARLIEST_TIMESTAMP).await?;
+                self.replica_manager.timestamp_at(topic, partition, offset.offset.max(log_start.unwrap_or(0))).await
+            };
+            let oldest_timestamp = oldest_timestamp.await.unwrap_or_else(|e| {
+                eprintln!("Failed to read the time index of {}-{}: {}", topic, partition, e);
+                None
+            });
+            partitions.push(partition_lag(topic, partition, offset.offset, high_watermark, oldest_timestamp, now_ms));
+        }
+
+        Some(GroupLag {
+            group_id: group_id.to_string(),
+            partitions,
+        })
+    }
+
+    /// admin-side reset of an inactive group's offsets; every partition is
+    /// resolved against its log before anything is committed
+    pub async fn reset_offsets(
+        &self,
+        group_id: &str,
+        partitions: &[TopicPartition],
+        strategy: OffsetResetStrategy,
+    ) -> Result<Vec<(TopicPartition, i64)>, GroupError> {
+        // fail fast; the coordinator checks again when committing
+        if !self.group_coordinator.is_group_empty(group_id).await {
+            return Err(GroupError::NonEmptyGroup(group_id.to_string()));
+        }
+
+        let mut resolved = Vec::with_capacity(partitions.len());
+        for tp in partitions {
+            let (topic, partition) = (tp.topic(), tp.partition());
+            // consumers can't read past the HW, so nothing resets beyond it
+            let high_watermark = self
+                .replica_manager
+                .high_watermark(topic, partition)
+                .await
+                .ok_or_else(|| GroupError::UnknownTopicOrPartition(topic.to_string(), partition))?;
+            let offset = match strategy {
+                OffsetResetStrategy::Earliest => self.replica_manager.list_offset(topic, partition, EARLIEST_TIMESTAMP).await?.unwrap_or(0),
+                OffsetResetStrategy::Latest => high_watermark,
+                OffsetResetStrategy::Timestamp(timestamp) => {
+                    let offset = self.replica_manager.list_offset(topic, partition, timestamp).await?;
+                    offset.map_or(high_watermark, |offset| offset.min(high_watermark))
+                }
+                OffsetResetStrategy::Offset(offset) => {
+                    let log_start = self.replica_manager.list_offset(topic, partition, EARLIEST_TIMESTAMP).await?.unwrap_or(0);
+                    offset.clamp(log_start, high_watermark)
+                }
+            };
+            resolved.push((tp.clone(), offset));
+        }
+
+        self.group_coordinator.reset_offsets(group_id, resolved.clone()).await?;
+        Ok(resolved)
+    }
+
+    /// refreshes the consumer lag gauges for every group
+    pub async fn report_consumer_lag(&self) {
+        let mut lags = Vec::new();
+        for listing in self.group_coordinator.list_groups(&[]).await {
+            lags.extend(self.consumer_lag(&listing.group_id).await);
+        }
+
+        // all values are computed before any gauge changes, so a scrape never sees
+        // them missing
+        let partition_ids: Vec<Vec<String>> = lags
+            .iter()
+            .map(|group_lag| group_lag.partitions.iter().map(|p| p.partition.to_string()).collect())
+            .collect();
+        let (mut lag, mut time_lag, mut group_lags) = (Vec::new(), Vec::new(), Vec::new());
+        for (group_lag, ids) in lags.iter().zip(&partition_ids) {
+            let group = group_lag.group_id.as_str();
+            for (p, partition) in group_lag.partitions.iter().zip(ids) {
+                let labels = vec![("group", group), ("topic", p.topic.as_str()), ("partition", partition.as_str())];
+                lag.push((labels.clone(), p.lag));
+                time_lag.push((labels, p.time_lag_ms));
+            }
+            group_lags.push((vec![("group", group)], group_lag.total_lag()));
+        }
+        self.metrics.replace_gauge("rafka_consumer_lag", lag).await;
+        self.metrics.replace_gauge("rafka_consumer_time_lag_ms", time_lag).await;
+        self.metrics.replace_gauge("rafka_consumer_group_lag", group_lags).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::core::metadata::MIN_INSYNC_REPLICAS_CONFIG;
+    use crate::core::replica_selector::{ClientMetadata, RackAwareReplicaSelector};
+
+    fn temp_broker() -> (PathBuf, Broker) {
+        let dir = std::env::temp_dir().join(format!("rafka-broker-{}", Uuid::new_v4()));
+        (dir.clone(), Broker::with_log_dir(0, dir))
+    }
+
+    // has the broker lead orders-0, replicated to `replicas`, with the given ISR
+    async fn lead_orders(broker: &Broker, replicas: Vec<i32>, isr: Vec<i32>, min_insync_replicas: i32) -> TopicPartition {
+        let overrides = BTreeMap::from([(MIN_INSYNC_REPLICAS_CONFIG.to_string(), min_insync_replicas.to_string())]);
+        let topic_id = Uuid::new_v4();
+        let replication_factor = replicas.len() as i32;
+        let mut topic = Topic::with_id("orders".to_string(), topic_id, replication_factor, TopicConfig::from_overrides(&overrides).unwrap());
+        topic.insert_partition(0, Partition::new(0));
+        broker.topic_manager().create(topic).await.unwrap();
+
+        let state = PartitionState {
+            topic: "orders".to_string(),
+            topic_id,
+            partition: 0,
+            leader: 0,
+            leader_epoch: 0,
+            isr,
+            replicas,
+            adding_replicas: Vec::new(),
+            removing_replicas: Vec::new(),
+        };
+        let results = broker.apply_leader_and_isr(1, 1, &[state], &[]).await.unwrap();
+        assert_eq!(results[0].1, KafkaErrorCode::None);
+        TopicPartition::new("orders".to_string(), 0)
+    }
+
+    fn records(values: &[&str]) -> Vec<u8> {
+        let values: Vec<Vec<u8>> = values.iter().map(|value| value.as_bytes().to_vec()).collect();
+        record_batch::build_batch(0, 0, Utc::now().timestamp_millis(), &values)
+    }
+
+    #[tokio::test]
+    async fn acks_all_completes_once_the_high_watermark_passes_the_appended_records() {
+        let (dir, broker) = temp_broker();
+        let tp = lead_orders(&broker, vec![0, 1], vec![0, 1], 2).await;
+
+        let produce = broker.append_records(AppendOrigin::Client, -1, Duration::from_secs(10), vec![(tp.clone(), records(&["a", "b"]))]);
+        let replicate = async {
+            // the follower first fetches without having the records, then with both
+            while broker.produce_purgatory().watched() == 0 {
+                tokio::task::yield_now().await;
+            }
+            broker.record_replica_fetch("orders", 0, 1, 0).await;
+            assert_eq!(broker.produce_purgatory().watched(), 1);
+            broker.record_replica_fetch("orders", 0, 1, 2).await;
+        };
+        let (results, ()) = tokio::join!(produce, replicate);
+
+        assert_eq!(results[0].error, KafkaErrorCode::None);
+        assert_eq!((results[0].base_offset, results[0].required_offset), (0, 2));
+        assert_eq!(broker.replica_manager().high_watermark("orders", 0).await, Some(2));
+        assert_eq!(broker.produce_purgatory().watched(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn acks_all_times_out_when_the_isr_doesnt_catch_up() {
+        let (dir, broker) = temp_broker();
+        let tp = lead_orders(&broker, vec![0, 1], vec![0, 1], 1).await;
+
+        let results = broker.append_records(AppendOrigin::Client, -1, Duration::from_millis(50), vec![(tp.clone(), records(&["a"]))]).await;
+        assert_eq!(results[0].error, KafkaErrorCode::RequestTimedOut);
+        // the records were still appended, acks=1 doesn't wait for them
+        assert_eq!(results[0].base_offset, 0);
+        let results = broker.append_records(AppendOrigin::Client, 1, Duration::from_millis(50), vec![(tp, records(&["b"]))]).await;
+        assert_eq!((results[0].error, results[0].base_offset), (KafkaErrorCode::None, 1));
+        assert_eq!(broker.produce_purgatory().watched(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn acks_all_needs_min_insync_replicas() {
+        let (dir, broker) = temp_broker();
+        let tp = lead_orders(&broker, vec![0, 1], vec![0], 2).await;
+
+        let results = broker.append_records(AppendOrigin::Client, -1, Duration::from_secs(10), vec![(tp.clone(), records(&["a"]))]).await;
+        assert_eq!(results[0].error, KafkaErrorCode::NotEnoughReplicas);
+        assert_eq!(broker.replica_manager().high_watermark("orders", 0).await, Some(0));
+        // acks=1 doesn't care how many replicas are in sync
+        let results = broker.append_records(AppendOrigin::Client, 1, Duration::from_secs(10), vec![(tp, records(&["a"]))]).await;
+        assert_eq!((results[0].error, results[0].base_offset), (KafkaErrorCode::None, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn consumer_fetch(max_wait_ms: i32, min_bytes: i32, tp: &TopicPartition) -> (FetchParams, Vec<FetchPartitionStatus>) {
+        let params = FetchParams {
+            replica_id: -1,
+            max_wait_ms,
+            min_bytes,
+            max_bytes: 1024 * 1024,
+            isolation_level: 0,
+            client_metadata: None,
+        };
+        let status = FetchPartitionStatus { tp: tp.clone(), fetch_offset: 0, current_leader_epoch: 0, max_bytes: 1024 * 1024 };
+        (params, vec![status])
+    }
+
+    #[tokio::test]
+    async fn delayed_fetch_completes_once_an_append_brings_min_bytes() {
+        let (dir, broker) = temp_broker();
+        let tp = lead_orders(&broker, vec![0, 1], vec![0], 1).await;
+        let (params, partitions) = consumer_fetch(10_000, 1, &tp);
+
+        let started = std::time::Instant::now();
+        let fetch = broker.fetch_messages(params, partitions);
+        let produce = async {
+            while broker.fetch_purgatory().watched() == 0 {
+                tokio::task::yield_now().await;
+            }
+            broker.append_records(AppendOrigin::Client, 1, Duration::from_secs(10), vec![(tp.clone(), records(&["a"]))]).await
+        };
+        let (data, produced) = tokio::join!(fetch, produce);
+
+        assert_eq!(produced[0].error, KafkaErrorCode::None);
+        assert_eq!(data[0].error, KafkaErrorCode::None);
+        assert_eq!(data[0].high_watermark, 1);
+        assert!(!data[0].records.is_empty());
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(broker.fetch_purgatory().watched(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn delayed_fetch_returns_what_is_there_at_max_wait() {
+        let (dir, broker) = temp_broker();
+        let tp = lead_orders(&broker, vec![0, 1], vec![0], 1).await;
+        let batch = records(&["a"]);
+        broker.append_records(AppendOrigin::Client, 1, Duration::from_secs(10), vec![(tp.clone(), batch.clone())]).await;
+
+        // min_bytes is never reached, so the fetch waits out max_wait
+        let (params, partitions) = consumer_fetch(50, 1024 * 1024, &tp);
+        let started = std::time::Instant::now();
+        let data = broker.fetch_messages(params, partitions).await;
+
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(data[0].error, KafkaErrorCode::None);
+        assert_eq!(data[0].high_watermark, 1);
+        assert_eq!(data[0].records, batch);
+        assert_eq!(broker.fetch_purgatory().watched(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn consumers_are_sent_to_an_in_sync_follower_in_their_rack() {
+        let (dir, broker) = temp_broker();
+        let broker = broker.with_rack("a".to_string());
+        broker.set_replica_selector(Box::new(RackAwareReplicaSelector));
+        broker.register_broker_rack(1, "b".to_string()).await;
+        broker.register_broker_rack(2, "c".to_string()).await;
+        let tp = lead_orders(&broker, vec![0, 1, 2], vec![0, 1], 1).await;
+        broker.append_records(AppendOrigin::Client, 1, Duration::from_secs(10), vec![(tp.clone(), records(&["a", "b"]))]).await;
+        // follower 1 is caught up, follower 2 is behind and out of the ISR
+        broker.record_replica_fetch("orders", 0, 1, 2).await;
+        broker.record_replica_fetch("orders", 0, 2, 1).await;
+        assert_eq!(broker.replica_manager().isr("orders", 0).await, Some(vec![0, 1]));
+
+        let preferred = |rack: &str| {
+            let (mut params, partitions) = consumer_fetch(0, 0, &tp);
+            params.client_metadata = Some(ClientMetadata { client_id: "billing".to_string(), rack_id: rack.to_string() });
+            let broker = &broker;
+            async move { broker.fetch_messages(params, partitions).await.remove(0) }
+        };
+
+        let data = preferred("b").await;
+        assert_eq!((data.error, data.preferred_read_replica), (KafkaErrorCode::None, Some(1)));
+        assert!(data.records.is_empty());
+        // the out-of-sync follower is never picked, the leader serves its rack
+        let data = preferred("c").await;
+        assert_eq!(data.preferred_read_replica, None);
+        assert!(!data.records.is_empty());
+        assert_eq!(preferred("a").await.preferred_read_replica, None);
+        assert_eq!(preferred("z").await.preferred_read_replica, None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn clients_cannot_write_control_batches_or_internal_topics() {
+        let (dir, broker) = temp_broker();
+        let tp = lead_orders(&broker, vec![0], vec![0], 1).await;
+        let marker = record_batch::build_control_batch(7, 0, true, 0, Utc::now().timestamp_millis());
+        let timeout = Duration::from_secs(10);
+
+        let mut mixed = records(&["a"]);
+        mixed.extend_from_slice(&marker);
+        for batch in [marker.clone(), mixed] {
+            let results = broker.append_records(AppendOrigin::Client, 1, timeout, vec![(tp.clone(), batch)]).await;
+            assert_eq!(results[0].error, KafkaErrorCode::InvalidRecord);
+        }
+        assert_eq!(broker.replica_manager().high_watermark("orders", 0).await, Some(0));
+
+        let forged = TopicPartition::new(TRANSACTION_STATE_TOPIC.to_string(), 0);
+        let results = broker.append_records(AppendOrigin::Client, 1, timeout, vec![(forged, records(&["{}"]))]).await;
+        assert_eq!(results[0].error, KafkaErrorCode::InvalidTopicException);
+
+        // the transaction coordinator writes its markers through the same path
+        let results = broker.append_records(AppendOrigin::Coordinator, 1, timeout, vec![(tp, marker)]).await;
+        assert_eq!((results[0].error, results[0].base_offset), (KafkaErrorCode::None, 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn offsets_are_not_reset_past_the_high_watermark() {
+        let (dir, broker) = temp_broker();
+        let tp = lead_orders(&broker, vec![0, 1], vec![0, 1], 1).await;
+        let results = broker.append_records(AppendOrigin::Client, 1, Duration::from_secs(10), vec![(tp.clone(), records(&["a", "b", "c"]))]).await;
+        assert_eq!(results[0].error, KafkaErrorCode::None);
+        // the follower has only the first record, so that's all consumers can read
+        broker.record_replica_fetch("orders", 0, 1, 1).await;
+        assert_eq!(broker.replica_manager().high_watermark("orders", 0).await, Some(1));
+
+        for strategy in [OffsetResetStrategy::Latest, OffsetResetStrategy::Offset(3), OffsetResetStrategy::Timestamp(i64::MAX)] {
+            let resolved = broker.reset_offsets("billing", std::slice::from_ref(&tp), strategy).await.unwrap();
+            assert_eq!(resolved, vec![(tp.clone(), 1)], "{:?}", strategy);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
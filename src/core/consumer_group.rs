@@ -1,17 +1,27 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::time::{Duration, SystemTime};
 
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::constants::{CONSUMER_GROUP_SESSION_TIMEOUT_MS, DEFAULT_SERVER_ASSIGNOR};
+use crate::core::assignor::{server_assignor_by_name, PartitionAssignor};
+use crate::core::group_coordinator::CONSUMER_PROTOCOL_TYPE;
+use crate::error::KafkaErrorCode;
 
-// TODO
 #[derive(Debug)]
 pub struct ConsumerGroup {
     group_id: String,
     members: HashMap<String, GroupMember>,
-    assignments: HashMap<String, Vec<TopicPartition>>,
-    generation_id: i32,
+    assignments: HashMap<String, Vec<TopicPartition>>, // target assignment
+    generation_id: i32, // doubles as the group epoch for consumer protocol groups
     protocol_type: String,
     leader: Option<String>,
     state: GroupState,
+    assignment_epoch: i32, // group epoch the target assignment was computed for
+    subscribed_topic_metadata: HashMap<String, i32>, // partition counts the target was computed against
+    offsets: HashMap<TopicPartition, OffsetAndMetadata>, // committed offsets
 }
 
 #[derive(Debug)]
@@ -23,19 +33,748 @@ pub struct GroupMember {
     rebalance_timeout_ms: i32,
     subscription: Vec<String>,  // list of subscribed topics
     last_heartbeat: SystemTime,
+    member_epoch: i32,
+    previous_member_epoch: i32,
+    instance_id: Option<String>,
+    rack_id: Option<String>,
+    server_assignor: Option<String>,
+    assigned_partitions: BTreeSet<TopicPartition>, // what the member owns right now
+    partitions_pending_revocation: BTreeSet<TopicPartition>, // what it still has to give up
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct TopicPartition {
     topic: String,
     partition: i32,
 }
 
+#[derive(Debug, Clone)]
+pub struct OffsetAndMetadata {
+    pub offset: i64,
+    pub leader_epoch: i32,
+    pub metadata: String,
+    pub commit_timestamp: i64,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum GroupState {
     Empty,
     PreparingRebalance,
     CompletingRebalance,
+    Assigning,
+    Reconciling,
     Stable,
     Dead,
-}
\ No newline at end of file
+}
+
+// one ConsumerGroupHeartbeat from a member, with topic ids already resolved to names
+#[derive(Debug)]
+pub struct MemberHeartbeat {
+    pub member_id: String,
+    pub member_epoch: i32,
+    pub client_id: String,
+    pub client_host: String,
+    pub instance_id: Option<String>,
+    pub rack_id: Option<String>,
+    pub rebalance_timeout_ms: i32,
+    pub subscribed_topic_names: Option<Vec<String>>,
+    pub server_assignor: Option<String>,
+    pub owned_partitions: Option<Vec<TopicPartition>>,
+}
+
+#[derive(Debug)]
+pub struct MemberAssignment {
+    pub member_id: String,
+    pub member_epoch: i32,
+    pub partitions: Vec<TopicPartition>,
+}
+
+#[derive(Debug, Error)]
+pub enum GroupError {
+    #[error("Group {0} not found")]
+    GroupIdNotFound(String),
+
+    #[error("Member {0} is not part of the group")]
+    UnknownMemberId(String),
+
+    #[error("Member {0} sent a stale epoch {1}")]
+    FencedMemberEpoch(String, i32),
+
+    #[error("Assignor {0} is not supported")]
+    UnsupportedAssignor(String),
+
+    #[error("Group {0} still has members")]
+    NonEmptyGroup(String),
+
+    #[error("Invalid group id")]
+    InvalidGroupId,
+
+    #[error("Group {0} is still subscribed to topic {1}")]
+    GroupSubscribedToTopic(String, String),
+
+    #[error("Unknown partition {0}-{1}")]
+    UnknownTopicOrPartition(String, i32),
+
+    #[error("Storage error: {0}")]
+    KafkaStorageError(String),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(&'static str),
+}
+
+impl GroupError {
+    pub fn error_code(&self) -> KafkaErrorCode {
+        match self {
+            GroupError::GroupIdNotFound(_) => KafkaErrorCode::GroupIdNotFound,
+            GroupError::UnknownMemberId(_) => KafkaErrorCode::UnknownMemberId,
+            GroupError::FencedMemberEpoch(_, _) => KafkaErrorCode::FencedMemberEpoch,
+            GroupError::UnsupportedAssignor(_) => KafkaErrorCode::UnsupportedAssignor,
+            GroupError::NonEmptyGroup(_) => KafkaErrorCode::NonEmptyGroup,
+            GroupError::InvalidGroupId => KafkaErrorCode::InvalidGroupId,
+            GroupError::GroupSubscribedToTopic(_, _) => KafkaErrorCode::GroupSubscribedToTopic,
+            GroupError::UnknownTopicOrPartition(_, _) => KafkaErrorCode::UnknownTopicOrPartition,
+            GroupError::KafkaStorageError(_) => KafkaErrorCode::KafkaStorageError,
+            GroupError::InvalidRequest(_) => KafkaErrorCode::InvalidRequest,
+        }
+    }
+}
+
+// member epochs with a special meaning in ConsumerGroupHeartbeat
+const JOIN_GROUP_MEMBER_EPOCH: i32 = 0;
+const LEAVE_GROUP_MEMBER_EPOCH: i32 = -1;
+const LEAVE_GROUP_STATIC_MEMBER_EPOCH: i32 = -2;
+
+impl GroupState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GroupState::Empty => "Empty",
+            GroupState::PreparingRebalance => "PreparingRebalance",
+            GroupState::CompletingRebalance => "CompletingRebalance",
+            GroupState::Assigning => "Assigning",
+            GroupState::Reconciling => "Reconciling",
+            GroupState::Stable => "Stable",
+            GroupState::Dead => "Dead",
+        }
+    }
+}
+
+impl TopicPartition {
+    pub fn new(topic: String, partition: i32) -> Self {
+        TopicPartition { topic, partition }
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn partition(&self) -> i32 {
+        self.partition
+    }
+}
+
+impl GroupMember {
+    pub fn new(
+        member_id: String,
+        client_id: String,
+        client_host: String,
+        session_timeout_ms: i32,
+        rebalance_timeout_ms: i32,
+        subscription: Vec<String>,
+    ) -> Self {
+        GroupMember {
+            member_id,
+            client_id,
+            client_host,
+            session_timeout_ms,
+            rebalance_timeout_ms,
+            subscription,
+            last_heartbeat: SystemTime::now(),
+            member_epoch: 0,
+            previous_member_epoch: 0,
+            instance_id: None,
+            rack_id: None,
+            server_assignor: None,
+            assigned_partitions: BTreeSet::new(),
+            partitions_pending_revocation: BTreeSet::new(),
+        }
+    }
+
+    pub fn member_id(&self) -> &str {
+        &self.member_id
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn client_host(&self) -> &str {
+        &self.client_host
+    }
+
+    pub fn session_timeout_ms(&self) -> i32 {
+        self.session_timeout_ms
+    }
+
+    pub fn rebalance_timeout_ms(&self) -> i32 {
+        self.rebalance_timeout_ms
+    }
+
+    pub fn subscription(&self) -> &[String] {
+        &self.subscription
+    }
+
+    pub fn last_heartbeat(&self) -> SystemTime {
+        self.last_heartbeat
+    }
+
+    pub fn member_epoch(&self) -> i32 {
+        self.member_epoch
+    }
+
+    pub fn instance_id(&self) -> Option<&str> {
+        self.instance_id.as_deref()
+    }
+
+    pub fn rack_id(&self) -> Option<&str> {
+        self.rack_id.as_deref()
+    }
+
+    pub fn assigned_partitions(&self) -> impl Iterator<Item = &TopicPartition> {
+        self.assigned_partitions.iter()
+    }
+
+    pub fn touch(&mut self) {
+        self.last_heartbeat = SystemTime::now();
+    }
+
+    fn session_expired(&self, now: SystemTime) -> bool {
+        let timeout = Duration::from_millis(self.session_timeout_ms.max(0) as u64);
+        now.duration_since(self.last_heartbeat).is_ok_and(|elapsed| elapsed > timeout)
+    }
+}
+
+impl ConsumerGroup {
+    pub fn new(group_id: String, protocol_type: String) -> Self {
+        ConsumerGroup {
+            group_id,
+            members: HashMap::new(),
+            assignments: HashMap::new(),
+            generation_id: 0,
+            protocol_type,
+            leader: None,
+            state: GroupState::Empty,
+            assignment_epoch: 0,
+            subscribed_topic_metadata: HashMap::new(),
+            offsets: HashMap::new(),
+        }
+    }
+
+    pub fn group_id(&self) -> &str {
+        &self.group_id
+    }
+
+    pub fn protocol_type(&self) -> &str {
+        &self.protocol_type
+    }
+
+    pub fn generation_id(&self) -> i32 {
+        self.generation_id
+    }
+
+    pub fn leader(&self) -> Option<&str> {
+        self.leader.as_deref()
+    }
+
+    pub fn state(&self) -> &GroupState {
+        &self.state
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &GroupMember> {
+        self.members.values()
+    }
+
+    pub fn member(&self, member_id: &str) -> Option<&GroupMember> {
+        self.members.get(member_id)
+    }
+
+    pub fn assignments(&self) -> &HashMap<String, Vec<TopicPartition>> {
+        &self.assignments
+    }
+
+    /// partitions the member owns right now. consumer protocol members track this
+    /// themselves, classic members own whatever the last rebalance gave them.
+    pub fn member_assignment(&self, member_id: &str) -> Vec<TopicPartition> {
+        match self.members.get(member_id) {
+            Some(member) if self.protocol_type == CONSUMER_PROTOCOL_TYPE && self.assignment_epoch > 0 => {
+                member.assigned_partitions.iter().cloned().collect()
+            }
+            Some(_) => self.assignments.get(member_id).cloned().unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+
+    /// the assignor in use, empty while the group has no members
+    pub fn protocol_name(&self) -> String {
+        if self.members.is_empty() {
+            return String::new();
+        }
+        self.preferred_assignor()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn transition_to_dead(&mut self) {
+        self.state = GroupState::Dead;
+    }
+
+    pub fn commit_offset(&mut self, tp: TopicPartition, offset: OffsetAndMetadata) {
+        self.offsets.insert(tp, offset);
+    }
+
+    pub fn committed_offset(&self, tp: &TopicPartition) -> Option<&OffsetAndMetadata> {
+        self.offsets.get(tp)
+    }
+
+    pub fn offsets(&self) -> &HashMap<TopicPartition, OffsetAndMetadata> {
+        &self.offsets
+    }
+
+    pub fn delete_offset(&mut self, tp: &TopicPartition) -> Option<OffsetAndMetadata> {
+        self.offsets.remove(tp)
+    }
+
+    /// whether a live member subscribes to or is assigned the topic
+    pub fn is_subscribed_to(&self, topic: &str) -> bool {
+        self.members.values().any(|member| {
+            member.subscription.iter().any(|t| t == topic)
+                || member.assigned_partitions.iter().any(|tp| tp.topic() == topic)
+        })
+    }
+
+    pub fn add_member(&mut self, member: GroupMember) {
+        if self.leader.is_none() {
+            self.leader = Some(member.member_id.clone());
+        }
+        self.members.insert(member.member_id.clone(), member);
+        self.state = GroupState::PreparingRebalance;
+    }
+
+    pub fn remove_member(&mut self, member_id: &str) -> Option<GroupMember> {
+        let removed = self.members.remove(member_id)?;
+        if self.leader.as_deref() == Some(member_id) {
+            self.leader = self.members.keys().min().cloned();
+        }
+        self.state = if self.members.is_empty() {
+            GroupState::Empty
+        } else {
+            GroupState::PreparingRebalance
+        };
+        Some(removed)
+    }
+
+    /// runs a broker-side assignment over the members' subscriptions and starts a new generation
+    pub fn rebalance(&mut self, assignor: &dyn PartitionAssignor, partitions_per_topic: &HashMap<String, i32>) {
+        let subscriptions: HashMap<String, Vec<String>> = self
+            .members
+            .values()
+            .map(|m| (m.member_id.clone(), m.subscription.clone()))
+            .collect();
+
+        self.assignments = assignor.assign(partitions_per_topic, &subscriptions, &self.assignments);
+        self.generation_id += 1;
+        self.state = if self.members.is_empty() {
+            GroupState::Empty
+        } else {
+            GroupState::Stable
+        };
+    }
+
+    /// handles one ConsumerGroupHeartbeat (KIP-848). the group owns the target assignment
+    /// and moves each member towards it partition by partition: a member first gives up
+    /// what it must revoke, and only gets partitions once their previous owner released them.
+    pub fn consumer_heartbeat(
+        &mut self,
+        request: MemberHeartbeat,
+        partitions_per_topic: &HashMap<String, i32>,
+    ) -> Result<MemberAssignment, GroupError> {
+        let mut epoch_bumped = self.expire_members(SystemTime::now());
+
+        let member_id = match request.member_epoch {
+            JOIN_GROUP_MEMBER_EPOCH => {
+                if request.subscribed_topic_names.is_none() {
+                    return Err(GroupError::InvalidRequest("subscribed topics must be set when joining"));
+                }
+                let member_id = if request.member_id.is_empty() {
+                    Uuid::new_v4().to_string()
+                } else {
+                    request.member_id.clone()
+                };
+                // a rejoin starts over with nothing owned
+                let member = GroupMember::new(
+                    member_id.clone(),
+                    request.client_id.clone(),
+                    request.client_host.clone(),
+                    CONSUMER_GROUP_SESSION_TIMEOUT_MS,
+                    request.rebalance_timeout_ms,
+                    Vec::new(),
+                );
+                self.members.insert(member_id.clone(), member);
+                epoch_bumped = true;
+                member_id
+            }
+            LEAVE_GROUP_MEMBER_EPOCH | LEAVE_GROUP_STATIC_MEMBER_EPOCH => {
+                if self.members.remove(&request.member_id).is_none() {
+                    return Err(GroupError::UnknownMemberId(request.member_id));
+                }
+                self.generation_id += 1;
+                self.update_state();
+                return Ok(MemberAssignment {
+                    member_id: request.member_id,
+                    member_epoch: request.member_epoch,
+                    partitions: Vec::new(),
+                });
+            }
+            epoch => {
+                let member = self
+                    .members
+                    .get(&request.member_id)
+                    .ok_or_else(|| GroupError::UnknownMemberId(request.member_id.clone()))?;
+                // the previous epoch is still accepted if the member lost our last response
+                // and doesn't claim anything it no longer owns
+                let lost_response = epoch == member.previous_member_epoch
+                    && request.owned_partitions.as_ref().is_some_and(|owned| {
+                        owned.iter().all(|tp| member.assigned_partitions.contains(tp))
+                    });
+                if epoch != member.member_epoch && !lost_response {
+                    return Err(GroupError::FencedMemberEpoch(request.member_id.clone(), epoch));
+                }
+                request.member_id.clone()
+            }
+        };
+
+        let member = self.members.get_mut(&member_id).expect("member was just looked up");
+        member.touch();
+        member.client_id = request.client_id;
+        member.client_host = request.client_host;
+        member.instance_id = request.instance_id;
+        member.rack_id = request.rack_id;
+        if request.rebalance_timeout_ms > 0 {
+            member.rebalance_timeout_ms = request.rebalance_timeout_ms;
+        }
+        if request.server_assignor.is_some() {
+            member.server_assignor = request.server_assignor;
+        }
+        if let Some(mut topics) = request.subscribed_topic_names {
+            topics.sort();
+            topics.dedup();
+            if topics != member.subscription {
+                member.subscription = topics;
+                epoch_bumped = true;
+            }
+        }
+        // the member acknowledges a revocation by no longer reporting the partitions
+        if let Some(owned) = request.owned_partitions {
+            let owned: HashSet<TopicPartition> = owned.into_iter().collect();
+            if member.partitions_pending_revocation.iter().all(|tp| !owned.contains(tp)) {
+                member.partitions_pending_revocation.clear();
+            }
+        }
+
+        // a partition count change on any subscribed topic also needs a new target
+        let subscribed_topic_metadata: HashMap<String, i32> = self
+            .members
+            .values()
+            .flat_map(|m| m.subscription.iter())
+            .filter_map(|topic| partitions_per_topic.get(topic).map(|count| (topic.clone(), *count)))
+            .collect();
+        if subscribed_topic_metadata != self.subscribed_topic_metadata {
+            self.subscribed_topic_metadata = subscribed_topic_metadata;
+            epoch_bumped = true;
+        }
+
+        if epoch_bumped {
+            self.generation_id += 1;
+        }
+        if self.assignment_epoch < self.generation_id {
+            self.compute_target_assignment(partitions_per_topic)?;
+        }
+
+        self.reconcile(&member_id);
+        self.update_state();
+
+        let member = &self.members[&member_id];
+        Ok(MemberAssignment {
+            member_id,
+            member_epoch: member.member_epoch,
+            partitions: member.assigned_partitions.iter().cloned().collect(),
+        })
+    }
+
+    /// removes members whose session ran out without a heartbeat. the remaining members
+    /// get a new target assignment on their next heartbeat.
+    pub fn expire_sessions(&mut self, now: SystemTime) {
+        if self.expire_members(now) {
+            self.generation_id += 1;
+            self.update_state();
+        }
+    }
+
+    // removes members whose session timed out, returns true if any were removed
+    fn expire_members(&mut self, now: SystemTime) -> bool {
+        let expired: Vec<String> = self
+            .members
+            .values()
+            .filter(|m| m.session_expired(now))
+            .map(|m| m.member_id.clone())
+            .collect();
+        for member_id in &expired {
+            println!("Member {} of group {} expired", member_id, self.group_id);
+            self.members.remove(member_id);
+        }
+        !expired.is_empty()
+    }
+
+    // the assignor preferred by most members wins, ties go to the name that sorts first
+    fn preferred_assignor(&self) -> String {
+        let mut votes: HashMap<&str, usize> = HashMap::new();
+        for member in self.members.values() {
+            if let Some(name) = &member.server_assignor {
+                *votes.entry(name.as_str()).or_default() += 1;
+            }
+        }
+        votes
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+            .map(|(name, _)| name.to_string())
+            .unwrap_or_else(|| DEFAULT_SERVER_ASSIGNOR.to_string())
+    }
+
+    fn compute_target_assignment(&mut self, partitions_per_topic: &HashMap<String, i32>) -> Result<(), GroupError> {
+        let name = self.preferred_assignor();
+        let assignor = server_assignor_by_name(&name).ok_or(GroupError::UnsupportedAssignor(name))?;
+        let subscriptions: HashMap<String, Vec<String>> = self
+            .members
+            .values()
+            .map(|m| (m.member_id.clone(), m.subscription.clone()))
+            .collect();
+
+        self.assignments = assignor.assign(partitions_per_topic, &subscriptions, &self.assignments);
+        self.assignment_epoch = self.generation_id;
+        Ok(())
+    }
+
+    fn reconcile(&mut self, member_id: &str) {
+        let target: BTreeSet<TopicPartition> = self
+            .assignments
+            .get(member_id)
+            .map(|partitions| partitions.iter().cloned().collect())
+            .unwrap_or_default();
+        let owned_by_others: HashSet<&TopicPartition> = self
+            .members
+            .values()
+            .filter(|m| m.member_id != member_id)
+            .flat_map(|m| m.assigned_partitions.iter().chain(m.partitions_pending_revocation.iter()))
+            .collect();
+        let unreleased: BTreeSet<TopicPartition> = target
+            .iter()
+            .filter(|tp| owned_by_others.contains(tp))
+            .cloned()
+            .collect();
+
+        let assignment_epoch = self.assignment_epoch;
+        let Some(member) = self.members.get_mut(member_id) else {
+            return;
+        };
+
+        // nothing moves until the member confirmed its previous revocation
+        if !member.partitions_pending_revocation.is_empty() {
+            return;
+        }
+
+        let revoke: BTreeSet<TopicPartition> = member.assigned_partitions.difference(&target).cloned().collect();
+        if !revoke.is_empty() {
+            member.assigned_partitions.retain(|tp| target.contains(tp));
+            member.partitions_pending_revocation = revoke;
+            return;
+        }
+
+        member.assigned_partitions = target.difference(&unreleased).cloned().collect();
+        if member.member_epoch != assignment_epoch {
+            member.previous_member_epoch = member.member_epoch;
+            member.member_epoch = assignment_epoch;
+        }
+    }
+
+    fn update_state(&mut self) {
+        if self.members.is_empty() {
+            self.state = GroupState::Empty;
+            return;
+        }
+        // the target is recomputed on the next heartbeat
+        if self.assignment_epoch < self.generation_id {
+            self.state = GroupState::Assigning;
+            return;
+        }
+        let converged = self.members.values().all(|m| {
+            m.member_epoch == self.assignment_epoch
+                && m.partitions_pending_revocation.is_empty()
+                && self
+                    .assignments
+                    .get(&m.member_id)
+                    .is_some_and(|target| target.len() == m.assigned_partitions.len())
+        });
+        self.state = if converged {
+            GroupState::Stable
+        } else {
+            GroupState::Reconciling
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat(member_id: &str, member_epoch: i32, owned: Option<Vec<TopicPartition>>) -> MemberHeartbeat {
+        MemberHeartbeat {
+            member_id: member_id.to_string(),
+            member_epoch,
+            client_id: "billing".to_string(),
+            client_host: "/127.0.0.1".to_string(),
+            instance_id: None,
+            rack_id: None,
+            rebalance_timeout_ms: 30_000,
+            subscribed_topic_names: (member_epoch == JOIN_GROUP_MEMBER_EPOCH).then(|| vec!["orders".to_string()]),
+            server_assignor: None,
+            owned_partitions: owned,
+        }
+    }
+
+    fn orders() -> HashMap<String, i32> {
+        HashMap::from([("orders".to_string(), 4)])
+    }
+
+    fn group() -> ConsumerGroup {
+        ConsumerGroup::new("billing".to_string(), CONSUMER_PROTOCOL_TYPE.to_string())
+    }
+
+    fn target(group: &ConsumerGroup, member_id: &str) -> Vec<TopicPartition> {
+        let mut target = group.assignments()[member_id].clone();
+        target.sort();
+        target
+    }
+
+    #[test]
+    fn a_lone_member_gets_every_partition() {
+        let mut group = group();
+        let joined = group.consumer_heartbeat(heartbeat("", 0, None), &orders()).unwrap();
+        assert!(!joined.member_id.is_empty());
+        assert_eq!((joined.member_epoch, joined.partitions.len()), (1, 4));
+        assert_eq!(*group.state(), GroupState::Stable);
+
+        // a steady heartbeat changes nothing
+        let steady = group.consumer_heartbeat(heartbeat(&joined.member_id, 1, Some(joined.partitions.clone())), &orders()).unwrap();
+        assert_eq!((steady.member_epoch, steady.partitions), (1, joined.partitions));
+        assert_eq!(group.generation_id(), 1);
+    }
+
+    #[test]
+    fn partitions_move_only_once_their_owner_revoked_them() {
+        let mut group = group();
+        let a = group.consumer_heartbeat(heartbeat("a", 0, None), &orders()).unwrap();
+        assert_eq!(a.partitions.len(), 4);
+
+        // b's share is still owned by a, so it gets the new epoch but nothing yet
+        let b = group.consumer_heartbeat(heartbeat("b", 0, None), &orders()).unwrap();
+        assert_eq!((b.member_epoch, b.partitions.len()), (2, 0));
+        assert_eq!(*group.state(), GroupState::Reconciling);
+        let (a_target, b_target) = (target(&group, "a"), target(&group, "b"));
+        assert_eq!((a_target.len(), b_target.len()), (2, 2));
+
+        // a gives up b's share first, staying in its epoch until it confirmed that
+        let a = group.consumer_heartbeat(heartbeat("a", 1, Some(a.partitions)), &orders()).unwrap();
+        assert_eq!((a.member_epoch, a.partitions.clone()), (1, a_target.clone()));
+        let b = group.consumer_heartbeat(heartbeat("b", 2, Some(Vec::new())), &orders()).unwrap();
+        assert!(b.partitions.is_empty());
+
+        let a = group.consumer_heartbeat(heartbeat("a", 1, Some(a.partitions)), &orders()).unwrap();
+        assert_eq!((a.member_epoch, a.partitions), (2, a_target));
+        let b = group.consumer_heartbeat(heartbeat("b", 2, Some(Vec::new())), &orders()).unwrap();
+        assert_eq!((b.member_epoch, b.partitions), (2, b_target));
+        assert_eq!(*group.state(), GroupState::Stable);
+    }
+
+    #[test]
+    fn stale_epochs_are_fenced_unless_the_response_was_lost() {
+        let mut group = group();
+        let a = group.consumer_heartbeat(heartbeat("a", 0, None), &orders()).unwrap();
+        group.consumer_heartbeat(heartbeat("b", 0, None), &orders()).unwrap();
+        let a = group.consumer_heartbeat(heartbeat("a", 1, Some(a.partitions)), &orders()).unwrap();
+        let a = group.consumer_heartbeat(heartbeat("a", 1, Some(a.partitions)), &orders()).unwrap();
+        assert_eq!(a.member_epoch, 2);
+
+        let fenced = group.consumer_heartbeat(heartbeat("a", 5, Some(a.partitions.clone())), &orders());
+        assert!(matches!(fenced, Err(GroupError::FencedMemberEpoch(_, 5))));
+        let unknown = group.consumer_heartbeat(heartbeat("c", 1, None), &orders());
+        assert!(matches!(unknown, Err(GroupError::UnknownMemberId(_))));
+
+        // a never saw epoch 2, and claims nothing it doesn't own
+        let lost = group.consumer_heartbeat(heartbeat("a", 1, Some(a.partitions.clone())), &orders()).unwrap();
+        assert_eq!((lost.member_epoch, lost.partitions), (2, a.partitions));
+        let claims_more = group.consumer_heartbeat(heartbeat("a", 1, Some(target(&group, "b"))), &orders());
+        assert!(matches!(claims_more, Err(GroupError::FencedMemberEpoch(_, 1))));
+    }
+
+    #[test]
+    fn leaving_members_bump_the_epoch_and_empty_the_group() {
+        let mut group = group();
+        let joined = group.consumer_heartbeat(heartbeat("", 0, None), &orders()).unwrap();
+        let missing_subscription = MemberHeartbeat { subscribed_topic_names: None, ..heartbeat("b", 0, None) };
+        assert!(matches!(group.consumer_heartbeat(missing_subscription, &orders()), Err(GroupError::InvalidRequest(_))));
+
+        let left = group.consumer_heartbeat(heartbeat(&joined.member_id, -1, None), &orders()).unwrap();
+        assert_eq!(left.member_epoch, -1);
+        assert!(group.is_empty());
+        assert_eq!(*group.state(), GroupState::Empty);
+        assert_eq!(group.generation_id(), 2);
+        let again = group.consumer_heartbeat(heartbeat(&joined.member_id, -1, None), &orders());
+        assert!(matches!(again, Err(GroupError::UnknownMemberId(_))));
+    }
+
+    #[test]
+    fn new_partitions_bring_a_new_target_assignment() {
+        let mut group = group();
+        let joined = group.consumer_heartbeat(heartbeat("a", 0, None), &orders()).unwrap();
+        let grown = HashMap::from([("orders".to_string(), 6)]);
+        let after = group.consumer_heartbeat(heartbeat("a", joined.member_epoch, Some(joined.partitions)), &grown).unwrap();
+        assert_eq!((after.member_epoch, after.partitions.len()), (2, 6));
+        assert_eq!(*group.state(), GroupState::Stable);
+    }
+
+    #[test]
+    fn members_that_stop_heartbeating_expire_without_anyone_else_heartbeating() {
+        let mut group = group();
+        let a = group.consumer_heartbeat(heartbeat("a", 0, None), &orders()).unwrap();
+        group.consumer_heartbeat(heartbeat("b", 0, None), &orders()).unwrap();
+        let generation = group.generation_id();
+
+        let now = SystemTime::now();
+        group.expire_sessions(now);
+        assert_eq!(group.members().count(), 2);
+
+        // a crashed, b is still around and picks up everything on its next heartbeat
+        let silent = Duration::from_millis(CONSUMER_GROUP_SESSION_TIMEOUT_MS as u64 + 1);
+        group.members.get_mut("a").unwrap().last_heartbeat = now - silent;
+        group.expire_sessions(now);
+        assert!(group.member("a").is_none());
+        assert_eq!((group.generation_id(), group.state()), (generation + 1, &GroupState::Assigning));
+        let again = group.consumer_heartbeat(heartbeat("a", a.member_epoch, None), &orders());
+        assert!(matches!(again, Err(GroupError::UnknownMemberId(_))));
+
+        group.expire_sessions(now + silent + silent);
+        assert!(group.is_empty());
+        assert_eq!(*group.state(), GroupState::Empty);
+    }
+}
@@ -0,0 +1,1578 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::{Mutex, Notify, RwLock};
+use uuid::Uuid;
+
+use crate::constants::{
+    BROKER_SESSION_TIMEOUT_MS, DEFAULT_NUM_PARTITIONS, DEFAULT_REPLICATION_FACTOR, LEADER_IMBALANCE_PER_BROKER_PERCENTAGE,
+    PRODUCER_ID_BLOCK_SIZE,
+};
+use crate::core::election::elect_leader;
+use crate::core::producer_id::ProducerIdBlock;
+use crate::core::placement::{assign_replicas, random_start, PlacementBroker};
+use crate::core::metadata::{BrokerRegistration, MetadataImage, MetadataRecord};
+use crate::core::topic::TopicConfig;
+use crate::error::KafkaErrorCode;
+use crate::raft::node::RaftNode;
+use crate::raft::RaftError;
+
+pub const NO_LEADER: i32 = -1;
+// leader of a partition whose topic is being deleted
+pub const LEADER_DURING_DELETE: i32 = -2;
+
+// topic names double as log directory names
+const MAX_TOPIC_NAME_LENGTH: usize = 249;
+
+// without a quorum there is only ever one controller
+const STANDALONE_CONTROLLER_EPOCH: i32 = 1;
+
+/// leadership and replica assignment of one partition, as the controller
+/// pushes it to brokers in LeaderAndIsr
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartitionState {
+    pub topic: String,
+    pub topic_id: Uuid,
+    pub partition: i32,
+    pub leader: i32, // NO_LEADER while the partition is offline
+    pub leader_epoch: i32,
+    pub isr: Vec<i32>,
+    pub replicas: Vec<i32>, // in preference order
+    // while a reassignment is ongoing `replicas` holds the target replicas followed
+    // by the ones being removed
+    #[serde(default)]
+    pub adding_replicas: Vec<i32>,
+    #[serde(default)]
+    pub removing_replicas: Vec<i32>,
+}
+
+impl PartitionState {
+    pub fn is_reassigning(&self) -> bool {
+        !self.adding_replicas.is_empty() || !self.removing_replicas.is_empty()
+    }
+
+    /// replicas the partition had before its ongoing reassignment
+    pub fn original_replicas(&self) -> Vec<i32> {
+        self.replicas.iter().copied().filter(|replica| !self.adding_replicas.contains(replica)).collect()
+    }
+
+    /// replicas the partition is left with once its reassignment completes
+    pub fn target_replicas(&self) -> Vec<i32> {
+        self.replicas.iter().copied().filter(|replica| !self.removing_replicas.contains(replica)).collect()
+    }
+}
+
+/// a topic as requested in CreateTopics. -1 partitions or replication factor takes
+/// the broker default; a manual assignment requires both to be -1.
+#[derive(Debug, Clone, Default)]
+pub struct NewTopic {
+    pub name: String,
+    pub num_partitions: i32,
+    pub replication_factor: i32,
+    pub assignments: BTreeMap<i32, Vec<i32>>, // partition -> replicas
+    pub configs: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Error)]
+pub enum ControllerError {
+    #[error("Topic {0} already exists")]
+    TopicAlreadyExists(String),
+
+    #[error("Topic name {0:?} is invalid: {1}")]
+    InvalidTopic(String, &'static str),
+
+    #[error("Unknown topic {0}")]
+    UnknownTopic(String),
+
+    #[error("Unknown topic id {0}")]
+    UnknownTopicId(Uuid),
+
+    #[error("{0}")]
+    InvalidConfig(String),
+
+    #[error("{0}")]
+    InvalidRequest(&'static str),
+
+    #[error("Number of partitions must be positive, got {0}")]
+    InvalidPartitions(i32),
+
+    #[error("Replication factor {0} doesn't fit the {1} live brokers")]
+    InvalidReplicationFactor(i32, usize),
+
+    #[error("Topic {0} has {1} partitions, which can't go to {2}")]
+    PartitionCountNotIncreased(String, i32, i32),
+
+    #[error("Unknown partition {0}-{1}")]
+    UnknownTopicOrPartition(String, i32),
+
+    #[error("Broker {2} doesn't lead partition {0}-{1}")]
+    NotLeader(String, i32, i32),
+
+    #[error("Leader epoch {2} of partition {0}-{1} is not the current one")]
+    FencedLeaderEpoch(String, i32, i32),
+
+    #[error("ISR {2:?} of partition {0}-{1} has brokers that aren't live replicas")]
+    IneligibleReplica(String, i32, Vec<i32>),
+
+    #[error("Replicas {2:?} aren't a valid assignment for partition {0}-{1}")]
+    InvalidReplicaAssignment(String, i32, Vec<i32>),
+
+    #[error("No reassignment of partition {0}-{1} is in progress")]
+    NoReassignmentInProgress(String, i32),
+
+    #[error("Partition {0}-{1} is already led by its preferred replica")]
+    ElectionNotNeeded(String, i32),
+
+    #[error("Preferred replica of partition {0}-{1} isn't live and in sync")]
+    PreferredLeaderNotAvailable(String, i32),
+
+    #[error("No live replica of partition {0}-{1} can lead it")]
+    EligibleLeadersNotAvailable(String, i32),
+
+    #[error("Broker {0} is already registered with a live session")]
+    DuplicateBrokerRegistration(i32),
+
+    #[error("Broker {0} is not registered")]
+    BrokerIdNotRegistered(i32),
+
+    #[error("Broker epoch {1} of broker {0} is not the current one")]
+    StaleBrokerEpoch(i32, i64),
+
+    #[error("Not the active controller, the quorum leader is {0:?}")]
+    NotController(Option<i32>),
+
+    #[error("Metadata quorum error: {0}")]
+    Quorum(RaftError),
+}
+
+impl ControllerError {
+    pub fn error_code(&self) -> KafkaErrorCode {
+        match self {
+            ControllerError::TopicAlreadyExists(_) => KafkaErrorCode::TopicAlreadyExists,
+            ControllerError::InvalidTopic(_, _) => KafkaErrorCode::InvalidTopicException,
+            ControllerError::UnknownTopic(_) => KafkaErrorCode::UnknownTopicOrPartition,
+            ControllerError::UnknownTopicId(_) => KafkaErrorCode::UnknownTopicId,
+            ControllerError::InvalidConfig(_) => KafkaErrorCode::InvalidConfig,
+            ControllerError::InvalidRequest(_) => KafkaErrorCode::InvalidRequest,
+            ControllerError::InvalidPartitions(_) => KafkaErrorCode::InvalidPartitions,
+            ControllerError::InvalidReplicationFactor(_, _) => KafkaErrorCode::InvalidReplicationFactor,
+            ControllerError::PartitionCountNotIncreased(_, _, _) => KafkaErrorCode::InvalidPartitions,
+            ControllerError::UnknownTopicOrPartition(_, _) => KafkaErrorCode::UnknownTopicOrPartition,
+            ControllerError::NotLeader(_, _, _) => KafkaErrorCode::NotLeaderOrFollower,
+            ControllerError::FencedLeaderEpoch(_, _, _) => KafkaErrorCode::FencedLeaderEpoch,
+            ControllerError::IneligibleReplica(_, _, _) => KafkaErrorCode::IneligibleReplica,
+            ControllerError::InvalidReplicaAssignment(_, _, _) => KafkaErrorCode::InvalidReplicaAssignment,
+            ControllerError::NoReassignmentInProgress(_, _) => KafkaErrorCode::NoReassignmentInProgress,
+            ControllerError::ElectionNotNeeded(_, _) => KafkaErrorCode::ElectionNotNeeded,
+            ControllerError::PreferredLeaderNotAvailable(_, _) => KafkaErrorCode::PreferredLeaderNotAvailable,
+            ControllerError::EligibleLeadersNotAvailable(_, _) => KafkaErrorCode::EligibleLeadersNotAvailable,
+            ControllerError::DuplicateBrokerRegistration(_) => KafkaErrorCode::DuplicateBrokerRegistration,
+            ControllerError::BrokerIdNotRegistered(_) => KafkaErrorCode::BrokerIdNotRegistered,
+            ControllerError::StaleBrokerEpoch(_, _) => KafkaErrorCode::StaleBrokerEpoch,
+            ControllerError::NotController(_) => KafkaErrorCode::NotController,
+            ControllerError::Quorum(_) => KafkaErrorCode::UnknownServerError,
+        }
+    }
+}
+
+/// ElectLeaders election types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElectionType {
+    Preferred, // move leadership back to the first replica
+    Unclean,   // bring offline partitions back, out-of-sync replicas included
+}
+
+impl TryFrom<i8> for ElectionType {
+    type Error = i8;
+
+    fn try_from(value: i8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ElectionType::Preferred),
+            1 => Ok(ElectionType::Unclean),
+            other => Err(other),
+        }
+    }
+}
+
+/// outcome of an election for one partition: its new leader, or why there was none
+#[derive(Debug)]
+pub struct ElectionResult {
+    pub topic: String,
+    pub partition: i32,
+    pub result: Result<i32, ControllerError>,
+}
+
+/// owns cluster metadata: live brokers, replica assignments, leaders and ISRs.
+/// Every change is written as metadata records, committed through the quorum when
+/// there is one, and the partition states it touched are queued to be pushed to
+/// brokers.
+#[derive(Debug)]
+pub struct Controller {
+    controller_id: i32,
+    quorum: Option<Arc<RaftNode>>, // None for a standalone controller
+    image: Arc<RwLock<MetadataImage>>,
+    write_lock: Mutex<()>, // one read-modify-commit at a time
+    pending_states: Mutex<Vec<PartitionState>>, // not yet sent in LeaderAndIsr
+    states_queued: Notify,
+    pushed_epoch: Mutex<i32>, // controller epoch every state was last queued in
+    sessions: Mutex<BrokerSessions>,
+}
+
+/// what a heartbeating broker is told about itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeartbeatResult {
+    pub is_caught_up: bool,
+    pub is_fenced: bool,
+    pub should_shutdown: bool,
+}
+
+// last heartbeat of every unfenced broker, as seen by the active controller. Only
+// kept in memory: a new controller gives every broker a full session to reach it.
+#[derive(Debug, Default)]
+struct BrokerSessions {
+    epoch: i32, // controller epoch the sessions were tracked in
+    last_heartbeat: HashMap<i32, Instant>,
+}
+
+impl Controller {
+    /// a controller with no quorum behind it; its metadata lives in memory only
+    pub fn new(controller_id: i32) -> Self {
+        Controller::build(controller_id, None, Arc::new(RwLock::new(MetadataImage::default())))
+    }
+
+    /// a controller that is active while its quorum node leads, and otherwise
+    /// refuses changes with NotController
+    pub fn with_quorum(raft: Arc<RaftNode>) -> Self {
+        Controller::build(raft.node_id(), Some(Arc::clone(&raft)), raft.image())
+    }
+
+    fn build(controller_id: i32, quorum: Option<Arc<RaftNode>>, image: Arc<RwLock<MetadataImage>>) -> Self {
+        Controller {
+            controller_id,
+            quorum,
+            image,
+            write_lock: Mutex::new(()),
+            pending_states: Mutex::new(Vec::new()),
+            states_queued: Notify::new(),
+            pushed_epoch: Mutex::new(0),
+            sessions: Mutex::new(BrokerSessions::default()),
+        }
+    }
+
+    pub fn controller_id(&self) -> i32 {
+        self.controller_id
+    }
+
+    /// the quorum term, so every newly elected controller fences the previous one
+    pub fn controller_epoch(&self) -> i32 {
+        self.quorum.as_ref().map_or(STANDALONE_CONTROLLER_EPOCH, |raft| raft.status().term)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active_epoch().is_ok()
+    }
+
+    fn active_epoch(&self) -> Result<i32, ControllerError> {
+        match &self.quorum {
+            None => Ok(STANDALONE_CONTROLLER_EPOCH),
+            Some(raft) => raft.active_term().ok_or_else(|| ControllerError::NotController(raft.status().leader_id)),
+        }
+    }
+
+    pub async fn live_brokers(&self) -> BTreeMap<i32, String> {
+        self.image.read().await.live_brokers()
+    }
+
+    pub async fn topic_id(&self, topic: &str) -> Option<Uuid> {
+        self.image.read().await.topics.get(topic).map(|topic| topic.topic_id)
+    }
+
+    pub async fn topic_name(&self, topic_id: Uuid) -> Option<String> {
+        self.image.read().await.topic_name(topic_id).map(str::to_string)
+    }
+
+    pub async fn topic_configs(&self, topic: &str) -> Option<BTreeMap<String, String>> {
+        self.image.read().await.configs.get(topic).cloned()
+    }
+
+    pub async fn partition_state(&self, topic: &str, partition: i32) -> Option<PartitionState> {
+        self.image.read().await.partition(topic, partition).cloned()
+    }
+
+    pub async fn partition_states(&self) -> Vec<PartitionState> {
+        self.image.read().await.partition_states()
+    }
+
+    // makes records part of the metadata: through the quorum in the epoch they were
+    // computed in, or straight into the image when standalone
+    async fn commit(&self, epoch: i32, records: Vec<MetadataRecord>) -> Result<(), ControllerError> {
+        match &self.quorum {
+            Some(raft) => match raft.propose(epoch, records).await {
+                Ok(_) => Ok(()),
+                Err(RaftError::NotLeader(leader_id)) => Err(ControllerError::NotController(leader_id)),
+                Err(e) => Err(ControllerError::Quorum(e)),
+            },
+            None => {
+                let mut image = self.image.write().await;
+                for record in &records {
+                    image.replay(record);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// registers a broker, fenced until it heartbeats, and returns its new broker
+    /// epoch. A broker still heartbeating under another incarnation is refused; one
+    /// that restarted without the controller noticing is fenced first, which moves
+    /// its leadership away.
+    pub async fn register_broker(&self, mut registration: BrokerRegistration) -> Result<i64, ControllerError> {
+        let _write = self.write_lock.lock().await;
+        let epoch = self.active_epoch()?;
+        let broker_id = registration.broker_id;
+        let mut records = Vec::new();
+        let mut changed = Vec::new();
+        {
+            let image = self.image.read().await;
+            if let Some(existing) = image.brokers.get(&broker_id) {
+                if existing.incarnation_id == registration.incarnation_id {
+                    return Ok(existing.broker_epoch); // a retried registration
+                }
+                if !existing.fenced {
+                    if self.session_alive(epoch, broker_id).await {
+                        return Err(ControllerError::DuplicateBrokerRegistration(broker_id));
+                    }
+                    changed = remove_broker_from_partitions(&image, broker_id, true);
+                }
+            }
+            registration.broker_epoch = image.brokers.values().map(|broker| broker.broker_epoch).max().unwrap_or(0) + 1;
+            registration.fenced = true;
+        }
+
+        let broker_epoch = registration.broker_epoch;
+        println!("Registered broker {} with epoch {} (rack {:?})", broker_id, broker_epoch, registration.rack);
+        records.push(MetadataRecord::RegisterBroker(registration));
+        records.extend(changed.iter().cloned().map(MetadataRecord::Partition));
+        self.commit(epoch, records).await?;
+        self.queue_states(&changed).await;
+        self.touch_session(epoch, broker_id).await;
+        Ok(broker_epoch)
+    }
+
+    /// renews a broker's session. The first heartbeat that doesn't ask to stay fenced
+    /// unfences the broker and brings back offline partitions it can lead. One asking
+    /// to shut down moves its leadership away, and is told to go ahead once it leads
+    /// nothing anymore, at which point it's fenced.
+    pub async fn broker_heartbeat(
+        &self,
+        broker_id: i32,
+        broker_epoch: i64,
+        want_fence: bool,
+        want_shutdown: bool,
+    ) -> Result<HeartbeatResult, ControllerError> {
+        let _write = self.write_lock.lock().await;
+        let epoch = self.active_epoch()?;
+        let (mut records, changed, result) = {
+            let image = self.image.read().await;
+            let registration = image.brokers.get(&broker_id).ok_or(ControllerError::BrokerIdNotRegistered(broker_id))?;
+            if registration.broker_epoch != broker_epoch {
+                return Err(ControllerError::StaleBrokerEpoch(broker_id, broker_epoch));
+            }
+            // brokers get partition state pushed in LeaderAndIsr rather than replaying
+            // the log, so there is nothing for them to catch up on
+            let mut result = HeartbeatResult { is_caught_up: true, is_fenced: registration.fenced, should_shutdown: false };
+
+            if want_shutdown {
+                let leads_partitions = image.partition_states().iter().any(|partition| partition.leader == broker_id);
+                if leads_partitions {
+                    (Vec::new(), remove_broker_from_partitions(&image, broker_id, false), result)
+                } else {
+                    // leadership moved on an earlier heartbeat and has been pushed out
+                    result.is_fenced = true;
+                    result.should_shutdown = true;
+                    let records = match registration.fenced {
+                        true => Vec::new(),
+                        false => vec![MetadataRecord::FenceBroker { broker_id }],
+                    };
+                    (records, Vec::new(), result)
+                }
+            } else if registration.fenced && !want_fence {
+                result.is_fenced = false;
+                let changed = elect_offline_partitions(&image, broker_id);
+                (vec![MetadataRecord::UnfenceBroker { broker_id }], changed, result)
+            } else if !registration.fenced && want_fence {
+                result.is_fenced = true;
+                let changed = remove_broker_from_partitions(&image, broker_id, true);
+                (vec![MetadataRecord::FenceBroker { broker_id }], changed, result)
+            } else {
+                (Vec::new(), Vec::new(), result)
+            }
+        };
+
+        let unfenced = records.contains(&MetadataRecord::UnfenceBroker { broker_id });
+        records.extend(changed.iter().cloned().map(MetadataRecord::Partition));
+        if !records.is_empty() {
+            self.commit(epoch, records).await?;
+        }
+        if unfenced {
+            println!("Broker {} is unfenced", broker_id);
+            // a newly unfenced broker knows none of the partition states
+            let states = self.partition_states().await;
+            self.queue_states(&states).await;
+        } else {
+            self.queue_states(&changed).await;
+        }
+        if result.should_shutdown {
+            println!("Broker {} finished controlled shutdown", broker_id);
+            self.sessions.lock().await.last_heartbeat.remove(&broker_id);
+        } else {
+            self.touch_session(epoch, broker_id).await;
+        }
+        Ok(result)
+    }
+
+    /// fences every unfenced broker that hasn't heartbeated within the session
+    /// timeout, electing new leaders for its partitions. Returns the fenced brokers.
+    pub async fn fence_expired_brokers(&self) -> Result<Vec<i32>, ControllerError> {
+        let Ok(epoch) = self.active_epoch() else {
+            return Ok(Vec::new());
+        };
+        let live = self.image.read().await.live_broker_ids();
+        let expired: Vec<i32> = {
+            let mut sessions = self.sessions.lock().await;
+            sessions.reset_if_stale(epoch);
+            let now = Instant::now();
+            sessions.last_heartbeat.retain(|broker_id, _| live.contains(broker_id));
+            live.iter()
+                .copied()
+                .filter(|broker_id| {
+                    let last_heartbeat = *sessions.last_heartbeat.entry(*broker_id).or_insert(now);
+                    now.duration_since(last_heartbeat) > Duration::from_millis(BROKER_SESSION_TIMEOUT_MS)
+                })
+                .collect()
+        };
+
+        for broker_id in &expired {
+            println!("Broker {} missed its heartbeats for {} ms, fencing it", broker_id, BROKER_SESSION_TIMEOUT_MS);
+            self.handle_broker_failure(*broker_id).await?;
+            self.sessions.lock().await.last_heartbeat.remove(broker_id);
+        }
+        Ok(expired)
+    }
+
+    async fn touch_session(&self, epoch: i32, broker_id: i32) {
+        let mut sessions = self.sessions.lock().await;
+        sessions.reset_if_stale(epoch);
+        sessions.last_heartbeat.insert(broker_id, Instant::now());
+    }
+
+    async fn session_alive(&self, epoch: i32, broker_id: i32) -> bool {
+        let mut sessions = self.sessions.lock().await;
+        sessions.reset_if_stale(epoch);
+        sessions
+            .last_heartbeat
+            .get(&broker_id)
+            .is_some_and(|last_heartbeat| last_heartbeat.elapsed() <= Duration::from_millis(BROKER_SESSION_TIMEOUT_MS))
+    }
+
+    /// creates a topic with the requested partitions, replication factor and config
+    /// overrides. Without a manual assignment, replicas are spread over the live
+    /// brokers and their racks. Each partition is led by its first live replica.
+    /// With `validate_only` the checks run but nothing is written.
+    pub async fn create_topic(
+        &self,
+        topic: &NewTopic,
+        validate_only: bool,
+    ) -> Result<(Uuid, Vec<PartitionState>), ControllerError> {
+        validate_topic_name(&topic.name)?;
+        TopicConfig::from_overrides(&topic.configs).map_err(|e| ControllerError::InvalidConfig(e.to_string()))?;
+        let _write = self.write_lock.lock().await;
+        let epoch = self.active_epoch()?;
+        let topic_id = match validate_only {
+            true => Uuid::nil(),
+            false => Uuid::new_v4(),
+        };
+        let created: Vec<PartitionState> = {
+            let image = self.image.read().await;
+            if image.topics.contains_key(&topic.name) {
+                return Err(ControllerError::TopicAlreadyExists(topic.name.clone()));
+            }
+            let live = image.live_broker_ids();
+            let assignment = match topic.assignments.is_empty() {
+                true => {
+                    let num_partitions = match topic.num_partitions {
+                        -1 => DEFAULT_NUM_PARTITIONS,
+                        n => n,
+                    };
+                    let replication_factor = match topic.replication_factor {
+                        -1 => DEFAULT_REPLICATION_FACTOR,
+                        n => n,
+                    };
+                    if num_partitions <= 0 {
+                        return Err(ControllerError::InvalidPartitions(num_partitions));
+                    }
+                    if replication_factor <= 0 || replication_factor as usize > live.len() {
+                        return Err(ControllerError::InvalidReplicationFactor(replication_factor, live.len()));
+                    }
+                    let brokers: Vec<PlacementBroker> = live
+                        .iter()
+                        .map(|&broker_id| PlacementBroker { broker_id, rack: image.brokers[&broker_id].rack.clone() })
+                        .collect();
+                    assign_replicas(
+                        &brokers,
+                        num_partitions,
+                        replication_factor,
+                        random_start(brokers.len()),
+                        random_start(brokers.len()),
+                    )
+                }
+                false => manual_assignment(&image, topic)?,
+            };
+            assignment
+                .into_iter()
+                .enumerate()
+                .map(|(partition, replicas)| new_partition(&live, &topic.name, topic_id, partition as i32, replicas))
+                .collect()
+        };
+        if validate_only {
+            return Ok((topic_id, created));
+        }
+
+        let mut records = vec![MetadataRecord::Topic { name: topic.name.clone(), topic_id }];
+        records.extend(topic.configs.iter().map(|(name, value)| MetadataRecord::Config {
+            topic: topic.name.clone(),
+            name: name.clone(),
+            value: Some(value.clone()),
+        }));
+        records.extend(created.iter().cloned().map(MetadataRecord::Partition));
+        self.commit(epoch, records).await?;
+
+        println!("Created topic {} ({}) with {} partitions", topic.name, topic_id, created.len());
+        self.queue_states(&created).await;
+        Ok((topic_id, created))
+    }
+
+    /// grows a topic to `count` partitions. The new ones get the given replicas, or are
+    /// spread over the live brokers with the topic's replication factor. Topics can't
+    /// shrink. With `validate_only` the checks run but nothing is written.
+    pub async fn create_partitions(
+        &self,
+        name: &str,
+        count: i32,
+        assignments: Option<Vec<Vec<i32>>>,
+        validate_only: bool,
+    ) -> Result<Vec<PartitionState>, ControllerError> {
+        let _write = self.write_lock.lock().await;
+        let epoch = self.active_epoch()?;
+        let created: Vec<PartitionState> = {
+            let image = self.image.read().await;
+            let topic = image.topics.get(name).ok_or_else(|| ControllerError::UnknownTopic(name.to_string()))?;
+            let current = topic.partitions.len() as i32;
+            if count <= current {
+                return Err(ControllerError::PartitionCountNotIncreased(name.to_string(), current, count));
+            }
+            let replication_factor = topic.partitions.values().next().map_or(0, |partition| partition.target_replicas().len());
+            let live = image.live_broker_ids();
+            let assignment = match assignments {
+                Some(assignment) => {
+                    if assignment.len() as i32 != count - current {
+                        return Err(ControllerError::InvalidRequest(
+                            "The number of replica assignments doesn't match the number of partitions to add",
+                        ));
+                    }
+                    for (partition, replicas) in (current..).zip(&assignment) {
+                        check_manual_replicas(&image, name, partition, replicas, replication_factor)?;
+                    }
+                    assignment
+                }
+                None => {
+                    if replication_factor > live.len() {
+                        return Err(ControllerError::InvalidReplicationFactor(replication_factor as i32, live.len()));
+                    }
+                    let brokers: Vec<PlacementBroker> = live
+                        .iter()
+                        .map(|&broker_id| PlacementBroker { broker_id, rack: image.brokers[&broker_id].rack.clone() })
+                        .collect();
+                    assign_replicas(
+                        &brokers,
+                        count - current,
+                        replication_factor as i32,
+                        random_start(brokers.len()),
+                        random_start(brokers.len()),
+                    )
+                }
+            };
+            (current..)
+                .zip(assignment)
+                .map(|(partition, replicas)| new_partition(&live, name, topic.topic_id, partition, replicas))
+                .collect()
+        };
+        if validate_only {
+            return Ok(created);
+        }
+
+        self.commit(epoch, created.iter().cloned().map(MetadataRecord::Partition).collect()).await?;
+        println!("Topic {} now has {} partitions", name, count);
+        self.queue_states(&created).await;
+        Ok(created)
+    }
+
+    /// removes a topic from the metadata and tells its replicas to delete their
+    /// partitions, which are pushed with LEADER_DURING_DELETE as their leader
+    pub async fn delete_topic(&self, name: &str) -> Result<Uuid, ControllerError> {
+        let _write = self.write_lock.lock().await;
+        let epoch = self.active_epoch()?;
+        let (topic_id, deleted) = {
+            let image = self.image.read().await;
+            let topic = image.topics.get(name).ok_or_else(|| ControllerError::UnknownTopic(name.to_string()))?;
+            let deleted: Vec<PartitionState> = topic
+                .partitions
+                .values()
+                .map(|partition| PartitionState {
+                    leader: LEADER_DURING_DELETE,
+                    leader_epoch: partition.leader_epoch + 1,
+                    isr: Vec::new(),
+                    ..partition.clone()
+                })
+                .collect();
+            (topic.topic_id, deleted)
+        };
+
+        self.commit(epoch, vec![MetadataRecord::RemoveTopic { name: name.to_string() }]).await?;
+        println!("Deleted topic {} ({})", name, topic_id);
+        self.queue_states(&deleted).await;
+        Ok(topic_id)
+    }
+
+    /// fences a broker, re-elects the partitions it led and drops it from every other
+    /// ISR. A partition with no eligible replica goes offline, keeping its last ISR so
+    /// that replica can be elected cleanly once it returns.
+    pub async fn handle_broker_failure(&self, broker_id: i32) -> Result<Vec<PartitionState>, ControllerError> {
+        let _write = self.write_lock.lock().await;
+        let epoch = self.active_epoch()?;
+        let changed = {
+            let image = self.image.read().await;
+            if !image.live_broker_ids().contains(&broker_id) {
+                return Ok(Vec::new());
+            }
+            remove_broker_from_partitions(&image, broker_id, true)
+        };
+
+        let mut records = vec![MetadataRecord::FenceBroker { broker_id }];
+        records.extend(changed.iter().cloned().map(MetadataRecord::Partition));
+        self.commit(epoch, records).await?;
+        self.queue_states(&changed).await;
+        Ok(changed)
+    }
+
+    /// hands a registered broker the next block of producer ids. A broker_epoch of -1
+    /// skips the epoch check.
+    pub async fn allocate_producer_ids(&self, broker_id: i32, broker_epoch: i64) -> Result<ProducerIdBlock, ControllerError> {
+        let _write = self.write_lock.lock().await;
+        let epoch = self.active_epoch()?;
+        let block = {
+            let image = self.image.read().await;
+            let registration = image.brokers.get(&broker_id).ok_or(ControllerError::BrokerIdNotRegistered(broker_id))?;
+            if broker_epoch != -1 && registration.broker_epoch != broker_epoch {
+                return Err(ControllerError::StaleBrokerEpoch(broker_id, broker_epoch));
+            }
+            ProducerIdBlock { first_producer_id: image.next_producer_id, size: PRODUCER_ID_BLOCK_SIZE }
+        };
+
+        let record = MetadataRecord::ProducerIds {
+            broker_id,
+            broker_epoch,
+            next_producer_id: block.last_producer_id() + 1,
+        };
+        self.commit(epoch, vec![record]).await?;
+        println!("Allocated producer ids {}..={} to broker {}", block.first_producer_id, block.last_producer_id(), broker_id);
+        Ok(block)
+    }
+
+    /// commits an ISR change proposed by a partition's leader (AlterPartition)
+    pub async fn alter_partition(
+        &self,
+        topic: &str,
+        partition: i32,
+        broker_id: i32,
+        leader_epoch: i32,
+        new_isr: Vec<i32>,
+    ) -> Result<PartitionState, ControllerError> {
+        let _write = self.write_lock.lock().await;
+        let epoch = self.active_epoch()?;
+        let current = {
+            let image = self.image.read().await;
+            let mut current = image
+                .partition(topic, partition)
+                .cloned()
+                .ok_or_else(|| ControllerError::UnknownTopicOrPartition(topic.to_string(), partition))?;
+            if current.leader != broker_id {
+                return Err(ControllerError::NotLeader(topic.to_string(), partition, broker_id));
+            }
+            if current.leader_epoch != leader_epoch {
+                return Err(ControllerError::FencedLeaderEpoch(topic.to_string(), partition, leader_epoch));
+            }
+            let live = image.live_broker_ids();
+            let eligible = new_isr.contains(&broker_id)
+                && new_isr.iter().all(|replica| current.replicas.contains(replica) && live.contains(replica));
+            if !eligible {
+                // the leader already applied it locally, so remind it of the committed ISR
+                self.queue_states(std::slice::from_ref(&current)).await;
+                return Err(ControllerError::IneligibleReplica(topic.to_string(), partition, new_isr));
+            }
+            current.isr = new_isr;
+            // an expansion may have brought the last target replica of a reassignment in sync
+            complete_reassignment(&image, current)
+        };
+
+        self.commit(epoch, vec![MetadataRecord::Partition(current.clone())]).await?;
+        self.queue_states(std::slice::from_ref(&current)).await;
+        Ok(current)
+    }
+
+    /// starts moving a partition to the `target` replicas, replacing any ongoing
+    /// reassignment, or cancels the ongoing one when `target` is None. New replicas
+    /// are added first; once all of them are in the ISR leadership moves if it has
+    /// to and the old replicas are dropped.
+    pub async fn alter_partition_reassignment(
+        &self,
+        topic: &str,
+        partition: i32,
+        target: Option<Vec<i32>>,
+    ) -> Result<PartitionState, ControllerError> {
+        let _write = self.write_lock.lock().await;
+        let epoch = self.active_epoch()?;
+        let changed = {
+            let image = self.image.read().await;
+            let current = image
+                .partition(topic, partition)
+                .cloned()
+                .ok_or_else(|| ControllerError::UnknownTopicOrPartition(topic.to_string(), partition))?;
+            match target {
+                Some(target) => {
+                    let live = image.live_broker_ids();
+                    let distinct: BTreeSet<i32> = target.iter().copied().collect();
+                    if target.is_empty() || distinct.len() != target.len() || !distinct.is_subset(&live) {
+                        return Err(ControllerError::InvalidReplicaAssignment(topic.to_string(), partition, target));
+                    }
+                    start_reassignment(&image, current, target)
+                }
+                None => {
+                    if !current.is_reassigning() {
+                        return Err(ControllerError::NoReassignmentInProgress(topic.to_string(), partition));
+                    }
+                    let original = current.original_replicas();
+                    // the original replicas may all have dropped out of the ISR meanwhile
+                    move_replicas(&image, current, original.clone())
+                        .ok_or_else(|| ControllerError::InvalidReplicaAssignment(topic.to_string(), partition, original))?
+                }
+            }
+        };
+
+        self.commit(epoch, vec![MetadataRecord::Partition(changed.clone())]).await?;
+        match changed.is_reassigning() {
+            true => println!(
+                "Reassigning {}-{}: adding {:?}, removing {:?}",
+                topic, partition, changed.adding_replicas, changed.removing_replicas
+            ),
+            false => println!("Replicas of {}-{} are now {:?}", topic, partition, changed.replicas),
+        }
+        self.queue_states(std::slice::from_ref(&changed)).await;
+        Ok(changed)
+    }
+
+    /// partitions with a reassignment in progress
+    pub async fn list_partition_reassignments(&self) -> Result<Vec<PartitionState>, ControllerError> {
+        self.active_epoch()?;
+        Ok(self.partition_states().await.into_iter().filter(PartitionState::is_reassigning).collect())
+    }
+
+    /// runs an election of the given type for each partition, or for every partition
+    /// when `partitions` is None. In that case partitions that didn't need one are
+    /// left out of the results.
+    pub async fn elect_leaders(
+        &self,
+        election_type: ElectionType,
+        partitions: Option<Vec<(String, i32)>>,
+    ) -> Result<Vec<ElectionResult>, ControllerError> {
+        let _write = self.write_lock.lock().await;
+        let epoch = self.active_epoch()?;
+        let (changed, results) = {
+            let image = self.image.read().await;
+            let requested = partitions.is_some();
+            let partitions = partitions.unwrap_or_else(|| {
+                image.partition_states().into_iter().map(|state| (state.topic, state.partition)).collect()
+            });
+
+            let mut changed = Vec::new();
+            let mut results = Vec::with_capacity(partitions.len());
+            for (topic, partition) in partitions {
+                let elected = match image.partition(&topic, partition) {
+                    Some(current) => match election_type {
+                        ElectionType::Preferred => elect_preferred_leader(&image, current),
+                        ElectionType::Unclean => elect_unclean_leader(&image, current),
+                    },
+                    None => Err(ControllerError::UnknownTopicOrPartition(topic.clone(), partition)),
+                };
+                let result = elected.map(|state| {
+                    let leader = state.leader;
+                    changed.push(state);
+                    leader
+                });
+                if requested || !matches!(result, Err(ControllerError::ElectionNotNeeded(_, _))) {
+                    results.push(ElectionResult { topic, partition, result });
+                }
+            }
+            (changed, results)
+        };
+
+        if !changed.is_empty() {
+            self.commit(epoch, changed.iter().cloned().map(MetadataRecord::Partition).collect()).await?;
+            for state in &changed {
+                println!("Elected broker {} as leader of {}-{} ({:?} election)", state.leader, state.topic, state.partition, election_type);
+            }
+            self.queue_states(&changed).await;
+        }
+        Ok(results)
+    }
+
+    /// moves leadership back to preferred replicas for every broker that leads less
+    /// than its share: more than LEADER_IMBALANCE_PER_BROKER_PERCENTAGE of the
+    /// partitions it's the preferred replica of are led by someone else. Returns the
+    /// partitions that moved.
+    pub async fn rebalance_leaders(&self) -> Result<Vec<PartitionState>, ControllerError> {
+        if self.active_epoch().is_err() {
+            return Ok(Vec::new());
+        }
+        let imbalanced: Vec<(String, i32)> = {
+            let image = self.image.read().await;
+            // preferred replica -> (partitions it should lead, the ones it doesn't)
+            let mut by_preferred: BTreeMap<i32, (usize, Vec<(String, i32)>)> = BTreeMap::new();
+            for state in image.partition_states() {
+                let Some(preferred) = state.replicas.first().copied() else {
+                    continue;
+                };
+                let entry = by_preferred.entry(preferred).or_default();
+                entry.0 += 1;
+                // partitions being reassigned get a leader once they're done
+                if state.leader != preferred && !state.is_reassigning() {
+                    entry.1.push((state.topic, state.partition));
+                }
+            }
+            by_preferred
+                .into_iter()
+                .filter(|(_, (total, not_led))| not_led.len() * 100 > total * LEADER_IMBALANCE_PER_BROKER_PERCENTAGE)
+                .flat_map(|(_, (_, not_led))| not_led)
+                .collect()
+        };
+        if imbalanced.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut moved = Vec::new();
+        for result in self.elect_leaders(ElectionType::Preferred, Some(imbalanced)).await? {
+            match result.result {
+                Ok(_) => moved.extend(self.partition_state(&result.topic, result.partition).await),
+                // the preferred replica is down or behind, try again next round
+                Err(ControllerError::PreferredLeaderNotAvailable(_, _)) => {}
+                Err(e) => println!("Leader rebalance of {}-{} failed: {}", result.topic, result.partition, e),
+            }
+        }
+        Ok(moved)
+    }
+
+    async fn queue_states(&self, states: &[PartitionState]) {
+        if states.is_empty() {
+            return;
+        }
+        let mut pending = self.pending_states.lock().await;
+        for state in states {
+            // a newer state of the same partition replaces the queued one
+            pending.retain(|queued| queued.topic != state.topic || queued.partition != state.partition);
+            pending.push(state.clone());
+        }
+        self.states_queued.notify_one();
+    }
+
+    /// waits for partition states brokers haven't been sent yet and takes them. A
+    /// controller only hands out states while active, and queues all of them once
+    /// per epoch since brokers may have missed changes made by its predecessor.
+    pub async fn take_pending_states(&self) -> Vec<PartitionState> {
+        let mut status = self.quorum.as_ref().map(|raft| raft.subscribe());
+        loop {
+            let notified = self.states_queued.notified();
+            if let Ok(epoch) = self.active_epoch() {
+                let mut pushed_epoch = self.pushed_epoch.lock().await;
+                if *pushed_epoch != epoch {
+                    *pushed_epoch = epoch;
+                    let states = self.partition_states().await;
+                    self.queue_states(&states).await;
+                }
+                let mut pending = self.pending_states.lock().await;
+                if !pending.is_empty() {
+                    return std::mem::take(&mut *pending);
+                }
+            }
+            match status.as_mut() {
+                Some(status) => {
+                    tokio::select! {
+                        _ = notified => {}
+                        _ = status.changed() => {}
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+
+    /// puts back states that couldn't be delivered, unless a newer one was queued meanwhile
+    pub async fn requeue_states(&self, states: Vec<PartitionState>) {
+        let mut pending = self.pending_states.lock().await;
+        for state in states {
+            if !pending.iter().any(|queued| queued.topic == state.topic && queued.partition == state.partition) {
+                pending.push(state);
+            }
+        }
+        if !pending.is_empty() {
+            self.states_queued.notify_one();
+        }
+    }
+}
+
+impl BrokerSessions {
+    fn reset_if_stale(&mut self, epoch: i32) {
+        if self.epoch != epoch {
+            self.epoch = epoch;
+            self.last_heartbeat.clear();
+        }
+    }
+}
+
+// topic names follow Kafka's rules, as they end up in directory names
+fn validate_topic_name(name: &str) -> Result<(), ControllerError> {
+    let reason = if name.is_empty() {
+        "it is empty"
+    } else if name == "." || name == ".." {
+        "it can't be '.' or '..'"
+    } else if name.len() > MAX_TOPIC_NAME_LENGTH {
+        "it is longer than 249 characters"
+    } else if !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+        "it may only contain ASCII alphanumerics, '.', '_' and '-'"
+    } else {
+        return Ok(());
+    };
+    Err(ControllerError::InvalidTopic(name.to_string(), reason))
+}
+
+// a manual assignment lists every partition from 0, all with the same number of replicas
+fn manual_assignment(image: &MetadataImage, topic: &NewTopic) -> Result<Vec<Vec<i32>>, ControllerError> {
+    if topic.num_partitions != -1 || topic.replication_factor != -1 {
+        return Err(ControllerError::InvalidRequest(
+            "A manual assignment requires the number of partitions and replication factor to be -1",
+        ));
+    }
+    let replication_factor = topic.assignments.values().next().map_or(0, Vec::len);
+    let mut assignment = Vec::new();
+    for (index, (&partition, replicas)) in topic.assignments.iter().enumerate() {
+        if partition != index as i32 {
+            return Err(ControllerError::InvalidReplicaAssignment(topic.name.clone(), partition, replicas.clone()));
+        }
+        check_manual_replicas(image, &topic.name, partition, replicas, replication_factor)?;
+        assignment.push(replicas.clone());
+    }
+    Ok(assignment)
+}
+
+// manually assigned replicas are distinct registered brokers, at least one of them live
+fn check_manual_replicas(
+    image: &MetadataImage,
+    topic: &str,
+    partition: i32,
+    replicas: &[i32],
+    replication_factor: usize,
+) -> Result<(), ControllerError> {
+    let distinct: BTreeSet<i32> = replicas.iter().copied().collect();
+    if replicas.is_empty()
+        || replicas.len() != replication_factor
+        || distinct.len() != replicas.len()
+        || !distinct.iter().all(|replica| image.brokers.contains_key(replica))
+        || distinct.is_disjoint(&image.live_broker_ids())
+    {
+        return Err(ControllerError::InvalidReplicaAssignment(topic.to_string(), partition, replicas.to_vec()));
+    }
+    Ok(())
+}
+
+// a partition created over `replicas`, some of which may be fenced when they were
+// assigned manually; it's led by its first live replica
+fn new_partition(live: &BTreeSet<i32>, topic: &str, topic_id: Uuid, partition: i32, replicas: Vec<i32>) -> PartitionState {
+    let isr: Vec<i32> = replicas.iter().copied().filter(|replica| live.contains(replica)).collect();
+    PartitionState {
+        topic: topic.to_string(),
+        topic_id,
+        partition,
+        leader: isr[0],
+        leader_epoch: 0,
+        isr,
+        replicas,
+        adding_replicas: Vec::new(),
+        removing_replicas: Vec::new(),
+    }
+}
+
+// new states of the partitions a broker leads or is in the ISR of, with leadership
+// moved to another live replica (or offline if there is none) and the broker out of
+// the ISR. Unclean elections follow the topic config only if `allow_unclean`.
+fn remove_broker_from_partitions(image: &MetadataImage, broker_id: i32, allow_unclean: bool) -> Vec<PartitionState> {
+    let mut live = image.live_broker_ids();
+    live.remove(&broker_id);
+
+    let mut changed = Vec::new();
+    for mut partition in image.partition_states() {
+        if partition.leader == broker_id {
+            let unclean = allow_unclean && image.unclean_leader_election_enable(&partition.topic);
+            match elect_leader(&partition.replicas, &partition.isr, &live, unclean) {
+                Some(election) => {
+                    if election.unclean {
+                        println!("Unclean leader election for {}-{}: broker {} was out of sync", partition.topic, partition.partition, election.leader);
+                    }
+                    partition.leader = election.leader;
+                    partition.isr = election.isr;
+                }
+                None => {
+                    println!("No eligible leader for {}-{} without broker {}, partition is offline", partition.topic, partition.partition, broker_id);
+                    partition.leader = NO_LEADER;
+                }
+            }
+            partition.leader_epoch += 1;
+        } else if partition.isr.contains(&broker_id) && partition.isr.len() > 1 {
+            partition.isr.retain(|replica| *replica != broker_id);
+        } else {
+            continue;
+        }
+        changed.push(partition);
+    }
+    changed
+}
+
+// offline partitions a newly unfenced broker can lead again
+fn elect_offline_partitions(image: &MetadataImage, broker_id: i32) -> Vec<PartitionState> {
+    let mut live = image.live_broker_ids();
+    live.insert(broker_id);
+
+    let mut changed = Vec::new();
+    for partition in image.partition_states() {
+        if partition.leader != NO_LEADER || !partition.replicas.contains(&broker_id) {
+            continue;
+        }
+        let unclean = image.unclean_leader_election_enable(&partition.topic);
+        if let Some(election) = elect_leader(&partition.replicas, &partition.isr, &live, unclean) {
+            println!("Partition {}-{} is back online with leader {}", partition.topic, partition.partition, election.leader);
+            changed.push(PartitionState {
+                leader: election.leader,
+                leader_epoch: partition.leader_epoch + 1,
+                isr: election.isr,
+                ..partition
+            });
+        }
+    }
+    changed
+}
+
+// the partition led by its preferred replica, the first one in `replicas`
+fn elect_preferred_leader(image: &MetadataImage, partition: &PartitionState) -> Result<PartitionState, ControllerError> {
+    let preferred = partition.replicas.first().copied().unwrap_or(NO_LEADER);
+    if partition.leader == preferred {
+        return Err(ControllerError::ElectionNotNeeded(partition.topic.clone(), partition.partition));
+    }
+    if !image.live_broker_ids().contains(&preferred) || !partition.isr.contains(&preferred) {
+        return Err(ControllerError::PreferredLeaderNotAvailable(partition.topic.clone(), partition.partition));
+    }
+    Ok(PartitionState { leader: preferred, leader_epoch: partition.leader_epoch + 1, ..partition.clone() })
+}
+
+// an offline partition back online, led by an out-of-sync replica if no ISR member
+// is left, whatever the topic's unclean.leader.election.enable says
+fn elect_unclean_leader(image: &MetadataImage, partition: &PartitionState) -> Result<PartitionState, ControllerError> {
+    if partition.leader != NO_LEADER {
+        return Err(ControllerError::ElectionNotNeeded(partition.topic.clone(), partition.partition));
+    }
+    let election = elect_leader(&partition.replicas, &partition.isr, &image.live_broker_ids(), true)
+        .ok_or_else(|| ControllerError::EligibleLeadersNotAvailable(partition.topic.clone(), partition.partition))?;
+    if election.unclean {
+        println!("Unclean leader election for {}-{}: broker {} was out of sync", partition.topic, partition.partition, election.leader);
+    }
+    Ok(PartitionState { leader: election.leader, leader_epoch: partition.leader_epoch + 1, isr: election.isr, ..partition.clone() })
+}
+
+// the partition moving towards `target`: target replicas first, then the original
+// ones it drops. Done right away if every target replica is already in sync.
+fn start_reassignment(image: &MetadataImage, mut partition: PartitionState, target: Vec<i32>) -> PartitionState {
+    let original = partition.original_replicas();
+    partition.adding_replicas = target.iter().copied().filter(|replica| !original.contains(replica)).collect();
+    partition.removing_replicas = original.iter().copied().filter(|replica| !target.contains(replica)).collect();
+    partition.replicas = target;
+    partition.replicas.extend(&partition.removing_replicas);
+    complete_reassignment(image, partition)
+}
+
+// finishes a reassignment once all target replicas are in the ISR
+fn complete_reassignment(image: &MetadataImage, partition: PartitionState) -> PartitionState {
+    let target = partition.target_replicas();
+    if !partition.is_reassigning() || !target.iter().all(|replica| partition.isr.contains(replica)) {
+        return partition;
+    }
+    let fallback = partition.clone();
+    move_replicas(image, partition, target).unwrap_or(fallback)
+}
+
+// the partition with exactly `replicas` and no reassignment, its ISR cut down to
+// them and a new leader among them if the current one isn't. None if no replica
+// left in the ISR can lead.
+fn move_replicas(image: &MetadataImage, mut partition: PartitionState, replicas: Vec<i32>) -> Option<PartitionState> {
+    partition.isr.retain(|replica| replicas.contains(replica));
+    if !replicas.contains(&partition.leader) {
+        let election = elect_leader(&replicas, &partition.isr, &image.live_broker_ids(), false)?;
+        partition.leader = election.leader;
+        partition.isr = election.isr;
+        partition.leader_epoch += 1;
+    }
+    partition.replicas = replicas;
+    partition.adding_replicas.clear();
+    partition.removing_replicas.clear();
+    Some(partition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::metadata::BrokerEndpoint;
+
+    fn registration(broker_id: i32) -> BrokerRegistration {
+        BrokerRegistration {
+            broker_id,
+            broker_epoch: -1,
+            incarnation_id: Uuid::new_v4(),
+            listeners: vec![BrokerEndpoint {
+                name: "PLAINTEXT".to_string(),
+                host: "localhost".to_string(),
+                port: 9092 + broker_id as u16,
+                security_protocol: 0,
+            }],
+            rack: None,
+            log_dirs: Vec::new(),
+            fenced: true,
+        }
+    }
+
+    // registers and unfences a broker, returning its broker epoch
+    async fn start_broker(controller: &Controller, broker_id: i32) -> i64 {
+        let broker_epoch = controller.register_broker(registration(broker_id)).await.unwrap();
+        let result = controller.broker_heartbeat(broker_id, broker_epoch, false, false).await.unwrap();
+        assert!(!result.is_fenced);
+        broker_epoch
+    }
+
+    // a topic with its partitions manually assigned to `replicas`, in order
+    async fn create_assigned(controller: &Controller, name: &str, replicas: &[&[i32]]) -> Vec<PartitionState> {
+        let topic = NewTopic {
+            name: name.to_string(),
+            num_partitions: -1,
+            replication_factor: -1,
+            assignments: (0..).zip(replicas.iter().map(|replicas| replicas.to_vec())).collect(),
+            configs: BTreeMap::new(),
+        };
+        controller.create_topic(&topic, false).await.unwrap().1
+    }
+
+    // makes a broker's last heartbeat older than the session timeout
+    async fn expire_session(controller: &Controller, broker_id: i32) {
+        let expired = Instant::now() - Duration::from_millis(BROKER_SESSION_TIMEOUT_MS + 1);
+        controller.sessions.lock().await.last_heartbeat.insert(broker_id, expired);
+    }
+
+    #[tokio::test]
+    async fn a_failed_leader_is_replaced_by_the_next_isr_member() {
+        let controller = Controller::new(0);
+        for broker_id in 0..3 {
+            start_broker(&controller, broker_id).await;
+        }
+        create_assigned(&controller, "orders", &[&[1, 2, 0], &[0, 1, 2]]).await;
+
+        let changed = controller.handle_broker_failure(1).await.unwrap();
+        assert_eq!(changed.len(), 2);
+        let led = controller.partition_state("orders", 0).await.unwrap();
+        assert_eq!((led.leader, led.leader_epoch, led.isr.clone()), (2, 1, vec![2, 0]));
+        // a follower only leaves the ISR, leadership stays put
+        let followed = controller.partition_state("orders", 1).await.unwrap();
+        assert_eq!((followed.leader, followed.leader_epoch, followed.isr.clone()), (0, 0, vec![0, 2]));
+        assert!(!controller.live_brokers().await.contains_key(&1));
+
+        let pushed = controller.take_pending_states().await;
+        assert!(pushed.contains(&led) && pushed.contains(&followed));
+    }
+
+    #[tokio::test]
+    async fn brokers_that_miss_their_heartbeats_are_fenced() {
+        let controller = Controller::new(0);
+        start_broker(&controller, 0).await;
+        let broker_epoch = start_broker(&controller, 1).await;
+        create_assigned(&controller, "orders", &[&[0, 1]]).await;
+        assert!(controller.fence_expired_brokers().await.unwrap().is_empty());
+
+        expire_session(&controller, 0).await;
+        controller.broker_heartbeat(1, broker_epoch, false, false).await.unwrap();
+        assert_eq!(controller.fence_expired_brokers().await.unwrap(), vec![0]);
+
+        let state = controller.partition_state("orders", 0).await.unwrap();
+        assert_eq!((state.leader, state.leader_epoch, state.isr), (1, 1, vec![1]));
+        assert_eq!(controller.live_brokers().await.keys().copied().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn controlled_shutdown_moves_leadership_before_fencing() {
+        let controller = Controller::new(0);
+        let broker_epoch = start_broker(&controller, 0).await;
+        start_broker(&controller, 1).await;
+        create_assigned(&controller, "orders", &[&[0, 1]]).await;
+
+        // the first request moves leadership away, the broker waits for it to land
+        let result = controller.broker_heartbeat(0, broker_epoch, false, true).await.unwrap();
+        assert!(!result.should_shutdown && !result.is_fenced);
+        let state = controller.partition_state("orders", 0).await.unwrap();
+        assert_eq!((state.leader, state.leader_epoch, state.isr), (1, 1, vec![1]));
+        assert!(controller.live_brokers().await.contains_key(&0));
+
+        let result = controller.broker_heartbeat(0, broker_epoch, false, true).await.unwrap();
+        assert!(result.should_shutdown && result.is_fenced);
+        assert!(!controller.live_brokers().await.contains_key(&0));
+    }
+
+    #[tokio::test]
+    async fn deleted_topics_are_pushed_with_leader_during_delete() {
+        let controller = Controller::new(0);
+        start_broker(&controller, 0).await;
+        start_broker(&controller, 1).await;
+        let created = create_assigned(&controller, "orders", &[&[0, 1], &[1, 0]]).await;
+        controller.take_pending_states().await;
+
+        let topic_id = controller.delete_topic("orders").await.unwrap();
+        assert_eq!(topic_id, created[0].topic_id);
+        assert_eq!(controller.topic_id("orders").await, None);
+        let pushed = controller.take_pending_states().await;
+        assert_eq!(pushed.len(), 2);
+        for state in pushed {
+            assert_eq!((state.leader, state.leader_epoch), (LEADER_DURING_DELETE, 1));
+            assert!(state.isr.is_empty());
+        }
+        assert!(matches!(controller.delete_topic("orders").await, Err(ControllerError::UnknownTopic(_))));
+    }
+
+    #[tokio::test]
+    async fn reassignments_complete_once_the_new_replicas_are_in_sync() {
+        let controller = Controller::new(0);
+        for broker_id in 0..4 {
+            start_broker(&controller, broker_id).await;
+        }
+        create_assigned(&controller, "orders", &[&[0, 1]]).await;
+
+        let started = controller.alter_partition_reassignment("orders", 0, Some(vec![2, 3])).await.unwrap();
+        assert_eq!(started.replicas, vec![2, 3, 0, 1]);
+        assert_eq!((started.adding_replicas.clone(), started.removing_replicas.clone()), (vec![2, 3], vec![0, 1]));
+        assert_eq!((started.leader, started.leader_epoch, started.isr.clone()), (0, 0, vec![0, 1]));
+        assert_eq!(controller.list_partition_reassignments().await.unwrap(), vec![started]);
+
+        // one new replica in sync isn't enough
+        let state = controller.alter_partition("orders", 0, 0, 0, vec![0, 1, 2]).await.unwrap();
+        assert!(state.is_reassigning());
+        let state = controller.alter_partition("orders", 0, 0, 0, vec![0, 1, 2, 3]).await.unwrap();
+        assert!(!state.is_reassigning());
+        assert_eq!((state.replicas, state.isr), (vec![2, 3], vec![2, 3]));
+        assert_eq!((state.leader, state.leader_epoch), (2, 1));
+        assert!(controller.list_partition_reassignments().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancelled_reassignments_go_back_to_the_original_replicas() {
+        let controller = Controller::new(0);
+        for broker_id in 0..3 {
+            start_broker(&controller, broker_id).await;
+        }
+        create_assigned(&controller, "orders", &[&[0, 1]]).await;
+        let cancelled = controller.alter_partition_reassignment("orders", 0, None).await;
+        assert!(matches!(cancelled, Err(ControllerError::NoReassignmentInProgress(_, 0))));
+
+        let started = controller.alter_partition_reassignment("orders", 0, Some(vec![1, 2])).await.unwrap();
+        assert_eq!((started.adding_replicas, started.removing_replicas), (vec![2], vec![0]));
+        let mut state = controller.alter_partition_reassignment("orders", 0, None).await.unwrap();
+        assert!(!state.is_reassigning());
+        state.replicas.sort_unstable();
+        assert_eq!((state.replicas, state.isr, state.leader), (vec![0, 1], vec![0, 1], 0));
+    }
+
+    #[tokio::test]
+    async fn reassignments_to_unknown_brokers_are_refused() {
+        let controller = Controller::new(0);
+        start_broker(&controller, 0).await;
+        start_broker(&controller, 1).await;
+        create_assigned(&controller, "orders", &[&[0, 1]]).await;
+
+        for target in [vec![0, 9], vec![0, 0], Vec::new()] {
+            let result = controller.alter_partition_reassignment("orders", 0, Some(target)).await;
+            assert!(matches!(result, Err(ControllerError::InvalidReplicaAssignment(_, 0, _))));
+        }
+        let result = controller.alter_partition_reassignment("orders", 1, Some(vec![0])).await;
+        assert!(matches!(result, Err(ControllerError::UnknownTopicOrPartition(_, 1))));
+        assert!(!controller.partition_state("orders", 0).await.unwrap().is_reassigning());
+    }
+
+    #[tokio::test]
+    async fn preferred_elections_need_the_preferred_replica_in_sync() {
+        let controller = Controller::new(0);
+        let broker_epoch = start_broker(&controller, 0).await;
+        start_broker(&controller, 1).await;
+        create_assigned(&controller, "orders", &[&[0, 1]]).await;
+        let partition = || Some(vec![("orders".to_string(), 0)]);
+
+        let results = controller.elect_leaders(ElectionType::Preferred, partition()).await.unwrap();
+        assert!(matches!(results[0].result, Err(ControllerError::ElectionNotNeeded(_, 0))));
+
+        // back from a failure, broker 0 is live but still out of the ISR
+        controller.handle_broker_failure(0).await.unwrap();
+        controller.broker_heartbeat(0, broker_epoch, false, false).await.unwrap();
+        let results = controller.elect_leaders(ElectionType::Preferred, partition()).await.unwrap();
+        assert!(matches!(results[0].result, Err(ControllerError::PreferredLeaderNotAvailable(_, 0))));
+        assert_eq!(controller.partition_state("orders", 0).await.unwrap().leader, 1);
+
+        controller.alter_partition("orders", 0, 1, 1, vec![1, 0]).await.unwrap();
+        let results = controller.elect_leaders(ElectionType::Preferred, partition()).await.unwrap();
+        assert_eq!(results[0].result.as_ref().ok(), Some(&0));
+        let state = controller.partition_state("orders", 0).await.unwrap();
+        assert_eq!((state.leader, state.leader_epoch), (0, 2));
+        // partitions that didn't need an election are left out when none were named
+        assert!(controller.elect_leaders(ElectionType::Preferred, None).await.unwrap().is_empty());
+    }
+
+    // broker 0 is the preferred replica of `on_time` + 1 partitions, all in sync,
+    // and leads all of them but one
+    async fn one_partition_off_its_preferred_replica(on_time: usize) -> Controller {
+        let controller = Controller::new(0);
+        let broker_epoch = start_broker(&controller, 0).await;
+        start_broker(&controller, 1).await;
+
+        // created while broker 0 is fenced, so broker 1 leads it
+        controller.broker_heartbeat(0, broker_epoch, true, false).await.unwrap();
+        create_assigned(&controller, "late", &[&[0, 1]]).await;
+        controller.broker_heartbeat(0, broker_epoch, false, false).await.unwrap();
+        controller.alter_partition("late", 0, 1, 0, vec![1, 0]).await.unwrap();
+
+        create_assigned(&controller, "orders", &vec![&[0, 1][..]; on_time]).await;
+        controller
+    }
+
+    #[tokio::test]
+    async fn leaders_rebalance_past_the_imbalance_threshold() {
+        // 1 out of 10 is exactly the 10% allowed
+        let controller = one_partition_off_its_preferred_replica(9).await;
+        assert!(controller.rebalance_leaders().await.unwrap().is_empty());
+        assert_eq!(controller.partition_state("late", 0).await.unwrap().leader, 1);
+
+        // 1 out of 9 is over it
+        let controller = one_partition_off_its_preferred_replica(8).await;
+        let moved = controller.rebalance_leaders().await.unwrap();
+        assert_eq!(moved.len(), 1);
+        assert_eq!((moved[0].topic.as_str(), moved[0].leader, moved[0].leader_epoch), ("late", 0, 1));
+        assert!(controller.rebalance_leaders().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_fence_the_broker_and_move_its_leadership() {
+        let controller = Controller::new(0);
+        let broker_epoch = start_broker(&controller, 0).await;
+        start_broker(&controller, 1).await;
+        create_assigned(&controller, "orders", &[&[0, 1], &[1, 0]]).await;
+
+        expire_session(&controller, 0).await;
+        assert_eq!(controller.fence_expired_brokers().await.unwrap(), vec![0]);
+        let led = controller.partition_state("orders", 0).await.unwrap();
+        assert_eq!((led.leader, led.leader_epoch, led.isr), (1, 1, vec![1]));
+        let followed = controller.partition_state("orders", 1).await.unwrap();
+        assert_eq!((followed.leader, followed.leader_epoch, followed.isr), (1, 0, vec![1]));
+
+        // the broker is fenced until it heartbeats again
+        assert!(controller.fence_expired_brokers().await.unwrap().is_empty());
+        let result = controller.broker_heartbeat(0, broker_epoch, false, false).await.unwrap();
+        assert!(!result.is_fenced);
+        assert!(controller.live_brokers().await.contains_key(&0));
+    }
+
+    #[tokio::test]
+    async fn re_registration_under_a_new_incarnation() {
+        let controller = Controller::new(0);
+        let first = registration(0);
+        let broker_epoch = controller.register_broker(first.clone()).await.unwrap();
+        controller.broker_heartbeat(0, broker_epoch, false, false).await.unwrap();
+        start_broker(&controller, 1).await;
+        create_assigned(&controller, "orders", &[&[0, 1]]).await;
+
+        // a retry of the same registration keeps the epoch
+        assert_eq!(controller.register_broker(first).await.unwrap(), broker_epoch);
+        // another process under the same id while the session is alive is refused
+        let duplicate = controller.register_broker(registration(0)).await;
+        assert!(matches!(duplicate, Err(ControllerError::DuplicateBrokerRegistration(0))));
+
+        // a restart the controller didn't notice fences the old incarnation first
+        expire_session(&controller, 0).await;
+        let new_epoch = controller.register_broker(registration(0)).await.unwrap();
+        assert!(new_epoch > broker_epoch);
+        let state = controller.partition_state("orders", 0).await.unwrap();
+        assert_eq!((state.leader, state.leader_epoch, state.isr), (1, 1, vec![1]));
+        assert!(!controller.live_brokers().await.contains_key(&0));
+
+        let stale = controller.broker_heartbeat(0, broker_epoch, false, false).await;
+        assert!(matches!(stale, Err(ControllerError::StaleBrokerEpoch(0, _))));
+        assert!(!controller.broker_heartbeat(0, new_epoch, false, false).await.unwrap().is_fenced);
+        assert!(controller.live_brokers().await.contains_key(&0));
+    }
+
+    #[tokio::test]
+    async fn replication_factors_beyond_the_live_brokers_are_refused() {
+        let controller = Controller::new(0);
+        start_broker(&controller, 0).await;
+        start_broker(&controller, 1).await;
+        let topic = |replication_factor| NewTopic {
+            name: "orders".to_string(),
+            num_partitions: 2,
+            replication_factor,
+            ..NewTopic::default()
+        };
+
+        let error = controller.create_topic(&topic(3), false).await.unwrap_err();
+        assert_eq!(error.error_code(), KafkaErrorCode::InvalidReplicationFactor);
+        assert_eq!(controller.topic_id("orders").await, None);
+
+        // fenced brokers don't count
+        let (_, created) = controller.create_topic(&topic(2), false).await.unwrap();
+        assert!(created.iter().all(|state| state.replicas.len() == 2));
+        controller.handle_broker_failure(1).await.unwrap();
+        let error = controller.create_partitions("orders", 3, None, false).await.unwrap_err();
+        assert_eq!(error.error_code(), KafkaErrorCode::InvalidReplicationFactor);
+    }
+
+    fn new_topic(name: &str, num_partitions: i32, replication_factor: i32) -> NewTopic {
+        NewTopic {
+            name: name.to_string(),
+            num_partitions,
+            replication_factor,
+            assignments: BTreeMap::new(),
+            configs: BTreeMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn created_topics_are_spread_over_the_live_brokers() {
+        let controller = Controller::new(0);
+        for broker_id in 0..3 {
+            start_broker(&controller, broker_id).await;
+        }
+        let mut orders = new_topic("orders", 4, 2);
+        orders.configs.insert("retention.ms".to_string(), "60000".to_string());
+
+        let (topic_id, created) = controller.create_topic(&orders, false).await.unwrap();
+        assert_eq!(controller.topic_id("orders").await, Some(topic_id));
+        assert_eq!(controller.topic_configs("orders").await, Some(orders.configs.clone()));
+        assert_eq!(created.len(), 4);
+        for state in &created {
+            assert_eq!(state.replicas.len(), 2);
+            assert_ne!(state.replicas[0], state.replicas[1]);
+            assert_eq!((state.leader, state.isr.clone()), (state.replicas[0], state.replicas.clone()));
+        }
+        assert_eq!(controller.take_pending_states().await.len(), 4);
+
+        // validation alone doesn't create anything
+        let (topic_id, validated) = controller.create_topic(&new_topic("payments", -1, -1), true).await.unwrap();
+        assert_eq!((topic_id, validated.len()), (Uuid::nil(), DEFAULT_NUM_PARTITIONS as usize));
+        assert_eq!(controller.topic_id("payments").await, None);
+        assert_eq!(controller.partition_states().await.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn invalid_topics_are_refused() {
+        let controller = Controller::new(0);
+        start_broker(&controller, 0).await;
+        start_broker(&controller, 1).await;
+        create_assigned(&controller, "orders", &[&[0, 1]]).await;
+
+        let mut bad_config = new_topic("payments", 1, 1);
+        bad_config.configs.insert("retention.ms".to_string(), "soon".to_string());
+        let mut sized_assignment = new_topic("payments", 1, 1);
+        sized_assignment.assignments.insert(0, vec![0]);
+        for (topic, error_code) in [
+            (new_topic("orders", 1, 1), KafkaErrorCode::TopicAlreadyExists),
+            (new_topic("bad name", 1, 1), KafkaErrorCode::InvalidTopicException),
+            (bad_config, KafkaErrorCode::InvalidConfig),
+            (new_topic("payments", 0, 1), KafkaErrorCode::InvalidPartitions),
+            (new_topic("payments", 1, 3), KafkaErrorCode::InvalidReplicationFactor),
+            (sized_assignment, KafkaErrorCode::InvalidRequest),
+        ] {
+            let error = controller.create_topic(&topic, false).await.unwrap_err();
+            assert_eq!(error.error_code(), error_code, "{}", error);
+        }
+        assert_eq!(controller.topic_id("payments").await, None);
+    }
+
+    #[tokio::test]
+    async fn deleted_topics_can_be_created_again_under_a_new_id() {
+        let controller = Controller::new(0);
+        start_broker(&controller, 0).await;
+        let (topic_id, _) = controller.create_topic(&new_topic("orders", 2, 1), false).await.unwrap();
+
+        assert_eq!(controller.delete_topic("orders").await.unwrap(), topic_id);
+        assert_eq!(controller.topic_name(topic_id).await, None);
+        assert_eq!(controller.topic_configs("orders").await, None);
+
+        let (recreated, _) = controller.create_topic(&new_topic("orders", 1, 1), false).await.unwrap();
+        assert_ne!(recreated, topic_id);
+        assert_eq!(controller.topic_name(recreated).await.as_deref(), Some("orders"));
+        assert!(controller.partition_state("orders", 1).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn partitions_can_be_added_but_never_removed() {
+        let controller = Controller::new(0);
+        start_broker(&controller, 0).await;
+        start_broker(&controller, 1).await;
+        let original = create_assigned(&controller, "orders", &[&[0, 1]]).await;
+        controller.take_pending_states().await;
+
+        let added = controller.create_partitions("orders", 3, None, false).await.unwrap();
+        assert_eq!(added.iter().map(|state| state.partition).collect::<Vec<_>>(), vec![1, 2]);
+        for state in &added {
+            assert_eq!(state.topic_id, original[0].topic_id);
+            assert_eq!(state.replicas.len(), 2);
+            assert_eq!((state.leader, state.leader_epoch), (state.replicas[0], 0));
+        }
+        assert_eq!(controller.take_pending_states().await, added);
+
+        // the new partitions may be placed by hand, as many as are added
+        let placed = controller.create_partitions("orders", 4, Some(vec![vec![1, 0]]), false).await.unwrap();
+        assert_eq!(placed[0].replicas, vec![1, 0]);
+        let error = controller.create_partitions("orders", 6, Some(vec![vec![1, 0]]), false).await.unwrap_err();
+        assert_eq!(error.error_code(), KafkaErrorCode::InvalidRequest);
+
+        for count in [4, 2, 0] {
+            let error = controller.create_partitions("orders", count, None, false).await.unwrap_err();
+            assert_eq!(error.error_code(), KafkaErrorCode::InvalidPartitions, "{}", error);
+        }
+        let validated = controller.create_partitions("orders", 5, None, true).await.unwrap();
+        assert_eq!(validated.len(), 1);
+        assert!(controller.partition_state("orders", 4).await.is_none());
+        assert!(controller.partition_state("orders", 3).await.is_some());
+
+        let error = controller.create_partitions("payments", 2, None, false).await.unwrap_err();
+        assert_eq!(error.error_code(), KafkaErrorCode::UnknownTopicOrPartition);
+    }
+}
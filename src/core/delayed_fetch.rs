@@ -0,0 +1,93 @@
+use crate::constants::READ_COMMITTED;
+use crate::core::consumer_group::TopicPartition;
+use crate::core::purgatory::DelayedOperation;
+use crate::core::replica_selector::ClientMetadata;
+use crate::core::replication::ReplicaManager;
+use crate::error::KafkaErrorCode;
+use crate::storage::log::AbortedTxn;
+
+/// how far into a partition's log a fetch may read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchIsolation {
+    LogEnd,        // replicas
+    HighWatermark, // read_uncommitted consumers
+    TxnCommitted,  // read_committed consumers, up to the last stable offset
+}
+
+// request-wide settings of a Fetch
+#[derive(Debug, Clone)]
+pub struct FetchParams {
+    pub replica_id: i32, // -1 for consumers
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub max_bytes: i32,
+    pub isolation_level: i8,
+    pub client_metadata: Option<ClientMetadata>, // consumers only
+}
+
+impl FetchParams {
+    pub fn is_from_follower(&self) -> bool {
+        self.replica_id >= 0
+    }
+
+    pub fn isolation(&self) -> FetchIsolation {
+        if self.is_from_follower() {
+            FetchIsolation::LogEnd
+        } else if self.isolation_level == READ_COMMITTED {
+            FetchIsolation::TxnCommitted
+        } else {
+            FetchIsolation::HighWatermark
+        }
+    }
+}
+
+// one partition of a Fetch
+#[derive(Debug, Clone)]
+pub struct FetchPartitionStatus {
+    pub tp: TopicPartition,
+    pub fetch_offset: i64,
+    pub current_leader_epoch: i32, // -1 when the fetcher doesn't know it
+    pub max_bytes: i32,
+}
+
+// what a fetch read from one partition
+#[derive(Debug)]
+pub struct FetchPartitionData {
+    pub tp: TopicPartition,
+    pub error: KafkaErrorCode,
+    pub high_watermark: i64,
+    pub last_stable_offset: i64,
+    pub log_start_offset: i64,
+    pub aborted_transactions: Option<Vec<AbortedTxn>>, // read_committed fetches only
+    pub preferred_read_replica: Option<i32>, // a replica the consumer should read from instead
+    pub records: Vec<u8>,
+}
+
+// a fetch that didn't find min_bytes yet; re-reads whenever one of its partitions
+// got appended to or moved its high watermark
+pub struct DelayedFetch<'a> {
+    replica_manager: &'a ReplicaManager,
+    params: FetchParams,
+    partitions: Vec<FetchPartitionStatus>,
+}
+
+impl<'a> DelayedFetch<'a> {
+    pub fn new(replica_manager: &'a ReplicaManager, params: FetchParams, partitions: Vec<FetchPartitionStatus>) -> Self {
+        DelayedFetch { replica_manager, params, partitions }
+    }
+}
+
+impl DelayedOperation for DelayedFetch<'_> {
+    type Output = Vec<FetchPartitionData>;
+
+    async fn try_complete(&self) -> Option<Self::Output> {
+        let data = self.replica_manager.read_from_local_log(&self.params, &self.partitions).await;
+        let bytes: usize = data.iter().map(|partition| partition.records.len()).sum();
+        let failed = data.iter().any(|partition| partition.error != KafkaErrorCode::None);
+        (failed || bytes >= self.params.min_bytes.max(0) as usize).then_some(data)
+    }
+
+    async fn on_expiration(&self) -> Self::Output {
+        self.replica_manager.read_from_local_log(&self.params, &self.partitions).await
+    }
+}
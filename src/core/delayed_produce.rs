@@ -0,0 +1,63 @@
+use crate::core::consumer_group::TopicPartition;
+use crate::core::purgatory::DelayedOperation;
+use crate::core::replication::ReplicaManager;
+use crate::error::KafkaErrorCode;
+
+// outcome of producing to one partition
+#[derive(Debug, Clone)]
+pub struct ProducePartitionResult {
+    pub tp: TopicPartition,
+    pub error: KafkaErrorCode,
+    pub base_offset: i64,
+    pub log_start_offset: i64,
+    pub required_offset: i64, // the HW must reach this before an acks=all produce is done
+}
+
+// an acks=all produce waiting for the ISR to replicate what was appended
+pub struct DelayedProduce<'a> {
+    replica_manager: &'a ReplicaManager,
+    results: Vec<ProducePartitionResult>,
+}
+
+impl<'a> DelayedProduce<'a> {
+    pub fn new(replica_manager: &'a ReplicaManager, results: Vec<ProducePartitionResult>) -> Self {
+        DelayedProduce { replica_manager, results }
+    }
+
+    // None while the partition is still waiting on replication
+    async fn partition_error(&self, result: &ProducePartitionResult) -> Option<KafkaErrorCode> {
+        if result.error != KafkaErrorCode::None {
+            return Some(result.error);
+        }
+        let (topic, partition) = (result.tp.topic(), result.tp.partition());
+        if !self.replica_manager.is_leader(topic, partition).await {
+            return Some(KafkaErrorCode::NotLeaderOrFollower);
+        }
+        match self.replica_manager.high_watermark(topic, partition).await {
+            Some(high_watermark) if high_watermark >= result.required_offset => Some(KafkaErrorCode::None),
+            _ => None,
+        }
+    }
+}
+
+impl DelayedOperation for DelayedProduce<'_> {
+    type Output = Vec<ProducePartitionResult>;
+
+    async fn try_complete(&self) -> Option<Self::Output> {
+        let mut completed = Vec::with_capacity(self.results.len());
+        for result in &self.results {
+            let error = self.partition_error(result).await?;
+            completed.push(ProducePartitionResult { error, ..result.clone() });
+        }
+        Some(completed)
+    }
+
+    async fn on_expiration(&self) -> Self::Output {
+        let mut expired = Vec::with_capacity(self.results.len());
+        for result in &self.results {
+            let error = self.partition_error(result).await.unwrap_or(KafkaErrorCode::RequestTimedOut);
+            expired.push(ProducePartitionResult { error, ..result.clone() });
+        }
+        expired
+    }
+}
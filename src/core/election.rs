@@ -0,0 +1,67 @@
+use std::collections::BTreeSet;
+
+// who leads a partition after an election, and the ISR that goes with it
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeaderElection {
+    pub leader: i32,
+    pub isr: Vec<i32>,
+    pub unclean: bool, // the leader wasn't in sync, records may have been lost
+}
+
+/// elects a leader for a partition whose leader went away: the first live ISR member
+/// in assignment order. With unclean election enabled and no live ISR member left,
+/// the first live replica of any kind takes over with itself as the only ISR member.
+pub fn elect_leader(
+    assignment: &[i32],
+    isr: &[i32],
+    live_brokers: &BTreeSet<i32>,
+    unclean_enabled: bool,
+) -> Option<LeaderElection> {
+    let live_isr: Vec<i32> = isr.iter().copied().filter(|replica| live_brokers.contains(replica)).collect();
+
+    if let Some(leader) = assignment.iter().copied().find(|replica| live_isr.contains(replica)) {
+        return Some(LeaderElection { leader, isr: live_isr, unclean: false });
+    }
+    if !unclean_enabled {
+        return None;
+    }
+    assignment
+        .iter()
+        .copied()
+        .find(|replica| live_brokers.contains(replica))
+        .map(|leader| LeaderElection { leader, isr: vec![leader], unclean: true })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn live(brokers: &[i32]) -> BTreeSet<i32> {
+        brokers.iter().copied().collect()
+    }
+
+    #[test]
+    fn the_first_live_isr_member_in_assignment_order_leads() {
+        // ISR order doesn't matter, the assignment does
+        let election = elect_leader(&[3, 1, 2], &[2, 1, 3], &live(&[1, 2]), false).unwrap();
+        assert_eq!(election, LeaderElection { leader: 1, isr: vec![2, 1], unclean: false });
+
+        let election = elect_leader(&[3, 1, 2], &[2, 1, 3], &live(&[1, 2, 3]), true).unwrap();
+        assert_eq!((election.leader, election.unclean), (3, false));
+    }
+
+    #[test]
+    fn no_leader_without_a_live_isr_member_unless_unclean() {
+        assert_eq!(elect_leader(&[1, 2, 3], &[], &live(&[1, 2, 3]), false), None);
+        assert_eq!(elect_leader(&[1, 2, 3], &[1], &live(&[2, 3]), false), None);
+    }
+
+    #[test]
+    fn unclean_election_falls_back_to_a_live_out_of_sync_replica() {
+        let election = elect_leader(&[1, 2, 3], &[1], &live(&[3, 2]), true).unwrap();
+        assert_eq!(election, LeaderElection { leader: 2, isr: vec![2], unclean: true });
+
+        // brokers that aren't replicas never lead
+        assert_eq!(elect_leader(&[1, 2], &[1], &live(&[4]), true), None);
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use chrono::Utc;
+use tokio::sync::RwLock;
+
+use crate::core::assignor::server_assignor_by_name;
+use crate::core::consumer_group::{
+    ConsumerGroup, GroupError, GroupState, MemberAssignment, MemberHeartbeat, OffsetAndMetadata, TopicPartition,
+};
+
+pub const CONSUMER_PROTOCOL_TYPE: &str = "consumer";
+
+// owns every consumer group hosted by this broker
+#[derive(Debug, Default)]
+pub struct GroupCoordinator {
+    groups: RwLock<HashMap<String, ConsumerGroup>>,
+    // TxnOffsetCommit offsets by group and producer, held back until the transaction ends
+    pending_transactional_offsets: RwLock<HashMap<(String, i64), HashMap<TopicPartition, OffsetAndMetadata>>>,
+}
+
+// snapshot of a group for DescribeGroups
+#[derive(Debug)]
+pub struct GroupDescription {
+    pub group_id: String,
+    pub state: &'static str,
+    pub protocol_type: String,
+    pub protocol: String,
+    pub members: Vec<MemberDescription>,
+}
+
+#[derive(Debug)]
+pub struct MemberDescription {
+    pub member_id: String,
+    pub instance_id: Option<String>,
+    pub client_id: String,
+    pub client_host: String,
+    pub subscription: Vec<String>,
+    pub assignment: Vec<TopicPartition>,
+}
+
+// where an admin reset moves a group's committed offsets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OffsetResetStrategy {
+    Earliest,
+    Latest,
+    Timestamp(i64), // first offset at or after this time, latest if there is none
+    Offset(i64),    // clamped to the partition's log range
+}
+
+#[derive(Debug)]
+pub struct GroupListing {
+    pub group_id: String,
+    pub protocol_type: String,
+    pub state: &'static str,
+}
+
+impl GroupCoordinator {
+    pub fn new() -> Self {
+        GroupCoordinator {
+            groups: RwLock::new(HashMap::new()),
+            pending_transactional_offsets: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// drops members whose session ran out in every group. a member otherwise only
+    /// expires when someone else in its group heartbeats, so a group whose consumers
+    /// all crashed would never become empty.
+    pub async fn expire_sessions(&self) {
+        let now = SystemTime::now();
+        for group in self.groups.write().await.values_mut() {
+            group.expire_sessions(now);
+        }
+    }
+
+    pub async fn consumer_group_heartbeat(
+        &self,
+        group_id: &str,
+        request: MemberHeartbeat,
+        partitions_per_topic: &HashMap<String, i32>,
+    ) -> Result<MemberAssignment, GroupError> {
+        if group_id.is_empty() {
+            return Err(GroupError::InvalidRequest("group id can't be empty"));
+        }
+        if let Some(name) = &request.server_assignor {
+            if server_assignor_by_name(name).is_none() {
+                return Err(GroupError::UnsupportedAssignor(name.clone()));
+            }
+        }
+
+        let mut groups = self.groups.write().await;
+        // only a joining member may create the group
+        let group = if request.member_epoch == 0 {
+            groups
+                .entry(group_id.to_string())
+                .or_insert_with(|| ConsumerGroup::new(group_id.to_string(), CONSUMER_PROTOCOL_TYPE.to_string()))
+        } else {
+            groups
+                .get_mut(group_id)
+                .ok_or_else(|| GroupError::GroupIdNotFound(group_id.to_string()))?
+        };
+
+        group.consumer_heartbeat(request, partitions_per_topic)
+    }
+
+    /// stores committed offsets, creating an empty group for standalone consumers
+    pub async fn commit_offsets(&self, group_id: &str, offsets: Vec<(TopicPartition, OffsetAndMetadata)>) -> Result<(), GroupError> {
+        if group_id.is_empty() {
+            return Err(GroupError::InvalidGroupId);
+        }
+        let mut groups = self.groups.write().await;
+        let group = groups
+            .entry(group_id.to_string())
+            .or_insert_with(|| ConsumerGroup::new(group_id.to_string(), CONSUMER_PROTOCOL_TYPE.to_string()));
+        for (tp, offset) in offsets {
+            group.commit_offset(tp, offset);
+        }
+        Ok(())
+    }
+
+    /// TxnOffsetCommit: offsets a transactional producer commits, visible once its
+    /// transaction commits
+    pub async fn commit_transactional_offsets(
+        &self,
+        group_id: &str,
+        producer_id: i64,
+        offsets: Vec<(TopicPartition, OffsetAndMetadata)>,
+    ) -> Result<(), GroupError> {
+        if group_id.is_empty() {
+            return Err(GroupError::InvalidGroupId);
+        }
+        let mut pending = self.pending_transactional_offsets.write().await;
+        pending.entry((group_id.to_string(), producer_id)).or_default().extend(offsets);
+        Ok(())
+    }
+
+    /// applies or drops a producer's transactional offsets once its COMMIT or ABORT is written
+    pub async fn complete_transactional_offsets(&self, group_ids: &[String], producer_id: i64, commit: bool) {
+        let mut pending = self.pending_transactional_offsets.write().await;
+        let mut groups = self.groups.write().await;
+        for group_id in group_ids {
+            let Some(offsets) = pending.remove(&(group_id.clone(), producer_id)) else {
+                continue;
+            };
+            if !commit {
+                continue;
+            }
+            let group = groups
+                .entry(group_id.clone())
+                .or_insert_with(|| ConsumerGroup::new(group_id.clone(), CONSUMER_PROTOCOL_TYPE.to_string()));
+            for (tp, offset) in offsets {
+                group.commit_offset(tp, offset);
+            }
+        }
+    }
+
+    /// OffsetDelete: removes committed offsets, unless a live member still consumes the topic
+    pub async fn delete_offsets(
+        &self,
+        group_id: &str,
+        partitions: Vec<TopicPartition>,
+    ) -> Result<Vec<(TopicPartition, Result<(), GroupError>)>, GroupError> {
+        if group_id.is_empty() {
+            return Err(GroupError::InvalidGroupId);
+        }
+        let mut groups = self.groups.write().await;
+        let group = groups
+            .get_mut(group_id)
+            .ok_or_else(|| GroupError::GroupIdNotFound(group_id.to_string()))?;
+        group.expire_sessions(SystemTime::now());
+
+        Ok(partitions
+            .into_iter()
+            .map(|tp| {
+                let result = if group.is_subscribed_to(tp.topic()) {
+                    Err(GroupError::GroupSubscribedToTopic(group_id.to_string(), tp.topic().to_string()))
+                } else {
+                    group.delete_offset(&tp);
+                    Ok(())
+                };
+                (tp, result)
+            })
+            .collect())
+    }
+
+    /// drops every offset committed, or pending in a transaction, for partitions of a
+    /// deleted topic
+    pub async fn on_partitions_deleted(&self, partitions: &[TopicPartition]) {
+        let mut pending = self.pending_transactional_offsets.write().await;
+        for offsets in pending.values_mut() {
+            offsets.retain(|tp, _| !partitions.contains(tp));
+        }
+        let mut groups = self.groups.write().await;
+        for group in groups.values_mut() {
+            for tp in partitions {
+                group.delete_offset(tp);
+            }
+        }
+    }
+
+    /// commits already-resolved reset offsets, only while the group has no members
+    pub async fn reset_offsets(&self, group_id: &str, offsets: Vec<(TopicPartition, i64)>) -> Result<(), GroupError> {
+        if group_id.is_empty() {
+            return Err(GroupError::InvalidGroupId);
+        }
+        let mut groups = self.groups.write().await;
+        let group = groups
+            .entry(group_id.to_string())
+            .or_insert_with(|| ConsumerGroup::new(group_id.to_string(), CONSUMER_PROTOCOL_TYPE.to_string()));
+        group.expire_sessions(SystemTime::now());
+        if !group.is_empty() {
+            return Err(GroupError::NonEmptyGroup(group_id.to_string()));
+        }
+
+        let now = Utc::now().timestamp_millis();
+        for (tp, offset) in offsets {
+            println!("Resetting offset of group {} for {}-{} to {}", group_id, tp.topic(), tp.partition(), offset);
+            group.commit_offset(
+                tp,
+                OffsetAndMetadata {
+                    offset,
+                    leader_epoch: -1,
+                    metadata: String::new(),
+                    commit_timestamp: now,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn is_group_empty(&self, group_id: &str) -> bool {
+        let mut groups = self.groups.write().await;
+        groups.get_mut(group_id).is_none_or(|group| {
+            group.expire_sessions(SystemTime::now());
+            group.is_empty()
+        })
+    }
+
+    pub async fn committed_offsets(&self, group_id: &str) -> Option<HashMap<TopicPartition, OffsetAndMetadata>> {
+        let groups = self.groups.read().await;
+        groups.get(group_id).map(|group| group.offsets().clone())
+    }
+
+    /// unknown groups are reported as Dead, like Kafka does
+    pub async fn describe_group(&self, group_id: &str) -> GroupDescription {
+        let mut groups = self.groups.write().await;
+        let Some(group) = groups.get_mut(group_id) else {
+            return GroupDescription {
+                group_id: group_id.to_string(),
+                state: GroupState::Dead.as_str(),
+                protocol_type: String::new(),
+                protocol: String::new(),
+                members: Vec::new(),
+            };
+        };
+        group.expire_sessions(SystemTime::now());
+
+        let mut members: Vec<MemberDescription> = group
+            .members()
+            .map(|member| MemberDescription {
+                member_id: member.member_id().to_string(),
+                instance_id: member.instance_id().map(str::to_string),
+                client_id: member.client_id().to_string(),
+                client_host: member.client_host().to_string(),
+                subscription: member.subscription().to_vec(),
+                assignment: group.member_assignment(member.member_id()),
+            })
+            .collect();
+        members.sort_by(|a, b| a.member_id.cmp(&b.member_id));
+
+        GroupDescription {
+            group_id: group.group_id().to_string(),
+            state: group.state().as_str(),
+            protocol_type: group.protocol_type().to_string(),
+            protocol: group.protocol_name(),
+            members,
+        }
+    }
+
+    /// lists groups, optionally only those in one of `states_filter` (case-insensitive)
+    pub async fn list_groups(&self, states_filter: &[String]) -> Vec<GroupListing> {
+        self.expire_sessions().await;
+        let groups = self.groups.read().await;
+        let mut listings: Vec<GroupListing> = groups
+            .values()
+            .filter(|group| {
+                states_filter.is_empty()
+                    || states_filter.iter().any(|state| state.eq_ignore_ascii_case(group.state().as_str()))
+            })
+            .map(|group| GroupListing {
+                group_id: group.group_id().to_string(),
+                protocol_type: group.protocol_type().to_string(),
+                state: group.state().as_str(),
+            })
+            .collect();
+        listings.sort_by(|a, b| a.group_id.cmp(&b.group_id));
+        listings
+    }
+
+    /// deletes empty groups together with their committed offsets
+    pub async fn delete_groups(&self, group_ids: &[String]) -> Vec<(String, Result<(), GroupError>)> {
+        let mut groups = self.groups.write().await;
+        let now = SystemTime::now();
+        group_ids
+            .iter()
+            .map(|group_id| {
+                if let Some(group) = groups.get_mut(group_id) {
+                    group.expire_sessions(now);
+                }
+                let result = match groups.get_mut(group_id) {
+                    _ if group_id.is_empty() => Err(GroupError::InvalidGroupId),
+                    None => Err(GroupError::GroupIdNotFound(group_id.clone())),
+                    Some(group) if !group.is_empty() => Err(GroupError::NonEmptyGroup(group_id.clone())),
+                    Some(group) => {
+                        group.transition_to_dead();
+                        groups.remove(group_id);
+                        println!("Deleted group {} and its committed offsets", group_id);
+                        Ok(())
+                    }
+                };
+                (group_id.clone(), result)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::consumer_group::GroupMember;
+
+    fn offset(offset: i64) -> OffsetAndMetadata {
+        OffsetAndMetadata { offset, leader_epoch: -1, metadata: String::new(), commit_timestamp: 0 }
+    }
+
+    fn tp(topic: &str, partition: i32) -> TopicPartition {
+        TopicPartition::new(topic.to_string(), partition)
+    }
+
+    // a group with offsets on orders and payments and one member consuming orders
+    async fn consuming_orders() -> GroupCoordinator {
+        let coordinator = GroupCoordinator::new();
+        let offsets = vec![(tp("orders", 0), offset(5)), (tp("payments", 0), offset(7))];
+        coordinator.commit_offsets("billing", offsets).await.unwrap();
+        let join = MemberHeartbeat {
+            member_id: String::new(),
+            member_epoch: 0,
+            client_id: "client".to_string(),
+            client_host: "/127.0.0.1".to_string(),
+            instance_id: None,
+            rack_id: None,
+            rebalance_timeout_ms: 30_000,
+            subscribed_topic_names: Some(vec!["orders".to_string()]),
+            server_assignor: None,
+            owned_partitions: None,
+        };
+        let partitions_per_topic = HashMap::from([("orders".to_string(), 1), ("payments".to_string(), 1)]);
+        coordinator.consumer_group_heartbeat("billing", join, &partitions_per_topic).await.unwrap();
+        coordinator
+    }
+
+    #[tokio::test]
+    async fn offset_delete_refuses_topics_the_group_is_subscribed_to() {
+        let coordinator = consuming_orders().await;
+
+        let results = coordinator.delete_offsets("billing", vec![tp("orders", 0), tp("payments", 0)]).await.unwrap();
+
+        assert!(matches!(&results[0], (_, Err(GroupError::GroupSubscribedToTopic(_, topic))) if topic == "orders"));
+        assert!(results[1].1.is_ok());
+        let committed = coordinator.committed_offsets("billing").await.unwrap();
+        assert_eq!(committed.keys().collect::<Vec<_>>(), vec![&tp("orders", 0)]);
+    }
+
+    #[tokio::test]
+    async fn offset_delete_of_an_unknown_group_fails() {
+        let coordinator = GroupCoordinator::new();
+        let result = coordinator.delete_offsets("billing", vec![tp("orders", 0)]).await;
+        assert!(matches!(result, Err(GroupError::GroupIdNotFound(_))));
+        assert!(matches!(coordinator.delete_offsets("", Vec::new()).await, Err(GroupError::InvalidGroupId)));
+    }
+
+    #[tokio::test]
+    async fn reset_offsets_needs_an_empty_group() {
+        let coordinator = consuming_orders().await;
+        let result = coordinator.reset_offsets("billing", vec![(tp("payments", 0), 0)]).await;
+        assert!(matches!(result, Err(GroupError::NonEmptyGroup(_))));
+        assert_eq!(coordinator.committed_offsets("billing").await.unwrap()[&tp("payments", 0)].offset, 7);
+
+        let coordinator = GroupCoordinator::new();
+        coordinator.commit_offsets("billing", vec![(tp("orders", 0), offset(5))]).await.unwrap();
+        coordinator.reset_offsets("billing", vec![(tp("orders", 0), 2), (tp("orders", 1), 0)]).await.unwrap();
+        let committed = coordinator.committed_offsets("billing").await.unwrap();
+        assert_eq!((committed[&tp("orders", 0)].offset, committed[&tp("orders", 1)].offset), (2, 0));
+    }
+
+    // a member with a zero session timeout, as if its consumer had crashed
+    async fn add_crashed_member(coordinator: &GroupCoordinator, group_id: &str) {
+        let member = GroupMember::new(
+            "crashed".to_string(),
+            "client".to_string(),
+            "/127.0.0.1".to_string(),
+            0,
+            30_000,
+            vec!["orders".to_string()],
+        );
+        coordinator.groups.write().await.get_mut(group_id).unwrap().add_member(member);
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+    }
+
+    #[tokio::test]
+    async fn admin_requests_expire_members_that_stopped_heartbeating() {
+        let coordinator = GroupCoordinator::new();
+        for group_id in ["billing", "audit", "shipping", "refunds"] {
+            coordinator.commit_offsets(group_id, vec![(tp("orders", 0), offset(5))]).await.unwrap();
+            add_crashed_member(&coordinator, group_id).await;
+        }
+
+        assert_eq!(coordinator.describe_group("billing").await.state, "Empty");
+        assert!(coordinator.describe_group("billing").await.members.is_empty());
+        assert!(coordinator.is_group_empty("audit").await);
+        let results = coordinator.delete_offsets("shipping", vec![tp("orders", 0)]).await.unwrap();
+        assert!(results[0].1.is_ok());
+        coordinator.reset_offsets("refunds", vec![(tp("orders", 0), 0)]).await.unwrap();
+
+        add_crashed_member(&coordinator, "audit").await;
+        assert!(coordinator.delete_groups(&["audit".to_string()]).await[0].1.is_ok());
+        add_crashed_member(&coordinator, "billing").await;
+        let listed = coordinator.list_groups(&["Empty".to_string()]).await;
+        assert_eq!(listed.iter().map(|group| group.group_id.as_str()).collect::<Vec<_>>(), vec!["billing", "refunds", "shipping"]);
+    }
+
+    #[tokio::test]
+    async fn the_session_sweep_empties_groups_whose_consumers_all_crashed() {
+        let coordinator = consuming_orders().await;
+        add_crashed_member(&coordinator, "billing").await;
+        coordinator.expire_sessions().await;
+        // the member that joined through a heartbeat is still within its session
+        let members: Vec<String> =
+            coordinator.groups.read().await["billing"].members().map(|member| member.member_id().to_string()).collect();
+        assert_eq!(members.len(), 1);
+        assert_ne!(members[0], "crashed");
+    }
+}
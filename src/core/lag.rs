@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PartitionLag {
+    pub topic: String,
+    pub partition: i32,
+    pub committed_offset: i64,
+    pub high_watermark: i64,
+    pub lag: i64,
+    pub time_lag_ms: i64, // age of the oldest message the group hasn't consumed yet
+}
+
+#[derive(Debug)]
+pub struct GroupLag {
+    pub group_id: String,
+    pub partitions: Vec<PartitionLag>,
+}
+
+impl GroupLag {
+    pub fn total_lag(&self) -> i64 {
+        self.partitions.iter().map(|p| p.lag).sum()
+    }
+
+    pub fn max_time_lag_ms(&self) -> i64 {
+        self.partitions.iter().map(|p| p.time_lag_ms).max().unwrap_or(0)
+    }
+
+    pub fn topic_lag(&self) -> BTreeMap<String, i64> {
+        let mut by_topic = BTreeMap::new();
+        for p in &self.partitions {
+            *by_topic.entry(p.topic.clone()).or_insert(0) += p.lag;
+        }
+        by_topic
+    }
+}
+
+/// lag of a group on one partition. `oldest_timestamp` is the time-index timestamp
+/// of the oldest message the group hasn't consumed, None if the log doesn't have it.
+pub fn partition_lag(
+    topic: &str,
+    partition: i32,
+    committed_offset: i64,
+    high_watermark: i64,
+    oldest_timestamp: Option<i64>,
+    now_ms: i64,
+) -> PartitionLag {
+    let lag = (high_watermark - committed_offset).max(0);
+    let time_lag_ms = match oldest_timestamp {
+        Some(timestamp) if lag > 0 => (now_ms - timestamp).max(0),
+        _ => 0,
+    };
+
+    PartitionLag {
+        topic: topic.to_string(),
+        partition,
+        committed_offset,
+        high_watermark,
+        lag,
+        time_lag_ms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lag_counts_messages_up_to_the_high_watermark() {
+        let lag = partition_lag("orders", 0, 40, 100, Some(5_000), 8_000);
+        assert_eq!((lag.lag, lag.time_lag_ms), (60, 3_000));
+
+        // caught up, or committed past what's visible yet
+        assert_eq!(partition_lag("orders", 0, 100, 100, Some(5_000), 8_000).time_lag_ms, 0);
+        assert_eq!(partition_lag("orders", 0, 120, 100, None, 8_000).lag, 0);
+        // a clock behind the message timestamps never gives a negative age
+        assert_eq!(partition_lag("orders", 0, 40, 100, Some(9_000), 8_000).time_lag_ms, 0);
+    }
+
+    #[test]
+    fn group_lag_adds_up_per_topic() {
+        let group = GroupLag {
+            group_id: "billing".to_string(),
+            partitions: vec![
+                partition_lag("orders", 0, 0, 10, Some(1_000), 4_000),
+                partition_lag("orders", 1, 5, 7, Some(3_000), 4_000),
+                partition_lag("payments", 0, 3, 3, None, 4_000),
+            ],
+        };
+        assert_eq!(group.total_lag(), 12);
+        assert_eq!(group.max_time_lag_ms(), 3_000);
+        assert_eq!(group.topic_lag(), BTreeMap::from([("orders".to_string(), 12), ("payments".to_string(), 0)]));
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::core::controller::PartitionState;
+
+pub const UNCLEAN_LEADER_ELECTION_ENABLE_CONFIG: &str = "unclean.leader.election.enable";
+pub const CLEANUP_POLICY_CONFIG: &str = "cleanup.policy";
+pub const RETENTION_MS_CONFIG: &str = "retention.ms";
+pub const MAX_MESSAGE_BYTES_CONFIG: &str = "max.message.bytes";
+pub const MIN_INSYNC_REPLICAS_CONFIG: &str = "min.insync.replicas";
+
+/// one change to cluster metadata, as written to the metadata log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MetadataRecord {
+    // a new registration replaces the broker's previous one
+    RegisterBroker(BrokerRegistration),
+    FenceBroker { broker_id: i32 },
+    UnfenceBroker { broker_id: i32 },
+    Topic { name: String, topic_id: Uuid },
+    // a deleted topic, its partitions and config overrides with it
+    RemoveTopic { name: String },
+    // full state of a partition, whether it's new or changed
+    Partition(PartitionState),
+    // a topic config override; None removes it
+    Config { topic: String, name: String, value: Option<String> },
+    // a block of producer ids handed to a broker; the next block starts at next_producer_id
+    ProducerIds { broker_id: i32, broker_epoch: i64, next_producer_id: i64 },
+    // written by every new quorum leader so earlier entries can commit
+    LeaderChange { leader_id: i32, epoch: i32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BrokerEndpoint {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub security_protocol: i16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BrokerRegistration {
+    pub broker_id: i32,
+    pub broker_epoch: i64, // assigned by the controller on every registration
+    pub incarnation_id: Uuid, // changes every time the broker process starts
+    pub listeners: Vec<BrokerEndpoint>,
+    pub rack: Option<String>,
+    pub log_dirs: Vec<Uuid>,
+    pub fenced: bool, // fenced brokers get no leadership and no LeaderAndIsr
+}
+
+impl BrokerRegistration {
+    /// host:port of the broker's first listener, the one other brokers connect to
+    pub fn address(&self) -> Option<String> {
+        self.listeners.first().map(|listener| format!("{}:{}", listener.host, listener.port))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopicImage {
+    pub topic_id: Uuid,
+    pub partitions: BTreeMap<i32, PartitionState>,
+}
+
+/// cluster metadata built by replaying records in log order
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetadataImage {
+    pub brokers: BTreeMap<i32, BrokerRegistration>,
+    pub topics: BTreeMap<String, TopicImage>,
+    pub configs: BTreeMap<String, BTreeMap<String, String>>, // topic -> overrides
+    #[serde(default)]
+    pub next_producer_id: i64, // first producer id no broker has been given yet
+}
+
+impl MetadataImage {
+    pub fn replay(&mut self, record: &MetadataRecord) {
+        match record {
+            MetadataRecord::RegisterBroker(registration) => {
+                self.brokers.insert(registration.broker_id, registration.clone());
+            }
+            MetadataRecord::FenceBroker { broker_id } => {
+                if let Some(broker) = self.brokers.get_mut(broker_id) {
+                    broker.fenced = true;
+                }
+            }
+            MetadataRecord::UnfenceBroker { broker_id } => {
+                if let Some(broker) = self.brokers.get_mut(broker_id) {
+                    broker.fenced = false;
+                }
+            }
+            MetadataRecord::Topic { name, topic_id } => {
+                self.topics.insert(name.clone(), TopicImage { topic_id: *topic_id, partitions: BTreeMap::new() });
+            }
+            MetadataRecord::RemoveTopic { name } => {
+                self.topics.remove(name);
+                self.configs.remove(name);
+            }
+            MetadataRecord::Partition(state) => {
+                if let Some(topic) = self.topics.get_mut(&state.topic) {
+                    topic.partitions.insert(state.partition, state.clone());
+                }
+            }
+            MetadataRecord::Config { topic, name, value } => {
+                let overrides = self.configs.entry(topic.clone()).or_default();
+                match value {
+                    Some(value) => {
+                        overrides.insert(name.clone(), value.clone());
+                    }
+                    None => {
+                        overrides.remove(name);
+                    }
+                }
+            }
+            MetadataRecord::ProducerIds { next_producer_id, .. } => {
+                self.next_producer_id = *next_producer_id;
+            }
+            MetadataRecord::LeaderChange { .. } => {}
+        }
+    }
+
+    /// registered brokers that aren't fenced, with their listener addresses
+    pub fn live_brokers(&self) -> BTreeMap<i32, String> {
+        self.brokers
+            .values()
+            .filter(|broker| !broker.fenced)
+            .filter_map(|broker| Some((broker.broker_id, broker.address()?)))
+            .collect()
+    }
+
+    pub fn live_broker_ids(&self) -> BTreeSet<i32> {
+        self.live_brokers().into_keys().collect()
+    }
+
+    pub fn topic_name(&self, topic_id: Uuid) -> Option<&str> {
+        self.topics.iter().find(|(_, topic)| topic.topic_id == topic_id).map(|(name, _)| name.as_str())
+    }
+
+    pub fn partition(&self, topic: &str, partition: i32) -> Option<&PartitionState> {
+        self.topics.get(topic)?.partitions.get(&partition)
+    }
+
+    pub fn partition_states(&self) -> Vec<PartitionState> {
+        self.topics.values().flat_map(|topic| topic.partitions.values().cloned()).collect()
+    }
+
+    pub fn topic_config(&self, topic: &str, name: &str) -> Option<&str> {
+        self.configs.get(topic)?.get(name).map(String::as_str)
+    }
+
+    pub fn unclean_leader_election_enable(&self, topic: &str) -> bool {
+        self.topic_config(topic, UNCLEAN_LEADER_ELECTION_ENABLE_CONFIG) == Some("true")
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+
+use tokio::sync::RwLock;
+
+// broker-wide gauges, rendered in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    gauges: RwLock<BTreeMap<String, i64>>, // "name{label=\"value\"}" -> value
+}
+
+fn series_key(name: &str, labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return name.to_string();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, value.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    format!("{}{{{}}}", name, labels.join(","))
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            gauges: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    pub async fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: i64) {
+        let mut gauges = self.gauges.write().await;
+        gauges.insert(series_key(name, labels), value);
+    }
+
+    pub async fn get_gauge(&self, name: &str, labels: &[(&str, &str)]) -> Option<i64> {
+        let gauges = self.gauges.read().await;
+        gauges.get(&series_key(name, labels)).copied()
+    }
+
+    /// sets every series of a gauge at once, dropping the ones not among them so values
+    /// for deleted groups/topics don't linger. Scrapes see either the old or the new set.
+    pub async fn replace_gauge(&self, name: &str, series: Vec<(Vec<(&str, &str)>, i64)>) {
+        let fresh: BTreeMap<String, i64> = series.iter().map(|(labels, value)| (series_key(name, labels), *value)).collect();
+        let mut gauges = self.gauges.write().await;
+        gauges.retain(|key, _| fresh.contains_key(key) || (key != name && !key.starts_with(&format!("{}{{", name))));
+        gauges.extend(fresh);
+    }
+
+    pub async fn render(&self) -> String {
+        let gauges = self.gauges.read().await;
+        let mut out = String::new();
+        let mut last_name = "";
+        for (key, value) in gauges.iter() {
+            let name = key.split('{').next().unwrap_or(key);
+            if name != last_name {
+                out.push_str(&format!("# TYPE {} gauge\n", name));
+                last_name = name;
+            }
+            out.push_str(&format!("{} {}\n", key, value));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn replacing_a_gauge_drops_only_its_stale_series() {
+        let metrics = Metrics::new();
+        metrics.set_gauge("rafka_consumer_group_lag", &[("group", "billing")], 5).await;
+        metrics.set_gauge("rafka_consumer_group_lag", &[("group", "audit")], 7).await;
+        metrics.set_gauge("rafka_consumer_group_lag_total", &[], 12).await;
+
+        metrics.replace_gauge("rafka_consumer_group_lag", vec![(vec![("group", "billing")], 3)]).await;
+        assert_eq!(metrics.get_gauge("rafka_consumer_group_lag", &[("group", "billing")]).await, Some(3));
+        assert_eq!(metrics.get_gauge("rafka_consumer_group_lag", &[("group", "audit")]).await, None);
+        // gauges that merely share the name's prefix are left alone
+        assert_eq!(metrics.get_gauge("rafka_consumer_group_lag_total", &[]).await, Some(12));
+    }
+}
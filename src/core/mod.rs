@@ -1,4 +1,20 @@
 pub mod topic;
+pub mod topic_manager;
 pub mod partition;
 pub mod consumer_group;
-pub mod replication; 
\ No newline at end of file
+pub mod replication;
+pub mod assignor;
+pub mod group_coordinator;
+pub mod transaction_coordinator;
+pub mod broker;
+pub mod lag;
+pub mod metrics;
+pub mod purgatory;
+pub mod delayed_produce;
+pub mod delayed_fetch;
+pub mod replica_selector;
+pub mod election;
+pub mod placement;
+pub mod producer_id;
+pub mod controller;
+pub mod metadata;
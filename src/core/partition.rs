@@ -9,6 +9,8 @@ pub struct Partition {
     replicas: RwLock<Vec<i32>>,     
     isr: RwLock<Vec<i32>>,          
     leader: RwLock<Option<i32>>,
+    leader_epoch: RwLock<i32>, // as last assigned by the controller, -1 before that
+    last_stable_offset: RwLock<i64>, // as of the last change to the local replica, -1 before that
 }
 
 #[derive(Debug)]
@@ -88,6 +90,12 @@ impl PartitionLog {
 
 }
 
+impl Default for PartitionLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Partition {
     pub fn new(id: i32) -> Self {
         Partition {
@@ -96,6 +104,8 @@ impl Partition {
             replicas: RwLock::new(Vec::new()),
             isr: RwLock::new(Vec::new()),
             leader: RwLock::new(None),
+            leader_epoch: RwLock::new(-1),
+            last_stable_offset: RwLock::new(-1),
         }
     }
 
@@ -152,6 +162,45 @@ impl Partition {
         *leader = Some(broker_id);
     }
 
+    // no replica can take over, the partition is offline
+    pub async fn clear_leader(&self) {
+        let mut leader = self.leader.write().await;
+        *leader = None;
+    }
+
+    pub async fn leader(&self) -> Option<i32> {
+        *self.leader.read().await
+    }
+
+    pub async fn leader_epoch(&self) -> i32 {
+        *self.leader_epoch.read().await
+    }
+
+    pub async fn set_leader_epoch(&self, leader_epoch: i32) {
+        let mut current = self.leader_epoch.write().await;
+        *current = leader_epoch;
+    }
+
+    /// offset up to which read_committed consumers can read: below it every
+    /// transaction has been committed or aborted
+    pub async fn last_stable_offset(&self) -> i64 {
+        *self.last_stable_offset.read().await
+    }
+
+    pub async fn set_last_stable_offset(&self, last_stable_offset: i64) {
+        let mut current = self.last_stable_offset.write().await;
+        *current = last_stable_offset;
+    }
+
+    // assigned replicas, in preference order
+    pub async fn replicas(&self) -> Vec<i32> {
+        self.replicas.read().await.clone()
+    }
+
+    pub async fn isr(&self) -> Vec<i32> {
+        self.isr.read().await.clone()
+    }
+
     pub async fn add_replica(&self, broker_id: i32) {
         let mut replicas = self.replicas.write().await;
         if !replicas.contains(&broker_id) {
@@ -159,6 +208,11 @@ impl Partition {
         }
     }
 
+    pub async fn set_replicas(&self, replica_list: Vec<i32>) {
+        let mut replicas = self.replicas.write().await;
+        *replicas = replica_list;
+    }
+
     pub async fn update_isr(&self, isr_list: Vec<i32>) {
         let mut isr = self.isr.write().await;
         *isr = isr_list;
@@ -0,0 +1,173 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::hash::BuildHasher;
+
+/// a broker replicas can be placed on, with its `broker.rack` if it has one
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlacementBroker {
+    pub broker_id: i32,
+    pub rack: Option<String>,
+}
+
+/// assigns replicas to `num_partitions` partitions the way Kafka's AdminUtils does.
+/// Partition p is led by the broker at position `start_index + p`, and its other
+/// replicas follow at a shift that grows each time the partitions wrap around the
+/// brokers, so partitions don't all share the same replica set. When every broker
+/// has a rack, brokers are interleaved rack by rack and a partition only gets two
+/// replicas in one rack once every rack holds one.
+///
+/// `brokers` can't be empty and `replication_factor` can't exceed its length.
+pub fn assign_replicas(
+    brokers: &[PlacementBroker],
+    num_partitions: i32,
+    replication_factor: i32,
+    start_index: usize,
+    replica_shift: usize,
+) -> Vec<Vec<i32>> {
+    let rack_aware = brokers.iter().all(|broker| broker.rack.is_some());
+    let arranged = if rack_aware { rack_alternated(brokers) } else { sorted_ids(brokers) };
+    let racks: BTreeMap<i32, &str> = brokers
+        .iter()
+        .filter_map(|broker| Some((broker.broker_id, broker.rack.as_deref()?)))
+        .collect();
+    let num_racks = racks.values().collect::<HashSet<_>>().len().max(1);
+
+    let num_brokers = arranged.len();
+    let replication_factor = replication_factor as usize;
+    let start_index = start_index % num_brokers;
+    let mut shift = replica_shift % num_brokers;
+
+    let mut assignment = Vec::with_capacity(num_partitions.max(0) as usize);
+    for partition in 0..num_partitions.max(0) as usize {
+        if partition > 0 && partition % num_brokers == 0 {
+            shift += 1;
+        }
+        let first = (partition + start_index) % num_brokers;
+        let mut replicas = vec![arranged[first]];
+
+        if !rack_aware {
+            for replica in 0..replication_factor.saturating_sub(1) {
+                replicas.push(arranged[replica_index(first, shift, replica, num_brokers)]);
+            }
+        } else {
+            let mut racks_used: HashSet<&str> = HashSet::from([racks[&arranged[first]]]);
+            let mut candidate = 0;
+            while replicas.len() < replication_factor {
+                let broker = arranged[replica_index(first, shift * num_racks, candidate, num_brokers)];
+                candidate += 1;
+                let rack = racks[&broker];
+                // skip a rack that already has a replica while another has none, and a
+                // broker that already has one
+                if (racks_used.contains(rack) && racks_used.len() < num_racks) || replicas.contains(&broker) {
+                    continue;
+                }
+                racks_used.insert(rack);
+                replicas.push(broker);
+            }
+        }
+        assignment.push(replicas);
+    }
+    assignment
+}
+
+/// a random position to start placing replicas from, so the first partitions of
+/// every topic don't land on the same brokers
+pub fn random_start(num_brokers: usize) -> usize {
+    (RandomState::new().hash_one(num_brokers) % num_brokers.max(1) as u64) as usize
+}
+
+// position of a follower replica: somewhere after the first replica, never on it
+fn replica_index(first: usize, shift: usize, replica: usize, num_brokers: usize) -> usize {
+    let offset = 1 + (shift + replica) % (num_brokers - 1);
+    (first + offset) % num_brokers
+}
+
+fn sorted_ids(brokers: &[PlacementBroker]) -> Vec<i32> {
+    let mut ids: Vec<i32> = brokers.iter().map(|broker| broker.broker_id).collect();
+    ids.sort_unstable();
+    ids
+}
+
+// brokers taken one rack at a time: the first broker of every rack, then the second...
+fn rack_alternated(brokers: &[PlacementBroker]) -> Vec<i32> {
+    let mut by_rack: BTreeMap<&str, VecDeque<i32>> = BTreeMap::new();
+    for broker in brokers {
+        by_rack.entry(broker.rack.as_deref().unwrap_or_default()).or_default().push_back(broker.broker_id);
+    }
+    for rack_brokers in by_rack.values_mut() {
+        rack_brokers.make_contiguous().sort_unstable();
+    }
+
+    let mut arranged = Vec::with_capacity(brokers.len());
+    while arranged.len() < brokers.len() {
+        for rack_brokers in by_rack.values_mut() {
+            arranged.extend(rack_brokers.pop_front());
+        }
+    }
+    arranged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placement_brokers(racks: &[(i32, Option<&str>)]) -> Vec<PlacementBroker> {
+        racks.iter().map(|(broker_id, rack)| PlacementBroker { broker_id: *broker_id, rack: rack.map(str::to_string) }).collect()
+    }
+
+    #[test]
+    fn followers_shift_once_the_partitions_wrap_around() {
+        let brokers = placement_brokers(&[(4, None), (0, None), (3, None), (1, None), (2, None)]);
+        let assignment = assign_replicas(&brokers, 7, 3, 0, 0);
+        assert_eq!(
+            assignment,
+            vec![
+                vec![0, 1, 2],
+                vec![1, 2, 3],
+                vec![2, 3, 4],
+                vec![3, 4, 0],
+                vec![4, 0, 1],
+                // second pass, followers one further away from the leader
+                vec![0, 2, 3],
+                vec![1, 3, 4],
+            ]
+        );
+    }
+
+    #[test]
+    fn the_start_index_picks_the_first_leader() {
+        let brokers = placement_brokers(&[(0, None), (1, None), (2, None)]);
+        let leaders: Vec<i32> = assign_replicas(&brokers, 4, 1, 2, 0).iter().map(|replicas| replicas[0]).collect();
+        assert_eq!(leaders, vec![2, 0, 1, 2]);
+        // indexes past the brokers wrap around
+        assert_eq!(assign_replicas(&brokers, 1, 2, 4, 0), vec![vec![1, 2]]);
+        assert_eq!(assign_replicas(&brokers, 1, 2, 0, 1), vec![vec![0, 2]]);
+    }
+
+    #[test]
+    fn replicas_spread_across_racks() {
+        let brokers = placement_brokers(&[(0, Some("a")), (1, Some("a")), (2, Some("b")), (3, Some("b")), (4, Some("c")), (5, Some("c"))]);
+        for start_index in 0..6 {
+            let assignment = assign_replicas(&brokers, 12, 3, start_index, start_index);
+            let mut led = BTreeMap::new();
+            for replicas in &assignment {
+                let racks: HashSet<&str> = replicas.iter().map(|id| brokers[*id as usize].rack.as_deref().unwrap()).collect();
+                assert_eq!(racks.len(), 3, "{:?} doesn't span every rack", replicas);
+                *led.entry(replicas[0]).or_insert(0) += 1;
+            }
+            assert!(led.values().all(|count| *count == 2), "uneven leaders {:?}", led);
+        }
+
+        // with fewer racks than replicas, no rack gets a second replica before every rack has one
+        let brokers = placement_brokers(&[(0, Some("a")), (1, Some("a")), (2, Some("a")), (3, Some("b"))]);
+        for replicas in assign_replicas(&brokers, 8, 3, 1, 0) {
+            assert!(replicas.contains(&3), "{:?} skipped rack b", replicas);
+        }
+    }
+
+    #[test]
+    fn brokers_without_a_rack_turn_off_rack_awareness() {
+        let brokers = placement_brokers(&[(0, Some("a")), (1, Some("a")), (2, None)]);
+        assert_eq!(assign_replicas(&brokers, 2, 2, 0, 0), vec![vec![0, 1], vec![1, 2]]);
+    }
+}
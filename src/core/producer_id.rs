@@ -0,0 +1,56 @@
+/// a range of producer ids handed to one broker (AllocateProducerIds)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProducerIdBlock {
+    pub first_producer_id: i64,
+    pub size: i32,
+}
+
+impl ProducerIdBlock {
+    pub fn last_producer_id(&self) -> i64 {
+        self.first_producer_id + self.size as i64 - 1
+    }
+}
+
+// the next block is asked for once this share of the current one is handed out
+const PREFETCH_PERCENTAGE: i64 = 90;
+
+/// producer ids a broker hands out in InitProducerId, from blocks the controller
+/// allocates. The next block is fetched ahead, before the current one runs out.
+#[derive(Debug, Default)]
+pub struct ProducerIdPool {
+    current: Option<ProducerIdBlock>,
+    next_producer_id: i64,
+    prefetched: Option<ProducerIdBlock>,
+}
+
+impl ProducerIdPool {
+    /// the next unused id, None until a block arrives
+    pub fn next_id(&mut self) -> Option<i64> {
+        let exhausted = self.current.is_none_or(|block| self.next_producer_id > block.last_producer_id());
+        if exhausted {
+            let block = self.prefetched.take()?;
+            self.current = Some(block);
+            self.next_producer_id = block.first_producer_id;
+        }
+        let producer_id = self.next_producer_id;
+        self.next_producer_id += 1;
+        Some(producer_id)
+    }
+
+    /// whether a block should be fetched: none is waiting and the current one is
+    /// missing or mostly used up
+    pub fn wants_block(&self) -> bool {
+        if self.prefetched.is_some() {
+            return false;
+        }
+        let Some(block) = self.current else {
+            return true;
+        };
+        let used = self.next_producer_id - block.first_producer_id;
+        used * 100 >= block.size as i64 * PREFETCH_PERCENTAGE
+    }
+
+    pub fn add_block(&mut self, block: ProducerIdBlock) {
+        self.prefetched = Some(block);
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+// a request that can't be answered yet, e.g. an acks=all produce waiting for the ISR
+pub trait DelayedOperation {
+    type Output;
+
+    /// Some once the operation can finish
+    fn try_complete(&self) -> impl Future<Output = Option<Self::Output>> + Send;
+
+    /// the answer when the timeout passes first
+    fn on_expiration(&self) -> impl Future<Output = Self::Output> + Send;
+}
+
+// parks delayed operations under the keys they depend on; whoever changes a key's
+// state calls check_and_complete so its watchers re-run try_complete
+#[derive(Debug)]
+pub struct DelayedOperationPurgatory<K: Eq + Hash> {
+    next_id: AtomicU64,
+    watchers: Mutex<HashMap<K, HashMap<u64, Arc<Notify>>>>,
+}
+
+// unregisters an operation however its wait ends, including the request being dropped
+struct Watch<'a, K: Eq + Hash> {
+    purgatory: &'a DelayedOperationPurgatory<K>,
+    id: u64,
+    keys: &'a [K],
+}
+
+impl<K: Eq + Hash> Drop for Watch<'_, K> {
+    fn drop(&mut self) {
+        let mut watchers = self.purgatory.watchers.lock().unwrap();
+        for key in self.keys {
+            if let Some(ops) = watchers.get_mut(key) {
+                ops.remove(&self.id);
+                if ops.is_empty() {
+                    watchers.remove(key);
+                }
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone> DelayedOperationPurgatory<K> {
+    pub fn new() -> Self {
+        DelayedOperationPurgatory {
+            next_id: AtomicU64::new(0),
+            watchers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// completes the operation right away if possible, otherwise waits on `keys`
+    /// until it completes or `timeout` passes
+    pub async fn try_complete_else_watch<O: DelayedOperation>(&self, operation: &O, keys: &[K], timeout: Duration) -> O::Output {
+        if let Some(output) = operation.try_complete().await {
+            return output;
+        }
+
+        // register before re-checking so a trigger in between leaves a permit behind
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let notify = Arc::new(Notify::new());
+        {
+            let mut watchers = self.watchers.lock().unwrap();
+            for key in keys {
+                watchers.entry(key.clone()).or_default().insert(id, Arc::clone(&notify));
+            }
+        }
+        let _watch = Watch { purgatory: self, id, keys };
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(output) = operation.try_complete().await {
+                return output;
+            }
+            tokio::select! {
+                _ = notify.notified() => {}
+                _ = tokio::time::sleep_until(deadline) => return operation.on_expiration().await,
+            }
+        }
+    }
+
+    /// wakes every operation watching `key`
+    pub fn check_and_complete(&self, key: &K) {
+        let watchers = self.watchers.lock().unwrap();
+        if let Some(ops) = watchers.get(key) {
+            for notify in ops.values() {
+                notify.notify_one();
+            }
+        }
+    }
+
+    /// number of operations currently waiting
+    pub fn watched(&self) -> usize {
+        let watchers = self.watchers.lock().unwrap();
+        let mut ids: Vec<u64> = watchers.values().flat_map(|ops| ops.keys().copied()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.len()
+    }
+}
+
+impl<K: Eq + Hash + Clone> Default for DelayedOperationPurgatory<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
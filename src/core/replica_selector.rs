@@ -0,0 +1,112 @@
+use std::fmt::Debug;
+
+use crate::core::consumer_group::TopicPartition;
+
+/// who a consumer fetch came from, as far as picking a replica goes
+#[derive(Debug, Clone)]
+pub struct ClientMetadata {
+    pub client_id: String,
+    pub rack_id: String, // client.rack, empty when the consumer didn't set one
+}
+
+/// a replica a consumer could read from, as its leader sees it
+#[derive(Debug, Clone)]
+pub struct ReplicaView {
+    pub broker_id: i32,
+    pub rack: Option<String>,
+    pub log_end_offset: i64,
+    pub time_since_last_caught_up_ms: i64,
+}
+
+/// the leader of a partition and the in-sync replicas that can serve the fetch,
+/// the leader included
+#[derive(Debug, Clone)]
+pub struct PartitionView {
+    pub leader: ReplicaView,
+    pub replicas: Vec<ReplicaView>,
+}
+
+/// picks the replica a consumer should fetch from, handed back to it as
+/// `preferred_read_replica` (Kafka's `replica.selector.class`)
+pub trait ReplicaSelector: Debug + Send + Sync {
+    /// id of the broker to read from; None keeps the consumer on the leader
+    fn select(&self, tp: &TopicPartition, client: &ClientMetadata, partition: &PartitionView) -> Option<i32>;
+}
+
+/// always reads from the leader, like a broker without a replica selector
+#[derive(Debug, Default)]
+pub struct LeaderSelector;
+
+impl ReplicaSelector for LeaderSelector {
+    fn select(&self, _tp: &TopicPartition, _client: &ClientMetadata, partition: &PartitionView) -> Option<i32> {
+        Some(partition.leader.broker_id)
+    }
+}
+
+/// sends consumers to a replica in their own rack, preferring the leader when it is
+/// there and otherwise the replica that is furthest along
+#[derive(Debug, Default)]
+pub struct RackAwareReplicaSelector;
+
+impl ReplicaSelector for RackAwareReplicaSelector {
+    fn select(&self, _tp: &TopicPartition, client: &ClientMetadata, partition: &PartitionView) -> Option<i32> {
+        if client.rack_id.is_empty() {
+            return Some(partition.leader.broker_id);
+        }
+        let same_rack: Vec<&ReplicaView> = partition
+            .replicas
+            .iter()
+            .filter(|replica| replica.rack.as_deref() == Some(client.rack_id.as_str()))
+            .collect();
+        if same_rack.is_empty() || same_rack.iter().any(|replica| replica.broker_id == partition.leader.broker_id) {
+            return Some(partition.leader.broker_id);
+        }
+        // most data first, then the one that was caught up most recently
+        same_rack
+            .into_iter()
+            .max_by(|a, b| {
+                a.log_end_offset
+                    .cmp(&b.log_end_offset)
+                    .then(b.time_since_last_caught_up_ms.cmp(&a.time_since_last_caught_up_ms))
+            })
+            .map(|replica| replica.broker_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replica(broker_id: i32, rack: &str, log_end_offset: i64, time_since_last_caught_up_ms: i64) -> ReplicaView {
+        ReplicaView { broker_id, rack: Some(rack.to_string()), log_end_offset, time_since_last_caught_up_ms }
+    }
+
+    fn select(rack_id: &str, replicas: Vec<ReplicaView>) -> Option<i32> {
+        let tp = TopicPartition::new("orders".to_string(), 0);
+        let client = ClientMetadata { client_id: "billing".to_string(), rack_id: rack_id.to_string() };
+        let partition = PartitionView { leader: replicas[0].clone(), replicas };
+        RackAwareReplicaSelector.select(&tp, &client, &partition)
+    }
+
+    #[test]
+    fn consumers_read_from_a_replica_in_their_rack() {
+        let replicas = vec![replica(0, "a", 10, 0), replica(1, "b", 10, 0), replica(2, "c", 10, 0)];
+        assert_eq!(select("b", replicas.clone()), Some(1));
+        assert_eq!(select("c", replicas), Some(2));
+    }
+
+    #[test]
+    fn the_leader_serves_consumers_without_a_replica_in_their_rack() {
+        let replicas = vec![replica(0, "a", 10, 0), replica(1, "b", 10, 0), replica(2, "a", 10, 0)];
+        assert_eq!(select("", replicas.clone()), Some(0));
+        assert_eq!(select("z", replicas.clone()), Some(0));
+        // the leader wins over a follower in the same rack
+        assert_eq!(select("a", replicas), Some(0));
+    }
+
+    #[test]
+    fn the_furthest_along_replica_in_the_rack_wins() {
+        let replicas = vec![replica(0, "a", 10, 0), replica(1, "b", 8, 0), replica(2, "b", 9, 500), replica(3, "b", 9, 100)];
+        assert_eq!(select("b", replicas), Some(3));
+    }
+}
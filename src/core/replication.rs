@@ -1,32 +1,118 @@
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::vec;
 use tokio::sync::RwLock;
 use tokio::fs::File;
 use chrono::Utc;
-use crate::storage::log::Log;
+use crate::constants::{LOG_SEGMENT_BYTES, REPLICA_LAG_TIME_MAX_MS};
+use crate::core::delayed_fetch::{FetchIsolation, FetchParams, FetchPartitionData, FetchPartitionStatus};
+use crate::core::replica_selector::{PartitionView, ReplicaView};
+use crate::error::KafkaErrorCode;
+use crate::storage::leader_epoch::UNDEFINED_EPOCH_OFFSET;
+use crate::storage::log::{AbortedTxn, Log};
+use crate::storage::producer_state::{ProducerBatch, ProducerStateError};
+use crate::storage::record_batch;
 use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// suffix of partition directories renamed for deletion, removed in the background
+pub const DELETED_DIR_SUFFIX: &str = "-delete";
+// kept in the partition's log directory so it goes away with it
+const PARTITION_METADATA_FILE: &str = "partition-metadata.json";
 
 #[derive(Serialize, Deserialize)]
 struct PartitionMetadata {
     leader_offset: i64,
+    high_watermark: i64,
     isr: Vec<i32>,
     timestamp: i64,
 }
 
+#[derive(Debug, Error)]
+pub enum ReplicationError {
+    #[error("No log for partition {0}-{1}")]
+    UnknownTopicOrPartition(String, i32),
+
+    #[error("Broker doesn't lead partition {0}-{1}")]
+    NotLeaderOrFollower(String, i32),
+
+    #[error("Offset {2} is out of range for partition {0}-{1}")]
+    OffsetOutOfRange(String, i32, i64),
+
+    #[error("Batch at offset {1} doesn't follow log end offset {0}")]
+    NonContiguousBatch(i64, i64),
+
+    #[error("Records are not valid v2 record batches")]
+    CorruptRecords,
+
+    #[error("Leader epoch {2} of partition {0}-{1} is older than the current one")]
+    FencedLeaderEpoch(String, i32, i32),
+
+    #[error("Leader epoch {2} of partition {0}-{1} is newer than the current one")]
+    UnknownLeaderEpoch(String, i32, i32),
+
+    #[error(transparent)]
+    ProducerState(#[from] ProducerStateError),
+
+    #[error("Log error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl ReplicationError {
+    pub fn error_code(&self) -> KafkaErrorCode {
+        match self {
+            ReplicationError::UnknownTopicOrPartition(_, _) => KafkaErrorCode::UnknownTopicOrPartition,
+            ReplicationError::NotLeaderOrFollower(_, _) => KafkaErrorCode::NotLeaderOrFollower,
+            ReplicationError::OffsetOutOfRange(_, _, _) => KafkaErrorCode::OffsetOutOfRange,
+            ReplicationError::NonContiguousBatch(_, _) => KafkaErrorCode::UnknownServerError,
+            ReplicationError::CorruptRecords => KafkaErrorCode::CorruptMessage,
+            ReplicationError::FencedLeaderEpoch(_, _, _) => KafkaErrorCode::FencedLeaderEpoch,
+            ReplicationError::UnknownLeaderEpoch(_, _, _) => KafkaErrorCode::UnknownLeaderEpoch,
+            ReplicationError::ProducerState(ProducerStateError::InvalidProducerEpoch(_, _, _)) => KafkaErrorCode::InvalidProducerEpoch,
+            ReplicationError::ProducerState(ProducerStateError::OutOfOrderSequence(_, _, _)) => KafkaErrorCode::OutOfOrderSequenceNumber,
+            ReplicationError::ProducerState(ProducerStateError::DuplicateSequence(_, _, _, _)) => KafkaErrorCode::DuplicateSequenceNumber,
+            ReplicationError::Io(_) => KafkaErrorCode::KafkaStorageError,
+        }
+    }
+}
+
+// where a follower partition resumes fetching from its leader
+#[derive(Debug, Clone)]
+pub struct PartitionFetchState {
+    pub topic: String,
+    pub partition_id: i32,
+    pub fetch_offset: i64,
+    pub last_fetched_epoch: i32,
+    pub current_leader_epoch: i32, // the leader's epoch, sent so either side can be fenced
+}
+
+// record batches read for a Fetch
+#[derive(Debug)]
+pub struct FetchedRecords {
+    pub records: Vec<u8>,
+    pub high_watermark: i64,
+    pub last_stable_offset: i64,
+    pub log_start_offset: i64,
+    pub aborted_transactions: Option<Vec<AbortedTxn>>, // for TxnCommitted reads
+}
+
 #[derive(Debug)]
 pub struct ReplicaManager {
     broker_id: i32,
+    log_dir: PathBuf,
     leader_partitions: Arc<RwLock<HashMap<(String, i32), LeaderState>>>,
     follower_partitions: Arc<RwLock<HashMap<(String, i32), FollowerState>>>,
-    partition_logs: HashMap<(String, i32), Log>,
+    partition_logs: Arc<RwLock<HashMap<(String, i32), Log>>>,
 }
 
 #[derive(Debug)]
 pub struct LeaderState {
-    topic: String,
-    partition_id: i32,
-    last_offset: i64,
+    leader_epoch: i32,
+    leader_epoch_start_offset: i64, // log end offset when this broker started leading
+    last_offset: i64, // leader's log end offset as of the last follower fetch
+    high_watermark: i64, // min log end offset across the ISR, never moves back
+    replicas: Vec<i32>, // assigned replicas, the only brokers allowed to fetch as followers
     isr: Vec<i32>, // in-sync replicas
     followers: HashMap<i32, FollowerProgress>,
     last_update_timestamp: i64, // for detecting stale leader
@@ -37,56 +123,331 @@ pub struct FollowerState {
     topic: String,
     partition_id: i32,
     leader_id: i32,
+    leader_epoch: i32, // the epoch `leader_id` leads in
     fetch_offset: i64,
     last_fetched_epoch: i32,
+    high_watermark: i64, // as last reported by the leader
     broker_id: i32,
+    truncation_pending: bool, // the log may hold a tail the new leader never had
 }
 
+// what the leader knows about one follower, from its fetches
 #[derive(Debug)]
 pub struct FollowerProgress {
-    broker_id: i32,
-    last_fetched_offset: i64,
+    last_fetched_offset: i64, // the follower's log end offset
     last_fetch_timestamp: i64,
+    last_fetch_leader_log_end_offset: i64,
+    last_caught_up_timestamp: i64, // last time the follower had everything the leader had
+}
+
+// what a follower fetch changed on the leader
+#[derive(Debug, Default)]
+pub struct FollowerFetchOutcome {
+    pub isr_change: Option<IsrChange>,
+    pub high_watermark_advanced: bool,
+}
+
+// a leader's ISR after it shrank or expanded
+#[derive(Debug, Clone)]
+pub struct IsrChange {
+    pub topic: String,
+    pub partition_id: i32,
+    pub leader_epoch: i32,
+    pub isr: Vec<i32>,
+    pub high_watermark: i64,
+}
+
+impl LeaderState {
+    // HW is the smallest log end offset in the ISR; it only ever advances
+    fn maybe_advance_high_watermark(&mut self, leader_id: i32) -> bool {
+        let min_log_end_offset = self
+            .isr
+            .iter()
+            .map(|replica| match self.followers.get(replica) {
+                _ if *replica == leader_id => self.last_offset,
+                Some(progress) => progress.last_fetched_offset,
+                None => self.high_watermark,
+            })
+            .min()
+            .unwrap_or(self.last_offset);
+
+        if min_log_end_offset > self.high_watermark {
+            self.high_watermark = min_log_end_offset;
+            return true;
+        }
+        false
+    }
 }
 
 impl ReplicaManager {
     pub fn new(broker_id: i32) -> Self {
-        Self { 
+        Self::with_log_dir(broker_id, PathBuf::from("data"))
+    }
+
+    pub fn with_log_dir(broker_id: i32, log_dir: PathBuf) -> Self {
+        Self {
             broker_id,
+            log_dir,
             leader_partitions: Arc::new(RwLock::new(HashMap::new())),
             follower_partitions: Arc::new(RwLock::new(HashMap::new())),
-            partition_logs: HashMap::new(),
+            partition_logs: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn log_dir(&self) -> &Path {
+        &self.log_dir
+    }
+
+    /// id of the log directory, kept in its meta.properties and created on first use
+    pub fn directory_id(&self) -> std::io::Result<Uuid> {
+        let path = self.log_dir.join("meta.properties");
+        match std::fs::read_to_string(&path) {
+            Ok(properties) => {
+                let id = properties
+                    .lines()
+                    .find_map(|line| line.strip_prefix("directory.id="))
+                    .and_then(|id| Uuid::parse_str(id.trim()).ok());
+                if let Some(id) = id {
+                    return Ok(id);
+                }
+            }
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            Err(_) => {}
+        }
+        let id = Uuid::new_v4();
+        std::fs::create_dir_all(&self.log_dir)?;
+        std::fs::write(&path, format!("version=1\nnode.id={}\ndirectory.id={}\n", self.broker_id, id))?;
+        Ok(id)
+    }
+
+    pub async fn add_partition_log(&self, topic: String, partition_id: i32, log: Log) {
+        let mut logs = self.partition_logs.write().await;
+        logs.insert((topic, partition_id), log);
+    }
+
+    async fn latest_epoch(&self, topic: &str, partition_id: i32) -> Option<i32> {
+        let logs = self.partition_logs.read().await;
+        logs.get(&(topic.to_string(), partition_id)).and_then(|log| log.latest_epoch())
+    }
+
+    async fn log_start_offset(&self, topic: &str, partition_id: i32) -> Option<i64> {
+        let logs = self.partition_logs.read().await;
+        logs.get(&(topic.to_string(), partition_id)).map(|log| log.log_start_offset())
+    }
+
+    async fn log_end_offset(&self, topic: &str, partition_id: i32) -> Option<i64> {
+        let logs = self.partition_logs.read().await;
+        logs.get(&(topic.to_string(), partition_id)).map(|log| log.log_end_offset())
+    }
+
+    /// offsets below which every transaction has its marker: the HW, held back by
+    /// the oldest transaction that is still open
+    pub async fn last_stable_offset(&self, topic: &str, partition_id: i32) -> Option<i64> {
+        let high_watermark = self.high_watermark(topic, partition_id).await?;
+        let logs = self.partition_logs.read().await;
+        let first_unstable_offset = logs.get(&(topic.to_string(), partition_id))?.first_unstable_offset();
+        Some(first_unstable_offset.map_or(high_watermark, |offset| offset.min(high_watermark)))
+    }
+
+    /// the leader's HW, or for a follower the HW its leader last reported
+    pub async fn high_watermark(&self, topic: &str, partition_id: i32) -> Option<i64> {
+        let key = (topic.to_string(), partition_id);
+        if let Some(leader) = self.leader_partitions.read().await.get(&key) {
+            return Some(leader.high_watermark);
+        }
+        let followers = self.follower_partitions.read().await;
+        followers.get(&key).map(|follower| follower.high_watermark.min(follower.fetch_offset))
+    }
+
+    pub async fn isr(&self, topic: &str, partition_id: i32) -> Option<Vec<i32>> {
+        let leaders = self.leader_partitions.read().await;
+        leaders.get(&(topic.to_string(), partition_id)).map(|leader| leader.isr.clone())
+    }
+
+    /// the ISR of a led partition as seen from its follower fetches, for picking a
+    /// replica consumers can read from. Racks are left for the caller to fill in.
+    pub async fn partition_view(&self, topic: &str, partition_id: i32, now_ms: i64) -> Option<PartitionView> {
+        let log_end_offset = self.log_end_offset(topic, partition_id).await?;
+        let leaders = self.leader_partitions.read().await;
+        let leader = leaders.get(&(topic.to_string(), partition_id))?;
+        let leader_view = ReplicaView {
+            broker_id: self.broker_id,
+            rack: None,
+            log_end_offset,
+            time_since_last_caught_up_ms: 0,
+        };
+        let mut replicas = vec![leader_view.clone()];
+        for replica in &leader.isr {
+            let Some(progress) = leader.followers.get(replica) else {
+                continue;
+            };
+            replicas.push(ReplicaView {
+                broker_id: *replica,
+                rack: None,
+                log_end_offset: progress.last_fetched_offset,
+                time_since_last_caught_up_ms: (now_ms - progress.last_caught_up_timestamp).max(0),
+            });
+        }
+        Some(PartitionView { leader: leader_view, replicas })
+    }
+
+    // opens {log_dir}/{topic}-{partition} unless this broker already hosts it
+    async fn ensure_log(&self, topic: &str, partition_id: i32) -> Result<(), ReplicationError> {
+        let mut logs = self.partition_logs.write().await;
+        if let Entry::Vacant(entry) = logs.entry((topic.to_string(), partition_id)) {
+            let dir = self.log_dir.join(format!("{}-{}", topic, partition_id));
+            let mut log = Log::new(dir, 0, LOG_SEGMENT_BYTES)?;
+            log.load_producer_state()?;
+            entry.insert(log);
         }
+        Ok(())
     }
 
-    pub async fn add_leader_partition(&mut self, topic:String, partition_id: i32) {
+    /// ListOffsets lookup against the partition's log, see Log::list_offset
+    pub async fn list_offset(&self, topic: &str, partition_id: i32, timestamp: i64) -> Result<Option<i64>, ReplicationError> {
+        let mut logs = self.partition_logs.write().await;
+        let log = logs
+            .get_mut(&(topic.to_string(), partition_id))
+            .ok_or_else(|| ReplicationError::UnknownTopicOrPartition(topic.to_string(), partition_id))?;
+        Ok(log.list_offset(timestamp)?)
+    }
+
+    /// time-index timestamp of the entry at `offset`, see Log::timestamp_at
+    pub async fn timestamp_at(&self, topic: &str, partition_id: i32, offset: i64) -> Result<Option<i64>, ReplicationError> {
+        let mut logs = self.partition_logs.write().await;
+        let log = logs
+            .get_mut(&(topic.to_string(), partition_id))
+            .ok_or_else(|| ReplicationError::UnknownTopicOrPartition(topic.to_string(), partition_id))?;
+        Ok(log.timestamp_at(offset)?)
+    }
+
+    /// starts leading a partition in the controller-assigned `leader_epoch`, which
+    /// starts at the current log end
+    pub async fn add_leader_partition(
+        &self,
+        topic: String,
+        partition_id: i32,
+        leader_epoch: i32,
+        mut replicas: Vec<i32>,
+        mut isr: Vec<i32>,
+    ) -> Result<(), ReplicationError> {
+        self.ensure_log(&topic, partition_id).await?;
         let key = (topic.clone(), partition_id);
-        let leader_state = LeaderState {
-            topic,
-            partition_id,
-            last_offset:0, 
-            isr: vec![self.broker_id], // assuming leader starts as in-sync
+        let last_offset = {
+            let mut logs = self.partition_logs.write().await;
+            let log = logs
+                .get_mut(&key)
+                .ok_or_else(|| ReplicationError::UnknownTopicOrPartition(topic.clone(), partition_id))?;
+            if log.latest_epoch().is_some_and(|latest| latest > leader_epoch) {
+                return Err(ReplicationError::FencedLeaderEpoch(topic, partition_id, leader_epoch));
+            }
+            log.assign_epoch(leader_epoch, log.log_end_offset())?;
+            log.log_end_offset()
+        };
+        // a former follower keeps the HW its old leader told it about
+        let high_watermark = {
+            let followers = self.follower_partitions.read().await;
+            followers.get(&key).map_or(0, |follower| follower.high_watermark.min(last_offset))
+        };
+        if !replicas.contains(&self.broker_id) {
+            replicas.insert(0, self.broker_id);
+        }
+        if !isr.contains(&self.broker_id) {
+            isr.insert(0, self.broker_id);
+        }
+        let mut leader_state = LeaderState {
+            leader_epoch,
+            leader_epoch_start_offset: last_offset,
+            last_offset,
+            high_watermark,
+            replicas,
+            isr,
             followers: HashMap::new(),
             last_update_timestamp: Utc::now().timestamp_millis()
         };
+        // alone in the ISR, everything the leader has is committed
+        leader_state.maybe_advance_high_watermark(self.broker_id);
 
         let mut leaders= self.leader_partitions.write().await;
         leaders.insert(key, leader_state);
+        println!("Leading {}-{} in epoch {} from offset {}", topic, partition_id, leader_epoch, last_offset);
+        Ok(())
+    }
+
+    /// the epoch this broker leads a partition in
+    pub async fn leader_epoch(&self, topic: &str, partition_id: i32) -> Option<i32> {
+        let leaders = self.leader_partitions.read().await;
+        leaders.get(&(topic.to_string(), partition_id)).map(|leader| leader.leader_epoch)
     }
 
-    pub async fn add_follower_partition(&mut self, topic: String, partition_id: i32, leader_id: i32, follower_id: i32, fetch_offset: i64) {
+    /// replaces a led partition's ISR with the one the controller committed
+    pub async fn set_isr(&self, topic: &str, partition_id: i32, mut isr: Vec<i32>) -> bool {
+        let mut leaders = self.leader_partitions.write().await;
+        let Some(leader) = leaders.get_mut(&(topic.to_string(), partition_id)) else {
+            return false;
+        };
+        if !isr.contains(&self.broker_id) {
+            isr.insert(0, self.broker_id);
+        }
+        if leader.isr == isr {
+            return false;
+        }
+        leader.isr = isr;
+        leader.last_update_timestamp = Utc::now().timestamp_millis();
+        leader.maybe_advance_high_watermark(self.broker_id);
+        true
+    }
+
+    /// replaces a led partition's assigned replicas after a reassignment; removed
+    /// replicas are no longer tracked and can't fetch as followers any more
+    pub async fn set_replicas(&self, topic: &str, partition_id: i32, mut replicas: Vec<i32>) {
+        let mut leaders = self.leader_partitions.write().await;
+        let Some(leader) = leaders.get_mut(&(topic.to_string(), partition_id)) else {
+            return;
+        };
+        if !replicas.contains(&self.broker_id) {
+            replicas.insert(0, self.broker_id);
+        }
+        leader.followers.retain(|replica, _| replicas.contains(replica));
+        leader.replicas = replicas;
+    }
+
+    /// whether `replica_id` is an assigned replica of a partition this broker leads
+    pub async fn is_assigned_replica(&self, topic: &str, partition_id: i32, replica_id: i32) -> bool {
+        let leaders = self.leader_partitions.read().await;
+        leaders
+            .get(&(topic.to_string(), partition_id))
+            .is_some_and(|leader| leader.replicas.contains(&replica_id))
+    }
+
+    pub async fn add_follower_partition(
+        &self,
+        topic: String,
+        partition_id: i32,
+        leader_id: i32,
+        leader_epoch: i32,
+        follower_id: i32,
+        fetch_offset: i64,
+    ) -> Result<(), ReplicationError> {
+        self.ensure_log(&topic, partition_id).await?;
         let key = (topic.clone(), partition_id);
+        // without any epoch there's nothing to compare against the leader
+        let latest_epoch = self.latest_epoch(&topic, partition_id).await;
         let follower_state = FollowerState {
             broker_id: follower_id,
             topic,
             partition_id,
             leader_id,
+            leader_epoch,
             fetch_offset,
-            last_fetched_epoch: 0,
+            last_fetched_epoch: latest_epoch.unwrap_or(-1),
+            high_watermark: 0,
+            truncation_pending: latest_epoch.is_some(),
         };
 
         let mut followers = self.follower_partitions.write().await;
         followers.insert(key, follower_state);
+        Ok(())
     }
 
     pub async fn update_leader_offset(&self, topic: String, partition_id: i32, offset: i64) {
@@ -99,19 +460,41 @@ impl ReplicaManager {
         }
     }
 
-    pub async fn remove_leader_partition(&mut self, topic: String, partition_id: i32) {
+    pub async fn remove_leader_partition(&self, topic: String, partition_id: i32) {
         let key = (topic, partition_id);
         let mut leaders = self.leader_partitions.write().await;
         leaders.remove(&key);
     }
 
-    pub async fn remove_follower_partition(&mut self, topic: String, partition_id: i32) {
+    pub async fn remove_follower_partition(&self, topic: String, partition_id: i32) {
         let key = (topic, partition_id);
         let mut followers = self.follower_partitions.write().await;
         followers.remove(&key);
     }
 
-    pub async fn is_follower_in_isr(&mut self, topic:String, partition_id: i32, follower_id: i32) -> bool {
+    /// forgets a partition of a deleted topic and deletes its log. The log directory
+    /// is renamed right away so a new topic of the same name starts out empty, and is
+    /// removed in the background.
+    pub async fn delete_partition(&self, topic: &str, partition_id: i32) -> Result<(), ReplicationError> {
+        self.remove_leader_partition(topic.to_string(), partition_id).await;
+        self.remove_follower_partition(topic.to_string(), partition_id).await;
+        // dropping the log closes its segments
+        self.partition_logs.write().await.remove(&(topic.to_string(), partition_id));
+
+        let dir = self.log_dir.join(format!("{}-{}", topic, partition_id));
+        if dir.exists() {
+            let deleted = self.log_dir.join(format!("{}-{}.{}{}", topic, partition_id, Uuid::new_v4().simple(), DELETED_DIR_SUFFIX));
+            std::fs::rename(&dir, &deleted)?;
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = std::fs::remove_dir_all(&deleted) {
+                    eprintln!("Failed to delete {}: {}", deleted.display(), e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    pub async fn is_follower_in_isr(&self, topic:String, partition_id: i32, follower_id: i32) -> bool {
         let key = (topic, partition_id);
         let leaders = self.leader_partitions.read().await;
         if let Some(leader) = leaders.get(&key) {
@@ -121,6 +504,379 @@ impl ReplicaManager {
         }
     }
 
+    pub async fn is_leader(&self, topic: &str, partition_id: i32) -> bool {
+        let leaders = self.leader_partitions.read().await;
+        leaders.contains_key(&(topic.to_string(), partition_id))
+    }
+
+    /// leaders this broker currently replicates from
+    pub async fn follower_leader_ids(&self) -> BTreeSet<i32> {
+        let followers = self.follower_partitions.read().await;
+        followers.values().map(|follower| follower.leader_id).collect()
+    }
+
+    pub async fn follower_fetch_states(&self, leader_id: i32) -> Vec<PartitionFetchState> {
+        let followers = self.follower_partitions.read().await;
+        let mut states: Vec<PartitionFetchState> = followers
+            .values()
+            .filter(|follower| follower.leader_id == leader_id && !follower.truncation_pending)
+            .map(|follower| PartitionFetchState {
+                topic: follower.topic.clone(),
+                partition_id: follower.partition_id,
+                fetch_offset: follower.fetch_offset,
+                last_fetched_epoch: follower.last_fetched_epoch,
+                current_leader_epoch: follower.leader_epoch,
+            })
+            .collect();
+        states.sort_by(|a, b| (&a.topic, a.partition_id).cmp(&(&b.topic, b.partition_id)));
+        states
+    }
+
+    /// partitions that must truncate to the leader's epoch end offset before fetching;
+    /// `last_fetched_epoch` is the latest epoch in the local log
+    pub async fn follower_truncation_states(&self, leader_id: i32) -> Vec<PartitionFetchState> {
+        let followers = self.follower_partitions.read().await;
+        let mut states: Vec<PartitionFetchState> = followers
+            .values()
+            .filter(|follower| follower.leader_id == leader_id && follower.truncation_pending)
+            .map(|follower| PartitionFetchState {
+                topic: follower.topic.clone(),
+                partition_id: follower.partition_id,
+                fetch_offset: follower.fetch_offset,
+                last_fetched_epoch: follower.last_fetched_epoch,
+                current_leader_epoch: follower.leader_epoch,
+            })
+            .collect();
+        states.sort_by(|a, b| (&a.topic, a.partition_id).cmp(&(&b.topic, b.partition_id)));
+        states
+    }
+
+    /// checks a request's current_leader_epoch against the epoch the partition is led in,
+    /// as this broker knows it as leader or follower. An older one is fenced, a newer one
+    /// means this broker hasn't heard of it yet; -1 means the caller doesn't know it.
+    pub async fn validate_current_leader_epoch(&self, topic: &str, partition_id: i32, current_leader_epoch: i32) -> Result<(), ReplicationError> {
+        if current_leader_epoch < 0 {
+            return Ok(());
+        }
+        let key = (topic.to_string(), partition_id);
+        let leader_epoch = match self.leader_partitions.read().await.get(&key) {
+            Some(leader) => Some(leader.leader_epoch),
+            None => self.follower_partitions.read().await.get(&key).map(|follower| follower.leader_epoch),
+        };
+        match leader_epoch {
+            Some(epoch) if current_leader_epoch < epoch => {
+                Err(ReplicationError::FencedLeaderEpoch(topic.to_string(), partition_id, current_leader_epoch))
+            }
+            Some(epoch) if current_leader_epoch > epoch => {
+                Err(ReplicationError::UnknownLeaderEpoch(topic.to_string(), partition_id, current_leader_epoch))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// OffsetForLeaderEpoch on the leader: the largest epoch <= `leader_epoch` it knows
+    /// and the offset that epoch ended at
+    pub async fn last_offset_for_leader_epoch(
+        &self,
+        topic: &str,
+        partition_id: i32,
+        current_leader_epoch: i32,
+        leader_epoch: i32,
+    ) -> Result<(i32, i64), ReplicationError> {
+        let key = (topic.to_string(), partition_id);
+        if !self.is_leader(topic, partition_id).await {
+            return Err(ReplicationError::NotLeaderOrFollower(topic.to_string(), partition_id));
+        }
+        self.validate_current_leader_epoch(topic, partition_id, current_leader_epoch).await?;
+
+        let logs = self.partition_logs.read().await;
+        let log = logs
+            .get(&key)
+            .ok_or_else(|| ReplicationError::UnknownTopicOrPartition(topic.to_string(), partition_id))?;
+        Ok(log.end_offset_for_epoch(leader_epoch))
+    }
+
+    /// cuts a follower's divergent tail using the leader's answer to OffsetForLeaderEpoch,
+    /// then lets it fetch again. Returns the new log end offset.
+    pub async fn truncate_to_epoch_end(
+        &self,
+        topic: &str,
+        partition_id: i32,
+        leader_epoch: i32,
+        leader_end_offset: i64,
+    ) -> Result<i64, ReplicationError> {
+        let key = (topic.to_string(), partition_id);
+        let mut followers = self.follower_partitions.write().await;
+        let follower = followers
+            .get_mut(&key)
+            .ok_or_else(|| ReplicationError::NotLeaderOrFollower(topic.to_string(), partition_id))?;
+        let mut logs = self.partition_logs.write().await;
+        let log = logs
+            .get_mut(&key)
+            .ok_or_else(|| ReplicationError::UnknownTopicOrPartition(topic.to_string(), partition_id))?;
+
+        let truncation_offset = if leader_end_offset == UNDEFINED_EPOCH_OFFSET {
+            // the leader knows no epoch this old, the HW is all that's known to be shared
+            follower.high_watermark.min(log.log_end_offset())
+        } else {
+            // where our copy of that epoch ends, in case the leader's ran longer
+            let (_, local_end_offset) = log.end_offset_for_epoch(leader_epoch);
+            leader_end_offset.min(local_end_offset).min(log.log_end_offset())
+        };
+        if truncation_offset < log.log_end_offset() {
+            println!(
+                "Truncating {}-{} from {} to {} (leader epoch {} ends at {})",
+                topic, partition_id, log.log_end_offset(), truncation_offset, leader_epoch, leader_end_offset
+            );
+            log.truncate_to(truncation_offset)?;
+        }
+
+        follower.fetch_offset = log.log_end_offset();
+        follower.last_fetched_epoch = log.latest_epoch().unwrap_or(-1);
+        follower.truncation_pending = false;
+        Ok(log.log_end_offset())
+    }
+
+    /// handles OFFSET_OUT_OF_RANGE from the leader. A follower behind the leader's log
+    /// start drops its whole log and starts over there; one past the leader's log end
+    /// truncates through OffsetForLeaderEpoch first. Returns the new fetch offset.
+    pub async fn handle_offset_out_of_range(
+        &self,
+        topic: &str,
+        partition_id: i32,
+        leader_log_start_offset: i64,
+    ) -> Result<i64, ReplicationError> {
+        let key = (topic.to_string(), partition_id);
+        let mut followers = self.follower_partitions.write().await;
+        let follower = followers
+            .get_mut(&key)
+            .ok_or_else(|| ReplicationError::NotLeaderOrFollower(topic.to_string(), partition_id))?;
+        if leader_log_start_offset < 0 || follower.fetch_offset >= leader_log_start_offset {
+            follower.truncation_pending = true;
+            return Ok(follower.fetch_offset);
+        }
+
+        let mut logs = self.partition_logs.write().await;
+        let log = logs
+            .get_mut(&key)
+            .ok_or_else(|| ReplicationError::UnknownTopicOrPartition(topic.to_string(), partition_id))?;
+        println!(
+            "Resetting {}-{} from offset {} to the leader's log start {}",
+            topic, partition_id, follower.fetch_offset, leader_log_start_offset
+        );
+        log.truncate_fully_and_start_at(leader_log_start_offset)?;
+        follower.fetch_offset = leader_log_start_offset;
+        follower.last_fetched_epoch = -1;
+        Ok(leader_log_start_offset)
+    }
+
+    /// serves a Fetch from the partition's log, with batch base offsets set to their log offsets.
+    /// Replicas read from the leader up to the log end. Consumers read only up to the high
+    /// watermark, from the leader or from a follower that learned it from its leader, and
+    /// read_committed ones only up to the last stable offset, along with the aborted
+    /// transactions in what they got.
+    pub async fn read_records(
+        &self,
+        topic: &str,
+        partition_id: i32,
+        fetch_offset: i64,
+        max_bytes: usize,
+        isolation: FetchIsolation,
+    ) -> Result<FetchedRecords, ReplicationError> {
+        let high_watermark = if isolation == FetchIsolation::LogEnd {
+            self.leader_high_watermark(topic, partition_id).await
+        } else {
+            self.high_watermark(topic, partition_id).await
+        };
+        let Some(high_watermark) = high_watermark else {
+            return Err(ReplicationError::NotLeaderOrFollower(topic.to_string(), partition_id));
+        };
+
+        let mut logs = self.partition_logs.write().await;
+        let log = logs
+            .get_mut(&(topic.to_string(), partition_id))
+            .ok_or_else(|| ReplicationError::UnknownTopicOrPartition(topic.to_string(), partition_id))?;
+
+        let last_stable_offset = log
+            .first_unstable_offset()
+            .map_or(high_watermark, |offset| offset.min(high_watermark));
+        let max_offset = match isolation {
+            FetchIsolation::LogEnd => i64::MAX,
+            FetchIsolation::HighWatermark => high_watermark,
+            FetchIsolation::TxnCommitted => last_stable_offset,
+        };
+        let log_start_offset = log.log_start_offset();
+        let log_end_offset = log.log_end_offset();
+        if fetch_offset < log_start_offset || fetch_offset > log_end_offset {
+            return Err(ReplicationError::OffsetOutOfRange(topic.to_string(), partition_id, fetch_offset));
+        }
+
+        let mut records = Vec::new();
+        let mut end_offset = fetch_offset;
+        for (offset, mut batch) in log.read_from(fetch_offset, max_bytes)? {
+            if offset >= max_offset {
+                break;
+            }
+            record_batch::set_base_offset(&mut batch, offset);
+            end_offset = offset + record_batch::record_count(&batch).unwrap_or(1);
+            records.extend_from_slice(&batch);
+        }
+
+        let aborted_transactions = match isolation {
+            FetchIsolation::TxnCommitted => Some(log.collect_aborted_txns(fetch_offset, end_offset)?),
+            _ => None,
+        };
+
+        Ok(FetchedRecords {
+            records,
+            high_watermark,
+            last_stable_offset,
+            log_start_offset,
+            aborted_transactions,
+        })
+    }
+
+    /// reads every partition of a Fetch, sharing its max_bytes across them
+    pub async fn read_from_local_log(&self, params: &FetchParams, partitions: &[FetchPartitionStatus]) -> Vec<FetchPartitionData> {
+        let mut remaining_bytes = params.max_bytes.max(0) as usize;
+        let mut data = Vec::with_capacity(partitions.len());
+        for status in partitions {
+            let (topic, partition_id) = (status.tp.topic(), status.tp.partition());
+            let max_bytes = remaining_bytes.min(status.max_bytes.max(0) as usize);
+            let mut partition = FetchPartitionData {
+                tp: status.tp.clone(),
+                error: KafkaErrorCode::None,
+                high_watermark: -1,
+                last_stable_offset: -1,
+                log_start_offset: -1,
+                aborted_transactions: None,
+                preferred_read_replica: None,
+                records: Vec::new(),
+            };
+            // only assigned replicas may read past the high watermark
+            if params.is_from_follower() && !self.is_assigned_replica(topic, partition_id, params.replica_id).await {
+                partition.error = KafkaErrorCode::NotLeaderOrFollower;
+                data.push(partition);
+                continue;
+            }
+            if let Err(e) = self.validate_current_leader_epoch(topic, partition_id, status.current_leader_epoch).await {
+                println!("Fetch of {}-{} failed: {}", topic, partition_id, e);
+                partition.error = e.error_code();
+                data.push(partition);
+                continue;
+            }
+            match self.read_records(topic, partition_id, status.fetch_offset, max_bytes, params.isolation()).await {
+                Ok(fetched) => {
+                    remaining_bytes = remaining_bytes.saturating_sub(fetched.records.len());
+                    partition.high_watermark = fetched.high_watermark;
+                    partition.last_stable_offset = fetched.last_stable_offset;
+                    partition.log_start_offset = fetched.log_start_offset;
+                    partition.aborted_transactions = fetched.aborted_transactions;
+                    partition.records = fetched.records;
+                }
+                Err(e) => {
+                    println!("Fetch of {}-{} failed: {}", topic, partition_id, e);
+                    // tells a follower that fell behind where to start over
+                    if matches!(e, ReplicationError::OffsetOutOfRange(_, _, _)) {
+                        partition.log_start_offset = self.log_start_offset(topic, partition_id).await.unwrap_or(-1);
+                    }
+                    partition.error = e.error_code();
+                }
+            }
+            data.push(partition);
+        }
+        data
+    }
+
+    async fn leader_high_watermark(&self, topic: &str, partition_id: i32) -> Option<i64> {
+        let leaders = self.leader_partitions.read().await;
+        leaders.get(&(topic.to_string(), partition_id)).map(|leader| leader.high_watermark)
+    }
+
+    pub async fn update_follower_high_watermark(&self, topic: &str, partition_id: i32, high_watermark: i64) {
+        let mut followers = self.follower_partitions.write().await;
+        if let Some(follower) = followers.get_mut(&(topic.to_string(), partition_id)) {
+            follower.high_watermark = high_watermark;
+        }
+    }
+
+    /// appends produced batches on the leader, assigning their offsets.
+    /// Returns the base offset of the first batch and the last offset written.
+    pub async fn append_as_leader(&self, topic: &str, partition_id: i32, records: &[u8]) -> Result<(i64, i64), ReplicationError> {
+        if !self.is_leader(topic, partition_id).await {
+            return Err(ReplicationError::NotLeaderOrFollower(topic.to_string(), partition_id));
+        }
+
+        let batches = record_batch::split_batches(records);
+        let consumed: usize = batches.iter().map(|batch| batch.len()).sum();
+        if batches.is_empty() || consumed != records.len() || batches.iter().any(|batch| record_batch::magic(batch) != Some(2)) {
+            return Err(ReplicationError::CorruptRecords);
+        }
+
+        let leader_epoch = {
+            let leaders = self.leader_partitions.read().await;
+            leaders.get(&(topic.to_string(), partition_id)).map_or(-1, |leader| leader.leader_epoch)
+        };
+        let (base_offset, last_offset) = {
+            let mut logs = self.partition_logs.write().await;
+            let log = logs
+                .get_mut(&(topic.to_string(), partition_id))
+                .ok_or_else(|| ReplicationError::UnknownTopicOrPartition(topic.to_string(), partition_id))?;
+
+            // every batch is checked before any is written, so a refused one doesn't
+            // leave the others behind
+            let producer_batches: Vec<ProducerBatch> = batches.iter().filter_map(|batch| ProducerBatch::from_batch(batch)).collect();
+            log.check_producer_batches(&producer_batches)?;
+
+            let base_offset = log.log_end_offset();
+            for batch in batches {
+                let mut batch = batch.to_vec();
+                record_batch::set_base_offset(&mut batch, log.log_end_offset());
+                record_batch::set_partition_leader_epoch(&mut batch, leader_epoch);
+                let timestamp = record_batch::max_timestamp(&batch)
+                    .filter(|ts| *ts >= 0)
+                    .unwrap_or_else(|| Utc::now().timestamp_millis());
+                log.append_record_batch(&batch, timestamp)?;
+            }
+            (base_offset, log.log_end_offset() - 1)
+        };
+
+        self.update_leader_log_end_offset(topic, partition_id).await;
+        Ok((base_offset, last_offset))
+    }
+
+    /// appends batches fetched from the leader, skipping any the log already has.
+    /// Returns the new log end offset and the leader epoch of the last batch.
+    pub async fn append_as_follower(&self, topic: &str, partition_id: i32, records: &[u8]) -> Result<Option<(i64, i32)>, ReplicationError> {
+        let mut logs = self.partition_logs.write().await;
+        let log = logs
+            .get_mut(&(topic.to_string(), partition_id))
+            .ok_or_else(|| ReplicationError::UnknownTopicOrPartition(topic.to_string(), partition_id))?;
+
+        let mut last_epoch = None;
+        for batch in record_batch::split_batches(records) {
+            let base_offset = record_batch::base_offset(batch).unwrap_or(-1);
+            if base_offset < log.log_end_offset() {
+                continue;
+            }
+            if base_offset > log.log_end_offset() {
+                return Err(ReplicationError::NonContiguousBatch(log.log_end_offset(), base_offset));
+            }
+
+            let epoch = record_batch::partition_leader_epoch(batch).unwrap_or(-1);
+            if epoch >= 0 {
+                log.assign_epoch(epoch, base_offset)?;
+            }
+            let timestamp = record_batch::max_timestamp(batch)
+                .filter(|ts| *ts >= 0)
+                .unwrap_or_else(|| Utc::now().timestamp_millis());
+            log.append_record_batch(batch, timestamp)?;
+            last_epoch = Some(epoch);
+        }
+
+        Ok(last_epoch.map(|epoch| (log.log_end_offset(), epoch)))
+    }
+
     pub async fn update_follower_fetch(&self, topic: String, partition_id: i32, follower_id: i32, fetch_offset: i64, fetch_epoch: i32) {
         let key = (topic.clone(), partition_id);
         let mut followers = self.follower_partitions.write().await;
@@ -134,38 +890,148 @@ impl ReplicaManager {
     }
 
 
+    /// records a follower fetch at `offset` (its log end offset), adds the follower back
+    /// to the ISR once it reaches both the high watermark and the start of the current
+    /// leader epoch, and advances the high watermark.
+    /// Fetches from brokers that aren't assigned replicas are ignored.
     pub async fn update_follower_progress(
     &self,
     topic: String,
     partition_id: i32,
     follower_id: i32,
     offset: i64,
-    ) {
+    ) -> FollowerFetchOutcome {
+        let Some(log_end_offset) = self.log_end_offset(&topic, partition_id).await else {
+            return FollowerFetchOutcome::default();
+        };
         let key = (topic.clone(), partition_id);
         let mut leaders = self.leader_partitions.write().await;
+        let Some(leader) = leaders.get_mut(&key) else {
+            return FollowerFetchOutcome::default();
+        };
+        if follower_id == self.broker_id || !leader.replicas.contains(&follower_id) {
+            return FollowerFetchOutcome::default();
+        }
+        let now = Utc::now().timestamp_millis();
+        leader.last_offset = log_end_offset;
 
-        if let Some(leader) = leaders.get_mut(&key) {
-            let progress = FollowerProgress {
-                broker_id: follower_id,
-                last_fetched_offset: offset,
-                last_fetch_timestamp: Utc::now().timestamp_millis(),
-            };
-            leader.followers.insert(follower_id, progress);
+        let progress = leader.followers.entry(follower_id).or_insert(FollowerProgress {
+            last_fetched_offset: offset,
+            last_fetch_timestamp: now,
+            last_fetch_leader_log_end_offset: log_end_offset,
+            last_caught_up_timestamp: 0,
+        });
+        // caught up now, or at least with what the leader had at the previous fetch
+        if offset >= log_end_offset {
+            progress.last_caught_up_timestamp = now;
+        } else if offset >= progress.last_fetch_leader_log_end_offset {
+            progress.last_caught_up_timestamp = progress.last_caught_up_timestamp.max(progress.last_fetch_timestamp);
+        }
+        progress.last_fetched_offset = offset;
+        progress.last_fetch_timestamp = now;
+        progress.last_fetch_leader_log_end_offset = log_end_offset;
+
+        let mut expanded = false;
+        // a follower short of the epoch start may still hold a divergent tail of an older epoch
+        let caught_up = offset >= leader.high_watermark && offset >= leader.leader_epoch_start_offset;
+        if !leader.isr.contains(&follower_id) && caught_up {
+            leader.isr.push(follower_id);
+            leader.isr.sort_unstable();
+            leader.last_update_timestamp = now;
+            expanded = true;
+            println!("Expanding ISR of {}-{} to {:?}", topic, partition_id, leader.isr);
+        }
+        let high_watermark_advanced = leader.maybe_advance_high_watermark(self.broker_id);
+
+        FollowerFetchOutcome {
+            isr_change: expanded.then(|| IsrChange {
+                topic,
+                partition_id,
+                leader_epoch: leader.leader_epoch,
+                isr: leader.isr.clone(),
+                high_watermark: leader.high_watermark,
+            }),
+            high_watermark_advanced,
+        }
+    }
+
+    /// advances the high watermark after the leader's own log grew
+    pub async fn update_leader_log_end_offset(&self, topic: &str, partition_id: i32) -> Option<i64> {
+        let log_end_offset = self.log_end_offset(topic, partition_id).await?;
+        let mut leaders = self.leader_partitions.write().await;
+        let leader = leaders.get_mut(&(topic.to_string(), partition_id))?;
+        leader.last_offset = log_end_offset;
+        leader.maybe_advance_high_watermark(self.broker_id);
+        Some(leader.high_watermark)
+    }
+
+    /// drops followers that haven't caught up within replica.lag.time.max.ms
+    pub async fn maybe_shrink_isr(&self, now_ms: i64) -> Vec<IsrChange> {
+        let mut leaders = self.leader_partitions.write().await;
+        let mut changes = Vec::new();
+        for ((topic, partition_id), leader) in leaders.iter_mut() {
+            let lagging: Vec<i32> = leader
+                .isr
+                .iter()
+                .copied()
+                .filter(|replica| *replica != self.broker_id)
+                .filter(|replica| {
+                    let caught_up = leader.followers.get(replica).map_or(leader.last_update_timestamp, |p| p.last_caught_up_timestamp);
+                    now_ms - caught_up > REPLICA_LAG_TIME_MAX_MS
+                })
+                .collect();
+            if lagging.is_empty() {
+                continue;
+            }
+
+            leader.isr.retain(|replica| !lagging.contains(replica));
+            leader.last_update_timestamp = now_ms;
+            leader.maybe_advance_high_watermark(self.broker_id);
+            println!("Shrinking ISR of {}-{} to {:?}, lagging: {:?}", topic, partition_id, leader.isr, lagging);
+            changes.push(IsrChange {
+                topic: topic.clone(),
+                partition_id: *partition_id,
+                leader_epoch: leader.leader_epoch,
+                isr: leader.isr.clone(),
+                high_watermark: leader.high_watermark,
+            });
         }
+        changes
     }
 
     pub async fn flush_state(&self) {
-    let leaders = self.leader_partitions.read().await;
+        let leaders = self.leader_partitions.read().await;
+        for ((topic, partition_id), state) in leaders.iter() {
+            self.write_metadata(topic, *partition_id, state).await;
+        }
+        drop(leaders);
+        // spares replaying the logs for producer state on the next start
+        let logs = self.partition_logs.read().await;
+        for ((topic, partition_id), log) in logs.iter() {
+            if let Err(e) = log.take_producer_snapshot() {
+                eprintln!("Failed to snapshot producer state of {}-{}: {}", topic, partition_id, e);
+            }
+        }
+    }
+
+    /// persists one partition, e.g. right after its ISR changed
+    pub async fn flush_partition_state(&self, topic: &str, partition_id: i32) {
+        let leaders = self.leader_partitions.read().await;
+        if let Some(state) = leaders.get(&(topic.to_string(), partition_id)) {
+            self.write_metadata(topic, partition_id, state).await;
+        }
+    }
 
-    for ((topic, partition_id), state) in leaders.iter() {
+    async fn write_metadata(&self, topic: &str, partition_id: i32, state: &LeaderState) {
         let metadata = PartitionMetadata {
             leader_offset: state.last_offset,
+            high_watermark: state.high_watermark,
             isr: state.isr.clone(),
             timestamp: state.last_update_timestamp,
         };
 
-        let path = format!("data/{}/{}-metadata.json", topic, partition_id);
-        if let Some(parent) = std::path::Path::new(&path).parent() {
+        let path = self.log_dir.join(format!("{}-{}", topic, partition_id)).join(PARTITION_METADATA_FILE);
+        if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent).ok();
         }
 
@@ -173,11 +1039,11 @@ impl ReplicaManager {
             Ok(f) => f,
             Err(e) => {
                 eprintln!("Failed to create metadata file: {}", e);
-                continue;
+                return;
             }
         };
         let json = match serde_json::to_string_pretty(&metadata) {
-            Ok(j) => j, 
+            Ok(j) => j,
             Err(e) => {
                 eprintln!("Failed to serialize metadata: {}", e);
                 return;
@@ -190,4 +1056,222 @@ impl ReplicaManager {
     }
 }
 
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::consumer_group::TopicPartition;
+
+    fn temp_replicas(broker_id: i32) -> (PathBuf, ReplicaManager) {
+        let dir = std::env::temp_dir().join(format!("rafka-replication-{}", Uuid::new_v4()));
+        (dir.clone(), ReplicaManager::with_log_dir(broker_id, dir))
+    }
+
+    async fn append(replicas: &ReplicaManager, count: usize) {
+        for i in 0..count {
+            let batch = record_batch::build_batch(0, 0, 1000, &[format!("value-{}", i).into_bytes()]);
+            replicas.append_as_leader("orders", 0, &batch).await.unwrap();
+        }
+    }
+
+    fn replica_fetch(replica_id: i32, fetch_offset: i64) -> (FetchParams, Vec<FetchPartitionStatus>) {
+        replica_fetch_in_epoch(replica_id, fetch_offset, -1)
+    }
+
+    fn replica_fetch_in_epoch(replica_id: i32, fetch_offset: i64, current_leader_epoch: i32) -> (FetchParams, Vec<FetchPartitionStatus>) {
+        let params = FetchParams { replica_id, max_wait_ms: 0, min_bytes: 1, max_bytes: i32::MAX, isolation_level: 0, client_metadata: None };
+        let status = FetchPartitionStatus {
+            tp: TopicPartition::new("orders".to_string(), 0),
+            fetch_offset,
+            current_leader_epoch,
+            max_bytes: i32::MAX,
+        };
+        (params, vec![status])
+    }
+
+    #[tokio::test]
+    async fn fetches_from_unassigned_brokers_are_rejected() {
+        let (dir, replicas) = temp_replicas(0);
+        replicas.add_leader_partition("orders".to_string(), 0, 0, vec![0, 1], vec![0]).await.unwrap();
+        append(&replicas, 3).await;
+
+        // an assigned follower reads past the HW and joins the ISR once it catches up
+        let (params, partitions) = replica_fetch(1, 0);
+        let data = replicas.read_from_local_log(&params, &partitions).await;
+        assert_eq!(data[0].error, KafkaErrorCode::None);
+        assert!(!data[0].records.is_empty());
+        let outcome = replicas.update_follower_progress("orders".to_string(), 0, 1, 3).await;
+        assert!(outcome.isr_change.is_some());
+
+        // any other replica id neither reads nor joins the ISR
+        let (params, partitions) = replica_fetch(7, 0);
+        let data = replicas.read_from_local_log(&params, &partitions).await;
+        assert_eq!(data[0].error, KafkaErrorCode::NotLeaderOrFollower);
+        assert!(data[0].records.is_empty());
+        let outcome = replicas.update_follower_progress("orders".to_string(), 0, 7, 3).await;
+        assert!(outcome.isr_change.is_none());
+        assert_eq!(replicas.isr("orders", 0).await, Some(vec![0, 1]));
+
+        // a replica reassigned away loses its access as well
+        replicas.set_replicas("orders", 0, vec![0, 2]).await;
+        let (params, partitions) = replica_fetch(1, 3);
+        let data = replicas.read_from_local_log(&params, &partitions).await;
+        assert_eq!(data[0].error, KafkaErrorCode::NotLeaderOrFollower);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn follower_behind_the_leader_log_start_starts_over_there() {
+        let (dir, replicas) = temp_replicas(1);
+        replicas.add_follower_partition("orders".to_string(), 0, 0, 0, 1, 0).await.unwrap();
+        let batch = record_batch::build_batch(0, 0, 1000, &[b"old".to_vec()]);
+        replicas.append_as_follower("orders", 0, &batch).await.unwrap();
+
+        // within the leader's log it truncates through OffsetForLeaderEpoch instead
+        assert_eq!(replicas.handle_offset_out_of_range("orders", 0, 0).await.unwrap(), 0);
+        assert_eq!(replicas.follower_truncation_states(0).await.len(), 1);
+        replicas.truncate_to_epoch_end("orders", 0, 0, 1).await.unwrap();
+
+        assert_eq!(replicas.handle_offset_out_of_range("orders", 0, 50).await.unwrap(), 50);
+        let states = replicas.follower_fetch_states(0).await;
+        assert_eq!((states[0].fetch_offset, states[0].last_fetched_epoch), (50, -1));
+        assert_eq!(replicas.log_end_offset("orders", 0).await, Some(50));
+        assert_eq!(replicas.log_start_offset("orders", 0).await, Some(50));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn requests_in_another_leader_epoch_are_fenced() {
+        let (dir, replicas) = temp_replicas(0);
+        replicas.add_leader_partition("orders".to_string(), 0, 5, vec![0, 1], vec![0, 1]).await.unwrap();
+        append(&replicas, 2).await;
+
+        for (epoch, error) in [
+            (-1, KafkaErrorCode::None),
+            (5, KafkaErrorCode::None),
+            (4, KafkaErrorCode::FencedLeaderEpoch),
+            (6, KafkaErrorCode::UnknownLeaderEpoch),
+        ] {
+            let (params, partitions) = replica_fetch_in_epoch(1, 0, epoch);
+            let data = replicas.read_from_local_log(&params, &partitions).await;
+            assert_eq!(data[0].error, error, "fetch in epoch {}", epoch);
+            assert_eq!(data[0].records.is_empty(), error != KafkaErrorCode::None);
+
+            let end_offset = replicas.last_offset_for_leader_epoch("orders", 0, epoch, 5).await;
+            assert_eq!(end_offset.map_err(|e| e.error_code()), if error == KafkaErrorCode::None { Ok((5, 2)) } else { Err(error) });
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn followers_fetch_in_the_leader_epoch_they_were_given() {
+        let (dir, replicas) = temp_replicas(1);
+        replicas.add_follower_partition("orders".to_string(), 0, 0, 7, 1, 0).await.unwrap();
+        let states = replicas.follower_fetch_states(0).await;
+        assert_eq!(states[0].current_leader_epoch, 7);
+        // consumers fetching from the follower are checked against the same epoch
+        let fenced = replicas.validate_current_leader_epoch("orders", 0, 6).await;
+        assert_eq!(fenced.map_err(|e| e.error_code()), Err(KafkaErrorCode::FencedLeaderEpoch));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn high_watermark_is_the_smallest_log_end_offset_in_the_isr() {
+        let (dir, replicas) = temp_replicas(0);
+        replicas.add_leader_partition("orders".to_string(), 0, 0, vec![0, 1, 2], vec![0, 1, 2]).await.unwrap();
+        append(&replicas, 5).await;
+        assert_eq!(replicas.high_watermark("orders", 0).await, Some(0));
+
+        replicas.update_follower_progress("orders".to_string(), 0, 1, 4).await;
+        assert_eq!(replicas.high_watermark("orders", 0).await, Some(0));
+        let outcome = replicas.update_follower_progress("orders".to_string(), 0, 2, 2).await;
+        assert!(outcome.high_watermark_advanced);
+        assert_eq!(replicas.high_watermark("orders", 0).await, Some(2));
+
+        replicas.update_follower_progress("orders".to_string(), 0, 2, 5).await;
+        assert_eq!(replicas.high_watermark("orders", 0).await, Some(4));
+        // a follower refetching from further back never moves the HW back
+        replicas.update_follower_progress("orders".to_string(), 0, 1, 3).await;
+        assert_eq!(replicas.high_watermark("orders", 0).await, Some(4));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn lagging_followers_leave_the_isr() {
+        let (dir, replicas) = temp_replicas(0);
+        replicas.add_leader_partition("orders".to_string(), 0, 0, vec![0, 1, 2], vec![0, 1, 2]).await.unwrap();
+        append(&replicas, 3).await;
+        replicas.update_follower_progress("orders".to_string(), 0, 1, 3).await;
+        replicas.update_follower_progress("orders".to_string(), 0, 2, 3).await;
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        // follower 1 keeps up with the next appends, follower 2 stops fetching
+        append(&replicas, 2).await;
+        let caught_up = Utc::now().timestamp_millis();
+        replicas.update_follower_progress("orders".to_string(), 0, 1, 5).await;
+        assert_eq!(replicas.high_watermark("orders", 0).await, Some(3));
+        assert!(replicas.maybe_shrink_isr(caught_up).await.is_empty());
+
+        let changes = replicas.maybe_shrink_isr(caught_up + REPLICA_LAG_TIME_MAX_MS).await;
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].isr, vec![0, 1]);
+        // without follower 2 holding it back the HW catches up
+        assert_eq!(changes[0].high_watermark, 5);
+        assert_eq!(replicas.isr("orders", 0).await, Some(vec![0, 1]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn followers_rejoin_the_isr_at_the_high_watermark() {
+        let (dir, replicas) = temp_replicas(0);
+        replicas.add_leader_partition("orders".to_string(), 0, 0, vec![0, 1, 2], vec![0, 2]).await.unwrap();
+        append(&replicas, 4).await;
+        replicas.update_follower_progress("orders".to_string(), 0, 2, 3).await;
+        assert_eq!(replicas.high_watermark("orders", 0).await, Some(3));
+
+        let outcome = replicas.update_follower_progress("orders".to_string(), 0, 1, 2).await;
+        assert!(outcome.isr_change.is_none());
+        assert_eq!(replicas.isr("orders", 0).await, Some(vec![0, 2]));
+
+        let outcome = replicas.update_follower_progress("orders".to_string(), 0, 1, 3).await;
+        let change = outcome.isr_change.unwrap();
+        assert_eq!((change.isr, change.leader_epoch), (vec![0, 1, 2], 0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn isr_expansion_waits_for_the_leader_epoch_start() {
+        let (dir, replicas) = temp_replicas(0);
+        // four records replicated as a follower, none of them known to be committed
+        replicas.add_follower_partition("orders".to_string(), 0, 5, 0, 0, 0).await.unwrap();
+        for offset in 0..4 {
+            let batch = record_batch::build_batch(offset, 0, 1000, &[b"value".to_vec()]);
+            replicas.append_as_follower("orders", 0, &batch).await.unwrap();
+        }
+        // epoch 1 starts at offset 4, with follower 2 in the ISR holding the HW at 0
+        replicas.add_leader_partition("orders".to_string(), 0, 1, vec![0, 1, 2], vec![0, 2]).await.unwrap();
+        assert_eq!(replicas.high_watermark("orders", 0).await, Some(0));
+
+        let outcome = replicas.update_follower_progress("orders".to_string(), 0, 1, 2).await;
+        assert!(outcome.isr_change.is_none(), "follower at the HW but short of the epoch start joined");
+        let outcome = replicas.update_follower_progress("orders".to_string(), 0, 1, 4).await;
+        assert_eq!(outcome.isr_change.unwrap().isr, vec![0, 1, 2]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn consecutive_batches_of_one_producer_append_together() {
+        let (dir, replicas) = temp_replicas(0);
+        replicas.add_leader_partition("orders".to_string(), 0, 0, vec![0], vec![0]).await.unwrap();
+        let batch = |sequence: i32| record_batch::build_producer_batch(0, 7, 0, sequence, false, 1000, &[b"value".to_vec()]);
+
+        let request = [batch(0), batch(1), batch(2)].concat();
+        assert_eq!(replicas.append_as_leader("orders", 0, &request).await.unwrap(), (0, 2));
+        // a retry of the whole request is a duplicate, and a gap inside one is refused
+        let retried = replicas.append_as_leader("orders", 0, &request).await.unwrap_err();
+        assert_eq!(retried.error_code(), KafkaErrorCode::DuplicateSequenceNumber);
+        let gap = replicas.append_as_leader("orders", 0, &[batch(3), batch(5)].concat()).await.unwrap_err();
+        assert_eq!(gap.error_code(), KafkaErrorCode::OutOfOrderSequenceNumber);
+        assert_eq!(replicas.append_as_leader("orders", 0, &[batch(3), batch(4)].concat()).await.unwrap(), (3, 4));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,22 +1,75 @@
-use std::{collections::HashMap, sync::Arc};
-use crate::{core::partition::{Message, Partition}};
+use std::{collections::{BTreeMap, HashMap}, sync::Arc};
+use crate::core::metadata::{
+    CLEANUP_POLICY_CONFIG, MAX_MESSAGE_BYTES_CONFIG, MIN_INSYNC_REPLICAS_CONFIG, RETENTION_MS_CONFIG,
+    UNCLEAN_LEADER_ELECTION_ENABLE_CONFIG,
+};
+use crate::core::partition::{Message, Partition};
+use crate::core::placement::{self, PlacementBroker};
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 use thiserror::Error;
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct Topic {
     name: String,
+    topic_id: Uuid,
     partitions: RwLock<HashMap<i32, Arc<Partition>>>,
     replication_factor: i32,
     config: TopicConfig,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TopicConfig {
     cleanup_policy: String,          // delete or compact
     retention_ms: i64,              // how long to keep messages
     max_message_bytes: i32,         // maximum size of a message
     min_insync_replicas: i32,       // minimum number of replicas that must acknowledge writes
+    unclean_leader_election_enable: bool, // allow out-of-sync replicas to become leader, losing data
+}
+
+// broker defaults, used until a topic carries its own overrides
+impl Default for TopicConfig {
+    fn default() -> Self {
+        TopicConfig {
+            cleanup_policy: "delete".to_string(),
+            retention_ms: 7 * 24 * 60 * 60 * 1000,
+            max_message_bytes: 1024 * 1024,
+            min_insync_replicas: 1,
+            unclean_leader_election_enable: false,
+        }
+    }
+}
+
+impl TopicConfig {
+    /// broker defaults with a topic's overrides applied on top
+    pub fn from_overrides(overrides: &BTreeMap<String, String>) -> Result<Self, TopicError> {
+        let mut config = TopicConfig::default();
+        for (name, value) in overrides {
+            let invalid = || TopicError::InvalidConfig(name.clone(), value.clone());
+            match name.as_str() {
+                CLEANUP_POLICY_CONFIG => match value.as_str() {
+                    "delete" | "compact" => config.cleanup_policy = value.clone(),
+                    _ => return Err(invalid()),
+                },
+                RETENTION_MS_CONFIG => {
+                    // -1 keeps messages forever
+                    config.retention_ms = value.parse().ok().filter(|ms| *ms >= -1).ok_or_else(invalid)?;
+                }
+                MAX_MESSAGE_BYTES_CONFIG => {
+                    config.max_message_bytes = value.parse().ok().filter(|bytes| *bytes >= 0).ok_or_else(invalid)?;
+                }
+                MIN_INSYNC_REPLICAS_CONFIG => {
+                    config.min_insync_replicas = value.parse().ok().filter(|n| *n >= 1).ok_or_else(invalid)?;
+                }
+                UNCLEAN_LEADER_ELECTION_ENABLE_CONFIG => {
+                    config.unclean_leader_election_enable = value.parse().map_err(|_| invalid())?;
+                }
+                _ => return Err(TopicError::UnknownConfig(name.clone())),
+            }
+        }
+        Ok(config)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -27,18 +80,44 @@ pub enum TopicError {
     #[error("Message too large")]
     MessageTooLarge,
 
+    #[error("Topic {0} already exists")]
+    TopicAlreadyExists(String),
+
+    #[error("Unknown topic config {0}")]
+    UnknownConfig(String),
+
+    #[error("Invalid value {1} for topic config {0}")]
+    InvalidConfig(String, String),
+
     #[error("Unknown error")]
     Unknown,
 }
 
 impl Topic {
     pub fn new(name:String, replication_factor:i32, config: TopicConfig) -> Self {
-        Topic { name, partitions: RwLock::new(HashMap::new()),  replication_factor, config }
+        Self::with_id(name, Uuid::new_v4(), replication_factor, config)
     }
 
-    pub async fn add_partition(&mut self, partition_id: i32, partition: Partition) {
+    // replicas of a topic must agree on its id, since Fetch addresses topics by id
+    pub fn with_id(name: String, topic_id: Uuid, replication_factor: i32, config: TopicConfig) -> Self {
+        Topic { name, topic_id, partitions: RwLock::new(HashMap::new()), replication_factor, config }
+    }
+
+    // fills in the partitions of a topic that isn't shared yet
+    pub fn insert_partition(&mut self, partition_id: i32, partition: Partition) {
+        self.partitions.get_mut().insert(partition_id, Arc::new(partition));
+    }
+
+    /// adds a partition unless the topic already has one with this id; either way
+    /// returns the one it now has
+    pub async fn add_partition(&self, partition_id: i32, partition: Partition) -> Arc<Partition> {
         let mut partitions = self.partitions.write().await;
-        partitions.insert(partition_id, Arc::new(partition));
+        Arc::clone(partitions.entry(partition_id).or_insert_with(|| Arc::new(partition)))
+    }
+
+    pub async fn remove_partition(&self, partition_id: i32) -> Option<Arc<Partition>> {
+        let mut partitions = self.partitions.write().await;
+        partitions.remove(&partition_id)
     }
 
     pub async fn enforce_retention(&mut self, now_ms: i64) {
@@ -48,7 +127,7 @@ impl Topic {
         for partition in partitions.values_mut() {
             match self.config.cleanup_policy.as_str() {
                 // deletes old messages based on time or size.
-                "delete" => {
+                "delete" if self.config.retention_ms >= 0 => {
                     let mut log = partition.log_write().await;
                     log.truncate_before_timestamp(cutoff);
                 }
@@ -57,6 +136,7 @@ impl Topic {
                     let mut log = partition.log_write().await;
                     log.compact();
                 }
+                "delete" => {}
                 _ => {
                     eprintln!("Unknown cleanup policy: {}", self.config.cleanup_policy);
                 }
@@ -101,12 +181,35 @@ impl Topic {
         &self.name
     }
 
-    pub fn assign_replicas(&self, broker_ids: &[i32]) -> Vec<i32> {
-        broker_ids
-            .iter()
-            .cloned()
-            .take(self.replication_factor as usize)
-            .collect()
+    pub fn topic_id(&self) -> Uuid {
+        self.topic_id
+    }
+
+    /// replicas for `num_partitions` partitions of this topic, spread over `brokers`
+    /// and their racks; none if there are fewer brokers than the replication factor
+    pub fn assign_replicas(&self, brokers: &[PlacementBroker], num_partitions: i32) -> Vec<Vec<i32>> {
+        if self.replication_factor <= 0 || brokers.len() < self.replication_factor as usize {
+            return Vec::new();
+        }
+        let start_index = placement::random_start(brokers.len());
+        let replica_shift = placement::random_start(brokers.len());
+        placement::assign_replicas(brokers, num_partitions, self.replication_factor, start_index, replica_shift)
+    }
+
+    pub fn replication_factor(&self) -> i32 {
+        self.replication_factor
+    }
+
+    pub fn config(&self) -> &TopicConfig {
+        &self.config
+    }
+
+    pub fn max_message_bytes(&self) -> i32 {
+        self.config.max_message_bytes
+    }
+
+    pub fn unclean_leader_election_enable(&self) -> bool {
+        self.config.unclean_leader_election_enable
     }
 
     pub async fn has_enough_replicas(&self, partition_id: i32) -> bool {
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::constants::{AUTO_CREATE_TOPICS_ENABLE, TRANSACTION_STATE_TOPIC};
+use crate::core::controller::{Controller, ControllerError, NewTopic};
+use crate::core::partition::Partition;
+use crate::core::replication::DELETED_DIR_SUFFIX;
+use crate::core::topic::{Topic, TopicConfig, TopicError};
+use crate::error::KafkaErrorCode;
+
+// partition directories are named {topic}-{partition}, so no partition can land here
+const TOPICS_DIR: &str = "__topics";
+
+// what's kept of a topic across restarts
+#[derive(Debug, Serialize, Deserialize)]
+struct TopicMetadata {
+    name: String,
+    topic_id: Uuid,
+    partitions: Vec<i32>,
+    replication_factor: i32,
+    config: TopicConfig,
+}
+
+#[derive(Debug, Default)]
+struct Topics {
+    by_name: HashMap<String, Arc<Topic>>,
+    by_id: HashMap<Uuid, Arc<Topic>>,
+}
+
+/// every topic this broker hosts, looked up by name or by topic id. Each topic's
+/// metadata is written to {log_dir}/__topics/{topic_id}.json whenever it changes,
+/// so topics come back when the broker restarts.
+#[derive(Debug)]
+pub struct TopicManager {
+    log_dir: PathBuf,
+    topics: RwLock<Topics>,
+}
+
+impl TopicManager {
+    /// reads back the topics persisted under `log_dir`; unreadable ones are skipped.
+    /// Partition directories whose deletion a restart interrupted are removed.
+    pub fn load(log_dir: PathBuf) -> Self {
+        remove_deleted_dirs(&log_dir);
+        let mut topics = Topics::default();
+        let entries = std::fs::read_dir(log_dir.join(TOPICS_DIR)).into_iter().flatten().flatten();
+        for entry in entries {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let metadata: TopicMetadata = match std::fs::read(&path).map(|json| serde_json::from_slice(&json)) {
+                Ok(Ok(metadata)) => metadata,
+                Ok(Err(e)) => {
+                    eprintln!("Skipping topic metadata {}, it is corrupt: {}", path.display(), e);
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Skipping topic metadata {}, failed to read it: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let mut topic = Topic::with_id(metadata.name, metadata.topic_id, metadata.replication_factor, metadata.config);
+            for partition_id in metadata.partitions {
+                topic.insert_partition(partition_id, Partition::new(partition_id));
+            }
+            let topic = Arc::new(topic);
+            topics.by_id.insert(metadata.topic_id, Arc::clone(&topic));
+            topics.by_name.insert(topic.name().to_string(), topic);
+        }
+        if !topics.by_name.is_empty() {
+            println!("Loaded {} topics from {}", topics.by_name.len(), log_dir.display());
+        }
+        TopicManager { log_dir, topics: RwLock::new(topics) }
+    }
+
+    pub async fn get(&self, name: &str) -> Option<Arc<Topic>> {
+        self.topics.read().await.by_name.get(name).cloned()
+    }
+
+    pub async fn get_by_id(&self, topic_id: Uuid) -> Option<Arc<Topic>> {
+        self.topics.read().await.by_id.get(&topic_id).cloned()
+    }
+
+    pub async fn all_topics(&self) -> Vec<Arc<Topic>> {
+        self.topics.read().await.by_name.values().cloned().collect()
+    }
+
+    pub async fn partitions_per_topic(&self) -> HashMap<String, i32> {
+        let topics = self.all_topics().await;
+        let mut counts = HashMap::with_capacity(topics.len());
+        for topic in topics {
+            counts.insert(topic.name().to_string(), topic.num_partitions().await as i32);
+        }
+        counts
+    }
+
+    /// registers and persists a new topic; its name and id must both be unused
+    pub async fn create(&self, topic: Topic) -> Result<Arc<Topic>, TopicError> {
+        let topic = Arc::new(topic);
+        {
+            let mut topics = self.topics.write().await;
+            if topics.by_name.contains_key(topic.name()) || topics.by_id.contains_key(&topic.topic_id()) {
+                return Err(TopicError::TopicAlreadyExists(topic.name().to_string()));
+            }
+            topics.by_name.insert(topic.name().to_string(), Arc::clone(&topic));
+            topics.by_id.insert(topic.topic_id(), Arc::clone(&topic));
+        }
+        self.persist(&topic).await;
+        Ok(topic)
+    }
+
+    /// looks a topic up for a client, asking the controller running on this broker to
+    /// create a missing one with the broker defaults. The client gets
+    /// LeaderNotAvailable and retries once the controller has pushed the new topic.
+    pub async fn get_or_auto_create(&self, name: &str, controller: Option<&Arc<Controller>>) -> Result<Arc<Topic>, KafkaErrorCode> {
+        if let Some(topic) = self.get(name).await {
+            return Ok(topic);
+        }
+        // internal topics are created with their own settings when they're first needed
+        let Some(controller) = controller.filter(|_| AUTO_CREATE_TOPICS_ENABLE && name != TRANSACTION_STATE_TOPIC) else {
+            return Err(KafkaErrorCode::UnknownTopicOrPartition);
+        };
+        let topic = NewTopic { name: name.to_string(), num_partitions: -1, replication_factor: -1, ..NewTopic::default() };
+        match controller.create_topic(&topic, false).await {
+            Ok(_) | Err(ControllerError::TopicAlreadyExists(_)) => Err(KafkaErrorCode::LeaderNotAvailable),
+            Err(e) => {
+                eprintln!("Failed to auto-create topic {}: {}", name, e);
+                Err(e.error_code())
+            }
+        }
+    }
+
+    /// adds the partitions a topic doesn't have yet, persisting it if any were new
+    pub async fn add_partitions(&self, topic: &Topic, partition_ids: &[i32]) {
+        let mut added = false;
+        for &partition_id in partition_ids {
+            if topic.get_partition(partition_id).await.is_none() {
+                topic.add_partition(partition_id, Partition::new(partition_id)).await;
+                added = true;
+            }
+        }
+        if added {
+            self.persist(topic).await;
+        }
+    }
+
+    /// drops a partition of a deleted topic. The topic is deleted with its last
+    /// partition, unless another topic has taken its name meanwhile.
+    pub async fn remove_partition(&self, topic: &Arc<Topic>, partition_id: i32) {
+        topic.remove_partition(partition_id).await;
+        if topic.num_partitions().await > 0 {
+            self.persist(topic).await;
+            return;
+        }
+        {
+            let mut topics = self.topics.write().await;
+            if !topics.by_name.get(topic.name()).is_some_and(|current| Arc::ptr_eq(current, topic)) {
+                return;
+            }
+            topics.by_name.remove(topic.name());
+            topics.by_id.remove(&topic.topic_id());
+        }
+        if let Err(e) = std::fs::remove_file(self.metadata_path(topic)) {
+            eprintln!("Failed to delete metadata of topic {}: {}", topic.name(), e);
+        }
+    }
+
+    fn metadata_path(&self, topic: &Topic) -> PathBuf {
+        self.log_dir.join(TOPICS_DIR).join(format!("{}.json", topic.topic_id()))
+    }
+
+    async fn persist(&self, topic: &Topic) {
+        let mut partitions = topic.all_partitions().await;
+        partitions.sort_unstable();
+        let metadata = TopicMetadata {
+            name: topic.name().to_string(),
+            topic_id: topic.topic_id(),
+            partitions,
+            replication_factor: topic.replication_factor(),
+            config: topic.config().clone(),
+        };
+        if let Err(e) = write_durably(&self.metadata_path(topic), &metadata) {
+            eprintln!("Failed to persist metadata of topic {}: {}", topic.name(), e);
+        }
+    }
+}
+
+// written aside, synced and renamed over the old file, then the directory is synced
+// so the rename survives a crash too
+fn write_durably(path: &Path, metadata: &TopicMetadata) -> std::io::Result<()> {
+    let dir = path.parent().expect("metadata files live in the topics directory");
+    std::fs::create_dir_all(dir)?;
+    let tmp = path.with_extension("json.tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(&serde_json::to_vec_pretty(metadata)?)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    File::open(dir)?.sync_all()
+}
+
+fn remove_deleted_dirs(log_dir: &Path) {
+    for entry in std::fs::read_dir(log_dir).into_iter().flatten().flatten() {
+        if entry.file_name().to_string_lossy().ends_with(DELETED_DIR_SUFFIX) {
+            if let Err(e) = std::fs::remove_dir_all(entry.path()) {
+                eprintln!("Failed to delete {}: {}", entry.path().display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::core::metadata::MAX_MESSAGE_BYTES_CONFIG;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("rafka-topics-{}", Uuid::new_v4()))
+    }
+
+    async fn create(manager: &TopicManager, name: &str, partitions: &[i32], config: TopicConfig) -> Arc<Topic> {
+        let topic = manager.create(Topic::new(name.to_string(), 3, config)).await.unwrap();
+        manager.add_partitions(&topic, partitions).await;
+        topic
+    }
+
+    #[tokio::test]
+    async fn topics_survive_reload() {
+        let dir = temp_dir();
+        let manager = TopicManager::load(dir.clone());
+        let overrides = BTreeMap::from([(MAX_MESSAGE_BYTES_CONFIG.to_string(), "2048".to_string())]);
+        let orders = create(&manager, "orders", &[0, 1, 2], TopicConfig::from_overrides(&overrides).unwrap()).await;
+        // shares its name with the directory of partition 0 of "orders"
+        let orders_0 = create(&manager, "orders-0", &[0], TopicConfig::default()).await;
+
+        let reloaded = TopicManager::load(dir.clone());
+        let topic = reloaded.get("orders").await.unwrap();
+        assert_eq!(topic.topic_id(), orders.topic_id());
+        assert_eq!(topic.replication_factor(), 3);
+        assert_eq!(topic.max_message_bytes(), 2048);
+        let mut partitions = topic.all_partitions().await;
+        partitions.sort_unstable();
+        assert_eq!(partitions, vec![0, 1, 2]);
+        assert_eq!(reloaded.get_by_id(orders_0.topic_id()).await.unwrap().name(), "orders-0");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleted_topic_is_gone_after_reload() {
+        let dir = temp_dir();
+        let manager = TopicManager::load(dir.clone());
+        let orders = create(&manager, "orders", &[0, 1], TopicConfig::default()).await;
+        create(&manager, "orders-0", &[0], TopicConfig::default()).await;
+
+        manager.remove_partition(&orders, 0).await;
+        let reloaded = TopicManager::load(dir.clone());
+        assert_eq!(reloaded.get("orders").await.unwrap().all_partitions().await, vec![1]);
+
+        manager.remove_partition(&orders, 1).await;
+        assert!(manager.get("orders").await.is_none());
+        let reloaded = TopicManager::load(dir.clone());
+        assert!(reloaded.get("orders").await.is_none());
+        assert!(reloaded.get_by_id(orders.topic_id()).await.is_none());
+        assert!(reloaded.get("orders-0").await.is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn interrupted_deletions_are_cleaned_up() {
+        let dir = temp_dir();
+        let manager = TopicManager::load(dir.clone());
+        create(&manager, "orders-0", &[0], TopicConfig::default()).await;
+        let deleted = dir.join(format!("orders-0.{}{}", Uuid::new_v4().simple(), DELETED_DIR_SUFFIX));
+        std::fs::create_dir_all(&deleted).unwrap();
+        std::fs::write(deleted.join("00000000000000000000.log"), b"").unwrap();
+
+        let reloaded = TopicManager::load(dir.clone());
+        assert!(!deleted.exists());
+        assert_eq!(reloaded.all_topics().await.len(), 1);
+        assert!(reloaded.get("orders-0").await.is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,597 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::{Mutex, Notify};
+
+use crate::constants::{TRANSACTION_MAX_TIMEOUT_MS, TRANSACTION_STATE_NUM_PARTITIONS, TRANSACTION_STATE_TOPIC};
+use crate::core::consumer_group::TopicPartition;
+use crate::error::KafkaErrorCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionState {
+    Empty, // a producer without an open transaction
+    Ongoing,
+    PrepareCommit, // markers are being written
+    PrepareAbort,
+    CompleteCommit,
+    CompleteAbort,
+}
+
+impl TransactionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionState::Empty => "Empty",
+            TransactionState::Ongoing => "Ongoing",
+            TransactionState::PrepareCommit => "PrepareCommit",
+            TransactionState::PrepareAbort => "PrepareAbort",
+            TransactionState::CompleteCommit => "CompleteCommit",
+            TransactionState::CompleteAbort => "CompleteAbort",
+        }
+    }
+}
+
+/// a transactional id's producer and its current transaction, as written to
+/// `__transaction_state`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionMetadata {
+    pub transactional_id: String,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub timeout_ms: i32,
+    pub state: TransactionState,
+    pub partitions: BTreeSet<TopicPartition>,
+    pub groups: BTreeSet<String>, // consumer groups the transaction commits offsets for
+    pub start_timestamp: i64, // when the ongoing transaction began, -1 outside of one
+    pub last_update_timestamp: i64,
+}
+
+/// COMMIT or ABORT markers still to be written for a transaction being completed
+#[derive(Debug, Clone)]
+pub struct TxnMarker {
+    pub transactional_id: String,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub commit: bool,
+    pub coordinator_epoch: i32,
+    pub partitions: Vec<TopicPartition>,
+    pub groups: Vec<String>,
+}
+
+impl TxnMarker {
+    fn for_transaction(metadata: &TransactionMetadata, coordinator_epoch: i32) -> Self {
+        TxnMarker {
+            transactional_id: metadata.transactional_id.clone(),
+            producer_id: metadata.producer_id,
+            producer_epoch: metadata.producer_epoch,
+            commit: metadata.state == TransactionState::PrepareCommit,
+            coordinator_epoch,
+            partitions: metadata.partitions.iter().cloned().collect(),
+            groups: metadata.groups.iter().cloned().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TransactionError {
+    #[error("Broker isn't the transaction coordinator of {0}")]
+    NotCoordinator(String),
+
+    #[error("Transactions of {0} are still being loaded")]
+    LoadInProgress(String),
+
+    #[error("Transactional id {0} is busy completing a transaction or writing its state")]
+    ConcurrentTransactions(String),
+
+    #[error("Producer {1} doesn't belong to transactional id {0}")]
+    InvalidProducerIdMapping(String, i64),
+
+    #[error("Epoch {1} of transactional id {0} isn't its current epoch {2}")]
+    InvalidProducerEpoch(String, i16, i16),
+
+    #[error("Transactional id {0} can't {1} in state {2}")]
+    InvalidTxnState(String, &'static str, &'static str),
+
+    #[error("Transaction timeout {0} ms is out of range")]
+    InvalidTransactionTimeout(i32),
+}
+
+impl TransactionError {
+    pub fn error_code(&self) -> KafkaErrorCode {
+        match self {
+            TransactionError::NotCoordinator(_) => KafkaErrorCode::NotCoordinator,
+            TransactionError::LoadInProgress(_) => KafkaErrorCode::CoordinatorLoadInProgress,
+            TransactionError::ConcurrentTransactions(_) => KafkaErrorCode::ConcurrentTransactions,
+            TransactionError::InvalidProducerIdMapping(_, _) => KafkaErrorCode::InvalidProducerIdMapping,
+            TransactionError::InvalidProducerEpoch(_, _, _) => KafkaErrorCode::InvalidProducerEpoch,
+            TransactionError::InvalidTxnState(_, _, _) => KafkaErrorCode::InvalidTxnState,
+            TransactionError::InvalidTransactionTimeout(_) => KafkaErrorCode::InvalidTransactionTimeout,
+        }
+    }
+}
+
+/// partition of `__transaction_state` holding a transactional id, the one Kafka picks
+pub fn partition_for(transactional_id: &str) -> i32 {
+    // Java's String.hashCode over UTF-16 code units
+    let hash = transactional_id
+        .encode_utf16()
+        .fold(0i32, |hash, unit| hash.wrapping_mul(31).wrapping_add(unit as i32));
+    (hash & 0x7fff_ffff) % TRANSACTION_STATE_NUM_PARTITIONS
+}
+
+// a `__transaction_state` partition this broker leads
+#[derive(Debug, Clone, Copy)]
+enum LogPartitionState {
+    Loading,
+    Loaded { coordinator_epoch: i32 }, // the partition's leader epoch
+}
+
+#[derive(Debug, Default)]
+struct CoordinatorState {
+    log_partitions: HashMap<i32, LogPartitionState>,
+    transactions: HashMap<String, TransactionMetadata>,
+    pending: HashSet<String>, // transactional ids with a transition being written
+}
+
+impl CoordinatorState {
+    fn coordinator_epoch(&self, transactional_id: &str) -> Result<i32, TransactionError> {
+        match self.log_partitions.get(&partition_for(transactional_id)) {
+            Some(LogPartitionState::Loaded { coordinator_epoch }) => Ok(*coordinator_epoch),
+            Some(LogPartitionState::Loading) => Err(TransactionError::LoadInProgress(transactional_id.to_string())),
+            None => Err(TransactionError::NotCoordinator(transactional_id.to_string())),
+        }
+    }
+
+    // the transaction a producer wants to change, if it is still that producer's
+    fn current(&self, transactional_id: &str, producer_id: i64, producer_epoch: i16) -> Result<&TransactionMetadata, TransactionError> {
+        self.coordinator_epoch(transactional_id)?;
+        if self.pending.contains(transactional_id) {
+            return Err(TransactionError::ConcurrentTransactions(transactional_id.to_string()));
+        }
+        let current = self
+            .transactions
+            .get(transactional_id)
+            .filter(|current| current.producer_id == producer_id)
+            .ok_or_else(|| TransactionError::InvalidProducerIdMapping(transactional_id.to_string(), producer_id))?;
+        if current.producer_epoch != producer_epoch {
+            return Err(TransactionError::InvalidProducerEpoch(transactional_id.to_string(), producer_epoch, current.producer_epoch));
+        }
+        Ok(current)
+    }
+}
+
+/// transactions of the transactional ids whose `__transaction_state` partitions this
+/// broker leads. Every change is prepared here, written to the partition and only
+/// then applied; a transactional id takes one change at a time.
+#[derive(Debug, Default)]
+pub struct TransactionCoordinator {
+    state: Mutex<CoordinatorState>,
+    pending_markers: Mutex<Vec<TxnMarker>>, // waiting to be written to partition leaders
+    markers_queued: Notify,
+}
+
+impl TransactionCoordinator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn transaction(&self, transactional_id: &str) -> Option<TransactionMetadata> {
+        self.state.lock().await.transactions.get(transactional_id).cloned()
+    }
+
+    /// refuses requests for a partition's transactional ids until it's loaded
+    pub async fn begin_loading(&self, partition: i32) {
+        self.state.lock().await.log_partitions.insert(partition, LogPartitionState::Loading);
+    }
+
+    /// takes over the transactions read back from a partition. Those that were being
+    /// committed or aborted get their markers written again.
+    pub async fn complete_loading(&self, partition: i32, coordinator_epoch: i32, transactions: Vec<TransactionMetadata>) {
+        let mut markers = Vec::new();
+        {
+            let mut state = self.state.lock().await;
+            // unloaded meanwhile
+            if !matches!(state.log_partitions.get(&partition), Some(LogPartitionState::Loading)) {
+                return;
+            }
+            for metadata in transactions {
+                if matches!(metadata.state, TransactionState::PrepareCommit | TransactionState::PrepareAbort) {
+                    markers.push(TxnMarker::for_transaction(&metadata, coordinator_epoch));
+                }
+                state.transactions.insert(metadata.transactional_id.clone(), metadata);
+            }
+            state.log_partitions.insert(partition, LogPartitionState::Loaded { coordinator_epoch });
+        }
+        println!("Loaded transactions of {}-{} in coordinator epoch {}", TRANSACTION_STATE_TOPIC, partition, coordinator_epoch);
+        self.requeue_markers(markers).await;
+    }
+
+    /// forgets the transactions of a partition another broker leads now
+    pub async fn unload(&self, partition: i32) {
+        let mut state = self.state.lock().await;
+        if state.log_partitions.remove(&partition).is_none() {
+            return;
+        }
+        state.transactions.retain(|transactional_id, _| partition_for(transactional_id) != partition);
+        state.pending.retain(|transactional_id| partition_for(transactional_id) != partition);
+        drop(state);
+        let mut markers = self.pending_markers.lock().await;
+        markers.retain(|marker| partition_for(&marker.transactional_id) != partition);
+    }
+
+    /// whether InitProducerId would need a fresh producer id: for a new transactional
+    /// id, or one whose producer used up its epochs
+    pub async fn needs_producer_id(&self, transactional_id: &str) -> bool {
+        let state = self.state.lock().await;
+        state
+            .transactions
+            .get(transactional_id)
+            .is_none_or(|current| current.producer_epoch >= i16::MAX - 1)
+    }
+
+    /// InitProducerId: a new producer, or the next epoch of the current one, which
+    /// fences off older instances. An ongoing transaction is aborted instead, leaving
+    /// the target in PrepareAbort; the producer retries once that completes.
+    pub async fn prepare_init_producer_id(
+        &self,
+        transactional_id: &str,
+        timeout_ms: i32,
+        new_producer_id: Option<i64>,
+        now_ms: i64,
+    ) -> Result<TransactionMetadata, TransactionError> {
+        if timeout_ms <= 0 || timeout_ms > TRANSACTION_MAX_TIMEOUT_MS {
+            return Err(TransactionError::InvalidTransactionTimeout(timeout_ms));
+        }
+        let mut state = self.state.lock().await;
+        state.coordinator_epoch(transactional_id)?;
+        let busy = || TransactionError::ConcurrentTransactions(transactional_id.to_string());
+        if state.pending.contains(transactional_id) {
+            return Err(busy());
+        }
+
+        let target = match state.transactions.get(transactional_id) {
+            None => TransactionMetadata {
+                transactional_id: transactional_id.to_string(),
+                producer_id: new_producer_id.ok_or_else(busy)?,
+                producer_epoch: 0,
+                timeout_ms,
+                state: TransactionState::Empty,
+                partitions: BTreeSet::new(),
+                groups: BTreeSet::new(),
+                start_timestamp: -1,
+                last_update_timestamp: now_ms,
+            },
+            Some(current) => match current.state {
+                TransactionState::PrepareCommit | TransactionState::PrepareAbort => return Err(busy()),
+                TransactionState::Ongoing => TransactionMetadata {
+                    producer_epoch: current.producer_epoch.saturating_add(1),
+                    state: TransactionState::PrepareAbort,
+                    last_update_timestamp: now_ms,
+                    ..current.clone()
+                },
+                TransactionState::Empty | TransactionState::CompleteCommit | TransactionState::CompleteAbort => {
+                    let (producer_id, producer_epoch) = if current.producer_epoch >= i16::MAX - 1 {
+                        (new_producer_id.ok_or_else(busy)?, 0)
+                    } else {
+                        (current.producer_id, current.producer_epoch + 1)
+                    };
+                    TransactionMetadata {
+                        transactional_id: transactional_id.to_string(),
+                        producer_id,
+                        producer_epoch,
+                        timeout_ms,
+                        state: TransactionState::Empty,
+                        partitions: BTreeSet::new(),
+                        groups: BTreeSet::new(),
+                        start_timestamp: -1,
+                        last_update_timestamp: now_ms,
+                    }
+                }
+            },
+        };
+        state.pending.insert(transactional_id.to_string());
+        Ok(target)
+    }
+
+    /// AddPartitionsToTxn, starting the transaction if none is ongoing. None when every
+    /// partition is already part of it.
+    pub async fn prepare_add_partitions(
+        &self,
+        transactional_id: &str,
+        producer_id: i64,
+        producer_epoch: i16,
+        partitions: &[TopicPartition],
+        now_ms: i64,
+    ) -> Result<Option<TransactionMetadata>, TransactionError> {
+        let mut state = self.state.lock().await;
+        let current = state.current(transactional_id, producer_id, producer_epoch)?;
+        if current.state == TransactionState::Ongoing && partitions.iter().all(|tp| current.partitions.contains(tp)) {
+            return Ok(None);
+        }
+        let mut target = Self::ongoing(current, now_ms)?;
+        target.partitions.extend(partitions.iter().cloned());
+        state.pending.insert(transactional_id.to_string());
+        Ok(Some(target))
+    }
+
+    /// AddOffsetsToTxn: the group's offsets get committed or dropped with the transaction
+    pub async fn prepare_add_group(
+        &self,
+        transactional_id: &str,
+        producer_id: i64,
+        producer_epoch: i16,
+        group_id: &str,
+        now_ms: i64,
+    ) -> Result<Option<TransactionMetadata>, TransactionError> {
+        let mut state = self.state.lock().await;
+        let current = state.current(transactional_id, producer_id, producer_epoch)?;
+        if current.state == TransactionState::Ongoing && current.groups.contains(group_id) {
+            return Ok(None);
+        }
+        let mut target = Self::ongoing(current, now_ms)?;
+        target.groups.insert(group_id.to_string());
+        state.pending.insert(transactional_id.to_string());
+        Ok(Some(target))
+    }
+
+    // the ongoing transaction a partition or group joins, a fresh one after the last ended
+    fn ongoing(current: &TransactionMetadata, now_ms: i64) -> Result<TransactionMetadata, TransactionError> {
+        let mut target = current.clone();
+        match current.state {
+            TransactionState::PrepareCommit | TransactionState::PrepareAbort => {
+                return Err(TransactionError::ConcurrentTransactions(current.transactional_id.clone()));
+            }
+            TransactionState::Ongoing => {}
+            TransactionState::Empty | TransactionState::CompleteCommit | TransactionState::CompleteAbort => {
+                target.partitions.clear();
+                target.groups.clear();
+                target.start_timestamp = now_ms;
+            }
+        }
+        target.state = TransactionState::Ongoing;
+        target.last_update_timestamp = now_ms;
+        Ok(target)
+    }
+
+    /// EndTxn: moves the ongoing transaction to PrepareCommit or PrepareAbort. None for
+    /// a retry of an EndTxn that already completed.
+    pub async fn prepare_end_txn(
+        &self,
+        transactional_id: &str,
+        producer_id: i64,
+        producer_epoch: i16,
+        commit: bool,
+        now_ms: i64,
+    ) -> Result<Option<TransactionMetadata>, TransactionError> {
+        let mut state = self.state.lock().await;
+        let current = state.current(transactional_id, producer_id, producer_epoch)?;
+        let target_state = match (current.state, commit) {
+            (TransactionState::Ongoing, true) => TransactionState::PrepareCommit,
+            (TransactionState::Ongoing, false) => TransactionState::PrepareAbort,
+            (TransactionState::CompleteCommit, true) | (TransactionState::CompleteAbort, false) => return Ok(None),
+            (TransactionState::PrepareCommit, true) | (TransactionState::PrepareAbort, false) => {
+                return Err(TransactionError::ConcurrentTransactions(transactional_id.to_string()));
+            }
+            (other, _) => {
+                let operation = if commit { "commit" } else { "abort" };
+                return Err(TransactionError::InvalidTxnState(transactional_id.to_string(), operation, other.as_str()));
+            }
+        };
+        let target = TransactionMetadata { state: target_state, last_update_timestamp: now_ms, ..current.clone() };
+        state.pending.insert(transactional_id.to_string());
+        Ok(Some(target))
+    }
+
+    /// CompleteCommit or CompleteAbort once every marker of `marker` is written. None
+    /// when the transaction moved on already, e.g. a marker written twice.
+    pub async fn prepare_complete(&self, marker: &TxnMarker, now_ms: i64) -> Result<Option<TransactionMetadata>, TransactionError> {
+        let mut state = self.state.lock().await;
+        let transactional_id = marker.transactional_id.as_str();
+        if state.coordinator_epoch(transactional_id)? != marker.coordinator_epoch {
+            return Err(TransactionError::NotCoordinator(transactional_id.to_string()));
+        }
+        if state.pending.contains(transactional_id) {
+            return Err(TransactionError::ConcurrentTransactions(transactional_id.to_string()));
+        }
+        let preparing = if marker.commit { TransactionState::PrepareCommit } else { TransactionState::PrepareAbort };
+        let Some(current) = state.transactions.get(transactional_id).filter(|current| {
+            current.producer_id == marker.producer_id && current.producer_epoch == marker.producer_epoch && current.state == preparing
+        }) else {
+            return Ok(None);
+        };
+        let target = TransactionMetadata {
+            state: if marker.commit { TransactionState::CompleteCommit } else { TransactionState::CompleteAbort },
+            partitions: BTreeSet::new(),
+            groups: BTreeSet::new(),
+            start_timestamp: -1,
+            last_update_timestamp: now_ms,
+            ..current.clone()
+        };
+        state.pending.insert(transactional_id.to_string());
+        Ok(Some(target))
+    }
+
+    /// starts aborting every transaction that outlived its timeout, bumping the epoch
+    /// so its producer can't carry on with it
+    pub async fn prepare_timed_out_aborts(&self, now_ms: i64) -> Vec<TransactionMetadata> {
+        let mut state = self.state.lock().await;
+        let expired: Vec<TransactionMetadata> = state
+            .transactions
+            .values()
+            .filter(|current| {
+                current.state == TransactionState::Ongoing
+                    && current.start_timestamp + current.timeout_ms as i64 <= now_ms
+                    && !state.pending.contains(&current.transactional_id)
+                    && state.coordinator_epoch(&current.transactional_id).is_ok()
+            })
+            .map(|current| TransactionMetadata {
+                producer_epoch: current.producer_epoch.saturating_add(1),
+                state: TransactionState::PrepareAbort,
+                last_update_timestamp: now_ms,
+                ..current.clone()
+            })
+            .collect();
+        for target in &expired {
+            state.pending.insert(target.transactional_id.clone());
+        }
+        expired
+    }
+
+    /// applies a prepared change once it's written, queueing the markers of a
+    /// transaction that started committing or aborting
+    pub async fn complete_transition(&self, target: TransactionMetadata) {
+        let marker = {
+            let mut state = self.state.lock().await;
+            let transactional_id = target.transactional_id.clone();
+            if !state.pending.remove(&transactional_id) {
+                return; // unloaded meanwhile
+            }
+            let Ok(coordinator_epoch) = state.coordinator_epoch(&transactional_id) else {
+                return;
+            };
+            let marker = matches!(target.state, TransactionState::PrepareCommit | TransactionState::PrepareAbort)
+                .then(|| TxnMarker::for_transaction(&target, coordinator_epoch));
+            state.transactions.insert(transactional_id, target);
+            marker
+        };
+        if let Some(marker) = marker {
+            self.requeue_markers(vec![marker]).await;
+        }
+    }
+
+    /// drops a prepared change that couldn't be written
+    pub async fn abort_transition(&self, transactional_id: &str) {
+        self.state.lock().await.pending.remove(transactional_id);
+    }
+
+    /// waits for markers to write and takes them
+    pub async fn take_pending_markers(&self) -> Vec<TxnMarker> {
+        loop {
+            let notified = self.markers_queued.notified();
+            {
+                let mut pending = self.pending_markers.lock().await;
+                if !pending.is_empty() {
+                    return std::mem::take(&mut *pending);
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// queues markers, e.g. ones whose partitions couldn't be written to yet. Markers
+    /// of a coordinator epoch this broker no longer holds are dropped.
+    pub async fn requeue_markers(&self, mut markers: Vec<TxnMarker>) {
+        {
+            let state = self.state.lock().await;
+            markers.retain(|marker| {
+                state.coordinator_epoch(&marker.transactional_id).is_ok_and(|epoch| epoch == marker.coordinator_epoch)
+            });
+        }
+        if markers.is_empty() {
+            return;
+        }
+        self.pending_markers.lock().await.extend(markers);
+        self.markers_queued.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TXN_ID: &str = "payments";
+
+    // a coordinator for TXN_ID with its producer initialized in epoch 0
+    async fn coordinator() -> TransactionCoordinator {
+        let coordinator = TransactionCoordinator::new();
+        coordinator.begin_loading(partition_for(TXN_ID)).await;
+        coordinator.complete_loading(partition_for(TXN_ID), 3, Vec::new()).await;
+        let target = coordinator.prepare_init_producer_id(TXN_ID, 60_000, Some(100), 0).await.unwrap();
+        assert_eq!((target.producer_id, target.producer_epoch), (100, 0));
+        coordinator.complete_transition(target).await;
+        coordinator
+    }
+
+    async fn begin(coordinator: &TransactionCoordinator, producer_epoch: i16, now_ms: i64) {
+        let tp = TopicPartition::new("orders".to_string(), 0);
+        let target = coordinator.prepare_add_partitions(TXN_ID, 100, producer_epoch, &[tp], now_ms).await.unwrap().unwrap();
+        coordinator.complete_transition(target).await;
+    }
+
+    fn is_concurrent<T>(result: Result<T, TransactionError>) -> bool {
+        matches!(result, Err(TransactionError::ConcurrentTransactions(_)))
+    }
+
+    #[tokio::test]
+    async fn one_change_at_a_time_per_transactional_id() {
+        let coordinator = coordinator().await;
+        let tp = TopicPartition::new("orders".to_string(), 0);
+
+        // a change that is still being written blocks the next one
+        let target = coordinator.prepare_add_partitions(TXN_ID, 100, 0, std::slice::from_ref(&tp), 0).await.unwrap().unwrap();
+        assert!(is_concurrent(coordinator.prepare_add_group(TXN_ID, 100, 0, "billing", 0).await));
+        assert!(is_concurrent(coordinator.prepare_init_producer_id(TXN_ID, 60_000, None, 0).await));
+        coordinator.complete_transition(target).await;
+
+        // and so does a transaction whose markers are being written
+        let target = coordinator.prepare_end_txn(TXN_ID, 100, 0, true, 0).await.unwrap().unwrap();
+        coordinator.complete_transition(target).await;
+        assert_eq!(coordinator.transaction(TXN_ID).await.unwrap().state, TransactionState::PrepareCommit);
+        assert!(is_concurrent(coordinator.prepare_add_partitions(TXN_ID, 100, 0, &[tp], 0).await));
+        assert!(is_concurrent(coordinator.prepare_end_txn(TXN_ID, 100, 0, true, 0).await));
+        assert!(is_concurrent(coordinator.prepare_init_producer_id(TXN_ID, 60_000, None, 0).await));
+        let aborting = coordinator.prepare_end_txn(TXN_ID, 100, 0, false, 0).await;
+        assert!(matches!(aborting, Err(TransactionError::InvalidTxnState(_, "abort", "PrepareCommit"))));
+
+        let marker = coordinator.take_pending_markers().await.remove(0);
+        let target = coordinator.prepare_complete(&marker, 0).await.unwrap().unwrap();
+        coordinator.complete_transition(target).await;
+        assert_eq!(coordinator.transaction(TXN_ID).await.unwrap().state, TransactionState::CompleteCommit);
+        // a retried EndTxn of the completed transaction is a no-op
+        assert!(coordinator.prepare_end_txn(TXN_ID, 100, 0, true, 0).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn a_new_producer_epoch_fences_the_old_one() {
+        let coordinator = coordinator().await;
+        begin(&coordinator, 0, 0).await;
+
+        // a second instance aborts the first one's transaction under the next epoch
+        let target = coordinator.prepare_init_producer_id(TXN_ID, 60_000, None, 0).await.unwrap();
+        assert_eq!((target.producer_epoch, target.state), (1, TransactionState::PrepareAbort));
+        coordinator.complete_transition(target).await;
+        let marker = coordinator.take_pending_markers().await.remove(0);
+        assert!(!marker.commit);
+        assert_eq!((marker.producer_epoch, marker.coordinator_epoch), (1, 3));
+
+        let fenced = coordinator.prepare_end_txn(TXN_ID, 100, 0, true, 0).await;
+        assert!(matches!(fenced, Err(TransactionError::InvalidProducerEpoch(_, 0, 1))));
+        let unknown = coordinator.prepare_end_txn(TXN_ID, 101, 1, true, 0).await;
+        assert!(matches!(unknown, Err(TransactionError::InvalidProducerIdMapping(_, 101))));
+    }
+
+    #[tokio::test]
+    async fn transactions_past_their_timeout_are_aborted() {
+        let coordinator = coordinator().await;
+        begin(&coordinator, 0, 1_000).await;
+
+        assert!(coordinator.prepare_timed_out_aborts(60_999).await.is_empty());
+        let expired = coordinator.prepare_timed_out_aborts(61_000).await;
+        assert_eq!(expired.len(), 1);
+        assert_eq!((expired[0].producer_epoch, expired[0].state), (1, TransactionState::PrepareAbort));
+        // not picked twice while the abort is being written
+        assert!(coordinator.prepare_timed_out_aborts(61_000).await.is_empty());
+        coordinator.complete_transition(expired.into_iter().next().unwrap()).await;
+
+        let marker = coordinator.take_pending_markers().await.remove(0);
+        assert!(!marker.commit);
+        assert_eq!(marker.partitions, vec![TopicPartition::new("orders".to_string(), 0)]);
+        // the producer can't go on with the transaction
+        let fenced = coordinator.prepare_end_txn(TXN_ID, 100, 0, true, 61_000).await;
+        assert!(matches!(fenced, Err(TransactionError::InvalidProducerEpoch(_, 0, 1))));
+
+        let target = coordinator.prepare_complete(&marker, 61_000).await.unwrap().unwrap();
+        assert_eq!(target.state, TransactionState::CompleteAbort);
+        assert!(target.partitions.is_empty());
+    }
+}
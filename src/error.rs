@@ -2,8 +2,58 @@ use crate::constants::MAX_MESSAGE_SIZE;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum KafkaErrorCode {
+    UnknownServerError = -1,
     None = 0,
+    OffsetOutOfRange = 1,
+    CorruptMessage = 2,
+    UnknownTopicOrPartition = 3,
+    LeaderNotAvailable = 5,
+    NotLeaderOrFollower = 6,
+    RequestTimedOut = 7,
+    MessageTooLarge = 10,
+    StaleControllerEpoch = 11,
+    CoordinatorLoadInProgress = 14,
+    CoordinatorNotAvailable = 15,
+    NotCoordinator = 16,
+    InvalidTopicException = 17,
+    NotEnoughReplicas = 19,
+    InvalidRequiredAcks = 21,
+    InvalidGroupId = 24,
+    UnknownMemberId = 25,
     UnsupportedVersion = 35,
+    TopicAlreadyExists = 36,
+    InvalidPartitions = 37,
+    InvalidReplicationFactor = 38,
+    InvalidReplicaAssignment = 39,
+    InvalidConfig = 40,
+    NotController = 41,
+    InvalidRequest = 42,
+    OutOfOrderSequenceNumber = 45,
+    DuplicateSequenceNumber = 46,
+    InvalidProducerEpoch = 47,
+    InvalidTxnState = 48,
+    InvalidProducerIdMapping = 49,
+    InvalidTransactionTimeout = 50,
+    ConcurrentTransactions = 51,
+    OperationNotAttempted = 55,
+    KafkaStorageError = 56,
+    FencedLeaderEpoch = 74,
+    UnknownLeaderEpoch = 75,
+    StaleBrokerEpoch = 77,
+    NonEmptyGroup = 68,
+    GroupIdNotFound = 69,
+    PreferredLeaderNotAvailable = 80,
+    EligibleLeadersNotAvailable = 83,
+    ElectionNotNeeded = 84,
+    NoReassignmentInProgress = 85,
+    GroupSubscribedToTopic = 86,
+    InvalidRecord = 87,
+    UnknownTopicId = 100,
+    DuplicateBrokerRegistration = 101,
+    BrokerIdNotRegistered = 102,
+    IneligibleReplica = 107,
+    FencedMemberEpoch = 110,
+    UnsupportedAssignor = 112,
 }
 
 impl From<KafkaErrorCode> for i16 {
@@ -16,6 +66,10 @@ impl From<KafkaErrorCode> for i16 {
 pub enum ServerError {
     IoError(std::io::Error),
     InvalidMessageSize(i32),
+    MalformedRequest(&'static str),
+    BrokerNotAvailable(i32),
+    ControllerNotAvailable,
+    ErrorResponse(i16),
 }
 
 impl From<std::io::Error> for ServerError {
@@ -31,6 +85,10 @@ impl std::fmt::Display for ServerError {
             ServerError::InvalidMessageSize(size) => {
                 write!(f, "Invalid message size: {} (max: {})", size, MAX_MESSAGE_SIZE)
             }
+            ServerError::MalformedRequest(reason) => write!(f, "Malformed request: {}", reason),
+            ServerError::BrokerNotAvailable(broker_id) => write!(f, "No known endpoint for broker {}", broker_id),
+            ServerError::ControllerNotAvailable => write!(f, "No controller known yet"),
+            ServerError::ErrorResponse(error_code) => write!(f, "Request failed with error code {}", error_code),
         }
     }
 }
@@ -1,5 +1,6 @@
 pub mod core;
 pub mod network;
 pub mod storage;
+pub mod raft;
 pub mod constants;
 pub mod error;
\ No newline at end of file
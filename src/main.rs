@@ -1,8 +1,144 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rafka::constants::{
+    AUTO_LEADER_REBALANCE_ENABLE, BROKER_HEARTBEAT_INTERVAL_MS, CONSUMER_GROUP_HEARTBEAT_INTERVAL_MS, CONTROLLED_SHUTDOWN_TIMEOUT_MS,
+    LAG_REPORT_INTERVAL_MS, LEADER_IMBALANCE_CHECK_INTERVAL_MS, REPLICA_LAG_TIME_MAX_MS,
+    TRANSACTION_ABORT_TIMED_OUT_CLEANUP_INTERVAL_MS,
+};
+use rafka::core::broker::Broker;
+use rafka::core::controller::Controller;
+use rafka::core::metadata::BrokerEndpoint;
+use rafka::core::replica_selector::RackAwareReplicaSelector;
+use rafka::network::alter_partition::AlterPartitionManager;
+use rafka::network::broker_lifecycle::BrokerLifecycleManager;
+use rafka::network::controller_channel::ControllerChannel;
+use rafka::network::metrics::MetricsServer;
+use rafka::network::producer_id::ProducerIdManager;
+use rafka::network::replica_fetcher::ReplicaFetcherManager;
 use rafka::network::server::KafkaServer;
+use rafka::network::txn_marker::TransactionMarkerManager;
+use rafka::raft::node::{RaftConfig, RaftNode};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let server = KafkaServer::new("127.0.0.1:9092")?;
-    server.run().await?;
+    let address = "127.0.0.1:9092";
+    // brokers and the controller talk to each other over their own listener
+    let inter_broker_address = "127.0.0.1:9094";
+    let broker = Arc::new(Broker::new(0));
+    broker.set_replica_selector(Box::new(RackAwareReplicaSelector));
+
+    // a single broker is its own controller, backed by a one-voter metadata quorum
+    let voters = BTreeMap::from([(broker.broker_id(), "127.0.0.1:9093".to_string())]);
+    let quorum = RaftConfig::new(broker.broker_id(), PathBuf::from("data/__cluster_metadata-0"), voters);
+    let raft = RaftNode::start(quorum).await?;
+    broker.attach_metadata_log(Arc::clone(&raft));
+    let controller = Arc::new(Controller::with_quorum(Arc::clone(&raft)));
+    broker.attach_controller(Arc::clone(&controller));
+
+    let mut status = raft.subscribe();
+    while raft.active_term().is_none() {
+        status.changed().await?;
+    }
+    tokio::spawn(ControllerChannel::new(Arc::clone(&controller)).run());
+
+    if AUTO_LEADER_REBALANCE_ENABLE {
+        let rebalancer = Arc::clone(&controller);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(LEADER_IMBALANCE_CHECK_INTERVAL_MS)).await;
+                if let Err(e) = rebalancer.rebalance_leaders().await {
+                    eprintln!("Leader rebalance failed: {}", e);
+                }
+            }
+        });
+    }
+
+    // fence brokers whose sessions ran out, checked as often as brokers heartbeat
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(BROKER_HEARTBEAT_INTERVAL_MS)).await;
+            if let Err(e) = controller.fence_expired_brokers().await {
+                eprintln!("Failed to fence expired brokers: {}", e);
+            }
+        }
+    });
+
+    // the first listener is the one other brokers connect to
+    let listeners = vec![
+        BrokerEndpoint { name: "INTERNAL".to_string(), host: "127.0.0.1".to_string(), port: 9094, security_protocol: 0 },
+        BrokerEndpoint { name: "PLAINTEXT".to_string(), host: "127.0.0.1".to_string(), port: 9092, security_protocol: 0 },
+    ];
+    let lifecycle = Arc::new(BrokerLifecycleManager::new(Arc::clone(&broker), listeners, vec![inter_broker_address.to_string()]));
+    tokio::spawn(Arc::clone(&lifecycle).run());
+    tokio::spawn(AlterPartitionManager::new(Arc::clone(&broker)).run());
+    tokio::spawn(ProducerIdManager::new(Arc::clone(&broker)).run());
+    tokio::spawn(TransactionMarkerManager::new(Arc::clone(&broker)).run());
+
+    let txn_reaper = Arc::clone(&broker);
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(TRANSACTION_ABORT_TIMED_OUT_CLEANUP_INTERVAL_MS)).await;
+            txn_reaper.abort_timed_out_transactions().await;
+        }
+    });
+
+    // consumers that stopped heartbeating leave their group even if no one else heartbeats
+    let group_sweeper = Arc::clone(&broker);
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(CONSUMER_GROUP_HEARTBEAT_INTERVAL_MS as u64)).await;
+            group_sweeper.group_coordinator().expire_sessions().await;
+        }
+    });
+
+    let reporter = Arc::clone(&broker);
+    tokio::spawn(async move {
+        loop {
+            reporter.report_consumer_lag().await;
+            tokio::time::sleep(Duration::from_millis(LAG_REPORT_INTERVAL_MS)).await;
+        }
+    });
+
+    // check at half the lag limit, like Kafka's isr-expiration task
+    let isr_checker = Arc::clone(&broker);
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(REPLICA_LAG_TIME_MAX_MS as u64 / 2)).await;
+            isr_checker.maybe_shrink_isrs().await;
+        }
+    });
+
+    let fetchers = ReplicaFetcherManager::new(Arc::clone(&broker));
+    tokio::spawn(async move { fetchers.run().await });
+
+    let metrics = MetricsServer::new("127.0.0.1:9404", Arc::clone(&broker));
+    tokio::spawn(async move {
+        if let Err(e) = metrics.run().await {
+            eprintln!("Metrics server error: {}", e);
+        }
+    });
+
+    let inter_broker = KafkaServer::new(inter_broker_address, Arc::clone(&broker))?.with_inter_broker_apis();
+    tokio::spawn(async move {
+        if let Err(e) = inter_broker.run().await {
+            eprintln!("Inter-broker listener error: {}", e);
+        }
+    });
+
+    let server = KafkaServer::new(address, Arc::clone(&broker))?;
+    tokio::select! {
+        result = server.run() => result?,
+        _ = tokio::signal::ctrl_c() => {
+            println!("Shutting down, moving partition leadership away first");
+            let timeout = Duration::from_millis(CONTROLLED_SHUTDOWN_TIMEOUT_MS);
+            if tokio::time::timeout(timeout, lifecycle.controlled_shutdown()).await.is_err() {
+                eprintln!("Controlled shutdown timed out");
+            }
+            broker.replica_manager().flush_state().await;
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
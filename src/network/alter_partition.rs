@@ -0,0 +1,89 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    constants::{API_KEY_ALTER_PARTITION, CONTROLLER_REQUEST_BACKOFF_MS},
+    core::broker::Broker,
+    core::replication::IsrChange,
+    error::{KafkaErrorCode, ServerError},
+    network::api::AlterPartitionResponse,
+    network::client::KafkaClient,
+    network::requests::{AlterPartitionData, AlterPartitionRequest, AlterPartitionTopic},
+};
+
+const ALTER_PARTITION_VERSION: i16 = 0;
+
+// sends ISR changes of partitions this broker leads to the controller
+pub struct AlterPartitionManager {
+    broker: Arc<Broker>,
+    client: Option<(i32, KafkaClient)>, // connection to the controller it was opened for
+}
+
+impl AlterPartitionManager {
+    pub fn new(broker: Arc<Broker>) -> Self {
+        AlterPartitionManager { broker, client: None }
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            let changes = self.broker.take_pending_isr_changes().await;
+            if let Err(e) = self.send(&changes).await {
+                eprintln!("AlterPartition to the controller failed: {}", e);
+                self.client = None;
+                self.broker.requeue_isr_changes(changes).await;
+                tokio::time::sleep(Duration::from_millis(CONTROLLER_REQUEST_BACKOFF_MS)).await;
+            }
+        }
+    }
+
+    async fn connected_client(&mut self) -> Result<&mut KafkaClient, ServerError> {
+        let controller_id = self
+            .broker
+            .known_controller_id()
+            .await
+            .ok_or(ServerError::ControllerNotAvailable)?;
+        if self.client.as_ref().is_none_or(|(id, _)| *id != controller_id) {
+            let address = self
+                .broker
+                .broker_endpoint(controller_id)
+                .await
+                .ok_or(ServerError::BrokerNotAvailable(controller_id))?;
+            let client_id = format!("alter-partition-{}", self.broker.broker_id());
+            self.client = Some((controller_id, KafkaClient::connect(&address, &client_id).await?));
+        }
+        Ok(&mut self.client.as_mut().unwrap().1)
+    }
+
+    // refused changes aren't retried: the controller follows up with LeaderAndIsr
+    async fn send(&mut self, changes: &[IsrChange]) -> Result<(), ServerError> {
+        let mut topics: Vec<AlterPartitionTopic> = Vec::new();
+        for change in changes {
+            let partition = AlterPartitionData {
+                partition: change.partition_id,
+                leader_epoch: change.leader_epoch,
+                new_isr: change.isr.clone(),
+            };
+            match topics.iter_mut().find(|t| t.topic == change.topic) {
+                Some(topic) => topic.partitions.push(partition),
+                None => topics.push(AlterPartitionTopic { topic: change.topic.clone(), partitions: vec![partition] }),
+            }
+        }
+        let request = AlterPartitionRequest { broker_id: self.broker.broker_id(), broker_epoch: -1, topics };
+
+        let client = self.connected_client().await?;
+        let body = client.send_request(API_KEY_ALTER_PARTITION, ALTER_PARTITION_VERSION, &request.encode()).await?;
+        let response = AlterPartitionResponse::parse(&body)?;
+        if response.error_code != i16::from(KafkaErrorCode::None) {
+            return Err(ServerError::ErrorResponse(response.error_code));
+        }
+
+        for topic in response.topics {
+            for partition in topic.partitions {
+                if partition.error_code != i16::from(KafkaErrorCode::None) {
+                    eprintln!("Controller refused ISR change of {}-{}: error {}", topic.topic, partition.partition, partition.error_code);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,293 @@
+use uuid::Uuid;
+
 use crate::{
-    error::KafkaErrorCode,
-    constants::{API_KEY_API_VERSIONS, API_KEY_FETCH},
+    error::{KafkaErrorCode, ServerError},
+    constants::{API_KEY_API_VERSIONS, API_KEY_PRODUCE, API_KEY_FETCH, API_KEY_CONSUMER_GROUP_HEARTBEAT, API_KEY_DESCRIBE_GROUPS, API_KEY_LIST_GROUPS, API_KEY_DELETE_GROUPS, API_KEY_OFFSET_DELETE, API_KEY_OFFSET_FOR_LEADER_EPOCH,
+        API_KEY_LEADER_AND_ISR, API_KEY_ALTER_PARTITION, API_KEY_BROKER_REGISTRATION, API_KEY_BROKER_HEARTBEAT,
+        API_KEY_ALTER_PARTITION_REASSIGNMENTS, API_KEY_LIST_PARTITION_REASSIGNMENTS, API_KEY_ELECT_LEADERS, API_KEY_INIT_PRODUCER_ID,
+        API_KEY_ALLOCATE_PRODUCER_IDS, API_KEY_ADD_PARTITIONS_TO_TXN, API_KEY_ADD_OFFSETS_TO_TXN, API_KEY_END_TXN,
+        API_KEY_WRITE_TXN_MARKERS, API_KEY_TXN_OFFSET_COMMIT, API_KEY_CREATE_TOPICS, API_KEY_DELETE_TOPICS,
+        API_KEY_CREATE_PARTITIONS},
+    core::consumer_group::TopicPartition,
+    core::controller::PartitionState,
+    core::delayed_produce::ProducePartitionResult,
+    core::producer_id::ProducerIdBlock,
+    core::group_coordinator::{GroupDescription, GroupListing},
+    network::handler::RequestDecoder,
 };
 
 pub struct ResponseBuilder;
 
+// Fetch v16 response, built by the leader and parsed by replica fetchers
+#[derive(Debug)]
+pub struct FetchResponse {
+    pub error_code: i16,
+    pub session_id: i32,
+    pub responses: Vec<FetchTopicResponse>,
+}
+
+#[derive(Debug)]
+pub struct FetchTopicResponse {
+    pub topic_id: Uuid,
+    pub partitions: Vec<FetchPartitionResponse>,
+}
+
+#[derive(Debug)]
+pub struct FetchPartitionResponse {
+    pub partition: i32,
+    pub error_code: i16,
+    pub high_watermark: i64,
+    pub last_stable_offset: i64,
+    pub log_start_offset: i64,
+    pub aborted_transactions: Option<Vec<(i64, i64)>>, // (producer_id, first_offset), read_committed only
+    pub preferred_read_replica: i32, // -1 to keep fetching from this broker
+    pub records: Vec<u8>,
+}
+
+// OffsetForLeaderEpoch v4 response, built by the leader and parsed by replica fetchers
+#[derive(Debug)]
+pub struct OffsetForLeaderEpochResponse {
+    pub topics: Vec<OffsetForLeaderTopicResult>,
+}
+
+#[derive(Debug)]
+pub struct OffsetForLeaderTopicResult {
+    pub topic: String,
+    pub partitions: Vec<EpochEndOffset>,
+}
+
+#[derive(Debug)]
+pub struct EpochEndOffset {
+    pub error_code: i16,
+    pub partition: i32,
+    pub leader_epoch: i32,
+    pub end_offset: i64,
+}
+
+#[derive(Debug)]
+pub struct LeaderAndIsrResponse {
+    pub error_code: i16,
+    pub partition_errors: Vec<(Uuid, i32, i16)>, // (topic id, partition, error code)
+}
+
+#[derive(Debug)]
+pub struct AlterPartitionResponse {
+    pub error_code: i16,
+    pub topics: Vec<AlterPartitionTopicResult>,
+}
+
+#[derive(Debug)]
+pub struct AlterPartitionTopicResult {
+    pub topic: String,
+    pub partitions: Vec<AlterPartitionResult>,
+}
+
+// the controller's view of the partition after the change, or why it was refused
+#[derive(Debug)]
+pub struct AlterPartitionResult {
+    pub partition: i32,
+    pub error_code: i16,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+    pub isr: Vec<i32>,
+}
+
+// per-partition outcome of an admin request, like starting a reassignment
+#[derive(Debug)]
+pub struct PartitionResult {
+    pub partition: i32,
+    pub error_code: KafkaErrorCode,
+    pub error_message: Option<String>,
+}
+
+// per-topic outcome of CreateTopics; partitions, replication factor and configs
+// are only sent back for topics that were (or would be) created
+#[derive(Debug)]
+pub struct CreatableTopicResult {
+    pub name: String,
+    pub topic_id: Uuid,
+    pub error_code: KafkaErrorCode,
+    pub error_message: Option<String>,
+    pub num_partitions: i32,
+    pub replication_factor: i16,
+    pub configs: Vec<(String, String)>, // overrides the topic was created with
+}
+
+// per-topic outcome of DeleteTopics, named or by id as it was requested
+#[derive(Debug)]
+pub struct DeletableTopicResult {
+    pub name: Option<String>,
+    pub topic_id: Uuid,
+    pub error_code: KafkaErrorCode,
+    pub error_message: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct BrokerRegistrationResponse {
+    pub error_code: i16,
+    pub broker_epoch: i64,
+}
+
+#[derive(Debug)]
+pub struct AllocateProducerIdsResponse {
+    pub error_code: i16,
+    pub producer_id_start: i64,
+    pub producer_id_len: i32,
+}
+
+#[derive(Debug)]
+pub struct WriteTxnMarkersResponse {
+    pub markers: Vec<WritableTxnMarkerResult>,
+}
+
+// per-partition errors of writing one producer's marker
+#[derive(Debug)]
+pub struct WritableTxnMarkerResult {
+    pub producer_id: i64,
+    pub topics: Vec<(String, Vec<(i32, i16)>)>,
+}
+
+#[derive(Debug)]
+pub struct BrokerHeartbeatResponse {
+    pub error_code: i16,
+    pub is_caught_up: bool,
+    pub is_fenced: bool,
+    pub should_shutdown: bool,
+}
+
+// (name, api_key, min_version, max_version) advertised in ApiVersions
+const SUPPORTED_APIS: &[(&str, i16, i16, i16)] = &[
+    ("API_VERSIONS", API_KEY_API_VERSIONS, 0, 4),
+    ("PRODUCE", API_KEY_PRODUCE, 9, 9),
+    ("FETCH", API_KEY_FETCH, 0, 16),
+    ("CONSUMER_GROUP_HEARTBEAT", API_KEY_CONSUMER_GROUP_HEARTBEAT, 0, 0),
+    ("DESCRIBE_GROUPS", API_KEY_DESCRIBE_GROUPS, 5, 5),
+    ("LIST_GROUPS", API_KEY_LIST_GROUPS, 4, 4),
+    ("DELETE_GROUPS", API_KEY_DELETE_GROUPS, 2, 2),
+    ("OFFSET_DELETE", API_KEY_OFFSET_DELETE, 0, 0),
+    ("OFFSET_FOR_LEADER_EPOCH", API_KEY_OFFSET_FOR_LEADER_EPOCH, 4, 4),
+    ("LEADER_AND_ISR", API_KEY_LEADER_AND_ISR, 5, 5),
+    ("ALTER_PARTITION", API_KEY_ALTER_PARTITION, 0, 0),
+    ("BROKER_REGISTRATION", API_KEY_BROKER_REGISTRATION, 2, 2),
+    ("BROKER_HEARTBEAT", API_KEY_BROKER_HEARTBEAT, 1, 1),
+    ("ALTER_PARTITION_REASSIGNMENTS", API_KEY_ALTER_PARTITION_REASSIGNMENTS, 0, 0),
+    ("LIST_PARTITION_REASSIGNMENTS", API_KEY_LIST_PARTITION_REASSIGNMENTS, 0, 0),
+    ("ELECT_LEADERS", API_KEY_ELECT_LEADERS, 2, 2),
+    ("INIT_PRODUCER_ID", API_KEY_INIT_PRODUCER_ID, 4, 4),
+    ("ALLOCATE_PRODUCER_IDS", API_KEY_ALLOCATE_PRODUCER_IDS, 0, 0),
+    ("ADD_PARTITIONS_TO_TXN", API_KEY_ADD_PARTITIONS_TO_TXN, 3, 3),
+    ("ADD_OFFSETS_TO_TXN", API_KEY_ADD_OFFSETS_TO_TXN, 3, 3),
+    ("END_TXN", API_KEY_END_TXN, 3, 3),
+    ("WRITE_TXN_MARKERS", API_KEY_WRITE_TXN_MARKERS, 1, 1),
+    ("TXN_OFFSET_COMMIT", API_KEY_TXN_OFFSET_COMMIT, 3, 3),
+    ("CREATE_TOPICS", API_KEY_CREATE_TOPICS, 7, 7),
+    ("DELETE_TOPICS", API_KEY_DELETE_TOPICS, 6, 6),
+    ("CREATE_PARTITIONS", API_KEY_CREATE_PARTITIONS, 3, 3),
+];
+
+// DescribeGroups v3+ sends this when include_authorized_operations is false
+const AUTHORIZED_OPERATIONS_OMITTED: i32 = i32::MIN;
+
+pub(crate) fn put_unsigned_varint(buf: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        buf.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+// compact arrays/strings store length + 1 so that 0 can mean null
+pub(crate) fn put_compact_array_len(buf: &mut Vec<u8>, len: usize) {
+    put_unsigned_varint(buf, len as u32 + 1);
+}
+
+pub(crate) fn put_compact_string(buf: &mut Vec<u8>, value: &str) {
+    put_compact_array_len(buf, value.len());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+pub(crate) fn put_compact_nullable_string(buf: &mut Vec<u8>, value: Option<&str>) {
+    match value {
+        Some(value) => put_compact_string(buf, value),
+        None => buf.push(0x00),
+    }
+}
+
+pub(crate) fn put_compact_i32_array(buf: &mut Vec<u8>, values: &[i32]) {
+    put_compact_array_len(buf, values.len());
+    for value in values {
+        buf.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+pub(crate) fn put_compact_bytes(buf: &mut Vec<u8>, value: &[u8]) {
+    put_compact_array_len(buf, value.len());
+    buf.extend_from_slice(value);
+}
+
+// int16 length prefix, used by the embedded consumer protocol
+fn put_string(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as i16).to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+// ConsumerProtocolSubscription v0: topics, null user_data
+fn encode_subscription(topics: &[String]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&0i16.to_be_bytes()); // version
+    buf.extend_from_slice(&(topics.len() as i32).to_be_bytes());
+    for topic in topics {
+        put_string(&mut buf, topic);
+    }
+    buf.extend_from_slice(&(-1i32).to_be_bytes()); // user_data
+    buf
+}
+
+// ConsumerProtocolAssignment v0: partitions grouped by topic, null user_data
+fn encode_assignment(partitions: &[TopicPartition]) -> Vec<u8> {
+    let mut by_topic: Vec<(&str, Vec<i32>)> = Vec::new();
+    for tp in partitions {
+        match by_topic.iter_mut().find(|(topic, _)| *topic == tp.topic()) {
+            Some((_, ids)) => ids.push(tp.partition()),
+            None => by_topic.push((tp.topic(), vec![tp.partition()])),
+        }
+    }
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&0i16.to_be_bytes()); // version
+    buf.extend_from_slice(&(by_topic.len() as i32).to_be_bytes());
+    for (topic, ids) in by_topic {
+        put_string(&mut buf, topic);
+        buf.extend_from_slice(&(ids.len() as i32).to_be_bytes());
+        for id in ids {
+            buf.extend_from_slice(&id.to_be_bytes());
+        }
+    }
+    buf.extend_from_slice(&(-1i32).to_be_bytes()); // user_data
+    buf
+}
+
+// correlation id followed by the header TAG_BUFFER (response header v1)
+fn flexible_response_header(correlation_id: i32) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&correlation_id.to_be_bytes());
+    body.push(0x00);
+    body
+}
+
+// response header v0 is just the correlation id
+fn response_header(correlation_id: i32) -> Vec<u8> {
+    correlation_id.to_be_bytes().to_vec()
+}
+
+// wrap in full response: prepend length
+fn size_prefixed(body: Vec<u8>) -> Vec<u8> {
+    let mut response = Vec::with_capacity(body.len() + 4);
+    response.extend_from_slice(&(body.len() as i32).to_be_bytes());
+    response.extend(body);
+    response
+}
+
 impl ResponseBuilder {
     pub fn build_api_versions_response(correlation_id: i32, error_code: KafkaErrorCode) -> Vec<u8> {
         let mut body = Vec::new();
@@ -12,22 +295,15 @@ impl ResponseBuilder {
         body.extend_from_slice(&correlation_id.to_be_bytes());
         body.extend_from_slice(&(error_code as i16).to_be_bytes());
 
-        // CompactArray length = 2 entries encoded as 0x03 (2 + 1)
-        body.push(0x03);
-
-        println!("ApiKey entry: API_VERSIONS (key {})", API_KEY_API_VERSIONS);
-        // --- First ApiKey Entry (API_VERSIONS) ---
-        body.extend_from_slice(&(API_KEY_API_VERSIONS as i16).to_be_bytes()); // api_key
-        body.extend_from_slice(&0i16.to_be_bytes()); // min_version
-        body.extend_from_slice(&4i16.to_be_bytes()); // max_version
-        body.push(0x00); // tag_buffer (empty)
+        put_compact_array_len(&mut body, SUPPORTED_APIS.len());
 
-        println!("ApiKey entry: FETCH (key {})", API_KEY_FETCH);
-        // --- Second ApiKey Entry (FETCH) ---
-        body.extend_from_slice(&(API_KEY_FETCH as i16).to_be_bytes()); // api_key
-        body.extend_from_slice(&0i16.to_be_bytes()); // min_version
-        body.extend_from_slice(&16i16.to_be_bytes()); // max_version
-        body.push(0x00); // tag_buffer (empty)
+        for (name, api_key, min_version, max_version) in SUPPORTED_APIS {
+            println!("ApiKey entry: {} (key {})", name, api_key);
+            body.extend_from_slice(&api_key.to_be_bytes()); // api_key
+            body.extend_from_slice(&min_version.to_be_bytes()); // min_version
+            body.extend_from_slice(&max_version.to_be_bytes()); // max_version
+            body.push(0x00); // tag_buffer (empty)
+        }
 
         // throttle_time_ms
         body.extend_from_slice(&0i32.to_be_bytes());
@@ -35,43 +311,854 @@ impl ResponseBuilder {
         // tag_buffer for response
         body.push(0x00);
 
-        // wrap in full response: prepend length
-        let mut response = Vec::new();
-        response.extend_from_slice(&(body.len() as i32).to_be_bytes());
-        response.extend(body);
+        let response = size_prefixed(body);
 
         println!("ApiVersions response built: ({} bytes)", response.len());
 
         response
     }
 
-    pub fn build_fetch_response(correlation_id: i32) -> Vec<u8> {
-        let mut body = Vec::new();
+    pub fn build_fetch_response(correlation_id: i32, error_code: KafkaErrorCode, responses: &[FetchTopicResponse]) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
 
-        body.extend_from_slice(&correlation_id.to_be_bytes());
-        
-        // Header TAG_BUFFER (Kafka protocol adds this for extensibility)
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+        // session_id, fetch sessions aren't supported
+        body.extend_from_slice(&0i32.to_be_bytes());
+
+        put_compact_array_len(&mut body, responses.len());
+        for topic in responses {
+            body.extend_from_slice(topic.topic_id.as_bytes());
+            put_compact_array_len(&mut body, topic.partitions.len());
+            for partition in &topic.partitions {
+                body.extend_from_slice(&partition.partition.to_be_bytes());
+                body.extend_from_slice(&partition.error_code.to_be_bytes());
+                body.extend_from_slice(&partition.high_watermark.to_be_bytes());
+                body.extend_from_slice(&partition.last_stable_offset.to_be_bytes());
+                body.extend_from_slice(&partition.log_start_offset.to_be_bytes());
+                match &partition.aborted_transactions {
+                    Some(aborted) => {
+                        put_compact_array_len(&mut body, aborted.len());
+                        for (producer_id, first_offset) in aborted {
+                            body.extend_from_slice(&producer_id.to_be_bytes());
+                            body.extend_from_slice(&first_offset.to_be_bytes());
+                            body.push(0x00); // tag_buffer
+                        }
+                    }
+                    None => body.push(0x00), // null
+                }
+                body.extend_from_slice(&partition.preferred_read_replica.to_be_bytes());
+                put_compact_bytes(&mut body, &partition.records);
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
         body.push(0x00);
 
-        // error_code (int16) - no error
-        body.extend_from_slice(&0i16.to_be_bytes());
+        size_prefixed(body)
+    }
+
+    pub fn build_produce_response(correlation_id: i32, responses: &[(String, Vec<ProducePartitionResult>)]) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        put_compact_array_len(&mut body, responses.len());
+        for (name, partitions) in responses {
+            put_compact_string(&mut body, name);
+            put_compact_array_len(&mut body, partitions.len());
+            for partition in partitions {
+                body.extend_from_slice(&partition.tp.partition().to_be_bytes());
+                body.extend_from_slice(&(partition.error as i16).to_be_bytes());
+                body.extend_from_slice(&partition.base_offset.to_be_bytes());
+                body.extend_from_slice(&(-1i64).to_be_bytes()); // log_append_time_ms, CreateTime only
+                body.extend_from_slice(&partition.log_start_offset.to_be_bytes());
+                put_compact_array_len(&mut body, 0); // record_errors
+                put_compact_nullable_string(&mut body, None); // error_message
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
 
         // throttle_time_ms
         body.extend_from_slice(&0i32.to_be_bytes());
 
-        // session_id
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_offset_for_leader_epoch_response(correlation_id: i32, topics: &[OffsetForLeaderTopicResult]) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
         body.extend_from_slice(&0i32.to_be_bytes());
 
-        // responses (compact array length = 0 elements → encoded as 1)
-        body.push(0x01);
+        put_compact_array_len(&mut body, topics.len());
+        for topic in topics {
+            put_compact_string(&mut body, &topic.topic);
+            put_compact_array_len(&mut body, topic.partitions.len());
+            for partition in &topic.partitions {
+                body.extend_from_slice(&partition.error_code.to_be_bytes());
+                body.extend_from_slice(&partition.partition.to_be_bytes());
+                body.extend_from_slice(&partition.leader_epoch.to_be_bytes());
+                body.extend_from_slice(&partition.end_offset.to_be_bytes());
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
 
         // body TAG_BUFFER
         body.push(0x00);
-        
-        let mut response = Vec::new();
-        response.extend_from_slice(&(body.len() as i32).to_be_bytes());
-        response.extend(body);
 
-        response
+        size_prefixed(body)
+    }
+
+    pub fn build_leader_and_isr_response(correlation_id: i32, error_code: KafkaErrorCode, partition_errors: &[(Uuid, i32, KafkaErrorCode)]) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+
+        let mut by_topic: Vec<(Uuid, Vec<(i32, KafkaErrorCode)>)> = Vec::new();
+        for (topic_id, partition, error) in partition_errors {
+            match by_topic.iter_mut().find(|(id, _)| id == topic_id) {
+                Some((_, errors)) => errors.push((*partition, *error)),
+                None => by_topic.push((*topic_id, vec![(*partition, *error)])),
+            }
+        }
+        put_compact_array_len(&mut body, by_topic.len());
+        for (topic_id, errors) in by_topic {
+            body.extend_from_slice(topic_id.as_bytes());
+            put_compact_array_len(&mut body, errors.len());
+            for (partition, error) in errors {
+                body.extend_from_slice(&partition.to_be_bytes());
+                body.extend_from_slice(&(error as i16).to_be_bytes());
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_alter_partition_response(correlation_id: i32, error_code: KafkaErrorCode, topics: &[AlterPartitionTopicResult]) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+
+        put_compact_array_len(&mut body, topics.len());
+        for topic in topics {
+            put_compact_string(&mut body, &topic.topic);
+            put_compact_array_len(&mut body, topic.partitions.len());
+            for partition in &topic.partitions {
+                body.extend_from_slice(&partition.partition.to_be_bytes());
+                body.extend_from_slice(&partition.error_code.to_be_bytes());
+                body.extend_from_slice(&partition.leader_id.to_be_bytes());
+                body.extend_from_slice(&partition.leader_epoch.to_be_bytes());
+                put_compact_i32_array(&mut body, &partition.isr);
+                body.extend_from_slice(&0i32.to_be_bytes()); // partition_epoch, unused
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_allocate_producer_ids_response(correlation_id: i32, error_code: KafkaErrorCode, block: Option<ProducerIdBlock>) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+        let (producer_id_start, producer_id_len) = block.map_or((-1, 0), |block| (block.first_producer_id, block.size));
+        body.extend_from_slice(&producer_id_start.to_be_bytes());
+        body.extend_from_slice(&producer_id_len.to_be_bytes());
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_init_producer_id_response(correlation_id: i32, error_code: KafkaErrorCode, producer_id: i64, producer_epoch: i16) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+        body.extend_from_slice(&producer_id.to_be_bytes());
+        body.extend_from_slice(&producer_epoch.to_be_bytes());
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_add_partitions_to_txn_response(correlation_id: i32, topics: &[(String, Vec<(i32, KafkaErrorCode)>)]) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+
+        put_compact_array_len(&mut body, topics.len());
+        for (name, partitions) in topics {
+            put_compact_string(&mut body, name);
+            put_compact_array_len(&mut body, partitions.len());
+            for (partition, error_code) in partitions {
+                body.extend_from_slice(&partition.to_be_bytes());
+                body.extend_from_slice(&(*error_code as i16).to_be_bytes());
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    // AddOffsetsToTxn and EndTxn responses are both just an error code
+    pub fn build_add_offsets_to_txn_response(correlation_id: i32, error_code: KafkaErrorCode) -> Vec<u8> {
+        Self::build_txn_error_response(correlation_id, error_code)
+    }
+
+    pub fn build_end_txn_response(correlation_id: i32, error_code: KafkaErrorCode) -> Vec<u8> {
+        Self::build_txn_error_response(correlation_id, error_code)
+    }
+
+    fn build_txn_error_response(correlation_id: i32, error_code: KafkaErrorCode) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_write_txn_markers_response(correlation_id: i32, markers: &[WritableTxnMarkerResult]) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        put_compact_array_len(&mut body, markers.len());
+        for marker in markers {
+            body.extend_from_slice(&marker.producer_id.to_be_bytes());
+            put_compact_array_len(&mut body, marker.topics.len());
+            for (name, partitions) in &marker.topics {
+                put_compact_string(&mut body, name);
+                put_compact_array_len(&mut body, partitions.len());
+                for (partition, error_code) in partitions {
+                    body.extend_from_slice(&partition.to_be_bytes());
+                    body.extend_from_slice(&error_code.to_be_bytes());
+                    body.push(0x00); // tag_buffer
+                }
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_txn_offset_commit_response(correlation_id: i32, topics: &[(String, Vec<(i32, KafkaErrorCode)>)]) -> Vec<u8> {
+        // same layout as AddPartitionsToTxn v3
+        Self::build_add_partitions_to_txn_response(correlation_id, topics)
+    }
+
+    pub fn build_broker_registration_response(correlation_id: i32, error_code: KafkaErrorCode, broker_epoch: i64) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+        body.extend_from_slice(&broker_epoch.to_be_bytes());
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_broker_heartbeat_response(
+        correlation_id: i32,
+        error_code: KafkaErrorCode,
+        is_caught_up: bool,
+        is_fenced: bool,
+        should_shutdown: bool,
+    ) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+        body.push(is_caught_up as u8);
+        body.push(is_fenced as u8);
+        body.push(should_shutdown as u8);
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_alter_partition_reassignments_response(
+        correlation_id: i32,
+        error_code: KafkaErrorCode,
+        topics: &[(String, Vec<PartitionResult>)],
+    ) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+        // error_message
+        put_compact_nullable_string(&mut body, None);
+
+        put_compact_array_len(&mut body, topics.len());
+        for (name, partitions) in topics {
+            put_compact_string(&mut body, name);
+            put_compact_array_len(&mut body, partitions.len());
+            for result in partitions {
+                body.extend_from_slice(&result.partition.to_be_bytes());
+                body.extend_from_slice(&(result.error_code as i16).to_be_bytes());
+                put_compact_nullable_string(&mut body, result.error_message.as_deref());
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_elect_leaders_response(
+        correlation_id: i32,
+        error_code: KafkaErrorCode,
+        topics: &[(String, Vec<PartitionResult>)],
+    ) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+
+        put_compact_array_len(&mut body, topics.len());
+        for (topic, partitions) in topics {
+            put_compact_string(&mut body, topic);
+            put_compact_array_len(&mut body, partitions.len());
+            for result in partitions {
+                body.extend_from_slice(&result.partition.to_be_bytes());
+                body.extend_from_slice(&(result.error_code as i16).to_be_bytes());
+                put_compact_nullable_string(&mut body, result.error_message.as_deref());
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_create_topics_response(correlation_id: i32, topics: &[CreatableTopicResult]) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+
+        put_compact_array_len(&mut body, topics.len());
+        for topic in topics {
+            put_compact_string(&mut body, &topic.name);
+            body.extend_from_slice(topic.topic_id.as_bytes());
+            body.extend_from_slice(&(topic.error_code as i16).to_be_bytes());
+            put_compact_nullable_string(&mut body, topic.error_message.as_deref());
+            if topic.error_code == KafkaErrorCode::None {
+                body.extend_from_slice(&topic.num_partitions.to_be_bytes());
+                body.extend_from_slice(&topic.replication_factor.to_be_bytes());
+                put_compact_array_len(&mut body, topic.configs.len());
+                for (name, value) in &topic.configs {
+                    put_compact_string(&mut body, name);
+                    put_compact_nullable_string(&mut body, Some(value));
+                    body.push(0); // read_only
+                    body.push(1); // config_source: DYNAMIC_TOPIC_CONFIG
+                    body.push(0); // is_sensitive
+                    body.push(0x00); // tag_buffer
+                }
+            } else {
+                body.extend_from_slice(&(-1i32).to_be_bytes());
+                body.extend_from_slice(&(-1i16).to_be_bytes());
+                // configs: null
+                body.push(0x00);
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_delete_topics_response(correlation_id: i32, topics: &[DeletableTopicResult]) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+
+        put_compact_array_len(&mut body, topics.len());
+        for topic in topics {
+            put_compact_nullable_string(&mut body, topic.name.as_deref());
+            body.extend_from_slice(topic.topic_id.as_bytes());
+            body.extend_from_slice(&(topic.error_code as i16).to_be_bytes());
+            put_compact_nullable_string(&mut body, topic.error_message.as_deref());
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    // one (topic, error, message) per topic asked to grow
+    pub fn build_create_partitions_response(
+        correlation_id: i32,
+        results: &[(String, KafkaErrorCode, Option<String>)],
+    ) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+
+        put_compact_array_len(&mut body, results.len());
+        for (name, error_code, error_message) in results {
+            put_compact_string(&mut body, name);
+            body.extend_from_slice(&(*error_code as i16).to_be_bytes());
+            put_compact_nullable_string(&mut body, error_message.as_deref());
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    // one entry per partition with a reassignment in progress
+    pub fn build_list_partition_reassignments_response(
+        correlation_id: i32,
+        error_code: KafkaErrorCode,
+        reassignments: &[PartitionState],
+    ) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+        // error_message
+        put_compact_nullable_string(&mut body, None);
+
+        let mut by_topic: Vec<(&str, Vec<&PartitionState>)> = Vec::new();
+        for state in reassignments {
+            match by_topic.iter_mut().find(|(topic, _)| *topic == state.topic) {
+                Some((_, states)) => states.push(state),
+                None => by_topic.push((&state.topic, vec![state])),
+            }
+        }
+        put_compact_array_len(&mut body, by_topic.len());
+        for (topic, states) in by_topic {
+            put_compact_string(&mut body, topic);
+            put_compact_array_len(&mut body, states.len());
+            for state in states {
+                body.extend_from_slice(&state.partition.to_be_bytes());
+                put_compact_i32_array(&mut body, &state.replicas);
+                put_compact_i32_array(&mut body, &state.adding_replicas);
+                put_compact_i32_array(&mut body, &state.removing_replicas);
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_consumer_group_heartbeat_response(
+        correlation_id: i32,
+        error_code: KafkaErrorCode,
+        member_id: Option<&str>,
+        member_epoch: i32,
+        heartbeat_interval_ms: i32,
+        assignment: Option<&[(Uuid, Vec<i32>)]>,
+    ) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+        // error_message
+        put_compact_nullable_string(&mut body, None);
+        put_compact_nullable_string(&mut body, member_id);
+        body.extend_from_slice(&member_epoch.to_be_bytes());
+        body.extend_from_slice(&heartbeat_interval_ms.to_be_bytes());
+
+        // assignment is a nullable struct: -1 for null, 1 when present
+        match assignment {
+            Some(topic_partitions) => {
+                body.push(0x01);
+                put_compact_array_len(&mut body, topic_partitions.len());
+                for (topic_id, partitions) in topic_partitions {
+                    body.extend_from_slice(topic_id.as_bytes());
+                    put_compact_i32_array(&mut body, partitions);
+                    body.push(0x00); // tag_buffer
+                }
+                body.push(0x00); // assignment tag_buffer
+            }
+            None => body.push(0xff),
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_describe_groups_response(correlation_id: i32, groups: &[GroupDescription], include_authorized_operations: bool) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+
+        put_compact_array_len(&mut body, groups.len());
+        for group in groups {
+            body.extend_from_slice(&(KafkaErrorCode::None as i16).to_be_bytes());
+            put_compact_string(&mut body, &group.group_id);
+            put_compact_string(&mut body, group.state);
+            put_compact_string(&mut body, &group.protocol_type);
+            put_compact_string(&mut body, &group.protocol);
+
+            put_compact_array_len(&mut body, group.members.len());
+            for member in &group.members {
+                put_compact_string(&mut body, &member.member_id);
+                put_compact_nullable_string(&mut body, member.instance_id.as_deref());
+                put_compact_string(&mut body, &member.client_id);
+                put_compact_string(&mut body, &member.client_host);
+                put_compact_bytes(&mut body, &encode_subscription(&member.subscription));
+                put_compact_bytes(&mut body, &encode_assignment(&member.assignment));
+                body.push(0x00); // tag_buffer
+            }
+
+            // authorization isn't implemented, so every operation is allowed
+            let authorized_operations = if include_authorized_operations { 0x0fff } else { AUTHORIZED_OPERATIONS_OMITTED };
+            body.extend_from_slice(&authorized_operations.to_be_bytes());
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_list_groups_response(correlation_id: i32, error_code: KafkaErrorCode, groups: &[GroupListing]) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+
+        put_compact_array_len(&mut body, groups.len());
+        for group in groups {
+            put_compact_string(&mut body, &group.group_id);
+            put_compact_string(&mut body, &group.protocol_type);
+            put_compact_string(&mut body, group.state);
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_delete_groups_response(correlation_id: i32, results: &[(String, KafkaErrorCode)]) -> Vec<u8> {
+        let mut body = flexible_response_header(correlation_id);
+
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+
+        put_compact_array_len(&mut body, results.len());
+        for (group_id, error_code) in results {
+            put_compact_string(&mut body, group_id);
+            body.extend_from_slice(&(*error_code as i16).to_be_bytes());
+            body.push(0x00); // tag_buffer
+        }
+
+        // body TAG_BUFFER
+        body.push(0x00);
+
+        size_prefixed(body)
+    }
+
+    pub fn build_offset_delete_response(
+        correlation_id: i32,
+        error_code: KafkaErrorCode,
+        topics: &[(String, Vec<(i32, KafkaErrorCode)>)],
+    ) -> Vec<u8> {
+        let mut body = response_header(correlation_id);
+
+        body.extend_from_slice(&(error_code as i16).to_be_bytes());
+        // throttle_time_ms
+        body.extend_from_slice(&0i32.to_be_bytes());
+
+        body.extend_from_slice(&(topics.len() as i32).to_be_bytes());
+        for (name, partitions) in topics {
+            put_string(&mut body, name);
+            body.extend_from_slice(&(partitions.len() as i32).to_be_bytes());
+            for (partition, error_code) in partitions {
+                body.extend_from_slice(&partition.to_be_bytes());
+                body.extend_from_slice(&(*error_code as i16).to_be_bytes());
+            }
+        }
+
+        size_prefixed(body)
+    }
+}
+
+impl FetchResponse {
+    // parses the body that follows the response header
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let _throttle_time_ms = decoder.read_i32()?;
+        let error_code = decoder.read_i16()?;
+        let session_id = decoder.read_i32()?;
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut responses = Vec::new();
+        for _ in 0..topic_count {
+            let topic_id = decoder.read_uuid()?;
+            let partition_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            let mut partitions = Vec::new();
+            for _ in 0..partition_count {
+                let partition = decoder.read_i32()?;
+                let error_code = decoder.read_i16()?;
+                let high_watermark = decoder.read_i64()?;
+                let last_stable_offset = decoder.read_i64()?;
+                let log_start_offset = decoder.read_i64()?;
+                let aborted_transactions = match decoder.read_compact_array_len()? {
+                    Some(count) => {
+                        let mut aborted = Vec::new();
+                        for _ in 0..count {
+                            let producer_id = decoder.read_i64()?;
+                            let first_offset = decoder.read_i64()?;
+                            decoder.skip_tagged_fields()?;
+                            aborted.push((producer_id, first_offset));
+                        }
+                        Some(aborted)
+                    }
+                    None => None,
+                };
+                let preferred_read_replica = decoder.read_i32()?;
+                let records = decoder.read_compact_nullable_bytes()?.unwrap_or_default().to_vec();
+                decoder.skip_tagged_fields()?;
+                partitions.push(FetchPartitionResponse {
+                    partition,
+                    error_code,
+                    high_watermark,
+                    last_stable_offset,
+                    log_start_offset,
+                    aborted_transactions,
+                    preferred_read_replica,
+                    records,
+                });
+            }
+            decoder.skip_tagged_fields()?;
+            responses.push(FetchTopicResponse { topic_id, partitions });
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(FetchResponse { error_code, session_id, responses })
+    }
+}
+
+impl OffsetForLeaderEpochResponse {
+    // parses the body that follows the response header
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let _throttle_time_ms = decoder.read_i32()?;
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let topic = decoder.read_compact_string()?;
+            let partition_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            let mut partitions = Vec::new();
+            for _ in 0..partition_count {
+                partitions.push(EpochEndOffset {
+                    error_code: decoder.read_i16()?,
+                    partition: decoder.read_i32()?,
+                    leader_epoch: decoder.read_i32()?,
+                    end_offset: decoder.read_i64()?,
+                });
+                decoder.skip_tagged_fields()?;
+            }
+            decoder.skip_tagged_fields()?;
+            topics.push(OffsetForLeaderTopicResult { topic, partitions });
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(OffsetForLeaderEpochResponse { topics })
+    }
+}
+
+impl LeaderAndIsrResponse {
+    // parses the body that follows the response header
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let error_code = decoder.read_i16()?;
+
+        let mut partition_errors = Vec::new();
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        for _ in 0..topic_count {
+            let topic_id = decoder.read_uuid()?;
+            let partition_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            for _ in 0..partition_count {
+                partition_errors.push((topic_id, decoder.read_i32()?, decoder.read_i16()?));
+                decoder.skip_tagged_fields()?;
+            }
+            decoder.skip_tagged_fields()?;
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(LeaderAndIsrResponse { error_code, partition_errors })
+    }
+}
+
+impl AlterPartitionResponse {
+    // parses the body that follows the response header
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let _throttle_time_ms = decoder.read_i32()?;
+        let error_code = decoder.read_i16()?;
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let topic = decoder.read_compact_string()?;
+            let partition_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            let mut partitions = Vec::new();
+            for _ in 0..partition_count {
+                let partition = decoder.read_i32()?;
+                let error_code = decoder.read_i16()?;
+                let leader_id = decoder.read_i32()?;
+                let leader_epoch = decoder.read_i32()?;
+                let isr = decoder.read_compact_i32_array()?;
+                let _partition_epoch = decoder.read_i32()?;
+                decoder.skip_tagged_fields()?;
+                partitions.push(AlterPartitionResult { partition, error_code, leader_id, leader_epoch, isr });
+            }
+            decoder.skip_tagged_fields()?;
+            topics.push(AlterPartitionTopicResult { topic, partitions });
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(AlterPartitionResponse { error_code, topics })
+    }
+}
+
+impl BrokerRegistrationResponse {
+    // parses the body that follows the response header
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let _throttle_time_ms = decoder.read_i32()?;
+        let error_code = decoder.read_i16()?;
+        let broker_epoch = decoder.read_i64()?;
+        decoder.skip_tagged_fields()?;
+
+        Ok(BrokerRegistrationResponse { error_code, broker_epoch })
+    }
+}
+
+impl AllocateProducerIdsResponse {
+    // parses the body that follows the response header
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let _throttle_time_ms = decoder.read_i32()?;
+        let error_code = decoder.read_i16()?;
+        let producer_id_start = decoder.read_i64()?;
+        let producer_id_len = decoder.read_i32()?;
+        decoder.skip_tagged_fields()?;
+
+        Ok(AllocateProducerIdsResponse { error_code, producer_id_start, producer_id_len })
+    }
+}
+
+impl WriteTxnMarkersResponse {
+    // parses the body that follows the response header
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let marker_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut markers = Vec::new();
+        for _ in 0..marker_count {
+            let producer_id = decoder.read_i64()?;
+            let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            let mut topics = Vec::new();
+            for _ in 0..topic_count {
+                let name = decoder.read_compact_string()?;
+                let partition_count = decoder.read_compact_array_len()?.unwrap_or(0);
+                let mut partitions = Vec::new();
+                for _ in 0..partition_count {
+                    let partition = decoder.read_i32()?;
+                    let error_code = decoder.read_i16()?;
+                    decoder.skip_tagged_fields()?;
+                    partitions.push((partition, error_code));
+                }
+                decoder.skip_tagged_fields()?;
+                topics.push((name, partitions));
+            }
+            decoder.skip_tagged_fields()?;
+            markers.push(WritableTxnMarkerResult { producer_id, topics });
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(WriteTxnMarkersResponse { markers })
+    }
+}
+
+impl BrokerHeartbeatResponse {
+    // parses the body that follows the response header
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let _throttle_time_ms = decoder.read_i32()?;
+        let error_code = decoder.read_i16()?;
+        let is_caught_up = decoder.read_i8()? != 0;
+        let is_fenced = decoder.read_i8()? != 0;
+        let should_shutdown = decoder.read_i8()? != 0;
+        decoder.skip_tagged_fields()?;
+
+        Ok(BrokerHeartbeatResponse { error_code, is_caught_up, is_fenced, should_shutdown })
     }
-} 
\ No newline at end of file
+}
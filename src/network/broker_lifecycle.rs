@@ -0,0 +1,190 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{watch, Notify};
+use uuid::Uuid;
+
+use crate::{
+    constants::{API_KEY_BROKER_HEARTBEAT, API_KEY_BROKER_REGISTRATION, BROKER_HEARTBEAT_INTERVAL_MS, CONTROLLER_REQUEST_BACKOFF_MS},
+    core::broker::Broker,
+    core::metadata::BrokerEndpoint,
+    error::{KafkaErrorCode, ServerError},
+    network::api::{BrokerHeartbeatResponse, BrokerRegistrationResponse},
+    network::client::KafkaClient,
+    network::requests::{BrokerHeartbeatRequest, BrokerRegistrationRequest},
+};
+
+const BROKER_REGISTRATION_VERSION: i16 = 2;
+const BROKER_HEARTBEAT_VERSION: i16 = 1;
+// cluster ids aren't checked by the controller yet
+const CLUSTER_ID: &str = "rafka";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrokerState {
+    Starting,                  // not registered yet
+    Recovery,                  // registered, still fenced
+    Running,                   // unfenced, can lead and follow partitions
+    PendingControlledShutdown, // waiting for the controller to move leadership away
+    ShuttingDown,              // the controller agreed, nothing is led here anymore
+}
+
+/// registers this broker with the controller, keeps its session alive with
+/// heartbeats and asks for controlled shutdown before the broker stops
+pub struct BrokerLifecycleManager {
+    broker: Arc<Broker>,
+    listeners: Vec<BrokerEndpoint>,
+    controllers: Vec<String>, // Kafka listeners of the controller nodes, tried in turn
+    incarnation_id: Uuid,
+    shutdown_requested: AtomicBool,
+    wakeup: Notify,
+    state: watch::Sender<BrokerState>,
+}
+
+// connection to whichever controller node answered last
+struct ControllerConnection {
+    index: usize,
+    client: Option<KafkaClient>,
+}
+
+impl BrokerLifecycleManager {
+    pub fn new(broker: Arc<Broker>, listeners: Vec<BrokerEndpoint>, controllers: Vec<String>) -> Self {
+        let (state, _) = watch::channel(BrokerState::Starting);
+        BrokerLifecycleManager {
+            broker,
+            listeners,
+            controllers,
+            incarnation_id: Uuid::new_v4(),
+            shutdown_requested: AtomicBool::new(false),
+            wakeup: Notify::new(),
+            state,
+        }
+    }
+
+    pub fn state(&self) -> BrokerState {
+        *self.state.borrow()
+    }
+
+    /// asks the controller to move leadership off this broker and waits until it
+    /// agrees the broker can stop
+    pub async fn controlled_shutdown(&self) {
+        let mut state = self.state.subscribe();
+        if *state.borrow_and_update() == BrokerState::Starting {
+            return; // never registered, so nothing to hand over
+        }
+        self.shutdown_requested.store(true, Ordering::SeqCst);
+        self.state.send_if_modified(|state| {
+            let pending = *state != BrokerState::ShuttingDown;
+            if pending {
+                *state = BrokerState::PendingControlledShutdown;
+            }
+            pending
+        });
+        self.wakeup.notify_one();
+        while *state.borrow_and_update() != BrokerState::ShuttingDown {
+            if state.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut connection = ControllerConnection { index: 0, client: None };
+        let mut broker_epoch = None;
+        loop {
+            let result = match broker_epoch {
+                None => self.register(&mut connection).await.map(|epoch| {
+                    broker_epoch = Some(epoch);
+                    true
+                }),
+                Some(epoch) => self.heartbeat(&mut connection, epoch).await,
+            };
+            match result {
+                // registered, or a heartbeat went through
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(ServerError::ErrorResponse(code))
+                    if code == i16::from(KafkaErrorCode::StaleBrokerEpoch) || code == i16::from(KafkaErrorCode::BrokerIdNotRegistered) =>
+                {
+                    println!("Broker {} lost its registration, registering again", self.broker.broker_id());
+                    broker_epoch = None;
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Broker {} lifecycle request failed: {}", self.broker.broker_id(), e);
+                    connection.client = None;
+                    connection.index = (connection.index + 1) % self.controllers.len().max(1);
+                    tokio::time::sleep(Duration::from_millis(CONTROLLER_REQUEST_BACKOFF_MS)).await;
+                    continue;
+                }
+            }
+            let interval = Duration::from_millis(BROKER_HEARTBEAT_INTERVAL_MS);
+            let _ = tokio::time::timeout(interval, self.wakeup.notified()).await;
+        }
+    }
+
+    async fn register(&self, connection: &mut ControllerConnection) -> Result<i64, ServerError> {
+        let request = BrokerRegistrationRequest {
+            broker_id: self.broker.broker_id(),
+            cluster_id: CLUSTER_ID.to_string(),
+            incarnation_id: self.incarnation_id,
+            listeners: self.listeners.clone(),
+            rack: self.broker.rack().map(str::to_string),
+            log_dirs: self.broker.log_dir_ids()?,
+        };
+        let body = self.send(connection, API_KEY_BROKER_REGISTRATION, BROKER_REGISTRATION_VERSION, &request.encode()).await?;
+        let response = BrokerRegistrationResponse::parse(&body)?;
+        if response.error_code != i16::from(KafkaErrorCode::None) {
+            return Err(ServerError::ErrorResponse(response.error_code));
+        }
+        println!("Broker {} registered with epoch {}", self.broker.broker_id(), response.broker_epoch);
+        self.state.send_if_modified(|state| {
+            let starting = *state == BrokerState::Starting;
+            if starting {
+                *state = BrokerState::Recovery;
+            }
+            starting
+        });
+        Ok(response.broker_epoch)
+    }
+
+    // false once the controller says the broker can shut down
+    async fn heartbeat(&self, connection: &mut ControllerConnection, broker_epoch: i64) -> Result<bool, ServerError> {
+        let request = BrokerHeartbeatRequest {
+            broker_id: self.broker.broker_id(),
+            broker_epoch,
+            current_metadata_offset: self.broker.metadata_offset(),
+            want_fence: false,
+            want_shutdown: self.shutdown_requested.load(Ordering::SeqCst),
+        };
+        let body = self.send(connection, API_KEY_BROKER_HEARTBEAT, BROKER_HEARTBEAT_VERSION, &request.encode()).await?;
+        let response = BrokerHeartbeatResponse::parse(&body)?;
+        if response.error_code != i16::from(KafkaErrorCode::None) {
+            return Err(ServerError::ErrorResponse(response.error_code));
+        }
+
+        if response.should_shutdown {
+            self.state.send_replace(BrokerState::ShuttingDown);
+            return Ok(false);
+        }
+        if !response.is_fenced {
+            self.state.send_if_modified(|state| {
+                let recovering = *state == BrokerState::Recovery;
+                if recovering {
+                    *state = BrokerState::Running;
+                }
+                recovering
+            });
+        }
+        Ok(true)
+    }
+
+    async fn send(&self, connection: &mut ControllerConnection, api_key: i16, api_version: i16, body: &[u8]) -> Result<Vec<u8>, ServerError> {
+        if connection.client.is_none() {
+            let address = self.controllers.get(connection.index).ok_or(ServerError::ControllerNotAvailable)?;
+            let client_id = format!("broker-{}-lifecycle", self.broker.broker_id());
+            connection.client = Some(KafkaClient::connect(address, &client_id).await?);
+        }
+        connection.client.as_mut().unwrap().send_request(api_key, api_version, body).await
+    }
+}
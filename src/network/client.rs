@@ -0,0 +1,64 @@
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+use crate::{
+    constants::API_KEY_API_VERSIONS,
+    error::ServerError,
+    network::handler::{MessageParser, RequestDecoder},
+    network::protocol::KafkaProtocolHandler,
+};
+
+// outgoing connection to another broker, one request in flight at a time
+pub struct KafkaClient {
+    stream: TcpStream,
+    client_id: String,
+    correlation_id: i32,
+}
+
+impl KafkaClient {
+    pub async fn connect(address: &str, client_id: &str) -> Result<Self, ServerError> {
+        let stream = TcpStream::connect(address).await?;
+        stream.set_nodelay(true)?;
+        Ok(KafkaClient {
+            stream,
+            client_id: client_id.to_string(),
+            correlation_id: 0,
+        })
+    }
+
+    /// sends a request and returns the response body that follows its header
+    pub async fn send_request(&mut self, api_key: i16, api_version: i16, body: &[u8]) -> Result<Vec<u8>, ServerError> {
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let flexible = KafkaProtocolHandler::is_flexible(api_key, api_version);
+
+        let mut message = Vec::with_capacity(body.len() + 16 + self.client_id.len());
+        message.extend_from_slice(&api_key.to_be_bytes());
+        message.extend_from_slice(&api_version.to_be_bytes());
+        message.extend_from_slice(&self.correlation_id.to_be_bytes());
+        message.extend_from_slice(&(self.client_id.len() as i16).to_be_bytes());
+        message.extend_from_slice(self.client_id.as_bytes());
+        if flexible {
+            message.push(0x00); // header tag_buffer
+        }
+        message.extend_from_slice(body);
+
+        self.stream.write_all(&(message.len() as i32).to_be_bytes()).await?;
+        self.stream.write_all(&message).await?;
+
+        let size = MessageParser::read_i32_async(&mut self.stream).await?;
+        if size < 4 {
+            return Err(ServerError::InvalidMessageSize(size));
+        }
+        let response = MessageParser::read_exact_bytes_async(&mut self.stream, size as usize).await?;
+
+        let mut decoder = RequestDecoder::new(&response);
+        if decoder.read_i32()? != self.correlation_id {
+            return Err(ServerError::MalformedRequest("response correlation id doesn't match"));
+        }
+        // ApiVersions responses always use header v0
+        if flexible && api_key != API_KEY_API_VERSIONS {
+            decoder.skip_tagged_fields()?;
+        }
+        Ok(decoder.remaining().to_vec())
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    constants::{API_KEY_LEADER_AND_ISR, CONTROLLER_REQUEST_BACKOFF_MS},
+    core::controller::{Controller, PartitionState},
+    error::{KafkaErrorCode, ServerError},
+    network::api::LeaderAndIsrResponse,
+    network::client::KafkaClient,
+    network::requests::LeaderAndIsrRequest,
+};
+
+const LEADER_AND_ISR_VERSION: i16 = 5;
+
+// pushes the controller's partition state changes to every live broker
+pub struct ControllerChannel {
+    controller: Arc<Controller>,
+    clients: HashMap<i32, KafkaClient>,
+}
+
+impl ControllerChannel {
+    pub fn new(controller: Arc<Controller>) -> Self {
+        ControllerChannel {
+            controller,
+            clients: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            let states = self.controller.take_pending_states().await;
+            if !self.send_leader_and_isr(&states).await {
+                // states are idempotent on brokers, so resending to everyone is fine
+                self.controller.requeue_states(states).await;
+                tokio::time::sleep(Duration::from_millis(CONTROLLER_REQUEST_BACKOFF_MS)).await;
+            }
+        }
+    }
+
+    // one LeaderAndIsr per live broker; false if any of them couldn't be reached
+    async fn send_leader_and_isr(&mut self, states: &[PartitionState]) -> bool {
+        let live_brokers = self.controller.live_brokers().await;
+        self.clients.retain(|broker_id, _| live_brokers.contains_key(broker_id));
+
+        let request = LeaderAndIsrRequest {
+            controller_id: self.controller.controller_id(),
+            controller_epoch: self.controller.controller_epoch(),
+            broker_epoch: -1,
+            partition_states: states.to_vec(),
+            live_leaders: live_brokers.iter().map(|(id, address)| (*id, address.clone())).collect(),
+        };
+        let body = request.encode();
+
+        let mut delivered = true;
+        for (broker_id, address) in &live_brokers {
+            if let Err(e) = self.send_to(*broker_id, address, &body).await {
+                eprintln!("LeaderAndIsr to broker {} failed: {}", broker_id, e);
+                self.clients.remove(broker_id);
+                delivered = false;
+            }
+        }
+        delivered
+    }
+
+    async fn send_to(&mut self, broker_id: i32, address: &str, body: &[u8]) -> Result<(), ServerError> {
+        if !self.clients.contains_key(&broker_id) {
+            let client_id = format!("controller-{}", self.controller.controller_id());
+            self.clients.insert(broker_id, KafkaClient::connect(address, &client_id).await?);
+        }
+        let client = self.clients.get_mut(&broker_id).unwrap();
+        let response = LeaderAndIsrResponse::parse(&client.send_request(API_KEY_LEADER_AND_ISR, LEADER_AND_ISR_VERSION, body).await?)?;
+
+        if response.error_code != i16::from(KafkaErrorCode::None) {
+            eprintln!("Broker {} rejected LeaderAndIsr with error {}", broker_id, response.error_code);
+        }
+        for (topic_id, partition, error_code) in response.partition_errors {
+            if error_code != i16::from(KafkaErrorCode::None) {
+                eprintln!("Broker {} failed to apply state of partition {} of topic {}: error {}", broker_id, partition, topic_id, error_code);
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use tokio::io::AsyncReadExt;
 use tokio::net::TcpStream;
+use uuid::Uuid;
 
 use crate::error::ServerError;
 
@@ -27,4 +28,168 @@ impl MessageParser {
                 .map_err(|_| ServerError::InvalidMessageSize(-1))?
         ))
     }
-} 
\ No newline at end of file
+} 
+
+// reads the fields of a request body that has already been pulled off the socket
+pub struct RequestDecoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> RequestDecoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        RequestDecoder { buf, pos: 0 }
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+
+    fn take(&mut self, size: usize) -> Result<&'a [u8], ServerError> {
+        if self.buf.len() - self.pos < size {
+            return Err(ServerError::MalformedRequest("unexpected end of request"));
+        }
+        let bytes = &self.buf[self.pos..self.pos + size];
+        self.pos += size;
+        Ok(bytes)
+    }
+
+    pub fn read_i8(&mut self) -> Result<i8, ServerError> {
+        Ok(self.take(1)?[0] as i8)
+    }
+
+    pub fn read_i16(&mut self) -> Result<i16, ServerError> {
+        let bytes = self.take(2)?;
+        Ok(i16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, ServerError> {
+        let bytes = self.take(4)?;
+        Ok(i32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, ServerError> {
+        let bytes = self.take(8)?;
+        Ok(i64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_uuid(&mut self) -> Result<Uuid, ServerError> {
+        let bytes = self.take(16)?;
+        Ok(Uuid::from_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_unsigned_varint(&mut self) -> Result<u32, ServerError> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(ServerError::MalformedRequest("varint is too long"))
+    }
+
+    fn read_utf8(&mut self, len: usize) -> Result<String, ServerError> {
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ServerError::MalformedRequest("string is not valid utf-8"))
+    }
+
+    // int16 length prefix, -1 for null (request header client_id)
+    pub fn read_nullable_string(&mut self) -> Result<Option<String>, ServerError> {
+        let len = self.read_i16()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        self.read_utf8(len as usize).map(Some)
+    }
+
+    pub fn read_string(&mut self) -> Result<String, ServerError> {
+        self.read_nullable_string()?
+            .ok_or(ServerError::MalformedRequest("unexpected null string"))
+    }
+
+    // int32 length prefix used by non-flexible versions, -1 for null
+    pub fn read_array_len(&mut self) -> Result<Option<usize>, ServerError> {
+        let len = self.read_i32()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        self.check_array_len(len as usize).map(Some)
+    }
+
+    pub fn read_compact_nullable_string(&mut self) -> Result<Option<String>, ServerError> {
+        // compact lengths are stored as length + 1, 0 means null
+        let len = self.read_unsigned_varint()? as usize;
+        if len == 0 {
+            return Ok(None);
+        }
+        self.read_utf8(len - 1).map(Some)
+    }
+
+    pub fn read_compact_string(&mut self) -> Result<String, ServerError> {
+        self.read_compact_nullable_string()?
+            .ok_or(ServerError::MalformedRequest("unexpected null string"))
+    }
+
+    // None for a null array
+    pub fn read_compact_array_len(&mut self) -> Result<Option<usize>, ServerError> {
+        let len = self.read_unsigned_varint()? as usize;
+        if len == 0 {
+            return Ok(None);
+        }
+        self.check_array_len(len - 1).map(Some)
+    }
+
+    // every element takes at least a byte, so a longer array can't be in the request
+    fn check_array_len(&self, len: usize) -> Result<usize, ServerError> {
+        if len > self.buf.len() - self.pos {
+            return Err(ServerError::MalformedRequest("array longer than the request"));
+        }
+        Ok(len)
+    }
+
+    pub fn read_compact_i32_array(&mut self) -> Result<Vec<i32>, ServerError> {
+        let len = self.read_compact_array_len()?.unwrap_or(0);
+        (0..len).map(|_| self.read_i32()).collect()
+    }
+
+    // None for a null array
+    pub fn read_compact_nullable_i32_array(&mut self) -> Result<Option<Vec<i32>>, ServerError> {
+        match self.read_compact_array_len()? {
+            Some(len) => (0..len).map(|_| self.read_i32()).collect::<Result<_, _>>().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn read_compact_string_array(&mut self) -> Result<Option<Vec<String>>, ServerError> {
+        match self.read_compact_array_len()? {
+            Some(len) => (0..len).map(|_| self.read_compact_string()).collect::<Result<_, _>>().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn read_compact_nullable_bytes(&mut self) -> Result<Option<&'a [u8]>, ServerError> {
+        let len = self.read_unsigned_varint()? as usize;
+        if len == 0 {
+            return Ok(None);
+        }
+        self.take(len - 1).map(Some)
+    }
+
+    // returns (tag, raw value) for every tagged field, callers pick the tags they know
+    pub fn read_tagged_fields(&mut self) -> Result<Vec<(u32, &'a [u8])>, ServerError> {
+        let count = self.read_unsigned_varint()?;
+        let mut fields = Vec::new();
+        for _ in 0..count {
+            let tag = self.read_unsigned_varint()?;
+            let size = self.read_unsigned_varint()? as usize;
+            fields.push((tag, self.take(size)?));
+        }
+        Ok(fields)
+    }
+
+    pub fn skip_tagged_fields(&mut self) -> Result<(), ServerError> {
+        self.read_tagged_fields().map(|_| ())
+    }
+}
@@ -0,0 +1,344 @@
+use std::sync::Arc;
+
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task;
+
+use crate::core::broker::Broker;
+use crate::core::consumer_group::{GroupError, TopicPartition};
+use crate::core::group_coordinator::OffsetResetStrategy;
+
+// requests are a request line and headers, without a body
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+// plain HTTP endpoint serving the broker's metrics, plus the admin routes:
+//   GET  /metrics                            Prometheus metrics, also served on /
+//   GET  /consumer-groups/{group}/lag        lag of a group per topic and partition
+//   POST /consumer-groups/{group}/reset-offsets?topic=..[&partition=..]&to=..
+//        moves an empty group's offsets to earliest, latest, timestamp:{ms} or offset:{n}
+pub struct MetricsServer {
+    address: String,
+    broker: Arc<Broker>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Route {
+    Metrics,
+    ConsumerLag(String),
+    ResetOffsets { group_id: String, topic: String, partition: Option<i32>, strategy: OffsetResetStrategy },
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(status: &'static str, body: serde_json::Value) -> Self {
+        Response { status, content_type: "application/json", body: body.to_string() }
+    }
+
+    fn error(status: &'static str, message: &str) -> Self {
+        Self::json(status, json!({ "error": message }))
+    }
+}
+
+impl MetricsServer {
+    pub fn new(address: &str, broker: Arc<Broker>) -> Self {
+        MetricsServer { address: address.to_string(), broker }
+    }
+
+    async fn serve(mut stream: TcpStream, broker: Arc<Broker>) -> std::io::Result<()> {
+        let request = read_request_head(&mut stream).await?;
+        let response = match request.as_deref().and_then(|head| head.lines().next()).map(parse_request_line) {
+            Some(Ok(route)) => handle(&broker, route).await,
+            Some(Err(response)) => response,
+            None => Response::error("400 Bad Request", "malformed request"),
+        };
+        let head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.status,
+            response.content_type,
+            response.body.len(),
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(response.body.as_bytes()).await?;
+        stream.shutdown().await
+    }
+
+    pub async fn run(&self) -> Result<(), std::io::Error> {
+        println!("Serving metrics on {}", self.address);
+        let listener = TcpListener::bind(&self.address).await?;
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let broker = Arc::clone(&self.broker);
+            task::spawn(async move {
+                if let Err(e) = Self::serve(stream, broker).await {
+                    eprintln!("Metrics request failed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+async fn handle(broker: &Broker, route: Route) -> Response {
+    match route {
+        Route::Metrics => Response {
+            status: "200 OK",
+            content_type: "text/plain; version=0.0.4",
+            body: broker.metrics().render().await,
+        },
+        Route::ConsumerLag(group_id) => match broker.consumer_lag(&group_id).await {
+            Some(lag) => Response::json(
+                "200 OK",
+                json!({
+                    "group_id": lag.group_id,
+                    "total_lag": lag.total_lag(),
+                    "max_time_lag_ms": lag.max_time_lag_ms(),
+                    "topics": lag.topic_lag(),
+                    "partitions": lag.partitions,
+                }),
+            ),
+            None => Response::error("404 Not Found", "unknown consumer group"),
+        },
+        Route::ResetOffsets { group_id, topic, partition, strategy } => {
+            let partitions = match partition {
+                Some(partition) => vec![partition],
+                None => match broker.topic_manager().get(&topic).await {
+                    Some(topic) => {
+                        let mut partitions = topic.all_partitions().await;
+                        partitions.sort_unstable();
+                        partitions
+                    }
+                    None => return Response::error("404 Not Found", "unknown topic"),
+                },
+            };
+            let partitions: Vec<TopicPartition> = partitions.into_iter().map(|p| TopicPartition::new(topic.clone(), p)).collect();
+            match broker.reset_offsets(&group_id, &partitions, strategy).await {
+                Ok(offsets) => {
+                    let offsets: Vec<_> = offsets
+                        .iter()
+                        .map(|(tp, offset)| json!({ "topic": tp.topic(), "partition": tp.partition(), "offset": offset }))
+                        .collect();
+                    Response::json("200 OK", json!({ "group_id": group_id, "offsets": offsets }))
+                }
+                Err(e) => {
+                    let status = match e {
+                        GroupError::NonEmptyGroup(_) => "409 Conflict",
+                        GroupError::UnknownTopicOrPartition(..) => "404 Not Found",
+                        GroupError::InvalidGroupId => "400 Bad Request",
+                        _ => "500 Internal Server Error",
+                    };
+                    Response::error(status, &e.to_string())
+                }
+            }
+        }
+    }
+}
+
+// everything up to the blank line after the headers; None if the client never sent it
+async fn read_request_head(stream: &mut TcpStream) -> std::io::Result<Option<String>> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+    Ok(String::from_utf8(request).ok())
+}
+
+fn parse_request_line(line: &str) -> Result<Route, Response> {
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(Response::error("400 Bad Request", "malformed request line"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let segments = path
+        .trim_matches('/')
+        .split('/')
+        .map(percent_decode)
+        .collect::<Option<Vec<String>>>()
+        .ok_or_else(|| Response::error("400 Bad Request", "malformed path"))?;
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    match (method, segments.as_slice()) {
+        ("GET", [""] | ["metrics"]) => Ok(Route::Metrics),
+        ("GET", ["consumer-groups", group, "lag"]) => Ok(Route::ConsumerLag(group.to_string())),
+        ("POST", ["consumer-groups", group, "reset-offsets"]) => parse_reset_offsets(group, query),
+        (_, [""] | ["metrics"] | ["consumer-groups", _, "lag" | "reset-offsets"]) => {
+            Err(Response::error("405 Method Not Allowed", "method not allowed"))
+        }
+        _ => Err(Response::error("404 Not Found", "no such route")),
+    }
+}
+
+fn parse_reset_offsets(group_id: &str, query: &str) -> Result<Route, Response> {
+    let bad_request = |message| Response::error("400 Bad Request", message);
+    let mut params = std::collections::HashMap::new();
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        params.insert(name, percent_decode(value).ok_or_else(|| bad_request("malformed query"))?);
+    }
+    let topic = params.get("topic").filter(|topic| !topic.is_empty()).ok_or_else(|| bad_request("topic is required"))?;
+    let partition = match params.get("partition") {
+        Some(partition) => Some(partition.parse().map_err(|_| bad_request("partition must be a number"))?),
+        None => None,
+    };
+    let to = params.get("to").map(String::as_str).unwrap_or_default();
+    let strategy = match to.split_once(':') {
+        None if to == "earliest" => OffsetResetStrategy::Earliest,
+        None if to == "latest" => OffsetResetStrategy::Latest,
+        Some(("timestamp", timestamp)) => {
+            OffsetResetStrategy::Timestamp(timestamp.parse().map_err(|_| bad_request("timestamp must be a number"))?)
+        }
+        Some(("offset", offset)) => OffsetResetStrategy::Offset(offset.parse().map_err(|_| bad_request("offset must be a number"))?),
+        _ => return Err(bad_request("to must be earliest, latest, timestamp:{ms} or offset:{n}")),
+    };
+    Ok(Route::ResetOffsets { group_id: group_id.to_string(), topic: topic.clone(), partition, strategy })
+}
+
+// decodes %XX escapes, e.g. in group ids; None for a broken escape or non-UTF-8 result
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::core::consumer_group::{OffsetAndMetadata, TopicPartition};
+    use crate::storage::record_batch;
+
+    fn committed(offset: i64) -> OffsetAndMetadata {
+        OffsetAndMetadata { offset, leader_epoch: -1, metadata: String::new(), commit_timestamp: 0 }
+    }
+
+    #[test]
+    fn requests_are_routed_by_path() {
+        assert_eq!(parse_request_line("GET / HTTP/1.1").ok(), Some(Route::Metrics));
+        assert_eq!(parse_request_line("GET /metrics?x=1 HTTP/1.1").ok(), Some(Route::Metrics));
+        assert_eq!(
+            parse_request_line("GET /consumer-groups/billing%20v2/lag HTTP/1.1").ok(),
+            Some(Route::ConsumerLag("billing v2".to_string()))
+        );
+        assert_eq!(parse_request_line("GET /consumer-groups/billing HTTP/1.1").err().unwrap().status, "404 Not Found");
+        assert_eq!(parse_request_line("GET /consumer-groups/%zz/lag HTTP/1.1").err().unwrap().status, "400 Bad Request");
+        assert_eq!(parse_request_line("DELETE /metrics HTTP/1.1").err().unwrap().status, "405 Method Not Allowed");
+    }
+
+    #[tokio::test]
+    async fn consumer_lag_is_served_per_partition() {
+        let dir = std::env::temp_dir().join(format!("rafka-lag-{}", uuid::Uuid::new_v4()));
+        let broker = Broker::with_log_dir(0, dir.clone());
+        let replicas = broker.replica_manager();
+        replicas.add_leader_partition("orders".to_string(), 0, 0, Vec::new(), Vec::new()).await.unwrap();
+        let sent_at = Utc::now().timestamp_millis() - 60_000;
+        for (i, timestamp) in [sent_at, sent_at + 30_000, sent_at + 40_000].into_iter().enumerate() {
+            let batch = record_batch::build_batch(0, 0, timestamp, &[format!("order-{}", i).into_bytes()]);
+            replicas.append_as_leader("orders", 0, &batch).await.unwrap();
+        }
+        let tp = TopicPartition::new("orders".to_string(), 0);
+        broker.group_coordinator().commit_offsets("billing", vec![(tp, committed(1))]).await.unwrap();
+
+        let response = handle(&broker, Route::ConsumerLag("billing".to_string())).await;
+        assert_eq!(response.status, "200 OK");
+        let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(body["total_lag"], 2);
+        assert_eq!(body["topics"]["orders"], 2);
+        let partition = &body["partitions"][0];
+        assert_eq!((partition["committed_offset"].as_i64(), partition["high_watermark"].as_i64()), (Some(1), Some(3)));
+        // the message at the committed offset was written 30 seconds ago
+        let time_lag_ms = partition["time_lag_ms"].as_i64().unwrap();
+        assert!((30_000..40_000).contains(&time_lag_ms), "time lag {}", time_lag_ms);
+        assert_eq!(body["max_time_lag_ms"], time_lag_ms);
+
+        let response = handle(&broker, Route::ConsumerLag("unknown".to_string())).await;
+        assert_eq!(response.status, "404 Not Found");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reset_offsets_requests_are_parsed() {
+        let route = parse_request_line("POST /consumer-groups/billing/reset-offsets?topic=orders&to=earliest HTTP/1.1");
+        assert_eq!(
+            route.ok(),
+            Some(Route::ResetOffsets {
+                group_id: "billing".to_string(),
+                topic: "orders".to_string(),
+                partition: None,
+                strategy: OffsetResetStrategy::Earliest,
+            })
+        );
+        let route = parse_request_line("POST /consumer-groups/billing/reset-offsets?topic=orders&partition=2&to=timestamp:1700 HTTP/1.1");
+        assert!(matches!(route, Ok(Route::ResetOffsets { partition: Some(2), strategy: OffsetResetStrategy::Timestamp(1700), .. })));
+
+        for bad in ["to=latest", "topic=orders", "topic=orders&to=offset:x", "topic=orders&partition=a&to=latest"] {
+            let line = format!("POST /consumer-groups/billing/reset-offsets?{} HTTP/1.1", bad);
+            assert_eq!(parse_request_line(&line).err().unwrap().status, "400 Bad Request", "{}", bad);
+        }
+        let line = "GET /consumer-groups/billing/reset-offsets?topic=orders&to=latest HTTP/1.1";
+        assert_eq!(parse_request_line(line).err().unwrap().status, "405 Method Not Allowed");
+    }
+
+    #[tokio::test]
+    async fn reset_offsets_resolves_against_the_log() {
+        let dir = std::env::temp_dir().join(format!("rafka-reset-{}", uuid::Uuid::new_v4()));
+        let broker = Broker::with_log_dir(0, dir.clone());
+        let replicas = broker.replica_manager();
+        replicas.add_leader_partition("orders".to_string(), 0, 0, Vec::new(), Vec::new()).await.unwrap();
+        for timestamp in [1_000, 2_000, 3_000] {
+            let batch = record_batch::build_batch(0, 0, timestamp, &[b"order".to_vec()]);
+            replicas.append_as_leader("orders", 0, &batch).await.unwrap();
+        }
+        let reset = |strategy| Route::ResetOffsets {
+            group_id: "billing".to_string(),
+            topic: "orders".to_string(),
+            partition: Some(0),
+            strategy,
+        };
+
+        for (strategy, expected) in [
+            (OffsetResetStrategy::Latest, 3),
+            (OffsetResetStrategy::Earliest, 0),
+            (OffsetResetStrategy::Timestamp(1_500), 1),
+            (OffsetResetStrategy::Timestamp(9_000), 3),
+            (OffsetResetStrategy::Offset(100), 3),
+        ] {
+            let response = handle(&broker, reset(strategy)).await;
+            assert_eq!(response.status, "200 OK");
+            let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+            assert_eq!(body["offsets"][0]["offset"], expected, "{:?}", strategy);
+        }
+        let committed = broker.group_coordinator().committed_offsets("billing").await.unwrap();
+        assert_eq!(committed[&TopicPartition::new("orders".to_string(), 0)].offset, 3);
+
+        let unknown = Route::ResetOffsets {
+            group_id: "billing".to_string(),
+            topic: "orders".to_string(),
+            partition: Some(7),
+            strategy: OffsetResetStrategy::Earliest,
+        };
+        assert_eq!(handle(&broker, unknown).await.status, "404 Not Found");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
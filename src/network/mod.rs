@@ -1,4 +1,13 @@
 pub mod api;
 pub mod server;
 pub mod protocol;
-pub mod handler; 
\ No newline at end of file
+pub mod handler;
+pub mod requests;
+pub mod metrics;
+pub mod client;
+pub mod replica_fetcher;
+pub mod controller_channel;
+pub mod alter_partition;
+pub mod broker_lifecycle;
+pub mod producer_id;
+pub mod txn_marker;
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    constants::{API_KEY_ALLOCATE_PRODUCER_IDS, CONTROLLER_REQUEST_BACKOFF_MS},
+    core::broker::Broker,
+    core::producer_id::ProducerIdBlock,
+    error::{KafkaErrorCode, ServerError},
+    network::api::AllocateProducerIdsResponse,
+    network::client::KafkaClient,
+    network::requests::AllocateProducerIdsRequest,
+};
+
+const ALLOCATE_PRODUCER_IDS_VERSION: i16 = 0;
+
+// keeps the broker stocked with producer ids, asking the controller for a new block
+// whenever the broker's pool runs low
+pub struct ProducerIdManager {
+    broker: Arc<Broker>,
+    client: Option<(i32, KafkaClient)>, // connection to the controller it was opened for
+}
+
+impl ProducerIdManager {
+    pub fn new(broker: Arc<Broker>) -> Self {
+        ProducerIdManager { broker, client: None }
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            self.broker.wait_for_producer_id_demand().await;
+            match self.allocate().await {
+                Ok(block) => self.broker.add_producer_id_block(block).await,
+                Err(e) => {
+                    eprintln!("AllocateProducerIds to the controller failed: {}", e);
+                    self.client = None;
+                    tokio::time::sleep(Duration::from_millis(CONTROLLER_REQUEST_BACKOFF_MS)).await;
+                }
+            }
+        }
+    }
+
+    async fn connected_client(&mut self) -> Result<&mut KafkaClient, ServerError> {
+        let controller_id = self
+            .broker
+            .known_controller_id()
+            .await
+            .ok_or(ServerError::ControllerNotAvailable)?;
+        if self.client.as_ref().is_none_or(|(id, _)| *id != controller_id) {
+            let address = self
+                .broker
+                .broker_endpoint(controller_id)
+                .await
+                .ok_or(ServerError::BrokerNotAvailable(controller_id))?;
+            let client_id = format!("producer-id-{}", self.broker.broker_id());
+            self.client = Some((controller_id, KafkaClient::connect(&address, &client_id).await?));
+        }
+        Ok(&mut self.client.as_mut().unwrap().1)
+    }
+
+    async fn allocate(&mut self) -> Result<ProducerIdBlock, ServerError> {
+        let request = AllocateProducerIdsRequest { broker_id: self.broker.broker_id(), broker_epoch: -1 };
+        let client = self.connected_client().await?;
+        let body = client
+            .send_request(API_KEY_ALLOCATE_PRODUCER_IDS, ALLOCATE_PRODUCER_IDS_VERSION, &request.encode())
+            .await?;
+        let response = AllocateProducerIdsResponse::parse(&body)?;
+        if response.error_code != i16::from(KafkaErrorCode::None) {
+            return Err(ServerError::ErrorResponse(response.error_code));
+        }
+        Ok(ProducerIdBlock { first_producer_id: response.producer_id_start, size: response.producer_id_len })
+    }
+}
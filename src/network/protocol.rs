@@ -1,7 +1,37 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use chrono::Utc;
+use uuid::Uuid;
+
 use crate::{
-    constants::{API_KEY_API_VERSIONS, SUPPORTED_VERSION_MIN, SUPPORTED_VERSION_MAX, API_KEY_FETCH},
-    error::KafkaErrorCode,
-    network::api::ResponseBuilder,
+    constants::{
+        API_KEY_API_VERSIONS, SUPPORTED_VERSION_MIN, SUPPORTED_VERSION_MAX, API_KEY_PRODUCE, API_KEY_FETCH, API_KEY_CONSUMER_GROUP_HEARTBEAT,
+        API_KEY_DESCRIBE_GROUPS, API_KEY_LIST_GROUPS, API_KEY_DELETE_GROUPS, API_KEY_OFFSET_DELETE,
+        API_KEY_OFFSET_FOR_LEADER_EPOCH, API_KEY_LEADER_AND_ISR, API_KEY_ALTER_PARTITION, API_KEY_BROKER_REGISTRATION,
+        API_KEY_BROKER_HEARTBEAT, API_KEY_ALTER_PARTITION_REASSIGNMENTS, API_KEY_LIST_PARTITION_REASSIGNMENTS, API_KEY_ELECT_LEADERS,
+        API_KEY_INIT_PRODUCER_ID, API_KEY_ALLOCATE_PRODUCER_IDS, API_KEY_ADD_PARTITIONS_TO_TXN, API_KEY_ADD_OFFSETS_TO_TXN,
+        API_KEY_END_TXN, API_KEY_WRITE_TXN_MARKERS, API_KEY_TXN_OFFSET_COMMIT, API_KEY_CREATE_TOPICS, API_KEY_DELETE_TOPICS,
+        API_KEY_CREATE_PARTITIONS, CONSUMER_GROUP_HEARTBEAT_INTERVAL_MS,
+    },
+    core::broker::{AppendOrigin, Broker},
+    core::controller::{ControllerError, ElectionType, NewTopic},
+    core::metadata::BrokerRegistration,
+    core::consumer_group::{MemberHeartbeat, OffsetAndMetadata, TopicPartition},
+    core::delayed_fetch::{FetchParams, FetchPartitionStatus},
+    core::delayed_produce::ProducePartitionResult,
+    core::replica_selector::ClientMetadata,
+    error::{KafkaErrorCode, ServerError},
+    storage::leader_epoch::{UNDEFINED_EPOCH, UNDEFINED_EPOCH_OFFSET},
+    network::api::{
+        AlterPartitionResult, AlterPartitionTopicResult, CreatableTopicResult, DeletableTopicResult, EpochEndOffset, FetchPartitionResponse, FetchTopicResponse,
+        OffsetForLeaderTopicResult, PartitionResult, ResponseBuilder, WritableTxnMarkerResult,
+    },
+    network::requests::{
+        AddOffsetsToTxnRequest, AddPartitionsToTxnRequest, AllocateProducerIdsRequest, AlterPartitionReassignmentsRequest, AlterPartitionRequest, BrokerHeartbeatRequest, BrokerRegistrationRequest, ConsumerGroupHeartbeatRequest,
+        CreatePartitionsRequest, CreateTopicsRequest, DeleteGroupsRequest, DeleteTopicsRequest, DescribeGroupsRequest, ElectLeadersRequest, FetchRequest, InitProducerIdRequest, LeaderAndIsrRequest, ListGroupsRequest, ListPartitionReassignmentsRequest,
+        OffsetDeleteRequest, OffsetForLeaderEpochRequest, ProduceRequest, EndTxnRequest, TxnOffsetCommitRequest, WriteTxnMarkersRequest,
+    },
 };
 
 #[derive(Debug)]
@@ -9,6 +39,9 @@ pub struct KafkaRequest {
     pub api_key: i16,
     pub api_version: i16,
     pub correlation_id: i32,
+    pub client_id: Option<String>,
+    pub client_host: String,
+    pub body: Vec<u8>, // everything after the request header
 }
 
 pub struct KafkaProtocolHandler;
@@ -16,30 +49,1467 @@ pub struct KafkaProtocolHandler;
 impl KafkaProtocolHandler {
     pub fn is_version_supported(api_key: i16, api_version: i16) -> bool {
         match api_key {
-            API_KEY_API_VERSIONS => api_version >= SUPPORTED_VERSION_MIN && api_version <= SUPPORTED_VERSION_MAX,
+            API_KEY_API_VERSIONS => (SUPPORTED_VERSION_MIN..=SUPPORTED_VERSION_MAX).contains(&api_version),
+            API_KEY_PRODUCE => api_version == 9,
             API_KEY_FETCH => api_version == 16, // only version 16 supported for Fetch now
+            API_KEY_CONSUMER_GROUP_HEARTBEAT => api_version == 0,
+            API_KEY_DESCRIBE_GROUPS => api_version == 5,
+            API_KEY_LIST_GROUPS => api_version == 4,
+            API_KEY_DELETE_GROUPS => api_version == 2,
+            API_KEY_OFFSET_DELETE => api_version == 0,
+            API_KEY_OFFSET_FOR_LEADER_EPOCH => api_version == 4,
+            API_KEY_LEADER_AND_ISR => api_version == 5,
+            API_KEY_ALTER_PARTITION => api_version == 0,
+            API_KEY_BROKER_REGISTRATION => api_version == 2,
+            API_KEY_BROKER_HEARTBEAT => api_version == 1,
+            API_KEY_ALTER_PARTITION_REASSIGNMENTS => api_version == 0,
+            API_KEY_LIST_PARTITION_REASSIGNMENTS => api_version == 0,
+            API_KEY_ELECT_LEADERS => api_version == 2,
+            API_KEY_INIT_PRODUCER_ID => api_version == 4,
+            API_KEY_ALLOCATE_PRODUCER_IDS => api_version == 0,
+            API_KEY_ADD_PARTITIONS_TO_TXN => api_version == 3,
+            API_KEY_ADD_OFFSETS_TO_TXN => api_version == 3,
+            API_KEY_END_TXN => api_version == 3,
+            API_KEY_WRITE_TXN_MARKERS => api_version == 1,
+            API_KEY_TXN_OFFSET_COMMIT => api_version == 3,
+            API_KEY_CREATE_TOPICS => api_version == 7,
+            API_KEY_DELETE_TOPICS => api_version == 6,
+            API_KEY_CREATE_PARTITIONS => api_version == 3,
+            _ => false,
+        }
+    }
+
+    /// APIs that change partition leadership, broker sessions or transaction state,
+    /// which only brokers and the controller may send
+    pub fn is_inter_broker_api(api_key: i16) -> bool {
+        matches!(
+            api_key,
+            API_KEY_LEADER_AND_ISR
+                | API_KEY_ALTER_PARTITION
+                | API_KEY_BROKER_REGISTRATION
+                | API_KEY_BROKER_HEARTBEAT
+                | API_KEY_WRITE_TXN_MARKERS
+                | API_KEY_ALLOCATE_PRODUCER_IDS
+        )
+    }
+
+    // flexible versions use request header v2, which ends with a TAG_BUFFER
+    pub fn is_flexible(api_key: i16, api_version: i16) -> bool {
+        match api_key {
+            API_KEY_API_VERSIONS => api_version >= 3,
+            API_KEY_PRODUCE => api_version >= 9,
+            API_KEY_FETCH => api_version >= 12,
+            API_KEY_CONSUMER_GROUP_HEARTBEAT => true,
+            API_KEY_DESCRIBE_GROUPS => api_version >= 5,
+            API_KEY_LIST_GROUPS => api_version >= 3,
+            API_KEY_DELETE_GROUPS => api_version >= 2,
+            API_KEY_OFFSET_FOR_LEADER_EPOCH => api_version >= 4,
+            API_KEY_LEADER_AND_ISR => api_version >= 4,
+            API_KEY_ALTER_PARTITION => true,
+            API_KEY_BROKER_REGISTRATION => true,
+            API_KEY_BROKER_HEARTBEAT => true,
+            API_KEY_ALTER_PARTITION_REASSIGNMENTS => true,
+            API_KEY_LIST_PARTITION_REASSIGNMENTS => true,
+            API_KEY_ELECT_LEADERS => api_version >= 2,
+            API_KEY_INIT_PRODUCER_ID => api_version >= 2,
+            API_KEY_ALLOCATE_PRODUCER_IDS => true,
+            API_KEY_ADD_PARTITIONS_TO_TXN => api_version >= 3,
+            API_KEY_ADD_OFFSETS_TO_TXN => api_version >= 3,
+            API_KEY_END_TXN => api_version >= 3,
+            API_KEY_WRITE_TXN_MARKERS => api_version >= 1,
+            API_KEY_TXN_OFFSET_COMMIT => api_version >= 3,
+            API_KEY_CREATE_TOPICS => api_version >= 5,
+            API_KEY_DELETE_TOPICS => api_version >= 4,
+            API_KEY_CREATE_PARTITIONS => api_version >= 2,
             _ => false,
         }
     }
 
-    pub fn process_request(request: &KafkaRequest) -> Vec<u8> {
+    /// the response to a request, empty when none is sent. an error means the request
+    /// can't be answered at all and the connection should be closed.
+    pub async fn process_request(request: &KafkaRequest, broker: &Broker) -> Result<Vec<u8>, ServerError> {
         let error_code = if Self::is_version_supported(request.api_key, request.api_version) {
             KafkaErrorCode::None
         } else {
             KafkaErrorCode::UnsupportedVersion
         };
 
-        match request.api_key {
+        let response = match request.api_key {
             API_KEY_API_VERSIONS => {
                 ResponseBuilder::build_api_versions_response(request.correlation_id, error_code)
             }
+            API_KEY_PRODUCE if error_code == KafkaErrorCode::None => {
+                Self::handle_produce(request, broker).await?
+            }
             API_KEY_FETCH if request.api_version == 16 => {
-                ResponseBuilder::build_fetch_response(request.correlation_id)
+                Self::handle_fetch(request, broker).await
+            }
+            API_KEY_CONSUMER_GROUP_HEARTBEAT => {
+                if error_code != KafkaErrorCode::None {
+                    return Ok(ResponseBuilder::build_consumer_group_heartbeat_response(request.correlation_id, error_code, None, 0, 0, None));
+                }
+                Self::handle_consumer_group_heartbeat(request, broker).await
+            }
+            API_KEY_DESCRIBE_GROUPS if error_code == KafkaErrorCode::None => {
+                Self::handle_describe_groups(request, broker).await?
+            }
+            API_KEY_LIST_GROUPS if error_code == KafkaErrorCode::None => {
+                Self::handle_list_groups(request, broker).await
+            }
+            API_KEY_DELETE_GROUPS if error_code == KafkaErrorCode::None => {
+                Self::handle_delete_groups(request, broker).await?
+            }
+            API_KEY_OFFSET_DELETE if error_code == KafkaErrorCode::None => {
+                Self::handle_offset_delete(request, broker).await
+            }
+            API_KEY_OFFSET_FOR_LEADER_EPOCH if error_code == KafkaErrorCode::None => {
+                Self::handle_offset_for_leader_epoch(request, broker).await?
+            }
+            API_KEY_LEADER_AND_ISR if error_code == KafkaErrorCode::None => {
+                Self::handle_leader_and_isr(request, broker).await
+            }
+            API_KEY_ALTER_PARTITION if error_code == KafkaErrorCode::None => {
+                Self::handle_alter_partition(request, broker).await
+            }
+            API_KEY_BROKER_REGISTRATION if error_code == KafkaErrorCode::None => {
+                Self::handle_broker_registration(request, broker).await
+            }
+            API_KEY_BROKER_HEARTBEAT if error_code == KafkaErrorCode::None => {
+                Self::handle_broker_heartbeat(request, broker).await
+            }
+            API_KEY_ALTER_PARTITION_REASSIGNMENTS if error_code == KafkaErrorCode::None => {
+                Self::handle_alter_partition_reassignments(request, broker).await
+            }
+            API_KEY_LIST_PARTITION_REASSIGNMENTS if error_code == KafkaErrorCode::None => {
+                Self::handle_list_partition_reassignments(request, broker).await
+            }
+            API_KEY_ELECT_LEADERS if error_code == KafkaErrorCode::None => {
+                Self::handle_elect_leaders(request, broker).await
+            }
+            API_KEY_INIT_PRODUCER_ID if error_code == KafkaErrorCode::None => {
+                Self::handle_init_producer_id(request, broker).await
+            }
+            API_KEY_ALLOCATE_PRODUCER_IDS if error_code == KafkaErrorCode::None => {
+                Self::handle_allocate_producer_ids(request, broker).await
+            }
+            API_KEY_ADD_PARTITIONS_TO_TXN if error_code == KafkaErrorCode::None => {
+                Self::handle_add_partitions_to_txn(request, broker).await?
+            }
+            API_KEY_ADD_OFFSETS_TO_TXN if error_code == KafkaErrorCode::None => {
+                Self::handle_add_offsets_to_txn(request, broker).await
+            }
+            API_KEY_END_TXN if error_code == KafkaErrorCode::None => {
+                Self::handle_end_txn(request, broker).await
+            }
+            API_KEY_WRITE_TXN_MARKERS if error_code == KafkaErrorCode::None => {
+                Self::handle_write_txn_markers(request, broker).await?
+            }
+            API_KEY_TXN_OFFSET_COMMIT if error_code == KafkaErrorCode::None => {
+                Self::handle_txn_offset_commit(request, broker).await?
+            }
+            API_KEY_CREATE_TOPICS if error_code == KafkaErrorCode::None => {
+                Self::handle_create_topics(request, broker).await
+            }
+            API_KEY_DELETE_TOPICS if error_code == KafkaErrorCode::None => {
+                Self::handle_delete_topics(request, broker).await
+            }
+            API_KEY_CREATE_PARTITIONS if error_code == KafkaErrorCode::None => {
+                Self::handle_create_partitions(request, broker).await
             }
             _ => {
                 println!("Unsupported API key: {}", request.api_key);
                 Vec::new() // Return empty response for unsupported APIs
             }
+        };
+        Ok(response)
+    }
+
+    // there's no top-level error code to answer a malformed request with
+    async fn handle_produce(request: &KafkaRequest, broker: &Broker) -> Result<Vec<u8>, ServerError> {
+        let produce = ProduceRequest::parse(&request.body).inspect_err(|e| eprintln!("Invalid Produce request: {}", e))?;
+
+        let mut entries = Vec::new();
+        let mut responses: Vec<(String, Vec<ProducePartitionResult>)> = Vec::with_capacity(produce.topics.len());
+        for topic in produce.topics {
+            for partition in topic.partitions {
+                entries.push((TopicPartition::new(topic.name.clone(), partition.index), partition.records));
+            }
+            responses.push((topic.name, Vec::new()));
+        }
+
+        let results = if (-1..=1).contains(&produce.acks) {
+            let timeout = Duration::from_millis(produce.timeout_ms.max(0) as u64);
+            broker.append_records(AppendOrigin::Client, produce.acks, timeout, entries).await
+        } else {
+            entries
+                .into_iter()
+                .map(|(tp, _)| ProducePartitionResult {
+                    tp,
+                    error: KafkaErrorCode::InvalidRequiredAcks,
+                    base_offset: -1,
+                    log_start_offset: -1,
+                    required_offset: -1,
+                })
+                .collect()
+        };
+
+        // acks=0 producers don't read a response
+        if produce.acks == 0 {
+            return Ok(Vec::new());
+        }
+
+        for result in results {
+            if let Some((_, partitions)) = responses.iter_mut().find(|(name, _)| name == result.tp.topic()) {
+                partitions.push(result);
+            }
+        }
+        Ok(ResponseBuilder::build_produce_response(request.correlation_id, &responses))
+    }
+
+    async fn handle_fetch(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let fetch = match FetchRequest::parse(&request.body) {
+            Ok(fetch) => fetch,
+            Err(e) => {
+                eprintln!("Invalid Fetch request: {}", e);
+                return ResponseBuilder::build_fetch_response(request.correlation_id, KafkaErrorCode::InvalidRequest, &[]);
+            }
+        };
+
+        // topic ids are resolved up front; an unknown one is answered without waiting
+        let mut statuses = Vec::new();
+        let mut unknown_topic = false;
+        let mut topic_names = Vec::with_capacity(fetch.topics.len());
+        for topic in &fetch.topics {
+            let topic_name = broker.topic_manager().get_by_id(topic.topic_id).await.map(|t| t.name().to_string());
+            match &topic_name {
+                Some(name) => statuses.extend(topic.partitions.iter().map(|partition| FetchPartitionStatus {
+                    tp: TopicPartition::new(name.clone(), partition.partition),
+                    fetch_offset: partition.fetch_offset,
+                    current_leader_epoch: partition.current_leader_epoch,
+                    max_bytes: partition.partition_max_bytes,
+                })),
+                None => unknown_topic = true,
+            }
+            topic_names.push(topic_name);
+        }
+
+        // followers send their rack along, the leader uses it to pick replicas for consumers
+        if fetch.replica_id >= 0 && !fetch.rack_id.is_empty() {
+            broker.register_broker_rack(fetch.replica_id, fetch.rack_id.clone()).await;
+        }
+        let client_metadata = (fetch.replica_id < 0).then(|| ClientMetadata {
+            client_id: request.client_id.clone().unwrap_or_default(),
+            rack_id: fetch.rack_id.clone(),
+        });
+        let params = FetchParams {
+            replica_id: fetch.replica_id,
+            max_wait_ms: if unknown_topic { 0 } else { fetch.max_wait_ms },
+            min_bytes: fetch.min_bytes,
+            max_bytes: fetch.max_bytes,
+            isolation_level: fetch.isolation_level,
+            client_metadata,
+        };
+        let mut fetched = broker.fetch_messages(params, statuses).await.into_iter();
+
+        let mut responses = Vec::with_capacity(fetch.topics.len());
+        for (topic, topic_name) in fetch.topics.iter().zip(&topic_names) {
+            let mut partitions = Vec::with_capacity(topic.partitions.len());
+            for partition in &topic.partitions {
+                let data = topic_name.as_ref().and_then(|_| fetched.next());
+                let response = match data {
+                    Some(data) => FetchPartitionResponse {
+                        partition: partition.partition,
+                        error_code: data.error.into(),
+                        high_watermark: data.high_watermark,
+                        last_stable_offset: data.last_stable_offset,
+                        log_start_offset: data.log_start_offset,
+                        aborted_transactions: data
+                            .aborted_transactions
+                            .map(|aborted| aborted.iter().map(|txn| (txn.producer_id, txn.first_offset)).collect()),
+                        preferred_read_replica: data.preferred_read_replica.unwrap_or(-1),
+                        records: data.records,
+                    },
+                    None => FetchPartitionResponse {
+                        partition: partition.partition,
+                        error_code: KafkaErrorCode::UnknownTopicId.into(),
+                        high_watermark: -1,
+                        last_stable_offset: -1,
+                        log_start_offset: -1,
+                        aborted_transactions: None,
+                        preferred_read_replica: -1,
+                        records: Vec::new(),
+                    },
+                };
+                partitions.push(response);
+            }
+            responses.push(FetchTopicResponse { topic_id: topic.topic_id, partitions });
+        }
+
+        ResponseBuilder::build_fetch_response(request.correlation_id, KafkaErrorCode::None, &responses)
+    }
+
+    // no top-level error code to answer a malformed request with
+    async fn handle_offset_for_leader_epoch(request: &KafkaRequest, broker: &Broker) -> Result<Vec<u8>, ServerError> {
+        let lookup = OffsetForLeaderEpochRequest::parse(&request.body)
+            .inspect_err(|e| eprintln!("Invalid OffsetForLeaderEpoch request: {}", e))?;
+
+        let mut topics = Vec::with_capacity(lookup.topics.len());
+        for topic in lookup.topics {
+            let mut partitions = Vec::with_capacity(topic.partitions.len());
+            for partition in topic.partitions {
+                let end_offset = broker
+                    .replica_manager()
+                    .last_offset_for_leader_epoch(&topic.topic, partition.partition, partition.current_leader_epoch, partition.leader_epoch)
+                    .await;
+                partitions.push(match end_offset {
+                    Ok((leader_epoch, end_offset)) => EpochEndOffset {
+                        error_code: KafkaErrorCode::None.into(),
+                        partition: partition.partition,
+                        leader_epoch,
+                        end_offset,
+                    },
+                    Err(e) => {
+                        println!("OffsetForLeaderEpoch on {}-{} failed: {}", topic.topic, partition.partition, e);
+                        EpochEndOffset {
+                            error_code: e.error_code().into(),
+                            partition: partition.partition,
+                            leader_epoch: UNDEFINED_EPOCH,
+                            end_offset: UNDEFINED_EPOCH_OFFSET,
+                        }
+                    }
+                });
+            }
+            topics.push(OffsetForLeaderTopicResult { topic: topic.topic, partitions });
+        }
+
+        Ok(ResponseBuilder::build_offset_for_leader_epoch_response(request.correlation_id, &topics))
+    }
+
+    async fn handle_leader_and_isr(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let update = match LeaderAndIsrRequest::parse(&request.body) {
+            Ok(update) => update,
+            Err(e) => {
+                eprintln!("Invalid LeaderAndIsr request: {}", e);
+                return ResponseBuilder::build_leader_and_isr_response(request.correlation_id, KafkaErrorCode::InvalidRequest, &[]);
+            }
+        };
+
+        let applied = broker
+            .apply_leader_and_isr(update.controller_id, update.controller_epoch, &update.partition_states, &update.live_leaders)
+            .await;
+        match applied {
+            Ok(results) => {
+                let partition_errors: Vec<_> = update
+                    .partition_states
+                    .iter()
+                    .zip(results)
+                    .map(|(state, (_, error))| (state.topic_id, state.partition, error))
+                    .collect();
+                ResponseBuilder::build_leader_and_isr_response(request.correlation_id, KafkaErrorCode::None, &partition_errors)
+            }
+            Err(error) => ResponseBuilder::build_leader_and_isr_response(request.correlation_id, error, &[]),
+        }
+    }
+
+    async fn handle_init_producer_id(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let init = match InitProducerIdRequest::parse(&request.body) {
+            Ok(init) => init,
+            Err(e) => {
+                eprintln!("Invalid InitProducerId request: {}", e);
+                return ResponseBuilder::build_init_producer_id_response(request.correlation_id, KafkaErrorCode::InvalidRequest, -1, -1);
+            }
+        };
+        if let Some(transactional_id) = init.transactional_id {
+            return match broker.init_transactional_producer(&transactional_id, init.transaction_timeout_ms).await {
+                Ok((producer_id, producer_epoch)) => {
+                    ResponseBuilder::build_init_producer_id_response(request.correlation_id, KafkaErrorCode::None, producer_id, producer_epoch)
+                }
+                Err(error) => ResponseBuilder::build_init_producer_id_response(request.correlation_id, error, -1, -1),
+            };
+        }
+
+        // an idempotent producer always starts over with a fresh id, even when it
+        // asks to bump the epoch of its current one
+        match broker.generate_producer_id().await {
+            Ok(producer_id) => ResponseBuilder::build_init_producer_id_response(request.correlation_id, KafkaErrorCode::None, producer_id, 0),
+            Err(error) => ResponseBuilder::build_init_producer_id_response(request.correlation_id, error, -1, -1),
+        }
+    }
+
+    async fn handle_allocate_producer_ids(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let Some(controller) = broker.controller() else {
+            return ResponseBuilder::build_allocate_producer_ids_response(request.correlation_id, KafkaErrorCode::NotController, None);
+        };
+        let allocate = match AllocateProducerIdsRequest::parse(&request.body) {
+            Ok(allocate) => allocate,
+            Err(e) => {
+                eprintln!("Invalid AllocateProducerIds request: {}", e);
+                return ResponseBuilder::build_allocate_producer_ids_response(request.correlation_id, KafkaErrorCode::InvalidRequest, None);
+            }
+        };
+
+        match controller.allocate_producer_ids(allocate.broker_id, allocate.broker_epoch).await {
+            Ok(block) => ResponseBuilder::build_allocate_producer_ids_response(request.correlation_id, KafkaErrorCode::None, Some(block)),
+            Err(e) => ResponseBuilder::build_allocate_producer_ids_response(request.correlation_id, e.error_code(), None),
+        }
+    }
+
+    // v3 has no top-level error code to answer a malformed request with
+    async fn handle_add_partitions_to_txn(request: &KafkaRequest, broker: &Broker) -> Result<Vec<u8>, ServerError> {
+        let add = AddPartitionsToTxnRequest::parse(&request.body)
+            .inspect_err(|e| eprintln!("Invalid AddPartitionsToTxn request: {}", e))?;
+
+        let partitions: Vec<TopicPartition> = add
+            .topics
+            .iter()
+            .flat_map(|(topic, partitions)| partitions.iter().map(|p| TopicPartition::new(topic.clone(), *p)))
+            .collect();
+        let results = broker
+            .add_partitions_to_txn(&add.transactional_id, add.producer_id, add.producer_epoch, partitions)
+            .await;
+
+        // answer in request order, topic by topic
+        let mut topics: Vec<(String, Vec<(i32, KafkaErrorCode)>)> = Vec::new();
+        for (tp, error_code) in results {
+            match topics.last_mut() {
+                Some((topic, partitions)) if topic == tp.topic() => partitions.push((tp.partition(), error_code)),
+                _ => topics.push((tp.topic().to_string(), vec![(tp.partition(), error_code)])),
+            }
+        }
+        Ok(ResponseBuilder::build_add_partitions_to_txn_response(request.correlation_id, &topics))
+    }
+
+    async fn handle_add_offsets_to_txn(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let add = match AddOffsetsToTxnRequest::parse(&request.body) {
+            Ok(add) => add,
+            Err(e) => {
+                eprintln!("Invalid AddOffsetsToTxn request: {}", e);
+                return ResponseBuilder::build_add_offsets_to_txn_response(request.correlation_id, KafkaErrorCode::InvalidRequest);
+            }
+        };
+
+        let error_code = broker
+            .add_offsets_to_txn(&add.transactional_id, add.producer_id, add.producer_epoch, &add.group_id)
+            .await;
+        ResponseBuilder::build_add_offsets_to_txn_response(request.correlation_id, error_code)
+    }
+
+    async fn handle_end_txn(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let end = match EndTxnRequest::parse(&request.body) {
+            Ok(end) => end,
+            Err(e) => {
+                eprintln!("Invalid EndTxn request: {}", e);
+                return ResponseBuilder::build_end_txn_response(request.correlation_id, KafkaErrorCode::InvalidRequest);
+            }
+        };
+
+        let error_code = broker
+            .end_txn(&end.transactional_id, end.producer_id, end.producer_epoch, end.committed)
+            .await;
+        ResponseBuilder::build_end_txn_response(request.correlation_id, error_code)
+    }
+
+    // no top-level error code to answer a malformed request with
+    async fn handle_write_txn_markers(request: &KafkaRequest, broker: &Broker) -> Result<Vec<u8>, ServerError> {
+        let write = WriteTxnMarkersRequest::parse(&request.body)
+            .inspect_err(|e| eprintln!("Invalid WriteTxnMarkers request: {}", e))?;
+
+        let mut markers = Vec::with_capacity(write.markers.len());
+        for marker in write.markers {
+            let partitions: Vec<TopicPartition> = marker
+                .topics
+                .iter()
+                .flat_map(|(topic, partitions)| partitions.iter().map(|p| TopicPartition::new(topic.clone(), *p)))
+                .collect();
+            let results = broker
+                .write_txn_markers(marker.producer_id, marker.producer_epoch, marker.committed, marker.coordinator_epoch, partitions)
+                .await;
+
+            let mut topics: Vec<(String, Vec<(i32, i16)>)> = Vec::new();
+            for (tp, error_code) in results {
+                match topics.last_mut() {
+                    Some((topic, partitions)) if topic == tp.topic() => partitions.push((tp.partition(), error_code.into())),
+                    _ => topics.push((tp.topic().to_string(), vec![(tp.partition(), error_code.into())])),
+                }
+            }
+            markers.push(WritableTxnMarkerResult { producer_id: marker.producer_id, topics });
+        }
+        Ok(ResponseBuilder::build_write_txn_markers_response(request.correlation_id, &markers))
+    }
+
+    // offsets are held by the group coordinator until the producer's transaction ends.
+    // a malformed request has no top-level error code to answer with.
+    async fn handle_txn_offset_commit(request: &KafkaRequest, broker: &Broker) -> Result<Vec<u8>, ServerError> {
+        let commit = TxnOffsetCommitRequest::parse(&request.body)
+            .inspect_err(|e| eprintln!("Invalid TxnOffsetCommit request: {}", e))?;
+
+        let now_ms = Utc::now().timestamp_millis();
+        let mut offsets = Vec::new();
+        for topic in &commit.topics {
+            for partition in &topic.partitions {
+                let offset = OffsetAndMetadata {
+                    offset: partition.committed_offset,
+                    leader_epoch: partition.committed_leader_epoch,
+                    metadata: partition.committed_metadata.clone().unwrap_or_default(),
+                    commit_timestamp: now_ms,
+                };
+                offsets.push((TopicPartition::new(topic.name.clone(), partition.partition_index), offset));
+            }
+        }
+        let error_code = match broker
+            .group_coordinator()
+            .commit_transactional_offsets(&commit.group_id, commit.producer_id, offsets)
+            .await
+        {
+            Ok(()) => KafkaErrorCode::None,
+            Err(e) => e.error_code(),
+        };
+
+        let topics: Vec<(String, Vec<(i32, KafkaErrorCode)>)> = commit
+            .topics
+            .iter()
+            .map(|topic| (topic.name.clone(), topic.partitions.iter().map(|p| (p.partition_index, error_code)).collect()))
+            .collect();
+        Ok(ResponseBuilder::build_txn_offset_commit_response(request.correlation_id, &topics))
+    }
+
+    async fn handle_alter_partition(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let Some(controller) = broker.controller() else {
+            return ResponseBuilder::build_alter_partition_response(request.correlation_id, KafkaErrorCode::NotController, &[]);
+        };
+        let alter = match AlterPartitionRequest::parse(&request.body) {
+            Ok(alter) => alter,
+            Err(e) => {
+                eprintln!("Invalid AlterPartition request: {}", e);
+                return ResponseBuilder::build_alter_partition_response(request.correlation_id, KafkaErrorCode::InvalidRequest, &[]);
+            }
+        };
+
+        let mut topics = Vec::with_capacity(alter.topics.len());
+        for topic in alter.topics {
+            let mut partitions = Vec::with_capacity(topic.partitions.len());
+            for partition in topic.partitions {
+                let committed = controller
+                    .alter_partition(&topic.topic, partition.partition, alter.broker_id, partition.leader_epoch, partition.new_isr)
+                    .await;
+                partitions.push(match committed {
+                    Ok(state) => AlterPartitionResult {
+                        partition: partition.partition,
+                        error_code: KafkaErrorCode::None.into(),
+                        leader_id: state.leader,
+                        leader_epoch: state.leader_epoch,
+                        isr: state.isr,
+                    },
+                    Err(e) => {
+                        println!("AlterPartition of {}-{} from broker {} refused: {}", topic.topic, partition.partition, alter.broker_id, e);
+                        AlterPartitionResult {
+                            partition: partition.partition,
+                            error_code: e.error_code().into(),
+                            leader_id: -1,
+                            leader_epoch: -1,
+                            isr: Vec::new(),
+                        }
+                    }
+                });
+            }
+            topics.push(AlterPartitionTopicResult { topic: topic.topic, partitions });
+        }
+
+        ResponseBuilder::build_alter_partition_response(request.correlation_id, KafkaErrorCode::None, &topics)
+    }
+
+    async fn handle_broker_registration(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let Some(controller) = broker.controller() else {
+            return ResponseBuilder::build_broker_registration_response(request.correlation_id, KafkaErrorCode::NotController, -1);
+        };
+        let registration = match BrokerRegistrationRequest::parse(&request.body) {
+            Ok(registration) => registration,
+            Err(e) => {
+                eprintln!("Invalid BrokerRegistration request: {}", e);
+                return ResponseBuilder::build_broker_registration_response(request.correlation_id, KafkaErrorCode::InvalidRequest, -1);
+            }
+        };
+
+        let registered = controller
+            .register_broker(BrokerRegistration {
+                broker_id: registration.broker_id,
+                broker_epoch: -1,
+                incarnation_id: registration.incarnation_id,
+                listeners: registration.listeners,
+                rack: registration.rack,
+                log_dirs: registration.log_dirs,
+                fenced: true,
+            })
+            .await;
+        match registered {
+            Ok(broker_epoch) => {
+                ResponseBuilder::build_broker_registration_response(request.correlation_id, KafkaErrorCode::None, broker_epoch)
+            }
+            Err(e) => {
+                println!("Registration of broker {} refused: {}", registration.broker_id, e);
+                ResponseBuilder::build_broker_registration_response(request.correlation_id, e.error_code(), -1)
+            }
         }
     }
-} 
\ No newline at end of file
+
+    async fn handle_broker_heartbeat(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let Some(controller) = broker.controller() else {
+            return ResponseBuilder::build_broker_heartbeat_response(request.correlation_id, KafkaErrorCode::NotController, false, true, false);
+        };
+        let heartbeat = match BrokerHeartbeatRequest::parse(&request.body) {
+            Ok(heartbeat) => heartbeat,
+            Err(e) => {
+                eprintln!("Invalid BrokerHeartbeat request: {}", e);
+                return ResponseBuilder::build_broker_heartbeat_response(request.correlation_id, KafkaErrorCode::InvalidRequest, false, true, false);
+            }
+        };
+
+        let result = controller
+            .broker_heartbeat(heartbeat.broker_id, heartbeat.broker_epoch, heartbeat.want_fence, heartbeat.want_shutdown)
+            .await;
+        match result {
+            Ok(result) => ResponseBuilder::build_broker_heartbeat_response(
+                request.correlation_id,
+                KafkaErrorCode::None,
+                result.is_caught_up,
+                result.is_fenced,
+                result.should_shutdown,
+            ),
+            Err(e) => {
+                println!("Heartbeat of broker {} refused: {}", heartbeat.broker_id, e);
+                ResponseBuilder::build_broker_heartbeat_response(request.correlation_id, e.error_code(), false, true, false)
+            }
+        }
+    }
+
+    async fn handle_alter_partition_reassignments(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let Some(controller) = broker.controller() else {
+            return ResponseBuilder::build_alter_partition_reassignments_response(request.correlation_id, KafkaErrorCode::NotController, &[]);
+        };
+        let alter = match AlterPartitionReassignmentsRequest::parse(&request.body) {
+            Ok(alter) => alter,
+            Err(e) => {
+                eprintln!("Invalid AlterPartitionReassignments request: {}", e);
+                return ResponseBuilder::build_alter_partition_reassignments_response(request.correlation_id, KafkaErrorCode::InvalidRequest, &[]);
+            }
+        };
+
+        let mut topics = Vec::with_capacity(alter.topics.len());
+        for topic in alter.topics {
+            let mut partitions = Vec::with_capacity(topic.partitions.len());
+            for (partition, target) in topic.partitions {
+                partitions.push(match controller.alter_partition_reassignment(&topic.name, partition, target).await {
+                    Ok(_) => PartitionResult { partition, error_code: KafkaErrorCode::None, error_message: None },
+                    Err(e) => {
+                        println!("Reassignment of {}-{} refused: {}", topic.name, partition, e);
+                        PartitionResult { partition, error_code: e.error_code(), error_message: Some(e.to_string()) }
+                    }
+                });
+            }
+            topics.push((topic.name, partitions));
+        }
+
+        ResponseBuilder::build_alter_partition_reassignments_response(request.correlation_id, KafkaErrorCode::None, &topics)
+    }
+
+    async fn handle_list_partition_reassignments(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let Some(controller) = broker.controller() else {
+            return ResponseBuilder::build_list_partition_reassignments_response(request.correlation_id, KafkaErrorCode::NotController, &[]);
+        };
+        let list = match ListPartitionReassignmentsRequest::parse(&request.body) {
+            Ok(list) => list,
+            Err(e) => {
+                eprintln!("Invalid ListPartitionReassignments request: {}", e);
+                return ResponseBuilder::build_list_partition_reassignments_response(request.correlation_id, KafkaErrorCode::InvalidRequest, &[]);
+            }
+        };
+
+        let mut reassignments = match controller.list_partition_reassignments().await {
+            Ok(reassignments) => reassignments,
+            Err(e) => return ResponseBuilder::build_list_partition_reassignments_response(request.correlation_id, e.error_code(), &[]),
+        };
+        if let Some(topics) = &list.topics {
+            reassignments.retain(|state| {
+                topics.iter().any(|(topic, partitions)| *topic == state.topic && partitions.contains(&state.partition))
+            });
+        }
+
+        ResponseBuilder::build_list_partition_reassignments_response(request.correlation_id, KafkaErrorCode::None, &reassignments)
+    }
+
+    async fn handle_elect_leaders(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let Some(controller) = broker.controller() else {
+            return ResponseBuilder::build_elect_leaders_response(request.correlation_id, KafkaErrorCode::NotController, &[]);
+        };
+        let elect = match ElectLeadersRequest::parse(&request.body) {
+            Ok(elect) => elect,
+            Err(e) => {
+                eprintln!("Invalid ElectLeaders request: {}", e);
+                return ResponseBuilder::build_elect_leaders_response(request.correlation_id, KafkaErrorCode::InvalidRequest, &[]);
+            }
+        };
+        let Ok(election_type) = ElectionType::try_from(elect.election_type) else {
+            return ResponseBuilder::build_elect_leaders_response(request.correlation_id, KafkaErrorCode::InvalidRequest, &[]);
+        };
+
+        let partitions = elect.topic_partitions.map(|topics| {
+            topics
+                .into_iter()
+                .flat_map(|(topic, partitions)| partitions.into_iter().map(move |partition| (topic.clone(), partition)))
+                .collect()
+        });
+        let results = match controller.elect_leaders(election_type, partitions).await {
+            Ok(results) => results,
+            Err(e) => return ResponseBuilder::build_elect_leaders_response(request.correlation_id, e.error_code(), &[]),
+        };
+
+        // answer topic by topic, in the order partitions were asked for
+        let mut topics: Vec<(String, Vec<PartitionResult>)> = Vec::new();
+        for election in results {
+            let result = match election.result {
+                Ok(_) => PartitionResult { partition: election.partition, error_code: KafkaErrorCode::None, error_message: None },
+                Err(e) => PartitionResult { partition: election.partition, error_code: e.error_code(), error_message: Some(e.to_string()) },
+            };
+            match topics.iter_mut().find(|(topic, _)| *topic == election.topic) {
+                Some((_, partitions)) => partitions.push(result),
+                None => topics.push((election.topic, vec![result])),
+            }
+        }
+
+        ResponseBuilder::build_elect_leaders_response(request.correlation_id, KafkaErrorCode::None, &topics)
+    }
+
+    async fn handle_create_topics(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let failed_topic = |name: &str, error_code: KafkaErrorCode, error_message: String| CreatableTopicResult {
+            name: name.to_string(),
+            topic_id: Uuid::nil(),
+            error_code,
+            error_message: Some(error_message),
+            num_partitions: -1,
+            replication_factor: -1,
+            configs: Vec::new(),
+        };
+        let create = match CreateTopicsRequest::parse(&request.body) {
+            Ok(create) => create,
+            Err(e) => {
+                // there's no top-level error code, so every topic that could be read gets one
+                eprintln!("Invalid CreateTopics request: {}", e);
+                let results: Vec<CreatableTopicResult> = CreateTopicsRequest::topic_names(&request.body)
+                    .iter()
+                    .map(|name| failed_topic(name, KafkaErrorCode::InvalidRequest, e.to_string()))
+                    .collect();
+                return ResponseBuilder::build_create_topics_response(request.correlation_id, &results);
+            }
+        };
+        // like Kafka, none of the copies of a topic listed more than once is created
+        let mut seen = HashSet::new();
+        let duplicated: HashSet<String> =
+            create.topics.iter().filter(|topic| !seen.insert(&topic.name)).map(|topic| topic.name.clone()).collect();
+
+        let mut results = Vec::with_capacity(create.topics.len());
+        for topic in create.topics {
+            let failed = |error_code: KafkaErrorCode, error_message: String| failed_topic(&topic.name, error_code, error_message);
+            if duplicated.contains(&topic.name) {
+                results.push(failed(KafkaErrorCode::InvalidRequest, format!("Topic {} is listed more than once", topic.name)));
+                continue;
+            }
+            let Some(controller) = broker.controller() else {
+                results.push(failed(KafkaErrorCode::NotController, "This broker doesn't run the controller".to_string()));
+                continue;
+            };
+            let configs: Option<BTreeMap<String, String>> =
+                topic.configs.iter().map(|(name, value)| Some((name.clone(), value.clone()?))).collect();
+            let Some(configs) = configs else {
+                results.push(failed(KafkaErrorCode::InvalidRequest, "Topic configs can't have null values".to_string()));
+                continue;
+            };
+            let assignments: BTreeMap<i32, Vec<i32>> = topic.assignments.iter().cloned().collect();
+            if assignments.len() != topic.assignments.len() {
+                results.push(failed(KafkaErrorCode::InvalidRequest, "A partition is assigned more than once".to_string()));
+                continue;
+            }
+            let new_topic = NewTopic {
+                name: topic.name.clone(),
+                num_partitions: topic.num_partitions,
+                replication_factor: topic.replication_factor as i32,
+                assignments,
+                configs,
+            };
+            results.push(match controller.create_topic(&new_topic, create.validate_only).await {
+                Ok((topic_id, partitions)) => CreatableTopicResult {
+                    name: topic.name,
+                    topic_id,
+                    error_code: KafkaErrorCode::None,
+                    error_message: None,
+                    num_partitions: partitions.len() as i32,
+                    replication_factor: partitions.first().map_or(0, |state| state.replicas.len() as i16),
+                    configs: new_topic.configs.into_iter().collect(),
+                },
+                Err(e) => failed(e.error_code(), e.to_string()),
+            });
+        }
+
+        ResponseBuilder::build_create_topics_response(request.correlation_id, &results)
+    }
+
+    async fn handle_delete_topics(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let delete = match DeleteTopicsRequest::parse(&request.body) {
+            Ok(delete) => delete,
+            Err(e) => {
+                // there's no top-level error code, so every topic that could be read gets one
+                eprintln!("Invalid DeleteTopics request: {}", e);
+                let results: Vec<DeletableTopicResult> = DeleteTopicsRequest::topic_names(&request.body)
+                    .into_iter()
+                    .map(|(name, topic_id)| DeletableTopicResult {
+                        name,
+                        topic_id,
+                        error_code: KafkaErrorCode::InvalidRequest,
+                        error_message: Some(e.to_string()),
+                    })
+                    .collect();
+                return ResponseBuilder::build_delete_topics_response(request.correlation_id, &results);
+            }
+        };
+
+        let mut results: Vec<DeletableTopicResult> = Vec::with_capacity(delete.topics.len());
+        for topic @ (name, topic_id) in &delete.topics {
+            let failed = |error_code: KafkaErrorCode, error_message: String| DeletableTopicResult {
+                name: name.clone(),
+                topic_id: *topic_id,
+                error_code,
+                error_message: Some(error_message),
+            };
+            // every copy of a topic listed more than once is rejected
+            if delete.topics.iter().filter(|other| *other == topic).count() > 1 {
+                results.push(failed(KafkaErrorCode::InvalidRequest, "Topic is listed more than once".to_string()));
+                continue;
+            }
+            let Some(controller) = broker.controller() else {
+                results.push(failed(KafkaErrorCode::NotController, "This broker doesn't run the controller".to_string()));
+                continue;
+            };
+            // a topic is given either by name or by id
+            let name = match (name, topic_id.is_nil()) {
+                (Some(name), true) => name.clone(),
+                (None, false) => match controller.topic_name(*topic_id).await {
+                    Some(name) => name,
+                    None => {
+                        let e = ControllerError::UnknownTopicId(*topic_id);
+                        results.push(failed(e.error_code(), e.to_string()));
+                        continue;
+                    }
+                },
+                _ => {
+                    results.push(failed(KafkaErrorCode::InvalidRequest, "Give either a topic name or a topic id".to_string()));
+                    continue;
+                }
+            };
+            results.push(match controller.delete_topic(&name).await {
+                Ok(topic_id) => DeletableTopicResult { name: Some(name), topic_id, error_code: KafkaErrorCode::None, error_message: None },
+                Err(e) => failed(e.error_code(), e.to_string()),
+            });
+        }
+
+        ResponseBuilder::build_delete_topics_response(request.correlation_id, &results)
+    }
+
+    async fn handle_create_partitions(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let create = match CreatePartitionsRequest::parse(&request.body) {
+            Ok(create) => create,
+            Err(e) => {
+                // there's no top-level error code, so every topic that could be read gets one
+                eprintln!("Invalid CreatePartitions request: {}", e);
+                let results: Vec<(String, KafkaErrorCode, Option<String>)> = CreatePartitionsRequest::topic_names(&request.body)
+                    .into_iter()
+                    .map(|name| (name, KafkaErrorCode::InvalidRequest, Some(e.to_string())))
+                    .collect();
+                return ResponseBuilder::build_create_partitions_response(request.correlation_id, &results);
+            }
+        };
+        // no copy of a topic listed more than once grows
+        let mut seen = HashSet::new();
+        let duplicated: HashSet<String> =
+            create.topics.iter().filter(|topic| !seen.insert(&topic.name)).map(|topic| topic.name.clone()).collect();
+
+        let mut results: Vec<(String, KafkaErrorCode, Option<String>)> = Vec::with_capacity(create.topics.len());
+        for topic in create.topics {
+            if duplicated.contains(&topic.name) {
+                let message = format!("Topic {} is listed more than once", topic.name);
+                results.push((topic.name, KafkaErrorCode::InvalidRequest, Some(message)));
+                continue;
+            }
+            let Some(controller) = broker.controller() else {
+                results.push((topic.name, KafkaErrorCode::NotController, Some("This broker doesn't run the controller".to_string())));
+                continue;
+            };
+            match controller.create_partitions(&topic.name, topic.count, topic.assignments, create.validate_only).await {
+                Ok(_) => results.push((topic.name, KafkaErrorCode::None, None)),
+                Err(e) => results.push((topic.name, e.error_code(), Some(e.to_string()))),
+            }
+        }
+
+        ResponseBuilder::build_create_partitions_response(request.correlation_id, &results)
+    }
+
+    async fn handle_consumer_group_heartbeat(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let heartbeat = match ConsumerGroupHeartbeatRequest::parse(&request.body) {
+            Ok(heartbeat) => heartbeat,
+            Err(e) => {
+                eprintln!("Invalid ConsumerGroupHeartbeat request: {}", e);
+                return ResponseBuilder::build_consumer_group_heartbeat_response(
+                    request.correlation_id, KafkaErrorCode::InvalidRequest, None, 0, 0, None,
+                );
+            }
+        };
+
+        // the wire protocol identifies topics by id, the group works with names
+        let owned_partitions = match heartbeat.topic_partitions {
+            Some(topic_partitions) => {
+                let mut owned = Vec::new();
+                for (topic_id, partitions) in topic_partitions {
+                    if let Some(topic) = broker.topic_manager().get_by_id(topic_id).await {
+                        for partition in partitions {
+                            owned.push(TopicPartition::new(topic.name().to_string(), partition));
+                        }
+                    }
+                }
+                Some(owned)
+            }
+            None => None,
+        };
+
+        let member_heartbeat = MemberHeartbeat {
+            member_id: heartbeat.member_id,
+            member_epoch: heartbeat.member_epoch,
+            client_id: request.client_id.clone().unwrap_or_default(),
+            client_host: request.client_host.clone(),
+            instance_id: heartbeat.instance_id,
+            rack_id: heartbeat.rack_id,
+            rebalance_timeout_ms: heartbeat.rebalance_timeout_ms,
+            subscribed_topic_names: heartbeat.subscribed_topic_names,
+            server_assignor: heartbeat.server_assignor,
+            owned_partitions,
+        };
+
+        let partitions_per_topic = broker.topic_manager().partitions_per_topic().await;
+        let result = broker
+            .group_coordinator()
+            .consumer_group_heartbeat(&heartbeat.group_id, member_heartbeat, &partitions_per_topic)
+            .await;
+
+        match result {
+            Ok(assignment) => {
+                let mut by_topic: BTreeMap<String, Vec<i32>> = BTreeMap::new();
+                for tp in &assignment.partitions {
+                    by_topic.entry(tp.topic().to_string()).or_default().push(tp.partition());
+                }
+                let mut topic_ids = HashMap::new();
+                for topic in by_topic.keys() {
+                    if let Some(topic) = broker.topic_manager().get(topic).await {
+                        topic_ids.insert(topic.name().to_string(), topic.topic_id());
+                    }
+                }
+                let topic_partitions: Vec<_> = by_topic
+                    .into_iter()
+                    .filter_map(|(topic, partitions)| topic_ids.get(&topic).map(|id| (*id, partitions)))
+                    .collect();
+
+                ResponseBuilder::build_consumer_group_heartbeat_response(
+                    request.correlation_id,
+                    KafkaErrorCode::None,
+                    Some(&assignment.member_id),
+                    assignment.member_epoch,
+                    CONSUMER_GROUP_HEARTBEAT_INTERVAL_MS,
+                    Some(&topic_partitions),
+                )
+            }
+            Err(e) => {
+                println!("ConsumerGroupHeartbeat for group {} failed: {}", heartbeat.group_id, e);
+                ResponseBuilder::build_consumer_group_heartbeat_response(
+                    request.correlation_id, e.error_code(), None, 0, 0, None,
+                )
+            }
+        }
+    }
+
+    // there's no top-level error code to answer a malformed request with
+    async fn handle_describe_groups(request: &KafkaRequest, broker: &Broker) -> Result<Vec<u8>, ServerError> {
+        let describe = DescribeGroupsRequest::parse(&request.body).inspect_err(|e| eprintln!("Invalid DescribeGroups request: {}", e))?;
+
+        let mut groups = Vec::with_capacity(describe.groups.len());
+        for group_id in &describe.groups {
+            groups.push(broker.group_coordinator().describe_group(group_id).await);
+        }
+
+        Ok(ResponseBuilder::build_describe_groups_response(request.correlation_id, &groups, describe.include_authorized_operations))
+    }
+
+    async fn handle_list_groups(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let list = match ListGroupsRequest::parse(&request.body) {
+            Ok(list) => list,
+            Err(e) => {
+                eprintln!("Invalid ListGroups request: {}", e);
+                return ResponseBuilder::build_list_groups_response(request.correlation_id, KafkaErrorCode::InvalidRequest, &[]);
+            }
+        };
+
+        let groups = broker.group_coordinator().list_groups(&list.states_filter).await;
+        ResponseBuilder::build_list_groups_response(request.correlation_id, KafkaErrorCode::None, &groups)
+    }
+
+    // like DescribeGroups, a malformed request has nothing to be answered with
+    async fn handle_delete_groups(request: &KafkaRequest, broker: &Broker) -> Result<Vec<u8>, ServerError> {
+        let delete = DeleteGroupsRequest::parse(&request.body).inspect_err(|e| eprintln!("Invalid DeleteGroups request: {}", e))?;
+
+        let results: Vec<(String, KafkaErrorCode)> = broker
+            .group_coordinator()
+            .delete_groups(&delete.groups_names)
+            .await
+            .into_iter()
+            .map(|(group_id, result)| {
+                let error_code = result.map_or_else(|e| e.error_code(), |_| KafkaErrorCode::None);
+                (group_id, error_code)
+            })
+            .collect();
+
+        Ok(ResponseBuilder::build_delete_groups_response(request.correlation_id, &results))
+    }
+
+    async fn handle_offset_delete(request: &KafkaRequest, broker: &Broker) -> Vec<u8> {
+        let delete = match OffsetDeleteRequest::parse(&request.body) {
+            Ok(delete) => delete,
+            Err(e) => {
+                eprintln!("Invalid OffsetDelete request: {}", e);
+                return ResponseBuilder::build_offset_delete_response(request.correlation_id, KafkaErrorCode::InvalidRequest, &[]);
+            }
+        };
+
+        let partitions: Vec<TopicPartition> = delete
+            .topics
+            .iter()
+            .flat_map(|(topic, partitions)| partitions.iter().map(|p| TopicPartition::new(topic.clone(), *p)))
+            .collect();
+
+        let results = match broker.group_coordinator().delete_offsets(&delete.group_id, partitions).await {
+            Ok(results) => results,
+            Err(e) => {
+                println!("OffsetDelete for group {} failed: {}", delete.group_id, e);
+                return ResponseBuilder::build_offset_delete_response(request.correlation_id, e.error_code(), &[]);
+            }
+        };
+
+        // answer in request order, topic by topic
+        let mut topics: Vec<(String, Vec<(i32, KafkaErrorCode)>)> = Vec::new();
+        for (tp, result) in results {
+            let error_code = result.map_or_else(|e| e.error_code(), |_| KafkaErrorCode::None);
+            match topics.last_mut() {
+                Some((topic, partitions)) if topic == tp.topic() => partitions.push((tp.partition(), error_code)),
+                _ => topics.push((tp.topic().to_string(), vec![(tp.partition(), error_code)])),
+            }
+        }
+
+        ResponseBuilder::build_offset_delete_response(request.correlation_id, KafkaErrorCode::None, &topics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::core::controller::Controller;
+    use crate::core::metadata::BrokerEndpoint;
+    use crate::network::api::{put_compact_array_len, put_compact_nullable_string, put_compact_string};
+    use crate::network::handler::RequestDecoder;
+
+    fn request(api_key: i16, api_version: i16, body: Vec<u8>) -> KafkaRequest {
+        KafkaRequest {
+            api_key,
+            api_version,
+            correlation_id: 7,
+            client_id: Some("admin".to_string()),
+            client_host: "/127.0.0.1".to_string(),
+            body,
+        }
+    }
+
+    // a compact string array followed by `trailer` and the body's tag buffer
+    fn names(names: &[&str], trailer: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        put_compact_array_len(&mut body, names.len());
+        for name in names {
+            put_compact_string(&mut body, name);
+        }
+        body.extend_from_slice(trailer);
+        body.push(0x00);
+        body
+    }
+
+    // skips the size, the flexible response header and throttle_time_ms
+    fn response_body(response: &[u8]) -> RequestDecoder<'_> {
+        RequestDecoder::new(&response[13..])
+    }
+
+    // "billing" has a member and is Stable, "audit" only has committed offsets and is Empty
+    async fn broker_with_groups() -> (std::path::PathBuf, Broker) {
+        let dir = std::env::temp_dir().join(format!("rafka-protocol-{}", Uuid::new_v4()));
+        let broker = Broker::with_log_dir(0, dir.clone());
+        let join = MemberHeartbeat {
+            member_id: String::new(),
+            member_epoch: 0,
+            client_id: "billing".to_string(),
+            client_host: "/127.0.0.1".to_string(),
+            instance_id: None,
+            rack_id: None,
+            rebalance_timeout_ms: 30_000,
+            subscribed_topic_names: Some(vec!["orders".to_string()]),
+            server_assignor: None,
+            owned_partitions: None,
+        };
+        let orders = HashMap::from([("orders".to_string(), 2)]);
+        broker.group_coordinator().consumer_group_heartbeat("billing", join, &orders).await.unwrap();
+        let offset = OffsetAndMetadata { offset: 5, leader_epoch: -1, metadata: String::new(), commit_timestamp: 0 };
+        broker.group_coordinator().commit_offsets("audit", vec![(TopicPartition::new("orders".to_string(), 0), offset)]).await.unwrap();
+        (dir, broker)
+    }
+
+    async fn list_groups(broker: &Broker, states: &[&str]) -> Vec<(String, String)> {
+        let response = KafkaProtocolHandler::process_request(&request(API_KEY_LIST_GROUPS, 4, names(states, &[])), broker).await.unwrap();
+        let mut decoder = response_body(&response);
+        assert_eq!(decoder.read_i16().unwrap(), KafkaErrorCode::None as i16);
+        let mut groups = Vec::new();
+        for _ in 0..decoder.read_compact_array_len().unwrap().unwrap() {
+            let group_id = decoder.read_compact_string().unwrap();
+            decoder.read_compact_string().unwrap(); // protocol_type
+            groups.push((group_id, decoder.read_compact_string().unwrap()));
+            decoder.skip_tagged_fields().unwrap();
+        }
+        groups
+    }
+
+    #[tokio::test]
+    async fn describe_groups_reports_members_and_unknown_groups_as_dead() {
+        let (dir, broker) = broker_with_groups().await;
+        let describe = request(API_KEY_DESCRIBE_GROUPS, 5, names(&["billing", "missing"], &[0]));
+        let response = KafkaProtocolHandler::process_request(&describe, &broker).await.unwrap();
+
+        let mut decoder = response_body(&response);
+        assert_eq!(decoder.read_compact_array_len().unwrap(), Some(2));
+        let mut described = Vec::new();
+        for _ in 0..2 {
+            assert_eq!(decoder.read_i16().unwrap(), KafkaErrorCode::None as i16);
+            let group_id = decoder.read_compact_string().unwrap();
+            let state = decoder.read_compact_string().unwrap();
+            decoder.read_compact_string().unwrap(); // protocol_type
+            decoder.read_compact_string().unwrap(); // protocol
+            let members = decoder.read_compact_array_len().unwrap().unwrap();
+            for _ in 0..members {
+                decoder.read_compact_string().unwrap(); // member_id
+                decoder.read_compact_nullable_string().unwrap(); // instance_id
+                assert_eq!(decoder.read_compact_string().unwrap(), "billing");
+                decoder.read_compact_string().unwrap(); // client_host
+                decoder.read_compact_nullable_bytes().unwrap(); // metadata
+                decoder.read_compact_nullable_bytes().unwrap(); // assignment
+                decoder.skip_tagged_fields().unwrap();
+            }
+            decoder.read_i32().unwrap(); // authorized_operations
+            decoder.skip_tagged_fields().unwrap();
+            described.push((group_id, state, members));
+        }
+        assert_eq!(
+            described,
+            vec![("billing".to_string(), "Stable".to_string(), 1), ("missing".to_string(), "Dead".to_string(), 0)]
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn list_groups_filters_by_state() {
+        let (dir, broker) = broker_with_groups().await;
+        assert_eq!(
+            list_groups(&broker, &[]).await,
+            vec![("audit".to_string(), "Empty".to_string()), ("billing".to_string(), "Stable".to_string())]
+        );
+        assert_eq!(list_groups(&broker, &["empty"]).await, vec![("audit".to_string(), "Empty".to_string())]);
+        assert!(list_groups(&broker, &["Dead"]).await.is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn delete_groups_only_removes_empty_groups() {
+        let (dir, broker) = broker_with_groups().await;
+        let delete = request(API_KEY_DELETE_GROUPS, 2, names(&["audit", "billing", "missing"], &[]));
+        let response = KafkaProtocolHandler::process_request(&delete, &broker).await.unwrap();
+
+        let mut decoder = response_body(&response);
+        let mut results = Vec::new();
+        for _ in 0..decoder.read_compact_array_len().unwrap().unwrap() {
+            let group_id = decoder.read_compact_string().unwrap();
+            results.push((group_id, decoder.read_i16().unwrap()));
+            decoder.skip_tagged_fields().unwrap();
+        }
+        assert_eq!(
+            results,
+            vec![
+                ("audit".to_string(), KafkaErrorCode::None as i16),
+                ("billing".to_string(), KafkaErrorCode::NonEmptyGroup as i16),
+                ("missing".to_string(), KafkaErrorCode::GroupIdNotFound as i16),
+            ]
+        );
+        assert_eq!(list_groups(&broker, &[]).await, vec![("billing".to_string(), "Stable".to_string())]);
+        assert!(broker.group_coordinator().committed_offsets("audit").await.is_none());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn malformed_group_requests_are_rejected() {
+        let (dir, broker) = broker_with_groups().await;
+        // an array length with no names behind it
+        let truncated = vec![0x03];
+        assert!(KafkaProtocolHandler::process_request(&request(API_KEY_DESCRIBE_GROUPS, 5, truncated.clone()), &broker).await.is_err());
+        assert!(KafkaProtocolHandler::process_request(&request(API_KEY_DELETE_GROUPS, 2, truncated.clone()), &broker).await.is_err());
+        assert_eq!(broker.group_coordinator().list_groups(&[]).await.len(), 2);
+
+        // ListGroups has a top-level error code to answer with
+        let response = KafkaProtocolHandler::process_request(&request(API_KEY_LIST_GROUPS, 4, truncated), &broker).await.unwrap();
+        assert_eq!(response_body(&response).read_i16().unwrap(), KafkaErrorCode::InvalidRequest as i16);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn malformed_produce_requests_close_the_connection() {
+        let (dir, broker) = broker_with_groups().await;
+        // a transactional id length with nothing behind it
+        assert!(KafkaProtocolHandler::process_request(&request(API_KEY_PRODUCE, 9, vec![0x03]), &broker).await.is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn malformed_transaction_requests_close_the_connection() {
+        let (dir, broker) = broker_with_groups().await;
+        // a length prefix with nothing behind it
+        let truncated = vec![0x03];
+        assert!(KafkaProtocolHandler::process_request(&request(API_KEY_ADD_PARTITIONS_TO_TXN, 3, truncated.clone()), &broker).await.is_err());
+        assert!(KafkaProtocolHandler::process_request(&request(API_KEY_WRITE_TXN_MARKERS, 1, truncated.clone()), &broker).await.is_err());
+        assert!(KafkaProtocolHandler::process_request(&request(API_KEY_TXN_OFFSET_COMMIT, 3, truncated), &broker).await.is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn malformed_offset_for_leader_epoch_requests_close_the_connection() {
+        let (dir, broker) = broker_with_groups().await;
+        // a topic count with no topics behind it
+        assert!(KafkaProtocolHandler::process_request(&request(API_KEY_OFFSET_FOR_LEADER_EPOCH, 4, vec![0xff, 0xff, 0xff, 0xff, 0x03]), &broker).await.is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    // a broker running a standalone controller with itself as the only live broker
+    async fn broker_with_controller() -> (std::path::PathBuf, Broker) {
+        let dir = std::env::temp_dir().join(format!("rafka-protocol-{}", Uuid::new_v4()));
+        let broker = Broker::with_log_dir(0, dir.clone());
+        let controller = Arc::new(Controller::new(0));
+        let registration = BrokerRegistration {
+            broker_id: 0,
+            broker_epoch: -1,
+            incarnation_id: Uuid::new_v4(),
+            listeners: vec![BrokerEndpoint {
+                name: "PLAINTEXT".to_string(),
+                host: "localhost".to_string(),
+                port: 9092,
+                security_protocol: 0,
+            }],
+            rack: None,
+            log_dirs: Vec::new(),
+            fenced: true,
+        };
+        let broker_epoch = controller.register_broker(registration).await.unwrap();
+        controller.broker_heartbeat(0, broker_epoch, false, false).await.unwrap();
+        broker.attach_controller(controller);
+        (dir, broker)
+    }
+
+    // a CreateTopics topic with one partition and one replica
+    fn creatable_topic(body: &mut Vec<u8>, name: &str) {
+        put_compact_string(body, name);
+        body.extend_from_slice(&1i32.to_be_bytes());
+        body.extend_from_slice(&1i16.to_be_bytes());
+        put_compact_array_len(body, 0); // assignments
+        put_compact_array_len(body, 0); // configs
+        body.push(0x00);
+    }
+
+    async fn create_topics(broker: &Broker, body: Vec<u8>) -> Vec<(String, i16)> {
+        let response = KafkaProtocolHandler::process_request(&request(API_KEY_CREATE_TOPICS, 7, body), broker).await.unwrap();
+        let mut decoder = response_body(&response);
+        let mut results = Vec::new();
+        for _ in 0..decoder.read_compact_array_len().unwrap().unwrap() {
+            let name = decoder.read_compact_string().unwrap();
+            decoder.read_uuid().unwrap();
+            results.push((name, decoder.read_i16().unwrap()));
+            decoder.read_compact_nullable_string().unwrap(); // error_message
+            decoder.read_i32().unwrap(); // num_partitions
+            decoder.read_i16().unwrap(); // replication_factor
+            for _ in 0..decoder.read_compact_array_len().unwrap().unwrap_or(0) {
+                decoder.read_compact_string().unwrap();
+                decoder.read_compact_nullable_string().unwrap();
+                decoder.read_i8().unwrap(); // read_only
+                decoder.read_i8().unwrap(); // config_source
+                decoder.read_i8().unwrap(); // is_sensitive
+                decoder.skip_tagged_fields().unwrap();
+            }
+            decoder.skip_tagged_fields().unwrap();
+        }
+        results
+    }
+
+    async fn delete_topics(broker: &Broker, body: Vec<u8>) -> Vec<(Option<String>, Uuid, i16)> {
+        let response = KafkaProtocolHandler::process_request(&request(API_KEY_DELETE_TOPICS, 6, body), broker).await.unwrap();
+        let mut decoder = response_body(&response);
+        let mut results = Vec::new();
+        for _ in 0..decoder.read_compact_array_len().unwrap().unwrap() {
+            let name = decoder.read_compact_nullable_string().unwrap();
+            let topic_id = decoder.read_uuid().unwrap();
+            results.push((name, topic_id, decoder.read_i16().unwrap()));
+            decoder.read_compact_nullable_string().unwrap(); // error_message
+            decoder.skip_tagged_fields().unwrap();
+        }
+        results
+    }
+
+    fn topics_by_name(names: &[&str]) -> Vec<u8> {
+        let mut body = Vec::new();
+        put_compact_array_len(&mut body, names.len());
+        for name in names {
+            put_compact_nullable_string(&mut body, Some(name));
+            body.extend_from_slice(Uuid::nil().as_bytes());
+            body.push(0x00);
+        }
+        body.extend_from_slice(&30_000i32.to_be_bytes());
+        body.push(0x00);
+        body
+    }
+
+    #[tokio::test]
+    async fn every_copy_of_a_duplicated_topic_is_refused() {
+        let (dir, broker) = broker_with_controller().await;
+        let mut body = Vec::new();
+        put_compact_array_len(&mut body, 3);
+        for name in ["orders", "payments", "orders"] {
+            creatable_topic(&mut body, name);
+        }
+        body.extend_from_slice(&30_000i32.to_be_bytes());
+        body.extend_from_slice(&[0x00, 0x00]); // validate_only, tag buffer
+
+        let invalid = KafkaErrorCode::InvalidRequest as i16;
+        assert_eq!(
+            create_topics(&broker, body).await,
+            vec![("orders".to_string(), invalid), ("payments".to_string(), 0), ("orders".to_string(), invalid)]
+        );
+        let controller = broker.controller().unwrap();
+        assert!(controller.topic_id("orders").await.is_none());
+        let payments = controller.topic_id("payments").await.unwrap();
+
+        let deleted = delete_topics(&broker, topics_by_name(&["payments", "payments"])).await;
+        assert_eq!(deleted, vec![(Some("payments".to_string()), Uuid::nil(), invalid); 2]);
+        assert_eq!(controller.topic_id("payments").await, Some(payments));
+        assert_eq!(
+            delete_topics(&broker, topics_by_name(&["payments"])).await,
+            vec![(Some("payments".to_string()), payments, 0)]
+        );
+        assert!(controller.topic_id("payments").await.is_none());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn malformed_topic_requests_get_an_error_for_every_topic_read() {
+        let (dir, broker) = broker_with_controller().await;
+        // the second topic is cut off after its name
+        let mut body = Vec::new();
+        put_compact_array_len(&mut body, 2);
+        creatable_topic(&mut body, "orders");
+        put_compact_string(&mut body, "payments");
+        let invalid = KafkaErrorCode::InvalidRequest as i16;
+        assert_eq!(create_topics(&broker, body).await, vec![("orders".to_string(), invalid), ("payments".to_string(), invalid)]);
+        assert!(broker.controller().unwrap().topic_id("orders").await.is_none());
+
+        // the topic id is cut off
+        let mut body = Vec::new();
+        put_compact_array_len(&mut body, 1);
+        put_compact_nullable_string(&mut body, Some("orders"));
+        body.extend_from_slice(&[0x01, 0x02]);
+        assert_eq!(delete_topics(&broker, body).await, vec![(Some("orders".to_string()), Uuid::nil(), invalid)]);
+
+        // not even the topic array can be read, but the request is still answered
+        assert!(create_topics(&broker, vec![0x05]).await.is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn create_partitions_answers_every_topic() {
+        let (dir, broker) = broker_with_controller().await;
+        let controller = broker.controller().unwrap();
+        for name in ["orders", "payments"] {
+            let topic = NewTopic { name: name.to_string(), num_partitions: 1, replication_factor: 1, ..NewTopic::default() };
+            controller.create_topic(&topic, false).await.unwrap();
+        }
+        let create_partitions = |body: Vec<u8>| async {
+            let response = KafkaProtocolHandler::process_request(&request(API_KEY_CREATE_PARTITIONS, 3, body), &broker).await.unwrap();
+            let mut decoder = response_body(&response);
+            let mut results = Vec::new();
+            for _ in 0..decoder.read_compact_array_len().unwrap().unwrap() {
+                let name = decoder.read_compact_string().unwrap();
+                results.push((name, decoder.read_i16().unwrap()));
+                decoder.read_compact_nullable_string().unwrap(); // error_message
+                decoder.skip_tagged_fields().unwrap();
+            }
+            results
+        };
+        let topic = |body: &mut Vec<u8>, name: &str, count: i32| {
+            put_compact_string(body, name);
+            body.extend_from_slice(&count.to_be_bytes());
+            body.push(0x00); // assignments: null
+            body.push(0x00);
+        };
+
+        let mut body = Vec::new();
+        put_compact_array_len(&mut body, 4);
+        topic(&mut body, "orders", 2);
+        topic(&mut body, "payments", 3);
+        topic(&mut body, "orders", 3);
+        topic(&mut body, "payments", 1);
+        body.extend_from_slice(&30_000i32.to_be_bytes());
+        body.extend_from_slice(&[0x00, 0x00]); // validate_only, tag buffer
+        let invalid = KafkaErrorCode::InvalidRequest as i16;
+        assert_eq!(
+            create_partitions(body).await,
+            vec![
+                ("orders".to_string(), invalid),
+                ("payments".to_string(), invalid),
+                ("orders".to_string(), invalid),
+                ("payments".to_string(), invalid),
+            ]
+        );
+        assert!(controller.partition_state("orders", 1).await.is_none());
+
+        // the second topic is cut off after its name
+        let mut body = Vec::new();
+        put_compact_array_len(&mut body, 2);
+        topic(&mut body, "orders", 2);
+        put_compact_string(&mut body, "payments");
+        assert_eq!(create_partitions(body).await, vec![("orders".to_string(), invalid), ("payments".to_string(), invalid)]);
+        assert!(controller.partition_state("orders", 1).await.is_none());
+
+        let mut body = Vec::new();
+        put_compact_array_len(&mut body, 2);
+        topic(&mut body, "orders", 2);
+        topic(&mut body, "payments", 1);
+        body.extend_from_slice(&30_000i32.to_be_bytes());
+        body.extend_from_slice(&[0x00, 0x00]);
+        assert_eq!(
+            create_partitions(body).await,
+            vec![("orders".to_string(), 0), ("payments".to_string(), KafkaErrorCode::InvalidPartitions as i16)]
+        );
+        assert!(controller.partition_state("orders", 1).await.is_some());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
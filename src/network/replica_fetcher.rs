@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::{
+    constants::{
+        API_KEY_FETCH, API_KEY_OFFSET_FOR_LEADER_EPOCH, REPLICA_FETCHER_CHECK_INTERVAL_MS, REPLICA_FETCH_BACKOFF_MAX_MS, REPLICA_FETCH_BACKOFF_MS,
+        REPLICA_FETCH_MAX_BYTES, REPLICA_FETCH_MIN_BYTES, REPLICA_FETCH_PARTITION_MAX_BYTES, REPLICA_FETCH_WAIT_MAX_MS,
+    },
+    core::broker::Broker,
+    core::consumer_group::TopicPartition,
+    core::replication::PartitionFetchState,
+    error::{KafkaErrorCode, ServerError},
+    network::api::{FetchResponse, OffsetForLeaderEpochResponse},
+    network::client::KafkaClient,
+    network::requests::{
+        FetchPartition, FetchRequest, FetchTopic, OffsetForLeaderEpochRequest, OffsetForLeaderPartition, OffsetForLeaderTopic,
+    },
+};
+
+const FETCH_VERSION: i16 = 16;
+const OFFSET_FOR_LEADER_EPOCH_VERSION: i16 = 4;
+
+// keeps one fetcher task running per leader this broker follows
+pub struct ReplicaFetcherManager {
+    broker: Arc<Broker>,
+    fetchers: Mutex<HashMap<i32, JoinHandle<()>>>,
+}
+
+impl ReplicaFetcherManager {
+    pub fn new(broker: Arc<Broker>) -> Self {
+        ReplicaFetcherManager {
+            broker,
+            fetchers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// starts fetchers for new leaders; a fetcher exits once it has no partitions left
+    pub async fn start_fetchers(&self) {
+        let mut fetchers = self.fetchers.lock().await;
+        fetchers.retain(|_, handle| !handle.is_finished());
+
+        for leader_id in self.broker.replica_manager().follower_leader_ids().await {
+            if leader_id == self.broker.broker_id() || fetchers.contains_key(&leader_id) {
+                continue;
+            }
+            let fetcher = ReplicaFetcher::new(leader_id, Arc::clone(&self.broker));
+            fetchers.insert(leader_id, tokio::spawn(fetcher.run()));
+        }
+    }
+
+    pub async fn run(&self) {
+        loop {
+            self.start_fetchers().await;
+            tokio::time::sleep(Duration::from_millis(REPLICA_FETCHER_CHECK_INTERVAL_MS)).await;
+        }
+    }
+
+    pub async fn shutdown(&self) {
+        let mut fetchers = self.fetchers.lock().await;
+        for (_, handle) in fetchers.drain() {
+            handle.abort();
+        }
+    }
+}
+
+// pulls every partition led by one source broker over a single connection
+struct ReplicaFetcher {
+    leader_id: i32,
+    broker: Arc<Broker>,
+    client: Option<KafkaClient>,
+    backoff_ms: u64, // after the leader couldn't be reached
+    delayed: HashMap<TopicPartition, PartitionBackoff>, // partitions the leader returned an error for
+}
+
+// when a failed partition is tried again, and how long the next failure waits
+struct PartitionBackoff {
+    until: Instant,
+    backoff_ms: u64,
+}
+
+impl ReplicaFetcher {
+    fn new(leader_id: i32, broker: Arc<Broker>) -> Self {
+        ReplicaFetcher {
+            leader_id,
+            broker,
+            client: None,
+            backoff_ms: REPLICA_FETCH_BACKOFF_MS,
+            delayed: HashMap::new(),
+        }
+    }
+
+    async fn run(mut self) {
+        println!("Replica fetcher for leader {} started", self.leader_id);
+        loop {
+            // partitions that just started following this leader first drop any divergent tail
+            let mut truncating = self.broker.replica_manager().follower_truncation_states(self.leader_id).await;
+            let mut partitions = self.broker.replica_manager().follower_fetch_states(self.leader_id).await;
+            if truncating.is_empty() && partitions.is_empty() {
+                println!("Replica fetcher for leader {} has no partitions left, stopping", self.leader_id);
+                return;
+            }
+
+            // partitions that failed wait out their own backoff, the others go on
+            let now = Instant::now();
+            let ready = |state: &PartitionFetchState, delayed: &HashMap<TopicPartition, PartitionBackoff>| {
+                delayed
+                    .get(&TopicPartition::new(state.topic.clone(), state.partition_id))
+                    .is_none_or(|backoff| backoff.until <= now)
+            };
+            truncating.retain(|state| ready(state, &self.delayed));
+            partitions.retain(|state| ready(state, &self.delayed));
+            if truncating.is_empty() && partitions.is_empty() {
+                let next = self.delayed.values().map(|backoff| backoff.until).min().unwrap_or(now);
+                tokio::time::sleep_until(next).await;
+                continue;
+            }
+
+            let result = if truncating.is_empty() {
+                self.fetch_once(&partitions).await
+            } else {
+                self.truncate_once(&truncating).await
+            };
+            match result {
+                Ok(failed) => {
+                    self.backoff_ms = REPLICA_FETCH_BACKOFF_MS;
+                    let attempted = truncating.iter().chain(&partitions);
+                    for state in attempted {
+                        let tp = TopicPartition::new(state.topic.clone(), state.partition_id);
+                        if failed.contains(&tp) {
+                            self.delay_partition(tp);
+                        } else {
+                            self.delayed.remove(&tp);
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Fetch from leader {} failed: {}", self.leader_id, e);
+                    self.client = None;
+                    self.back_off().await;
+                }
+            }
+        }
+    }
+
+    async fn back_off(&mut self) {
+        tokio::time::sleep(Duration::from_millis(self.backoff_ms)).await;
+        self.backoff_ms = (self.backoff_ms * 2).min(REPLICA_FETCH_BACKOFF_MAX_MS);
+    }
+
+    // holds a partition back, twice as long as last time it failed
+    fn delay_partition(&mut self, tp: TopicPartition) {
+        let backoff_ms = match self.delayed.get(&tp) {
+            Some(backoff) => (backoff.backoff_ms * 2).min(REPLICA_FETCH_BACKOFF_MAX_MS),
+            None => REPLICA_FETCH_BACKOFF_MS,
+        };
+        let until = Instant::now() + Duration::from_millis(backoff_ms);
+        self.delayed.insert(tp, PartitionBackoff { until, backoff_ms });
+    }
+
+    async fn connected_client(&mut self) -> Result<&mut KafkaClient, ServerError> {
+        if self.client.is_none() {
+            let address = self
+                .broker
+                .broker_endpoint(self.leader_id)
+                .await
+                .ok_or(ServerError::BrokerNotAvailable(self.leader_id))?;
+            let client_id = format!("replica-fetcher-{}-{}", self.broker.broker_id(), self.leader_id);
+            self.client = Some(KafkaClient::connect(&address, &client_id).await?);
+        }
+        Ok(self.client.as_mut().unwrap())
+    }
+
+    // one OffsetForLeaderEpoch round trip, returning the partitions that came back with an error
+    async fn truncate_once(&mut self, partitions: &[PartitionFetchState]) -> Result<Vec<TopicPartition>, ServerError> {
+        let mut topics: Vec<OffsetForLeaderTopic> = Vec::new();
+        for state in partitions {
+            let partition = OffsetForLeaderPartition {
+                partition: state.partition_id,
+                current_leader_epoch: state.current_leader_epoch,
+                leader_epoch: state.last_fetched_epoch,
+            };
+            match topics.iter_mut().find(|t| t.topic == state.topic) {
+                Some(topic) => topic.partitions.push(partition),
+                None => topics.push(OffsetForLeaderTopic { topic: state.topic.clone(), partitions: vec![partition] }),
+            }
+        }
+        let request = OffsetForLeaderEpochRequest { replica_id: self.broker.broker_id(), topics };
+
+        let client = self.connected_client().await?;
+        let body = client
+            .send_request(API_KEY_OFFSET_FOR_LEADER_EPOCH, OFFSET_FOR_LEADER_EPOCH_VERSION, &request.encode())
+            .await?;
+        let response = OffsetForLeaderEpochResponse::parse(&body)?;
+
+        let mut failed = Vec::new();
+        for topic in response.topics {
+            for partition in topic.partitions {
+                if partition.error_code != i16::from(KafkaErrorCode::None) {
+                    eprintln!(
+                        "OffsetForLeaderEpoch of {}-{} from leader {} returned error {}",
+                        topic.topic, partition.partition, self.leader_id, partition.error_code
+                    );
+                    failed.push(TopicPartition::new(topic.topic.clone(), partition.partition));
+                    continue;
+                }
+                let truncated = self
+                    .broker
+                    .replica_manager()
+                    .truncate_to_epoch_end(&topic.topic, partition.partition, partition.leader_epoch, partition.end_offset)
+                    .await;
+                if let Err(e) = truncated {
+                    eprintln!("Failed to truncate {}-{}: {}", topic.topic, partition.partition, e);
+                    failed.push(TopicPartition::new(topic.topic.clone(), partition.partition));
+                }
+            }
+        }
+        Ok(failed)
+    }
+
+    // one Fetch round trip, returning the partitions that came back with an error
+    async fn fetch_once(&mut self, partitions: &[PartitionFetchState]) -> Result<Vec<TopicPartition>, ServerError> {
+        let mut failed = Vec::new();
+        let mut topics: Vec<FetchTopic> = Vec::new();
+        for state in partitions {
+            let Some(topic) = self.broker.topic_manager().get(&state.topic).await else {
+                eprintln!("Follower partition {}-{} has no local topic", state.topic, state.partition_id);
+                failed.push(TopicPartition::new(state.topic.clone(), state.partition_id));
+                continue;
+            };
+            let partition = FetchPartition {
+                partition: state.partition_id,
+                current_leader_epoch: state.current_leader_epoch,
+                fetch_offset: state.fetch_offset,
+                last_fetched_epoch: state.last_fetched_epoch,
+                log_start_offset: -1,
+                partition_max_bytes: REPLICA_FETCH_PARTITION_MAX_BYTES,
+            };
+            match topics.iter_mut().find(|t| t.topic_id == topic.topic_id()) {
+                Some(fetch_topic) => fetch_topic.partitions.push(partition),
+                None => topics.push(FetchTopic { topic_id: topic.topic_id(), partitions: vec![partition] }),
+            }
+        }
+        if topics.is_empty() {
+            return Ok(failed);
+        }
+
+        let request = FetchRequest {
+            replica_id: self.broker.broker_id(),
+            max_wait_ms: REPLICA_FETCH_WAIT_MAX_MS,
+            min_bytes: REPLICA_FETCH_MIN_BYTES,
+            max_bytes: REPLICA_FETCH_MAX_BYTES,
+            isolation_level: 0,
+            session_id: 0,
+            session_epoch: -1,
+            topics,
+            rack_id: self.broker.rack().unwrap_or_default().to_string(),
+        };
+
+        let client = self.connected_client().await?;
+        let body = client.send_request(API_KEY_FETCH, FETCH_VERSION, &request.encode()).await?;
+        let response = FetchResponse::parse(&body)?;
+        if response.error_code != i16::from(KafkaErrorCode::None) {
+            eprintln!("Leader {} rejected fetch with error {}", self.leader_id, response.error_code);
+            failed.extend(partitions.iter().map(|state| TopicPartition::new(state.topic.clone(), state.partition_id)));
+            return Ok(failed);
+        }
+
+        for topic_response in response.responses {
+            let Some(topic) = self.broker.topic_manager().get_by_id(topic_response.topic_id).await else {
+                continue;
+            };
+            for partition in topic_response.partitions {
+                let tp = TopicPartition::new(topic.name().to_string(), partition.partition);
+                if partition.error_code == i16::from(KafkaErrorCode::OffsetOutOfRange) {
+                    let reset = self
+                        .broker
+                        .replica_manager()
+                        .handle_offset_out_of_range(topic.name(), partition.partition, partition.log_start_offset)
+                        .await;
+                    if let Err(e) = reset {
+                        eprintln!("Failed to reset {}-{} after OFFSET_OUT_OF_RANGE: {}", topic.name(), partition.partition, e);
+                        failed.push(tp);
+                    }
+                    continue;
+                }
+                if partition.error_code != i16::from(KafkaErrorCode::None) {
+                    eprintln!(
+                        "Fetch of {}-{} from leader {} returned error {}",
+                        topic.name(), partition.partition, self.leader_id, partition.error_code
+                    );
+                    failed.push(tp);
+                    continue;
+                }
+                self.broker
+                    .replica_manager()
+                    .update_follower_high_watermark(topic.name(), partition.partition, partition.high_watermark)
+                    .await;
+                // consumers reading from this follower may be waiting on the new HW
+                if partition.records.is_empty() {
+                    self.broker.on_partition_changed(&tp).await;
+                    continue;
+                }
+
+                let appended = self
+                    .broker
+                    .replica_manager()
+                    .append_as_follower(topic.name(), partition.partition, &partition.records)
+                    .await;
+                match appended {
+                    Ok(Some((log_end_offset, epoch))) => {
+                        self.broker
+                            .replica_manager()
+                            .update_follower_fetch(topic.name().to_string(), partition.partition, self.broker.broker_id(), log_end_offset, epoch)
+                            .await;
+                        self.broker.on_partition_changed(&tp).await;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        eprintln!("Failed to append fetched batches to {}-{}: {}", topic.name(), partition.partition, e);
+                        failed.push(tp);
+                    }
+                }
+            }
+        }
+
+        Ok(failed)
+    }
+}
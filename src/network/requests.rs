@@ -0,0 +1,1291 @@
+use uuid::Uuid;
+
+use crate::{
+    core::controller::PartitionState,
+    core::metadata::BrokerEndpoint,
+    error::ServerError,
+    network::api::{
+        put_compact_array_len, put_compact_i32_array, put_compact_nullable_string, put_compact_string, put_unsigned_varint,
+    },
+    network::handler::RequestDecoder,
+};
+
+// ConsumerGroupHeartbeat v0 (KIP-848)
+#[derive(Debug)]
+pub struct ConsumerGroupHeartbeatRequest {
+    pub group_id: String,
+    pub member_id: String,
+    pub member_epoch: i32,
+    pub instance_id: Option<String>,
+    pub rack_id: Option<String>,
+    pub rebalance_timeout_ms: i32,
+    pub subscribed_topic_names: Option<Vec<String>>,
+    pub server_assignor: Option<String>,
+    pub topic_partitions: Option<Vec<(Uuid, Vec<i32>)>>,
+}
+
+impl ConsumerGroupHeartbeatRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+
+        let group_id = decoder.read_compact_string()?;
+        let member_id = decoder.read_compact_string()?;
+        let member_epoch = decoder.read_i32()?;
+        let instance_id = decoder.read_compact_nullable_string()?;
+        let rack_id = decoder.read_compact_nullable_string()?;
+        let rebalance_timeout_ms = decoder.read_i32()?;
+        let subscribed_topic_names = decoder.read_compact_string_array()?;
+        let server_assignor = decoder.read_compact_nullable_string()?;
+
+        let topic_partitions = match decoder.read_compact_array_len()? {
+            Some(len) => {
+                let mut topics = Vec::new();
+                for _ in 0..len {
+                    let topic_id = decoder.read_uuid()?;
+                    let partitions = decoder.read_compact_i32_array()?;
+                    decoder.skip_tagged_fields()?;
+                    topics.push((topic_id, partitions));
+                }
+                Some(topics)
+            }
+            None => None,
+        };
+        decoder.skip_tagged_fields()?;
+
+        Ok(ConsumerGroupHeartbeatRequest {
+            group_id,
+            member_id,
+            member_epoch,
+            instance_id,
+            rack_id,
+            rebalance_timeout_ms,
+            subscribed_topic_names,
+            server_assignor,
+            topic_partitions,
+        })
+    }
+}
+
+// DescribeGroups v5
+#[derive(Debug)]
+pub struct DescribeGroupsRequest {
+    pub groups: Vec<String>,
+    pub include_authorized_operations: bool,
+}
+
+impl DescribeGroupsRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let groups = decoder.read_compact_string_array()?.unwrap_or_default();
+        let include_authorized_operations = decoder.read_i8()? != 0;
+        decoder.skip_tagged_fields()?;
+        Ok(DescribeGroupsRequest { groups, include_authorized_operations })
+    }
+}
+
+// ListGroups v4
+#[derive(Debug)]
+pub struct ListGroupsRequest {
+    pub states_filter: Vec<String>,
+}
+
+impl ListGroupsRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let states_filter = decoder.read_compact_string_array()?.unwrap_or_default();
+        decoder.skip_tagged_fields()?;
+        Ok(ListGroupsRequest { states_filter })
+    }
+}
+
+// DeleteGroups v2
+#[derive(Debug)]
+pub struct DeleteGroupsRequest {
+    pub groups_names: Vec<String>,
+}
+
+impl DeleteGroupsRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let groups_names = decoder.read_compact_string_array()?.unwrap_or_default();
+        decoder.skip_tagged_fields()?;
+        Ok(DeleteGroupsRequest { groups_names })
+    }
+}
+
+// OffsetDelete v0, not flexible
+#[derive(Debug)]
+pub struct OffsetDeleteRequest {
+    pub group_id: String,
+    pub topics: Vec<(String, Vec<i32>)>,
+}
+
+impl OffsetDeleteRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let group_id = decoder.read_string()?;
+
+        let topic_count = decoder.read_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let name = decoder.read_string()?;
+            let partition_count = decoder.read_array_len()?.unwrap_or(0);
+            let partitions = (0..partition_count)
+                .map(|_| decoder.read_i32())
+                .collect::<Result<Vec<_>, _>>()?;
+            topics.push((name, partitions));
+        }
+
+        Ok(OffsetDeleteRequest { group_id, topics })
+    }
+}
+
+// Produce v9
+#[derive(Debug)]
+pub struct ProduceRequest {
+    pub transactional_id: Option<String>,
+    pub acks: i16,
+    pub timeout_ms: i32,
+    pub topics: Vec<ProduceTopic>,
+}
+
+#[derive(Debug)]
+pub struct ProduceTopic {
+    pub name: String,
+    pub partitions: Vec<ProducePartition>,
+}
+
+#[derive(Debug)]
+pub struct ProducePartition {
+    pub index: i32,
+    pub records: Vec<u8>, // record batches
+}
+
+impl ProduceRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let transactional_id = decoder.read_compact_nullable_string()?;
+        let acks = decoder.read_i16()?;
+        let timeout_ms = decoder.read_i32()?;
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let name = decoder.read_compact_string()?;
+            let partition_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            let mut partitions = Vec::new();
+            for _ in 0..partition_count {
+                let index = decoder.read_i32()?;
+                let records = decoder.read_compact_nullable_bytes()?.unwrap_or_default().to_vec();
+                decoder.skip_tagged_fields()?;
+                partitions.push(ProducePartition { index, records });
+            }
+            decoder.skip_tagged_fields()?;
+            topics.push(ProduceTopic { name, partitions });
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(ProduceRequest { transactional_id, acks, timeout_ms, topics })
+    }
+}
+
+// Fetch v16; replicas identify themselves through the ReplicaState tagged field
+#[derive(Debug)]
+pub struct FetchRequest {
+    pub replica_id: i32, // -1 for consumers
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub max_bytes: i32,
+    pub isolation_level: i8,
+    pub session_id: i32,
+    pub session_epoch: i32,
+    pub topics: Vec<FetchTopic>,
+    pub rack_id: String,
+}
+
+#[derive(Debug)]
+pub struct FetchTopic {
+    pub topic_id: Uuid,
+    pub partitions: Vec<FetchPartition>,
+}
+
+#[derive(Debug)]
+pub struct FetchPartition {
+    pub partition: i32,
+    pub current_leader_epoch: i32,
+    pub fetch_offset: i64,
+    pub last_fetched_epoch: i32,
+    pub log_start_offset: i64,
+    pub partition_max_bytes: i32,
+}
+
+const FETCH_REPLICA_STATE_TAG: u32 = 1;
+
+impl FetchRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+
+        let max_wait_ms = decoder.read_i32()?;
+        let min_bytes = decoder.read_i32()?;
+        let max_bytes = decoder.read_i32()?;
+        let isolation_level = decoder.read_i8()?;
+        let session_id = decoder.read_i32()?;
+        let session_epoch = decoder.read_i32()?;
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let topic_id = decoder.read_uuid()?;
+            let partition_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            let mut partitions = Vec::new();
+            for _ in 0..partition_count {
+                partitions.push(FetchPartition {
+                    partition: decoder.read_i32()?,
+                    current_leader_epoch: decoder.read_i32()?,
+                    fetch_offset: decoder.read_i64()?,
+                    last_fetched_epoch: decoder.read_i32()?,
+                    log_start_offset: decoder.read_i64()?,
+                    partition_max_bytes: decoder.read_i32()?,
+                });
+                decoder.skip_tagged_fields()?;
+            }
+            decoder.skip_tagged_fields()?;
+            topics.push(FetchTopic { topic_id, partitions });
+        }
+
+        // forgotten_topics_data only matters for incremental fetch sessions
+        let forgotten_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        for _ in 0..forgotten_count {
+            decoder.read_uuid()?;
+            decoder.read_compact_i32_array()?;
+            decoder.skip_tagged_fields()?;
+        }
+
+        let rack_id = decoder.read_compact_string()?;
+
+        let mut replica_id = -1;
+        for (tag, data) in decoder.read_tagged_fields()? {
+            if tag == FETCH_REPLICA_STATE_TAG {
+                replica_id = RequestDecoder::new(data).read_i32()?;
+            }
+        }
+
+        Ok(FetchRequest {
+            replica_id,
+            max_wait_ms,
+            min_bytes,
+            max_bytes,
+            isolation_level,
+            session_id,
+            session_epoch,
+            topics,
+            rack_id,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.max_wait_ms.to_be_bytes());
+        body.extend_from_slice(&self.min_bytes.to_be_bytes());
+        body.extend_from_slice(&self.max_bytes.to_be_bytes());
+        body.push(self.isolation_level as u8);
+        body.extend_from_slice(&self.session_id.to_be_bytes());
+        body.extend_from_slice(&self.session_epoch.to_be_bytes());
+
+        put_compact_array_len(&mut body, self.topics.len());
+        for topic in &self.topics {
+            body.extend_from_slice(topic.topic_id.as_bytes());
+            put_compact_array_len(&mut body, topic.partitions.len());
+            for partition in &topic.partitions {
+                body.extend_from_slice(&partition.partition.to_be_bytes());
+                body.extend_from_slice(&partition.current_leader_epoch.to_be_bytes());
+                body.extend_from_slice(&partition.fetch_offset.to_be_bytes());
+                body.extend_from_slice(&partition.last_fetched_epoch.to_be_bytes());
+                body.extend_from_slice(&partition.log_start_offset.to_be_bytes());
+                body.extend_from_slice(&partition.partition_max_bytes.to_be_bytes());
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        put_compact_array_len(&mut body, 0); // forgotten_topics_data
+        put_compact_string(&mut body, &self.rack_id);
+
+        if self.replica_id >= 0 {
+            // ReplicaState { replica_id, replica_epoch, tag_buffer }
+            let mut replica_state = Vec::new();
+            replica_state.extend_from_slice(&self.replica_id.to_be_bytes());
+            replica_state.extend_from_slice(&(-1i64).to_be_bytes());
+            replica_state.push(0x00);
+
+            put_unsigned_varint(&mut body, 1);
+            put_unsigned_varint(&mut body, FETCH_REPLICA_STATE_TAG);
+            put_unsigned_varint(&mut body, replica_state.len() as u32);
+            body.extend_from_slice(&replica_state);
+        } else {
+            body.push(0x00);
+        }
+        body
+    }
+}
+
+// OffsetForLeaderEpoch v4; followers send it before fetching to find where their log diverged
+#[derive(Debug)]
+pub struct OffsetForLeaderEpochRequest {
+    pub replica_id: i32, // -1 for consumers
+    pub topics: Vec<OffsetForLeaderTopic>,
+}
+
+#[derive(Debug)]
+pub struct OffsetForLeaderTopic {
+    pub topic: String,
+    pub partitions: Vec<OffsetForLeaderPartition>,
+}
+
+#[derive(Debug)]
+pub struct OffsetForLeaderPartition {
+    pub partition: i32,
+    pub current_leader_epoch: i32,
+    pub leader_epoch: i32,
+}
+
+impl OffsetForLeaderEpochRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let replica_id = decoder.read_i32()?;
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let topic = decoder.read_compact_string()?;
+            let partition_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            let mut partitions = Vec::new();
+            for _ in 0..partition_count {
+                partitions.push(OffsetForLeaderPartition {
+                    partition: decoder.read_i32()?,
+                    current_leader_epoch: decoder.read_i32()?,
+                    leader_epoch: decoder.read_i32()?,
+                });
+                decoder.skip_tagged_fields()?;
+            }
+            decoder.skip_tagged_fields()?;
+            topics.push(OffsetForLeaderTopic { topic, partitions });
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(OffsetForLeaderEpochRequest { replica_id, topics })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.replica_id.to_be_bytes());
+
+        put_compact_array_len(&mut body, self.topics.len());
+        for topic in &self.topics {
+            put_compact_string(&mut body, &topic.topic);
+            put_compact_array_len(&mut body, topic.partitions.len());
+            for partition in &topic.partitions {
+                body.extend_from_slice(&partition.partition.to_be_bytes());
+                body.extend_from_slice(&partition.current_leader_epoch.to_be_bytes());
+                body.extend_from_slice(&partition.leader_epoch.to_be_bytes());
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        body.push(0x00);
+        body
+    }
+}
+
+// LeaderAndIsr v5; the controller pushes partition leadership and ISRs to brokers
+#[derive(Debug)]
+pub struct LeaderAndIsrRequest {
+    pub controller_id: i32,
+    pub controller_epoch: i32,
+    pub broker_epoch: i64,
+    pub partition_states: Vec<PartitionState>, // grouped by topic on the wire
+    pub live_leaders: Vec<(i32, String)>, // broker id -> host:port
+}
+
+impl LeaderAndIsrRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let controller_id = decoder.read_i32()?;
+        let controller_epoch = decoder.read_i32()?;
+        let broker_epoch = decoder.read_i64()?;
+        let _request_type = decoder.read_i8()?;
+
+        let mut partition_states = Vec::new();
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        for _ in 0..topic_count {
+            let topic = decoder.read_compact_string()?;
+            let topic_id = decoder.read_uuid()?;
+            let partition_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            for _ in 0..partition_count {
+                let partition = decoder.read_i32()?;
+                let _controller_epoch = decoder.read_i32()?;
+                let leader = decoder.read_i32()?;
+                let leader_epoch = decoder.read_i32()?;
+                let isr = decoder.read_compact_i32_array()?;
+                let _partition_epoch = decoder.read_i32()?;
+                let replicas = decoder.read_compact_i32_array()?;
+                let adding_replicas = decoder.read_compact_i32_array()?;
+                let removing_replicas = decoder.read_compact_i32_array()?;
+                let _is_new = decoder.read_i8()?;
+                decoder.skip_tagged_fields()?;
+                partition_states.push(PartitionState {
+                    topic: topic.clone(),
+                    topic_id,
+                    partition,
+                    leader,
+                    leader_epoch,
+                    isr,
+                    replicas,
+                    adding_replicas,
+                    removing_replicas,
+                });
+            }
+            decoder.skip_tagged_fields()?;
+        }
+
+        let leader_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut live_leaders = Vec::new();
+        for _ in 0..leader_count {
+            let broker_id = decoder.read_i32()?;
+            let host = decoder.read_compact_string()?;
+            let port = decoder.read_i32()?;
+            decoder.skip_tagged_fields()?;
+            live_leaders.push((broker_id, format!("{}:{}", host, port)));
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(LeaderAndIsrRequest { controller_id, controller_epoch, broker_epoch, partition_states, live_leaders })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.controller_id.to_be_bytes());
+        body.extend_from_slice(&self.controller_epoch.to_be_bytes());
+        body.extend_from_slice(&self.broker_epoch.to_be_bytes());
+        body.push(0x00); // type, always an incremental update
+
+        let mut by_topic: Vec<(&str, Uuid, Vec<&PartitionState>)> = Vec::new();
+        for state in &self.partition_states {
+            match by_topic.iter_mut().find(|(topic, _, _)| *topic == state.topic) {
+                Some((_, _, states)) => states.push(state),
+                None => by_topic.push((&state.topic, state.topic_id, vec![state])),
+            }
+        }
+        put_compact_array_len(&mut body, by_topic.len());
+        for (topic, topic_id, states) in by_topic {
+            put_compact_string(&mut body, topic);
+            body.extend_from_slice(topic_id.as_bytes());
+            put_compact_array_len(&mut body, states.len());
+            for state in states {
+                body.extend_from_slice(&state.partition.to_be_bytes());
+                body.extend_from_slice(&self.controller_epoch.to_be_bytes());
+                body.extend_from_slice(&state.leader.to_be_bytes());
+                body.extend_from_slice(&state.leader_epoch.to_be_bytes());
+                put_compact_i32_array(&mut body, &state.isr);
+                body.extend_from_slice(&0i32.to_be_bytes()); // partition_epoch, unused
+                put_compact_i32_array(&mut body, &state.replicas);
+                put_compact_i32_array(&mut body, &state.adding_replicas);
+                put_compact_i32_array(&mut body, &state.removing_replicas);
+                body.push(0x00); // is_new
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        put_compact_array_len(&mut body, self.live_leaders.len());
+        for (broker_id, address) in &self.live_leaders {
+            let (host, port) = address.rsplit_once(':').unwrap_or((address.as_str(), "0"));
+            body.extend_from_slice(&broker_id.to_be_bytes());
+            put_compact_string(&mut body, host);
+            body.extend_from_slice(&port.parse::<i32>().unwrap_or(0).to_be_bytes());
+            body.push(0x00); // tag_buffer
+        }
+
+        body.push(0x00);
+        body
+    }
+}
+
+// AlterPartition v0; a leader asks the controller to commit its new ISR
+#[derive(Debug)]
+pub struct AlterPartitionRequest {
+    pub broker_id: i32,
+    pub broker_epoch: i64,
+    pub topics: Vec<AlterPartitionTopic>,
+}
+
+#[derive(Debug)]
+pub struct AlterPartitionTopic {
+    pub topic: String,
+    pub partitions: Vec<AlterPartitionData>,
+}
+
+#[derive(Debug)]
+pub struct AlterPartitionData {
+    pub partition: i32,
+    pub leader_epoch: i32,
+    pub new_isr: Vec<i32>,
+}
+
+impl AlterPartitionRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let broker_id = decoder.read_i32()?;
+        let broker_epoch = decoder.read_i64()?;
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let topic = decoder.read_compact_string()?;
+            let partition_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            let mut partitions = Vec::new();
+            for _ in 0..partition_count {
+                let partition = decoder.read_i32()?;
+                let leader_epoch = decoder.read_i32()?;
+                let new_isr = decoder.read_compact_i32_array()?;
+                let _partition_epoch = decoder.read_i32()?;
+                decoder.skip_tagged_fields()?;
+                partitions.push(AlterPartitionData { partition, leader_epoch, new_isr });
+            }
+            decoder.skip_tagged_fields()?;
+            topics.push(AlterPartitionTopic { topic, partitions });
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(AlterPartitionRequest { broker_id, broker_epoch, topics })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.broker_id.to_be_bytes());
+        body.extend_from_slice(&self.broker_epoch.to_be_bytes());
+
+        put_compact_array_len(&mut body, self.topics.len());
+        for topic in &self.topics {
+            put_compact_string(&mut body, &topic.topic);
+            put_compact_array_len(&mut body, topic.partitions.len());
+            for partition in &topic.partitions {
+                body.extend_from_slice(&partition.partition.to_be_bytes());
+                body.extend_from_slice(&partition.leader_epoch.to_be_bytes());
+                put_compact_i32_array(&mut body, &partition.new_isr);
+                body.extend_from_slice(&0i32.to_be_bytes()); // partition_epoch, unused
+                body.push(0x00); // tag_buffer
+            }
+            body.push(0x00); // tag_buffer
+        }
+
+        body.push(0x00);
+        body
+    }
+}
+
+// AllocateProducerIds v0; a broker asking the controller for producer ids to hand out
+#[derive(Debug)]
+pub struct AllocateProducerIdsRequest {
+    pub broker_id: i32,
+    pub broker_epoch: i64,
+}
+
+impl AllocateProducerIdsRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let broker_id = decoder.read_i32()?;
+        let broker_epoch = decoder.read_i64()?;
+        decoder.skip_tagged_fields()?;
+
+        Ok(AllocateProducerIdsRequest { broker_id, broker_epoch })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.broker_id.to_be_bytes());
+        body.extend_from_slice(&self.broker_epoch.to_be_bytes());
+        body.push(0x00);
+        body
+    }
+}
+
+// BrokerRegistration v2; a broker announcing itself to the controller on startup
+#[derive(Debug)]
+pub struct BrokerRegistrationRequest {
+    pub broker_id: i32,
+    pub cluster_id: String,
+    pub incarnation_id: Uuid,
+    pub listeners: Vec<BrokerEndpoint>,
+    pub rack: Option<String>,
+    pub log_dirs: Vec<Uuid>,
+}
+
+impl BrokerRegistrationRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let broker_id = decoder.read_i32()?;
+        let cluster_id = decoder.read_compact_string()?;
+        let incarnation_id = decoder.read_uuid()?;
+
+        let listener_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut listeners = Vec::new();
+        for _ in 0..listener_count {
+            let name = decoder.read_compact_string()?;
+            let host = decoder.read_compact_string()?;
+            let port = decoder.read_i16()? as u16;
+            let security_protocol = decoder.read_i16()?;
+            decoder.skip_tagged_fields()?;
+            listeners.push(BrokerEndpoint { name, host, port, security_protocol });
+        }
+
+        // supported features aren't negotiated yet
+        let feature_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        for _ in 0..feature_count {
+            let _name = decoder.read_compact_string()?;
+            let _min_supported_version = decoder.read_i16()?;
+            let _max_supported_version = decoder.read_i16()?;
+            decoder.skip_tagged_fields()?;
+        }
+
+        let rack = decoder.read_compact_nullable_string()?;
+        let _is_migrating_zk_broker = decoder.read_i8()?;
+        let log_dir_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut log_dirs = Vec::new();
+        for _ in 0..log_dir_count {
+            log_dirs.push(decoder.read_uuid()?);
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(BrokerRegistrationRequest { broker_id, cluster_id, incarnation_id, listeners, rack, log_dirs })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.broker_id.to_be_bytes());
+        put_compact_string(&mut body, &self.cluster_id);
+        body.extend_from_slice(self.incarnation_id.as_bytes());
+
+        put_compact_array_len(&mut body, self.listeners.len());
+        for listener in &self.listeners {
+            put_compact_string(&mut body, &listener.name);
+            put_compact_string(&mut body, &listener.host);
+            body.extend_from_slice(&listener.port.to_be_bytes());
+            body.extend_from_slice(&listener.security_protocol.to_be_bytes());
+            body.push(0x00); // tag_buffer
+        }
+        put_compact_array_len(&mut body, 0); // features
+
+        put_compact_nullable_string(&mut body, self.rack.as_deref());
+        body.push(0); // is_migrating_zk_broker
+        put_compact_array_len(&mut body, self.log_dirs.len());
+        for log_dir in &self.log_dirs {
+            body.extend_from_slice(log_dir.as_bytes());
+        }
+
+        body.push(0x00);
+        body
+    }
+}
+
+// BrokerHeartbeat v1; keeps a registered broker's session alive
+#[derive(Debug)]
+pub struct BrokerHeartbeatRequest {
+    pub broker_id: i32,
+    pub broker_epoch: i64,
+    pub current_metadata_offset: i64,
+    pub want_fence: bool,
+    pub want_shutdown: bool,
+}
+
+impl BrokerHeartbeatRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let broker_id = decoder.read_i32()?;
+        let broker_epoch = decoder.read_i64()?;
+        let current_metadata_offset = decoder.read_i64()?;
+        let want_fence = decoder.read_i8()? != 0;
+        let want_shutdown = decoder.read_i8()? != 0;
+        // offline_log_dirs comes as a tagged field; every log dir counts as online
+        decoder.skip_tagged_fields()?;
+
+        Ok(BrokerHeartbeatRequest { broker_id, broker_epoch, current_metadata_offset, want_fence, want_shutdown })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.broker_id.to_be_bytes());
+        body.extend_from_slice(&self.broker_epoch.to_be_bytes());
+        body.extend_from_slice(&self.current_metadata_offset.to_be_bytes());
+        body.push(self.want_fence as u8);
+        body.push(self.want_shutdown as u8);
+
+        body.push(0x00);
+        body
+    }
+}
+
+// AlterPartitionReassignments v0
+#[derive(Debug)]
+pub struct AlterPartitionReassignmentsRequest {
+    pub timeout_ms: i32,
+    pub topics: Vec<ReassignableTopic>,
+}
+
+#[derive(Debug)]
+pub struct ReassignableTopic {
+    pub name: String,
+    pub partitions: Vec<(i32, Option<Vec<i32>>)>, // target replicas, None cancels
+}
+
+impl AlterPartitionReassignmentsRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let timeout_ms = decoder.read_i32()?;
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let name = decoder.read_compact_string()?;
+            let partition_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            let mut partitions = Vec::new();
+            for _ in 0..partition_count {
+                let partition_index = decoder.read_i32()?;
+                let replicas = decoder.read_compact_nullable_i32_array()?;
+                decoder.skip_tagged_fields()?;
+                partitions.push((partition_index, replicas));
+            }
+            decoder.skip_tagged_fields()?;
+            topics.push(ReassignableTopic { name, partitions });
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(AlterPartitionReassignmentsRequest { timeout_ms, topics })
+    }
+}
+
+// ListPartitionReassignments v0
+#[derive(Debug)]
+pub struct ListPartitionReassignmentsRequest {
+    pub timeout_ms: i32,
+    pub topics: Option<Vec<(String, Vec<i32>)>>, // None lists every ongoing reassignment
+}
+
+impl ListPartitionReassignmentsRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let timeout_ms = decoder.read_i32()?;
+
+        let topics = match decoder.read_compact_array_len()? {
+            Some(topic_count) => {
+                let mut topics = Vec::new();
+                for _ in 0..topic_count {
+                    let name = decoder.read_compact_string()?;
+                    let partition_indexes = decoder.read_compact_i32_array()?;
+                    decoder.skip_tagged_fields()?;
+                    topics.push((name, partition_indexes));
+                }
+                Some(topics)
+            }
+            None => None,
+        };
+        decoder.skip_tagged_fields()?;
+
+        Ok(ListPartitionReassignmentsRequest { timeout_ms, topics })
+    }
+}
+
+// ElectLeaders v2
+#[derive(Debug)]
+pub struct ElectLeadersRequest {
+    pub election_type: i8,
+    pub topic_partitions: Option<Vec<(String, Vec<i32>)>>, // None elects for every partition
+    pub timeout_ms: i32,
+}
+
+impl ElectLeadersRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let election_type = decoder.read_i8()?;
+
+        let topic_partitions = match decoder.read_compact_array_len()? {
+            Some(topic_count) => {
+                let mut topics = Vec::new();
+                for _ in 0..topic_count {
+                    let topic = decoder.read_compact_string()?;
+                    let partitions = decoder.read_compact_i32_array()?;
+                    decoder.skip_tagged_fields()?;
+                    topics.push((topic, partitions));
+                }
+                Some(topics)
+            }
+            None => None,
+        };
+        let timeout_ms = decoder.read_i32()?;
+        decoder.skip_tagged_fields()?;
+
+        Ok(ElectLeadersRequest { election_type, topic_partitions, timeout_ms })
+    }
+}
+
+// InitProducerId v4
+#[derive(Debug)]
+pub struct InitProducerIdRequest {
+    pub transactional_id: Option<String>,
+    pub transaction_timeout_ms: i32,
+    pub producer_id: i64, // -1 unless an existing producer wants its epoch bumped
+    pub producer_epoch: i16,
+}
+
+impl InitProducerIdRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let transactional_id = decoder.read_compact_nullable_string()?;
+        let transaction_timeout_ms = decoder.read_i32()?;
+        let producer_id = decoder.read_i64()?;
+        let producer_epoch = decoder.read_i16()?;
+        decoder.skip_tagged_fields()?;
+
+        Ok(InitProducerIdRequest { transactional_id, transaction_timeout_ms, producer_id, producer_epoch })
+    }
+}
+
+// AddPartitionsToTxn v3
+#[derive(Debug)]
+pub struct AddPartitionsToTxnRequest {
+    pub transactional_id: String,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub topics: Vec<(String, Vec<i32>)>,
+}
+
+impl AddPartitionsToTxnRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let transactional_id = decoder.read_compact_string()?;
+        let producer_id = decoder.read_i64()?;
+        let producer_epoch = decoder.read_i16()?;
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let name = decoder.read_compact_string()?;
+            let partitions = decoder.read_compact_i32_array()?;
+            decoder.skip_tagged_fields()?;
+            topics.push((name, partitions));
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(AddPartitionsToTxnRequest { transactional_id, producer_id, producer_epoch, topics })
+    }
+}
+
+// AddOffsetsToTxn v3
+#[derive(Debug)]
+pub struct AddOffsetsToTxnRequest {
+    pub transactional_id: String,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub group_id: String,
+}
+
+impl AddOffsetsToTxnRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let transactional_id = decoder.read_compact_string()?;
+        let producer_id = decoder.read_i64()?;
+        let producer_epoch = decoder.read_i16()?;
+        let group_id = decoder.read_compact_string()?;
+        decoder.skip_tagged_fields()?;
+
+        Ok(AddOffsetsToTxnRequest { transactional_id, producer_id, producer_epoch, group_id })
+    }
+}
+
+// EndTxn v3
+#[derive(Debug)]
+pub struct EndTxnRequest {
+    pub transactional_id: String,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub committed: bool, // false aborts
+}
+
+impl EndTxnRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let transactional_id = decoder.read_compact_string()?;
+        let producer_id = decoder.read_i64()?;
+        let producer_epoch = decoder.read_i16()?;
+        let committed = decoder.read_i8()? != 0;
+        decoder.skip_tagged_fields()?;
+
+        Ok(EndTxnRequest { transactional_id, producer_id, producer_epoch, committed })
+    }
+}
+
+// WriteTxnMarkers v1; a transaction coordinator asks partition leaders to end a transaction
+#[derive(Debug)]
+pub struct WriteTxnMarkersRequest {
+    pub markers: Vec<WritableTxnMarker>,
+}
+
+#[derive(Debug)]
+pub struct WritableTxnMarker {
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub committed: bool,
+    pub topics: Vec<(String, Vec<i32>)>,
+    pub coordinator_epoch: i32,
+}
+
+impl WriteTxnMarkersRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let marker_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut markers = Vec::new();
+        for _ in 0..marker_count {
+            let producer_id = decoder.read_i64()?;
+            let producer_epoch = decoder.read_i16()?;
+            let committed = decoder.read_i8()? != 0;
+            let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            let mut topics = Vec::new();
+            for _ in 0..topic_count {
+                let name = decoder.read_compact_string()?;
+                let partitions = decoder.read_compact_i32_array()?;
+                decoder.skip_tagged_fields()?;
+                topics.push((name, partitions));
+            }
+            let coordinator_epoch = decoder.read_i32()?;
+            decoder.skip_tagged_fields()?;
+            markers.push(WritableTxnMarker { producer_id, producer_epoch, committed, topics, coordinator_epoch });
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(WriteTxnMarkersRequest { markers })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        put_compact_array_len(&mut body, self.markers.len());
+        for marker in &self.markers {
+            body.extend_from_slice(&marker.producer_id.to_be_bytes());
+            body.extend_from_slice(&marker.producer_epoch.to_be_bytes());
+            body.push(marker.committed as u8);
+            put_compact_array_len(&mut body, marker.topics.len());
+            for (name, partitions) in &marker.topics {
+                put_compact_string(&mut body, name);
+                put_compact_i32_array(&mut body, partitions);
+                body.push(0x00); // tag_buffer
+            }
+            body.extend_from_slice(&marker.coordinator_epoch.to_be_bytes());
+            body.push(0x00); // tag_buffer
+        }
+
+        body.push(0x00);
+        body
+    }
+}
+
+// TxnOffsetCommit v3
+#[derive(Debug)]
+pub struct TxnOffsetCommitRequest {
+    pub transactional_id: String,
+    pub group_id: String,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub generation_id: i32,
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+    pub topics: Vec<TxnOffsetCommitTopic>,
+}
+
+#[derive(Debug)]
+pub struct TxnOffsetCommitTopic {
+    pub name: String,
+    pub partitions: Vec<TxnOffsetCommitPartition>,
+}
+
+#[derive(Debug)]
+pub struct TxnOffsetCommitPartition {
+    pub partition_index: i32,
+    pub committed_offset: i64,
+    pub committed_leader_epoch: i32,
+    pub committed_metadata: Option<String>,
+}
+
+impl TxnOffsetCommitRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+        let transactional_id = decoder.read_compact_string()?;
+        let group_id = decoder.read_compact_string()?;
+        let producer_id = decoder.read_i64()?;
+        let producer_epoch = decoder.read_i16()?;
+        let generation_id = decoder.read_i32()?;
+        let member_id = decoder.read_compact_string()?;
+        let group_instance_id = decoder.read_compact_nullable_string()?;
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let name = decoder.read_compact_string()?;
+            let partition_count = decoder.read_compact_array_len()?.unwrap_or(0);
+            let mut partitions = Vec::new();
+            for _ in 0..partition_count {
+                let partition_index = decoder.read_i32()?;
+                let committed_offset = decoder.read_i64()?;
+                let committed_leader_epoch = decoder.read_i32()?;
+                let committed_metadata = decoder.read_compact_nullable_string()?;
+                decoder.skip_tagged_fields()?;
+                partitions.push(TxnOffsetCommitPartition { partition_index, committed_offset, committed_leader_epoch, committed_metadata });
+            }
+            decoder.skip_tagged_fields()?;
+            topics.push(TxnOffsetCommitTopic { name, partitions });
+        }
+        decoder.skip_tagged_fields()?;
+
+        Ok(TxnOffsetCommitRequest {
+            transactional_id,
+            group_id,
+            producer_id,
+            producer_epoch,
+            generation_id,
+            member_id,
+            group_instance_id,
+            topics,
+        })
+    }
+}
+
+// CreateTopics v7
+#[derive(Debug)]
+pub struct CreateTopicsRequest {
+    pub topics: Vec<CreatableTopic>,
+    pub timeout_ms: i32,
+    pub validate_only: bool,
+}
+
+#[derive(Debug)]
+pub struct CreatableTopic {
+    pub name: String,
+    pub num_partitions: i32,     // -1 for the broker default or a manual assignment
+    pub replication_factor: i16, // same
+    pub assignments: Vec<(i32, Vec<i32>)>, // partition -> replicas
+    pub configs: Vec<(String, Option<String>)>,
+}
+
+impl CreateTopicsRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let name = decoder.read_compact_string()?;
+            topics.push(Self::read_topic(&mut decoder, name)?);
+        }
+        let timeout_ms = decoder.read_i32()?;
+        let validate_only = decoder.read_i8()? != 0;
+        decoder.skip_tagged_fields()?;
+
+        Ok(CreateTopicsRequest { topics, timeout_ms, validate_only })
+    }
+
+    /// the topic names of a request that doesn't parse, up to where it breaks off,
+    /// so each of them can be answered with an error
+    pub fn topic_names(body: &[u8]) -> Vec<String> {
+        let mut decoder = RequestDecoder::new(body);
+        let mut names = Vec::new();
+        let topic_count = decoder.read_compact_array_len().ok().flatten().unwrap_or(0);
+        for _ in 0..topic_count {
+            let Ok(name) = decoder.read_compact_string() else { break };
+            let complete = Self::read_topic(&mut decoder, name.clone()).is_ok();
+            names.push(name);
+            if !complete {
+                break;
+            }
+        }
+        names
+    }
+
+    // everything of a topic after its name
+    fn read_topic(decoder: &mut RequestDecoder, name: String) -> Result<CreatableTopic, ServerError> {
+        let num_partitions = decoder.read_i32()?;
+        let replication_factor = decoder.read_i16()?;
+
+        let assignment_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut assignments = Vec::new();
+        for _ in 0..assignment_count {
+            let partition_index = decoder.read_i32()?;
+            let broker_ids = decoder.read_compact_i32_array()?;
+            decoder.skip_tagged_fields()?;
+            assignments.push((partition_index, broker_ids));
+        }
+
+        let config_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut configs = Vec::new();
+        for _ in 0..config_count {
+            let config_name = decoder.read_compact_string()?;
+            let value = decoder.read_compact_nullable_string()?;
+            decoder.skip_tagged_fields()?;
+            configs.push((config_name, value));
+        }
+        decoder.skip_tagged_fields()?;
+        Ok(CreatableTopic { name, num_partitions, replication_factor, assignments, configs })
+    }
+}
+
+// DeleteTopics v6; topics are named or given by id
+#[derive(Debug)]
+pub struct DeleteTopicsRequest {
+    pub topics: Vec<(Option<String>, Uuid)>,
+    pub timeout_ms: i32,
+}
+
+impl DeleteTopicsRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let name = decoder.read_compact_nullable_string()?;
+            let topic_id = decoder.read_uuid()?;
+            decoder.skip_tagged_fields()?;
+            topics.push((name, topic_id));
+        }
+        let timeout_ms = decoder.read_i32()?;
+        decoder.skip_tagged_fields()?;
+
+        Ok(DeleteTopicsRequest { topics, timeout_ms })
+    }
+
+    /// the topics of a request that doesn't parse, up to where it breaks off. A topic
+    /// whose id was cut off gets the nil id.
+    pub fn topic_names(body: &[u8]) -> Vec<(Option<String>, Uuid)> {
+        let mut decoder = RequestDecoder::new(body);
+        let mut topics = Vec::new();
+        let topic_count = decoder.read_compact_array_len().ok().flatten().unwrap_or(0);
+        for _ in 0..topic_count {
+            let Ok(name) = decoder.read_compact_nullable_string() else { break };
+            let topic_id = decoder.read_uuid();
+            let complete = topic_id.is_ok() && decoder.skip_tagged_fields().is_ok();
+            topics.push((name, topic_id.unwrap_or(Uuid::nil())));
+            if !complete {
+                break;
+            }
+        }
+        topics
+    }
+}
+
+// CreatePartitions v3
+#[derive(Debug)]
+pub struct CreatePartitionsRequest {
+    pub topics: Vec<CreatePartitionsTopic>,
+    pub timeout_ms: i32,
+    pub validate_only: bool,
+}
+
+#[derive(Debug)]
+pub struct CreatePartitionsTopic {
+    pub name: String,
+    pub count: i32,                          // partitions the topic should end up with
+    pub assignments: Option<Vec<Vec<i32>>>, // replicas of each new partition
+}
+
+impl CreatePartitionsRequest {
+    pub fn parse(body: &[u8]) -> Result<Self, ServerError> {
+        let mut decoder = RequestDecoder::new(body);
+
+        let topic_count = decoder.read_compact_array_len()?.unwrap_or(0);
+        let mut topics = Vec::new();
+        for _ in 0..topic_count {
+            let name = decoder.read_compact_string()?;
+            topics.push(Self::read_topic(&mut decoder, name)?);
+        }
+        let timeout_ms = decoder.read_i32()?;
+        let validate_only = decoder.read_i8()? != 0;
+        decoder.skip_tagged_fields()?;
+
+        Ok(CreatePartitionsRequest { topics, timeout_ms, validate_only })
+    }
+
+    /// the topic names of a request that doesn't parse, up to where it breaks off
+    pub fn topic_names(body: &[u8]) -> Vec<String> {
+        let mut decoder = RequestDecoder::new(body);
+        let mut names = Vec::new();
+        let topic_count = decoder.read_compact_array_len().ok().flatten().unwrap_or(0);
+        for _ in 0..topic_count {
+            let Ok(name) = decoder.read_compact_string() else { break };
+            let complete = Self::read_topic(&mut decoder, name.clone()).is_ok();
+            names.push(name);
+            if !complete {
+                break;
+            }
+        }
+        names
+    }
+
+    // everything of a topic after its name
+    fn read_topic(decoder: &mut RequestDecoder, name: String) -> Result<CreatePartitionsTopic, ServerError> {
+        let count = decoder.read_i32()?;
+        let assignments = match decoder.read_compact_array_len()? {
+            Some(assignment_count) => {
+                let mut assignments = Vec::new();
+                for _ in 0..assignment_count {
+                    assignments.push(decoder.read_compact_i32_array()?);
+                    decoder.skip_tagged_fields()?;
+                }
+                Some(assignments)
+            }
+            None => None,
+        };
+        decoder.skip_tagged_fields()?;
+        Ok(CreatePartitionsTopic { name, count, assignments })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_string(buf: &mut Vec<u8>, value: &str) {
+        buf.extend_from_slice(&(value.len() as i16).to_be_bytes());
+        buf.extend_from_slice(value.as_bytes());
+    }
+
+    #[test]
+    fn array_count_beyond_request_is_rejected() {
+        let mut body = Vec::new();
+        put_unsigned_varint(&mut body, u32::MAX);
+        let mut decoder = RequestDecoder::new(&body);
+        assert!(matches!(decoder.read_compact_array_len(), Err(ServerError::MalformedRequest(_))));
+
+        let mut body = Vec::new();
+        put_string(&mut body, "group");
+        body.extend_from_slice(&i32::MAX.to_be_bytes());
+        assert!(matches!(OffsetDeleteRequest::parse(&body), Err(ServerError::MalformedRequest(_))));
+
+        let mut body = Vec::new();
+        put_unsigned_varint(&mut body, u32::MAX);
+        body.extend_from_slice(&30_000i32.to_be_bytes());
+        assert!(matches!(DeleteTopicsRequest::parse(&body), Err(ServerError::MalformedRequest(_))));
+    }
+
+    #[test]
+    fn offset_delete_parses() {
+        let mut body = Vec::new();
+        put_string(&mut body, "group");
+        body.extend_from_slice(&1i32.to_be_bytes());
+        put_string(&mut body, "orders");
+        body.extend_from_slice(&2i32.to_be_bytes());
+        body.extend_from_slice(&0i32.to_be_bytes());
+        body.extend_from_slice(&3i32.to_be_bytes());
+
+        let request = OffsetDeleteRequest::parse(&body).unwrap();
+        assert_eq!(request.group_id, "group");
+        assert_eq!(request.topics, vec![("orders".to_string(), vec![0, 3])]);
+    }
+}
@@ -1,26 +1,43 @@
+use std::sync::Arc;
+
 use tokio::net::{TcpListener, TcpStream};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::task;
 
 use crate::{
     constants::MAX_MESSAGE_SIZE,
+    core::broker::Broker,
     error::ServerError,
     network::protocol::{KafkaProtocolHandler, KafkaRequest},
-    network::handler::MessageParser,
+    network::handler::{MessageParser, RequestDecoder},
 };
 
+// api key, api version and correlation id
+const REQUEST_HEADER_MIN_SIZE: i32 = 8;
+
+#[derive(Clone)]
 pub struct KafkaServer {
     address: String,
+    broker: Arc<Broker>,
+    inter_broker: bool, // serves the APIs brokers and the controller send each other
 }
 
 impl KafkaServer {
-    pub fn new(address: &str) -> Result<Self, std::io::Error> {
+    pub fn new(address: &str, broker: Arc<Broker>) -> Result<Self, std::io::Error> {
         println!("Server bound to {}", address);
-        Ok(KafkaServer { address: address.to_string() })
+        Ok(KafkaServer { address: address.to_string(), broker, inter_broker: false })
+    }
+
+    /// makes this the listener other brokers and the controller connect to. Only it
+    /// serves LeaderAndIsr, AlterPartition and the other inter-broker APIs.
+    pub fn with_inter_broker_apis(mut self) -> Self {
+        self.inter_broker = true;
+        self
     }
 
     fn validate_message_size(&self, size: i32) -> Result<(), ServerError> {
-        if size <= 0 {
+        // at least the api key, api version and correlation id of the header
+        if size < REQUEST_HEADER_MIN_SIZE {
             return Err(ServerError::InvalidMessageSize(size));
         }
         if size as usize > MAX_MESSAGE_SIZE {
@@ -29,7 +46,7 @@ impl KafkaServer {
         Ok(())
     }
 
-    async fn read_request(&self, stream: &mut TcpStream) -> Result<KafkaRequest, ServerError> {
+    async fn read_request(&self, stream: &mut TcpStream, client_host: &str) -> Result<KafkaRequest, ServerError> {
         let message_size = MessageParser::read_i32_async(stream).await?;
         self.validate_message_size(message_size)?;
 
@@ -37,17 +54,29 @@ impl KafkaServer {
         let api_version = MessageParser::read_i16_async(stream).await?;
         let correlation_id = MessageParser::read_i32_async(stream).await?;
 
-        // Read and discard remaining bytes to consume the entire request
-        let remaining_size = message_size as usize - 8; // 8 bytes already read
-        if remaining_size > 0 {
-            let mut remaining = vec![0; remaining_size];
-            stream.read_exact(&mut remaining).await?;
+        // Read the rest of the request: client_id, optional header tags, then the body
+        // grown as the bytes arrive, so a size that's never sent allocates nothing
+        let remaining_size = (message_size - REQUEST_HEADER_MIN_SIZE) as usize; // already read
+        let mut remaining = Vec::new();
+        (&mut *stream).take(remaining_size as u64).read_to_end(&mut remaining).await?;
+        if remaining.len() < remaining_size {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
         }
 
+        let mut decoder = RequestDecoder::new(&remaining);
+        let client_id = decoder.read_nullable_string()?;
+        if KafkaProtocolHandler::is_flexible(api_key, api_version) {
+            decoder.skip_tagged_fields()?;
+        }
+        let body = decoder.remaining().to_vec();
+
         Ok(KafkaRequest {
             api_key,
             api_version,
             correlation_id,
+            client_id,
+            client_host: client_host.to_string(),
+            body,
         })
     }
 
@@ -56,14 +85,28 @@ impl KafkaServer {
             eprintln!("Failed to get peer address: {}", e);
             "0.0.0.0:0".parse().unwrap()
         });
+        let client_host = format!("/{}", peer_addr.ip());
 
         loop {
-            match self.read_request(&mut stream).await {
+            match self.read_request(&mut stream, &client_host).await {
                 Ok(request) => {
-                    println!("Processing request from {}: {:?}", peer_addr, request);
+                    println!(
+                        "Processing request from {}: api_key={} api_version={} correlation_id={}",
+                        peer_addr, request.api_key, request.api_version, request.correlation_id
+                    );
+                    if !self.inter_broker && KafkaProtocolHandler::is_inter_broker_api(request.api_key) {
+                        eprintln!("Closing connection from {}: api_key={} is only served to brokers", peer_addr, request.api_key);
+                        break;
+                    }
+
+                    let response = match KafkaProtocolHandler::process_request(&request, &self.broker).await {
+                        Ok(response) => response,
+                        Err(e) => {
+                            eprintln!("Closing connection from {}: {}", peer_addr, e);
+                            break;
+                        }
+                    };
 
-                    let response = KafkaProtocolHandler::process_request(&request);
-                    
                     if !response.is_empty() {
                         stream.write_all(&response).await?;
                         println!("Response sent to {} for correlation ID: {}", peer_addr, request.correlation_id);
@@ -83,7 +126,7 @@ impl KafkaServer {
     }
 
     pub async fn run(&self) -> Result<(), std::io::Error> {
-        println!("Starting Kafka server on port 9092");
+        println!("Starting Kafka server on {}", self.address);
 
         let listener = TcpListener::bind(&self.address).await?;
 
@@ -91,9 +134,7 @@ impl KafkaServer {
             let (stream, addr) = listener.accept().await?;
             println!("New client connected from: {}", addr);
 
-            let server_clone = KafkaServer {
-                address: self.address.clone(),
-            };
+            let server_clone = self.clone();
 
             task::spawn(async move {
                 if let Err(e) = server_clone.handle_client(stream).await {
@@ -102,4 +143,94 @@ impl KafkaServer {
             });
         }
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{API_KEY_API_VERSIONS, API_KEY_DESCRIBE_GROUPS, API_KEY_LEADER_AND_ISR};
+    use crate::network::client::KafkaClient;
+    use crate::network::requests::LeaderAndIsrRequest;
+
+    // serves a single connection on a free port, returning its address
+    async fn serve_one(server: KafkaServer) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = server.handle_client(stream).await;
+        });
+        address
+    }
+
+    fn leader_and_isr() -> Vec<u8> {
+        let request = LeaderAndIsrRequest {
+            controller_id: 1,
+            controller_epoch: 1,
+            broker_epoch: -1,
+            partition_states: Vec::new(),
+            live_leaders: Vec::new(),
+        };
+        request.encode()
+    }
+
+    #[tokio::test]
+    async fn messages_shorter_than_the_header_close_the_connection() {
+        let dir = std::env::temp_dir().join(format!("rafka-server-{}", uuid::Uuid::new_v4()));
+        let server = KafkaServer::new("127.0.0.1:0", Arc::new(Broker::with_log_dir(0, dir.clone()))).unwrap();
+        for size in [0, 1, 7] {
+            assert!(matches!(server.validate_message_size(size), Err(ServerError::InvalidMessageSize(_))));
+        }
+        assert!(server.validate_message_size(8).is_ok());
+
+        let mut stream = TcpStream::connect(serve_one(server).await).await.unwrap();
+        stream.write_all(&7i32.to_be_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        assert_eq!(stream.read_to_end(&mut response).await.unwrap(), 0);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn inter_broker_apis_are_only_served_on_their_listener() {
+        let dir = std::env::temp_dir().join(format!("rafka-server-{}", uuid::Uuid::new_v4()));
+        let broker = Arc::new(Broker::with_log_dir(0, dir.clone()));
+
+        let clients = KafkaServer::new("127.0.0.1:0", Arc::clone(&broker)).unwrap();
+        let mut client = KafkaClient::connect(&serve_one(clients).await, "client").await.unwrap();
+        assert!(client.send_request(API_KEY_API_VERSIONS, 3, &[0]).await.is_ok());
+        // the connection is closed rather than answered
+        assert!(client.send_request(API_KEY_LEADER_AND_ISR, 5, &leader_and_isr()).await.is_err());
+
+        let brokers = KafkaServer::new("127.0.0.1:0", Arc::clone(&broker)).unwrap().with_inter_broker_apis();
+        let mut client = KafkaClient::connect(&serve_one(brokers).await, "controller-1").await.unwrap();
+        assert!(client.send_request(API_KEY_LEADER_AND_ISR, 5, &leader_and_isr()).await.is_ok());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn requests_that_cannot_be_answered_close_the_connection() {
+        let dir = std::env::temp_dir().join(format!("rafka-server-{}", uuid::Uuid::new_v4()));
+        let server = KafkaServer::new("127.0.0.1:0", Arc::new(Broker::with_log_dir(0, dir.clone()))).unwrap();
+        let mut client = KafkaClient::connect(&serve_one(server).await, "client").await.unwrap();
+        // a DescribeGroups body that's cut off after the array length
+        assert!(client.send_request(API_KEY_DESCRIBE_GROUPS, 5, &[0x03]).await.is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn requests_cut_off_before_their_declared_size_close_the_connection() {
+        let dir = std::env::temp_dir().join(format!("rafka-server-{}", uuid::Uuid::new_v4()));
+        let server = KafkaServer::new("127.0.0.1:0", Arc::new(Broker::with_log_dir(0, dir.clone()))).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        // claims the largest size allowed, then sends the header and hangs up
+        client.write_all(&(MAX_MESSAGE_SIZE as i32).to_be_bytes()).await.unwrap();
+        client.write_all(&[0, 18, 0, 3, 0, 0, 0, 1]).await.unwrap();
+        client.shutdown().await.unwrap();
+        let result = server.read_request(&mut stream, "/127.0.0.1").await;
+        assert!(matches!(result, Err(ServerError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    constants::{API_KEY_WRITE_TXN_MARKERS, CONTROLLER_REQUEST_BACKOFF_MS},
+    core::broker::Broker,
+    core::consumer_group::TopicPartition,
+    core::transaction_coordinator::TxnMarker,
+    error::{KafkaErrorCode, ServerError},
+    network::api::WriteTxnMarkersResponse,
+    network::client::KafkaClient,
+    network::requests::{WritableTxnMarker, WriteTxnMarkersRequest},
+};
+
+const WRITE_TXN_MARKERS_VERSION: i16 = 1;
+
+// errors after which a marker is written again, once leadership or the ISR settles
+const RETRIABLE_ERRORS: &[KafkaErrorCode] = &[
+    KafkaErrorCode::NotLeaderOrFollower,
+    KafkaErrorCode::NotEnoughReplicas,
+    KafkaErrorCode::RequestTimedOut,
+    KafkaErrorCode::KafkaStorageError,
+];
+
+// writes the COMMIT and ABORT markers of transactions this broker coordinates to the
+// leaders of their partitions, then has the coordinator complete the transaction
+pub struct TransactionMarkerManager {
+    broker: Arc<Broker>,
+    clients: HashMap<i32, KafkaClient>, // connections to partition leaders by broker id
+}
+
+impl TransactionMarkerManager {
+    pub fn new(broker: Arc<Broker>) -> Self {
+        TransactionMarkerManager { broker, clients: HashMap::new() }
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            let markers = self.broker.transaction_coordinator().take_pending_markers().await;
+            let mut unfinished = Vec::new();
+            for marker in markers {
+                unfinished.extend(self.write(marker).await);
+            }
+            if !unfinished.is_empty() {
+                tokio::time::sleep(Duration::from_millis(CONTROLLER_REQUEST_BACKOFF_MS)).await;
+                self.broker.transaction_coordinator().requeue_markers(unfinished).await;
+            }
+        }
+    }
+
+    // writes the marker wherever it can; returns it with the partitions left to retry,
+    // or completes the transaction when there are none
+    async fn write(&mut self, marker: TxnMarker) -> Option<TxnMarker> {
+        let mut by_leader: BTreeMap<i32, Vec<TopicPartition>> = BTreeMap::new();
+        let mut remaining = Vec::new();
+        for tp in &marker.partitions {
+            match self.broker.partition_leader(tp).await {
+                Some(leader) => by_leader.entry(leader).or_default().push(tp.clone()),
+                None => remaining.push(tp.clone()),
+            }
+        }
+
+        for (leader, partitions) in by_leader {
+            let results = if leader == self.broker.broker_id() {
+                self.broker
+                    .write_txn_markers(marker.producer_id, marker.producer_epoch, marker.commit, marker.coordinator_epoch, partitions)
+                    .await
+                    .into_iter()
+                    .map(|(tp, error)| (tp, i16::from(error)))
+                    .collect()
+            } else {
+                match self.send(leader, &marker, &partitions).await {
+                    Ok(results) => results,
+                    Err(e) => {
+                        eprintln!("WriteTxnMarkers to broker {} failed: {}", leader, e);
+                        self.clients.remove(&leader);
+                        remaining.extend(partitions);
+                        continue;
+                    }
+                }
+            };
+
+            for (tp, error_code) in results {
+                if error_code == i16::from(KafkaErrorCode::None) {
+                    continue;
+                }
+                if RETRIABLE_ERRORS.iter().any(|retriable| i16::from(*retriable) == error_code) {
+                    remaining.push(tp);
+                } else {
+                    // e.g. a deleted partition, which nobody reads the transaction from anymore
+                    eprintln!(
+                        "Giving up on the transaction marker of {} for {}-{}: error {}",
+                        marker.transactional_id,
+                        tp.topic(),
+                        tp.partition(),
+                        error_code
+                    );
+                }
+            }
+        }
+
+        if remaining.is_empty() {
+            self.broker.complete_transaction(marker).await;
+            return None;
+        }
+        Some(TxnMarker { partitions: remaining, ..marker })
+    }
+
+    async fn connected_client(&mut self, broker_id: i32) -> Result<&mut KafkaClient, ServerError> {
+        if !self.clients.contains_key(&broker_id) {
+            let address = self
+                .broker
+                .broker_endpoint(broker_id)
+                .await
+                .ok_or(ServerError::BrokerNotAvailable(broker_id))?;
+            let client_id = format!("txn-marker-{}", self.broker.broker_id());
+            self.clients.insert(broker_id, KafkaClient::connect(&address, &client_id).await?);
+        }
+        Ok(self.clients.get_mut(&broker_id).unwrap())
+    }
+
+    async fn send(&mut self, leader: i32, marker: &TxnMarker, partitions: &[TopicPartition]) -> Result<Vec<(TopicPartition, i16)>, ServerError> {
+        let mut topics: Vec<(String, Vec<i32>)> = Vec::new();
+        for tp in partitions {
+            match topics.iter_mut().find(|(topic, _)| topic == tp.topic()) {
+                Some((_, indexes)) => indexes.push(tp.partition()),
+                None => topics.push((tp.topic().to_string(), vec![tp.partition()])),
+            }
+        }
+        let request = WriteTxnMarkersRequest {
+            markers: vec![WritableTxnMarker {
+                producer_id: marker.producer_id,
+                producer_epoch: marker.producer_epoch,
+                committed: marker.commit,
+                topics,
+                coordinator_epoch: marker.coordinator_epoch,
+            }],
+        };
+
+        let client = self.connected_client(leader).await?;
+        let body = client
+            .send_request(API_KEY_WRITE_TXN_MARKERS, WRITE_TXN_MARKERS_VERSION, &request.encode())
+            .await?;
+        let response = WriteTxnMarkersResponse::parse(&body)?;
+        Ok(response
+            .markers
+            .into_iter()
+            .flat_map(|result| result.topics)
+            .flat_map(|(topic, partitions)| {
+                partitions
+                    .into_iter()
+                    .map(move |(partition, error_code)| (TopicPartition::new(topic.clone(), partition), error_code))
+            })
+            .collect())
+    }
+}
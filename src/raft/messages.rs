@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use crate::core::metadata::MetadataImage;
+use crate::raft::metadata_log::MetadataBatch;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteRequest {
+    pub term: i32,
+    pub candidate_id: i32,
+    pub last_log_offset: i64,
+    pub last_log_term: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteResponse {
+    pub term: i32,
+    pub vote_granted: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendEntriesRequest {
+    pub term: i32,
+    pub leader_id: i32,
+    pub prev_log_offset: i64,
+    pub prev_log_term: i32,
+    pub entries: Vec<MetadataBatch>, // empty for heartbeats
+    pub leader_commit: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendEntriesResponse {
+    pub term: i32,
+    pub success: bool,
+    // on success the last offset now matching the leader, otherwise where the
+    // leader should retry from (the offset before it)
+    pub last_log_offset: i64,
+}
+
+/// cluster metadata as of `last_offset`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub last_offset: i64,
+    pub last_term: i32,
+    pub image: MetadataImage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallSnapshotRequest {
+    pub term: i32,
+    pub leader_id: i32,
+    pub snapshot: Snapshot,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallSnapshotResponse {
+    pub term: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RaftRequest {
+    Vote(VoteRequest),
+    AppendEntries(AppendEntriesRequest),
+    InstallSnapshot(InstallSnapshotRequest),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RaftResponse {
+    Vote(VoteResponse),
+    AppendEntries(AppendEntriesResponse),
+    InstallSnapshot(InstallSnapshotResponse),
+}
@@ -0,0 +1,318 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::core::metadata::MetadataRecord;
+use crate::storage::log::Log;
+use crate::storage::record_batch;
+
+/// records appended together by one leader, in one term
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetadataBatch {
+    pub base_offset: i64,
+    pub term: i32,
+    pub records: Vec<MetadataRecord>,
+}
+
+impl MetadataBatch {
+    pub fn last_offset(&self) -> i64 {
+        self.base_offset + self.records.len() as i64 - 1
+    }
+}
+
+/// the replicated metadata log. Every batch is written to a storage::log::Log as a
+/// v2 record batch whose partitionLeaderEpoch is the Raft term, and kept in memory
+/// for term lookups and replication. Offsets up to `snapshot_offset` only live in
+/// the snapshot, and the segments holding nothing past it are deleted.
+#[derive(Debug)]
+pub struct MetadataLog {
+    dir: PathBuf,
+    segment_bytes: u64,
+    log: Log,
+    batches: Vec<MetadataBatch>,
+    snapshot_offset: i64, // -1 without a snapshot
+    snapshot_term: i32,
+}
+
+impl MetadataLog {
+    /// opens the log in `dir` where it left off. A torn or unreadable tail is
+    /// truncated; a log that doesn't continue the snapshot is replaced by an empty
+    /// one starting right after it, which the snapshot has to be on disk for.
+    pub fn open(dir: PathBuf, segment_bytes: u64, snapshot_offset: i64, snapshot_term: i32) -> io::Result<Self> {
+        let replaced = replaced_dir(&dir);
+        if replaced.exists() {
+            fs::remove_dir_all(&replaced)?;
+        }
+        let mut log = Log::new(dir.clone(), snapshot_offset + 1, segment_bytes)?;
+        let mut batches = Vec::new();
+        let mut end_offset = log.log_start_offset();
+        for (offset, data) in log.read_from(end_offset, usize::MAX)? {
+            match decode_batch(offset, &data) {
+                Some(batch) if offset == end_offset => {
+                    end_offset = batch.last_offset() + 1;
+                    batches.push(batch);
+                }
+                _ => break, // nothing past a torn write can be trusted
+            }
+        }
+        log.truncate_to(end_offset)?;
+
+        let mut metadata_log = MetadataLog { dir, segment_bytes, log, batches, snapshot_offset, snapshot_term };
+        let continues_snapshot = match metadata_log.batch_containing(snapshot_offset) {
+            Some(batch) => batch.term == snapshot_term,
+            None => metadata_log.log.log_start_offset() == snapshot_offset + 1,
+        };
+        if continues_snapshot {
+            metadata_log.compact_to(snapshot_offset, snapshot_term)?;
+        } else {
+            metadata_log.reset_to_snapshot(snapshot_offset, snapshot_term)?;
+        }
+        Ok(metadata_log)
+    }
+
+    /// next offset to be written
+    pub fn end_offset(&self) -> i64 {
+        self.log.log_end_offset()
+    }
+
+    /// offset and term of the last entry, falling back to the snapshot's
+    pub fn last_offset_and_term(&self) -> (i64, i32) {
+        match self.batches.last() {
+            Some(batch) => (batch.last_offset(), batch.term),
+            None => (self.snapshot_offset, self.snapshot_term),
+        }
+    }
+
+    pub fn snapshot_offset(&self) -> i64 {
+        self.snapshot_offset
+    }
+
+    /// term of the entry at `offset`; None if the log doesn't have it anymore or yet
+    pub fn term_at(&self, offset: i64) -> Option<i32> {
+        if offset == self.snapshot_offset {
+            return Some(self.snapshot_term);
+        }
+        self.batch_containing(offset).map(|batch| batch.term)
+    }
+
+    fn batch_containing(&self, offset: i64) -> Option<&MetadataBatch> {
+        self.batches
+            .iter()
+            .find(|batch| batch.base_offset <= offset && offset <= batch.last_offset())
+    }
+
+    pub fn append(&mut self, term: i32, records: Vec<MetadataRecord>) -> io::Result<MetadataBatch> {
+        let batch = MetadataBatch { base_offset: self.end_offset(), term, records };
+        self.append_batch(batch.clone())?;
+        Ok(batch)
+    }
+
+    /// appends a batch replicated from the leader, which has to start at the log end
+    pub fn append_batch(&mut self, batch: MetadataBatch) -> io::Result<()> {
+        if batch.base_offset != self.end_offset() || batch.records.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "metadata batch doesn't start at the log end"));
+        }
+        let values = batch
+            .records
+            .iter()
+            .map(|record| serde_json::to_vec(record).map_err(io::Error::other))
+            .collect::<io::Result<Vec<_>>>()?;
+        let encoded = record_batch::build_batch(batch.base_offset, batch.term, Utc::now().timestamp_millis(), &values);
+        self.log.append_batch(&encoded, batch.records.len() as i64, Utc::now().timestamp_millis())?;
+        if self.log.latest_epoch().is_none_or(|latest| latest < batch.term) {
+            self.log.assign_epoch(batch.term, batch.base_offset)?;
+        }
+        // acknowledging a batch to the leader, or counting it towards the quorum as
+        // the leader, promises it survives a crash
+        self.log.sync()?;
+        self.batches.push(batch);
+        Ok(())
+    }
+
+    /// batches starting at `offset`, at most `max_batches` of them
+    pub fn batches_from(&self, offset: i64, max_batches: usize) -> Vec<MetadataBatch> {
+        self.batches
+            .iter()
+            .skip_while(|batch| batch.last_offset() < offset)
+            .take(max_batches)
+            .cloned()
+            .collect()
+    }
+
+    /// batches after `after` whose last offset is at most `offset`
+    pub fn batches_between(&self, after: i64, offset: i64) -> Vec<MetadataBatch> {
+        self.batches
+            .iter()
+            .filter(|batch| batch.base_offset > after && batch.last_offset() <= offset)
+            .cloned()
+            .collect()
+    }
+
+    /// base offset of the batch holding `offset`, or `offset` itself if no batch does
+    pub fn batch_start(&self, offset: i64) -> i64 {
+        self.batch_containing(offset).map_or(offset, |batch| batch.base_offset)
+    }
+
+    /// drops the batch holding `offset` and everything after it
+    pub fn truncate_from(&mut self, offset: i64) -> io::Result<()> {
+        let cut = self.batch_start(offset);
+        self.log.truncate_to(cut)?;
+        self.batches.retain(|batch| batch.base_offset < cut);
+        Ok(())
+    }
+
+    /// forgets batches a snapshot through `offset` now covers and deletes the
+    /// segments only they were in. The snapshot has to be on disk already.
+    pub fn compact_to(&mut self, offset: i64, term: i32) -> io::Result<()> {
+        self.batches.retain(|batch| batch.last_offset() > offset);
+        self.snapshot_offset = offset;
+        self.snapshot_term = term;
+        self.log.delete_segments_before(offset + 1)
+    }
+
+    /// replaces the whole log with a snapshot received from the leader, which has
+    /// to be on disk already. The old log is moved aside first, so a crash leaves
+    /// either it or nothing behind, and `open` starts over from the snapshot.
+    pub fn reset_to_snapshot(&mut self, offset: i64, term: i32) -> io::Result<()> {
+        let replaced = replaced_dir(&self.dir);
+        if replaced.exists() {
+            fs::remove_dir_all(&replaced)?;
+        }
+        fs::rename(&self.dir, &replaced)?;
+        self.log = Log::new(self.dir.clone(), offset + 1, self.segment_bytes)?;
+        self.batches.clear();
+        self.snapshot_offset = offset;
+        self.snapshot_term = term;
+        fs::remove_dir_all(&replaced)
+    }
+}
+
+// where a log replaced by a snapshot waits to be removed
+fn replaced_dir(dir: &Path) -> PathBuf {
+    dir.with_extension("replaced")
+}
+
+fn decode_batch(base_offset: i64, data: &[u8]) -> Option<MetadataBatch> {
+    let term = record_batch::partition_leader_epoch(data)?;
+    let records = record_batch::record_values(data)?
+        .into_iter()
+        .map(|value| serde_json::from_slice(&value?).ok())
+        .collect::<Option<Vec<MetadataRecord>>>()?;
+    (!records.is_empty()).then_some(MetadataBatch { base_offset, term, records })
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::constants::LOG_SEGMENT_BYTES;
+
+    // small enough that every batch gets a segment of its own
+    const SEGMENT_BYTES: u64 = 200;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("rafka-metadata-log-{}", Uuid::new_v4()))
+    }
+
+    fn topic(name: &str) -> Vec<MetadataRecord> {
+        vec![MetadataRecord::Topic { name: name.to_string(), topic_id: Uuid::new_v4() }]
+    }
+
+    fn segment_count(dir: &Path) -> usize {
+        fs::read_dir(dir)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "log"))
+            .count()
+    }
+
+    #[test]
+    fn reopening_keeps_the_log_in_place() {
+        let dir = temp_dir();
+        let mut log = MetadataLog::open(dir.clone(), SEGMENT_BYTES, -1, -1).unwrap();
+        let first = log.append(1, topic("a")).unwrap();
+        let second = log.append(2, topic("b")).unwrap();
+        drop(log);
+        let segments = segment_count(&dir);
+
+        let mut log = MetadataLog::open(dir.clone(), SEGMENT_BYTES, -1, -1).unwrap();
+        assert_eq!(log.batches_from(0, 10), vec![first, second]);
+        assert_eq!(log.last_offset_and_term(), (1, 2));
+        assert_eq!(segment_count(&dir), segments);
+        assert_eq!(log.append(2, topic("c")).unwrap().base_offset, 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reopening_truncates_a_torn_tail() {
+        let dir = temp_dir();
+        let mut log = MetadataLog::open(dir.clone(), LOG_SEGMENT_BYTES, -1, -1).unwrap();
+        log.append(1, topic("a")).unwrap();
+        log.append(1, topic("b")).unwrap();
+        drop(log);
+
+        // a whole entry whose batch doesn't decode, then half of one
+        let segment = dir.join(format!("{:020}.log", 0));
+        let mut data = fs::read(&segment).unwrap();
+        data.extend_from_slice(&12u32.to_be_bytes());
+        data.extend_from_slice(&2i64.to_be_bytes());
+        data.extend_from_slice(&[0xff; 4]);
+        data.extend_from_slice(&40u32.to_be_bytes());
+        fs::write(&segment, data).unwrap();
+
+        let mut log = MetadataLog::open(dir.clone(), LOG_SEGMENT_BYTES, -1, -1).unwrap();
+        assert_eq!(log.end_offset(), 2);
+        assert_eq!(log.append(1, topic("c")).unwrap().base_offset, 2);
+        drop(log);
+        let log = MetadataLog::open(dir.clone(), LOG_SEGMENT_BYTES, -1, -1).unwrap();
+        assert_eq!(log.batches_from(0, 10).len(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compaction_deletes_covered_segments() {
+        let dir = temp_dir();
+        let mut log = MetadataLog::open(dir.clone(), SEGMENT_BYTES, -1, -1).unwrap();
+        for name in ["a", "b", "c", "d", "e"] {
+            log.append(1, topic(name)).unwrap();
+        }
+        assert_eq!(segment_count(&dir), 5);
+
+        log.compact_to(2, 1).unwrap();
+        assert_eq!(segment_count(&dir), 2);
+        assert_eq!(log.term_at(1), None);
+        assert_eq!(log.term_at(2), Some(1));
+        drop(log);
+
+        let log = MetadataLog::open(dir.clone(), SEGMENT_BYTES, 2, 1).unwrap();
+        let offsets: Vec<i64> = log.batches_from(0, 10).iter().map(|batch| batch.base_offset).collect();
+        assert_eq!(offsets, vec![3, 4]);
+        assert_eq!(log.last_offset_and_term(), (4, 1));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn log_not_continuing_the_snapshot_is_replaced() {
+        let dir = temp_dir();
+        let mut log = MetadataLog::open(dir.clone(), SEGMENT_BYTES, -1, -1).unwrap();
+        log.append(1, topic("a")).unwrap();
+        log.append(1, topic("b")).unwrap();
+        drop(log);
+
+        // a crash after a snapshot from the leader was installed, before the log was reset
+        let mut log = MetadataLog::open(dir.clone(), SEGMENT_BYTES, 5, 3).unwrap();
+        assert_eq!(log.end_offset(), 6);
+        assert_eq!(log.last_offset_and_term(), (5, 3));
+        assert!(log.batches_from(0, 10).is_empty());
+        assert_eq!(log.append(3, topic("c")).unwrap().base_offset, 6);
+        drop(log);
+
+        let log = MetadataLog::open(dir.clone(), SEGMENT_BYTES, 5, 3).unwrap();
+        assert_eq!(log.last_offset_and_term(), (6, 3));
+        assert!(!replaced_dir(&dir).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,26 @@
+pub mod messages;
+pub mod metadata_log;
+pub mod node;
+pub mod transport;
+
+use thiserror::Error;
+
+use crate::error::ServerError;
+
+#[derive(Debug, Error)]
+pub enum RaftError {
+    #[error("Not the quorum leader, current leader is {0:?}")]
+    NotLeader(Option<i32>),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Malformed quorum message: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("Quorum request failed: {0}")]
+    Transport(#[from] ServerError),
+
+    #[error("Quorum request to {0} timed out")]
+    Timeout(String),
+}
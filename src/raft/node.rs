@@ -0,0 +1,918 @@
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::fs;
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch, Mutex, Notify, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::{
+    constants::{
+        LOG_SEGMENT_BYTES, METADATA_SNAPSHOT_MAX_RECORDS, QUORUM_ELECTION_TIMEOUT_MS, QUORUM_HEARTBEAT_INTERVAL_MS,
+        QUORUM_MAX_BATCHES_PER_APPEND,
+    },
+    core::metadata::{MetadataImage, MetadataRecord},
+    raft::messages::{
+        AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse, RaftRequest,
+        RaftResponse, Snapshot, VoteRequest, VoteResponse,
+    },
+    raft::metadata_log::MetadataLog,
+    raft::transport::{self, RaftClient},
+    raft::RaftError,
+};
+
+const QUORUM_STATE_FILE: &str = "quorum-state";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const LOG_DIR: &str = "log";
+
+#[derive(Debug, Clone)]
+pub struct RaftConfig {
+    pub node_id: i32,
+    pub voters: BTreeMap<i32, String>, // node id -> quorum listener address
+    // nodes the leader replicates to without counting them towards the quorum,
+    // e.g. brokers building their metadata cache
+    pub observers: BTreeMap<i32, String>,
+    pub dir: PathBuf,
+    pub election_timeout_ms: u64,
+    pub heartbeat_interval_ms: u64,
+    pub snapshot_max_records: i64,
+    pub log_segment_bytes: u64,
+}
+
+impl RaftConfig {
+    pub fn new(node_id: i32, dir: PathBuf, voters: BTreeMap<i32, String>) -> Self {
+        RaftConfig {
+            node_id,
+            voters,
+            observers: BTreeMap::new(),
+            dir,
+            election_timeout_ms: QUORUM_ELECTION_TIMEOUT_MS,
+            heartbeat_interval_ms: QUORUM_HEARTBEAT_INTERVAL_MS,
+            snapshot_max_records: METADATA_SNAPSHOT_MAX_RECORDS,
+            log_segment_bytes: LOG_SEGMENT_BYTES,
+        }
+    }
+
+    fn is_voter(&self) -> bool {
+        self.voters.contains_key(&self.node_id)
+    }
+
+    fn address(&self) -> Option<&String> {
+        self.voters.get(&self.node_id).or_else(|| self.observers.get(&self.node_id))
+    }
+
+    // every other node, voter or observer
+    fn peers(&self) -> BTreeMap<i32, String> {
+        self.voters
+            .iter()
+            .chain(&self.observers)
+            .filter(|(id, _)| **id != self.node_id)
+            .map(|(id, address)| (*id, address.clone()))
+            .collect()
+    }
+
+    fn majority(&self) -> usize {
+        self.voters.len() / 2 + 1
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+    Observer,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RaftStatus {
+    pub role: Role,
+    pub term: i32,
+    pub leader_id: Option<i32>,
+    // leader that has applied everything committed before its term, so its
+    // image is current and it can accept proposals
+    pub active: bool,
+}
+
+// survives restarts so a node never votes twice in a term
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HardState {
+    current_term: i32,
+    voted_for: Option<i32>,
+}
+
+#[derive(Debug)]
+struct RaftState {
+    role: Role,
+    term: i32,
+    voted_for: Option<i32>,
+    leader_id: Option<i32>,
+    log: MetadataLog,
+    snapshot: Option<Snapshot>,
+    commit_offset: i64,
+    applied_offset: i64,
+    leader_start_offset: i64, // where this node's LeaderChange record went
+    last_contact: Instant,    // last heartbeat from a leader, or vote granted
+    next_offset: BTreeMap<i32, i64>,
+    match_offset: BTreeMap<i32, i64>,
+}
+
+/// one member of the metadata quorum. Voters elect a leader, which appends
+/// proposed records and commits them once a majority of voters has them.
+/// Every node, observers included, replays committed records into its image.
+#[derive(Debug)]
+pub struct RaftNode {
+    config: RaftConfig,
+    state: Mutex<RaftState>,
+    image: Arc<RwLock<MetadataImage>>,
+    status: watch::Sender<RaftStatus>,
+    applied: watch::Sender<i64>,
+    peer_wakeups: BTreeMap<i32, Notify>, // new entries for the peer's replicator
+    stopped: AtomicBool,
+    tasks: std::sync::Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl RaftNode {
+    /// recovers the node from its directory, binds its quorum listener and starts
+    /// the election timer and one replicator per peer
+    pub async fn start(config: RaftConfig) -> Result<Arc<Self>, RaftError> {
+        let address = config
+            .address()
+            .cloned()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "node isn't part of the quorum"))?;
+        fs::create_dir_all(&config.dir)?;
+
+        let hard_state: HardState = read_json(&config.dir.join(QUORUM_STATE_FILE))?.unwrap_or_default();
+        let snapshot: Option<Snapshot> = read_json(&config.dir.join(SNAPSHOT_FILE))?;
+        let (snapshot_offset, snapshot_term) = snapshot.as_ref().map_or((-1, -1), |s| (s.last_offset, s.last_term));
+        let log = MetadataLog::open(config.dir.join(LOG_DIR), config.log_segment_bytes, snapshot_offset, snapshot_term)?;
+        let image = snapshot.as_ref().map(|s| s.image.clone()).unwrap_or_default();
+
+        let role = if config.is_voter() { Role::Follower } else { Role::Observer };
+        let (status, _) = watch::channel(RaftStatus { role, term: hard_state.current_term, leader_id: None, active: false });
+        let (applied, _) = watch::channel(snapshot_offset);
+        let peers = config.peers();
+
+        let node = Arc::new(RaftNode {
+            state: Mutex::new(RaftState {
+                role,
+                term: hard_state.current_term,
+                voted_for: hard_state.voted_for,
+                leader_id: None,
+                log,
+                snapshot,
+                commit_offset: snapshot_offset,
+                applied_offset: snapshot_offset,
+                leader_start_offset: 0,
+                last_contact: Instant::now(),
+                next_offset: BTreeMap::new(),
+                match_offset: BTreeMap::new(),
+            }),
+            image: Arc::new(RwLock::new(image)),
+            status,
+            applied,
+            peer_wakeups: peers.keys().map(|id| (*id, Notify::new())).collect(),
+            stopped: AtomicBool::new(false),
+            tasks: std::sync::Mutex::new(Vec::new()),
+            config,
+        });
+
+        let listener = TcpListener::bind(&address).await?;
+        println!("Quorum node {} listening on {} as {:?}", node.config.node_id, address, role);
+        let mut tasks = vec![tokio::spawn(transport::serve(listener, Arc::clone(&node)))];
+        if role != Role::Observer {
+            tasks.push(tokio::spawn(Arc::clone(&node).run_election_timer()));
+        }
+        for (peer_id, address) in peers {
+            tasks.push(tokio::spawn(Arc::clone(&node).run_replicator(peer_id, address)));
+        }
+        *node.tasks.lock().unwrap() = tasks;
+        Ok(node)
+    }
+
+    /// stops taking part in the quorum, as if the node had crashed
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        self.status.send_modify(|status| {
+            status.leader_id = None;
+            status.active = false;
+            if status.role != Role::Observer {
+                status.role = Role::Follower;
+            }
+        });
+    }
+
+    pub fn node_id(&self) -> i32 {
+        self.config.node_id
+    }
+
+    /// metadata as of the last applied record
+    pub fn image(&self) -> Arc<RwLock<MetadataImage>> {
+        Arc::clone(&self.image)
+    }
+
+    /// offset of the last record replayed into the image
+    pub fn applied_offset(&self) -> i64 {
+        *self.applied.borrow()
+    }
+
+    pub fn status(&self) -> RaftStatus {
+        self.status.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<RaftStatus> {
+        self.status.subscribe()
+    }
+
+    /// the term this node leads and accepts proposals in, if it does
+    pub fn active_term(&self) -> Option<i32> {
+        let status = self.status.borrow();
+        (status.role == Role::Leader && status.active).then_some(status.term)
+    }
+
+    /// appends records as one batch and waits until they're committed and applied.
+    /// Fails if this node isn't leading `term`, or loses leadership meanwhile.
+    pub async fn propose(&self, term: i32, records: Vec<MetadataRecord>) -> Result<i64, RaftError> {
+        let mut applied = self.applied.subscribe();
+        let mut status = self.status.subscribe();
+        let last_offset = {
+            let mut state = self.state.lock().await;
+            if state.role != Role::Leader || state.term != term {
+                return Err(RaftError::NotLeader(state.leader_id));
+            }
+            let batch = state.log.append(term, records)?;
+            self.wake_peers();
+            self.advance_commit(&mut state).await?;
+            batch.last_offset()
+        };
+
+        loop {
+            let reached = *applied.borrow_and_update() >= last_offset;
+            {
+                let status = status.borrow_and_update();
+                if status.term != term || status.role != Role::Leader {
+                    return Err(RaftError::NotLeader(status.leader_id));
+                }
+            }
+            if reached {
+                return Ok(last_offset);
+            }
+            tokio::select! {
+                _ = applied.changed() => {}
+                _ = status.changed() => {}
+            }
+        }
+    }
+
+    /// answers a request from another quorum node; None once the node is stopped
+    pub async fn handle(&self, request: RaftRequest) -> Result<Option<RaftResponse>, RaftError> {
+        if self.stopped.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        let response = match request {
+            RaftRequest::Vote(request) => RaftResponse::Vote(self.handle_vote(request).await?),
+            RaftRequest::AppendEntries(request) => RaftResponse::AppendEntries(self.handle_append_entries(request).await?),
+            RaftRequest::InstallSnapshot(request) => {
+                RaftResponse::InstallSnapshot(self.handle_install_snapshot(request).await?)
+            }
+        };
+        Ok(Some(response))
+    }
+
+    async fn handle_vote(&self, request: VoteRequest) -> Result<VoteResponse, RaftError> {
+        let mut state = self.state.lock().await;
+        if request.term > state.term {
+            self.become_follower(&mut state, request.term, None)?;
+        }
+        let (last_offset, last_term) = state.log.last_offset_and_term();
+        // the candidate's log has to be at least as complete as ours
+        let up_to_date = (request.last_log_term, request.last_log_offset) >= (last_term, last_offset);
+        let vote_granted = self.config.is_voter()
+            && request.term == state.term
+            && state.voted_for.is_none_or(|candidate| candidate == request.candidate_id)
+            && up_to_date;
+        if vote_granted {
+            state.voted_for = Some(request.candidate_id);
+            state.last_contact = Instant::now();
+            self.persist_hard_state(&state)?;
+        }
+        Ok(VoteResponse { term: state.term, vote_granted })
+    }
+
+    async fn handle_append_entries(&self, request: AppendEntriesRequest) -> Result<AppendEntriesResponse, RaftError> {
+        let mut state = self.state.lock().await;
+        let (last_offset, _) = state.log.last_offset_and_term();
+        if request.term < state.term {
+            return Ok(AppendEntriesResponse { term: state.term, success: false, last_log_offset: last_offset });
+        }
+        if request.term > state.term || state.leader_id != Some(request.leader_id) || state.role == Role::Candidate {
+            self.become_follower(&mut state, request.term, Some(request.leader_id))?;
+        }
+        state.last_contact = Instant::now();
+
+        let failure = |last_log_offset| AppendEntriesResponse { term: request.term, success: false, last_log_offset };
+        if request.prev_log_offset > last_offset {
+            return Ok(failure(last_offset));
+        }
+        // below the snapshot everything is committed, so it matches by definition
+        if request.prev_log_offset >= state.log.snapshot_offset()
+            && state.log.term_at(request.prev_log_offset) != Some(request.prev_log_term)
+        {
+            return Ok(failure(state.log.batch_start(request.prev_log_offset) - 1));
+        }
+
+        let mut matched = request.prev_log_offset;
+        for batch in request.entries {
+            let batch_last_offset = batch.last_offset();
+            if batch_last_offset > state.log.snapshot_offset() {
+                match state.log.term_at(batch.base_offset) {
+                    Some(term) if term == batch.term => {}
+                    Some(_) => {
+                        state.log.truncate_from(batch.base_offset)?;
+                        state.log.append_batch(batch)?;
+                    }
+                    None => state.log.append_batch(batch)?,
+                }
+            }
+            matched = batch_last_offset;
+        }
+
+        let commit_offset = request.leader_commit.min(matched);
+        if commit_offset > state.commit_offset {
+            state.commit_offset = commit_offset;
+            self.apply_committed(&mut state).await?;
+        }
+        Ok(AppendEntriesResponse { term: request.term, success: true, last_log_offset: matched })
+    }
+
+    async fn handle_install_snapshot(&self, request: InstallSnapshotRequest) -> Result<InstallSnapshotResponse, RaftError> {
+        let mut state = self.state.lock().await;
+        if request.term < state.term {
+            return Ok(InstallSnapshotResponse { term: state.term });
+        }
+        if request.term > state.term || state.leader_id != Some(request.leader_id) || state.role == Role::Candidate {
+            self.become_follower(&mut state, request.term, Some(request.leader_id))?;
+        }
+        state.last_contact = Instant::now();
+
+        let snapshot = request.snapshot;
+        if snapshot.last_offset <= state.commit_offset {
+            return Ok(InstallSnapshotResponse { term: request.term });
+        }
+        // the snapshot goes to disk before any of the log it replaces is dropped.
+        // Entries past it can stay if the log agrees with it
+        write_json(&self.config.dir.join(SNAPSHOT_FILE), &snapshot)?;
+        if state.log.term_at(snapshot.last_offset) == Some(snapshot.last_term) {
+            state.log.compact_to(snapshot.last_offset, snapshot.last_term)?;
+        } else {
+            state.log.reset_to_snapshot(snapshot.last_offset, snapshot.last_term)?;
+        }
+        *self.image.write().await = snapshot.image.clone();
+        state.commit_offset = snapshot.last_offset;
+        state.applied_offset = snapshot.last_offset;
+        println!("Quorum node {} installed a snapshot at offset {}", self.config.node_id, snapshot.last_offset);
+        state.snapshot = Some(snapshot);
+        self.applied.send_replace(state.applied_offset);
+        Ok(InstallSnapshotResponse { term: request.term })
+    }
+
+    async fn run_election_timer(self: Arc<Self>) {
+        let mut timeout = self.election_timeout();
+        loop {
+            let deadline = {
+                let state = self.state.lock().await;
+                (state.role != Role::Leader).then_some(state.last_contact + timeout)
+            };
+            match deadline {
+                None => tokio::time::sleep(timeout).await,
+                Some(deadline) if Instant::now() >= deadline => {
+                    self.state.lock().await.last_contact = Instant::now();
+                    // votes are collected in the background so the timer keeps running
+                    tokio::spawn(Arc::clone(&self).start_election());
+                    timeout = self.election_timeout();
+                }
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+            }
+        }
+    }
+
+    // somewhere between one and two election timeouts, so nodes rarely time out together
+    fn election_timeout(&self) -> Duration {
+        let base = self.config.election_timeout_ms.max(1);
+        let jitter = RandomState::new().hash_one(self.config.node_id) % base;
+        Duration::from_millis(base + jitter)
+    }
+
+    async fn start_election(self: Arc<Self>) {
+        let request = {
+            let mut state = self.state.lock().await;
+            state.term += 1;
+            state.role = Role::Candidate;
+            state.voted_for = Some(self.config.node_id);
+            state.leader_id = None;
+            state.last_contact = Instant::now();
+            if let Err(e) = self.persist_hard_state(&state) {
+                eprintln!("Quorum node {} couldn't persist its vote: {}", self.config.node_id, e);
+                return;
+            }
+            self.publish_status(&state);
+            println!("Quorum node {} starting an election for term {}", self.config.node_id, state.term);
+
+            if self.config.majority() == 1 {
+                self.become_leader(&mut state).await;
+                return;
+            }
+            let (last_log_offset, last_log_term) = state.log.last_offset_and_term();
+            VoteRequest { term: state.term, candidate_id: self.config.node_id, last_log_offset, last_log_term }
+        };
+
+        let (tx, mut rx) = mpsc::channel(self.config.voters.len());
+        for (voter_id, address) in &self.config.voters {
+            if *voter_id == self.config.node_id {
+                continue;
+            }
+            let (tx, address, request) = (tx.clone(), address.clone(), RaftRequest::Vote(request.clone()));
+            tokio::spawn(async move {
+                let _ = tx.send(RaftClient::new(&address).send(&request).await).await;
+            });
+        }
+        drop(tx);
+
+        let mut votes = 1;
+        while let Some(result) = rx.recv().await {
+            let Ok(RaftResponse::Vote(response)) = result else {
+                continue;
+            };
+            let mut state = self.state.lock().await;
+            if response.term > state.term {
+                if let Err(e) = self.become_follower(&mut state, response.term, None) {
+                    eprintln!("Quorum node {} couldn't persist its term: {}", self.config.node_id, e);
+                }
+                return;
+            }
+            if state.role != Role::Candidate || state.term != request.term {
+                return;
+            }
+            if response.vote_granted {
+                votes += 1;
+                if votes >= self.config.majority() {
+                    self.become_leader(&mut state).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn become_leader(&self, state: &mut RaftState) {
+        state.role = Role::Leader;
+        state.leader_id = Some(self.config.node_id);
+        let end_offset = state.log.end_offset();
+        state.next_offset = self.peer_wakeups.keys().map(|id| (*id, end_offset)).collect();
+        state.match_offset.clear();
+
+        // entries from earlier terms only commit along with one from this term
+        let leader_change = MetadataRecord::LeaderChange { leader_id: self.config.node_id, epoch: state.term };
+        match state.log.append(state.term, vec![leader_change]) {
+            Ok(batch) => state.leader_start_offset = batch.base_offset,
+            Err(e) => {
+                eprintln!("Quorum node {} couldn't append to its log, staying a follower: {}", self.config.node_id, e);
+                state.role = Role::Follower;
+                state.leader_id = None;
+                return;
+            }
+        }
+        println!("Quorum node {} is the leader for term {}", self.config.node_id, state.term);
+        self.publish_status(state);
+        self.wake_peers();
+        if let Err(e) = self.advance_commit(state).await {
+            eprintln!("Quorum node {} failed to apply committed records: {}", self.config.node_id, e);
+        }
+    }
+
+    fn become_follower(&self, state: &mut RaftState, term: i32, leader_id: Option<i32>) -> Result<(), RaftError> {
+        if term > state.term {
+            state.term = term;
+            state.voted_for = None;
+            self.persist_hard_state(state)?;
+        }
+        if state.role != Role::Observer {
+            state.role = Role::Follower;
+        }
+        state.leader_id = leader_id;
+        state.last_contact = Instant::now();
+        self.publish_status(state);
+        Ok(())
+    }
+
+    async fn run_replicator(self: Arc<Self>, peer_id: i32, address: String) {
+        let mut client = RaftClient::new(&address);
+        let heartbeat = Duration::from_millis(self.config.heartbeat_interval_ms);
+        let wakeup = &self.peer_wakeups[&peer_id];
+        loop {
+            let Some((term, request)) = self.replication_request(peer_id).await else {
+                let _ = tokio::time::timeout(heartbeat, wakeup.notified()).await;
+                continue;
+            };
+            match client.send(&request).await {
+                Ok(response) => match self.handle_replication_response(peer_id, term, &request, response).await {
+                    Ok(true) => {
+                        let _ = tokio::time::timeout(heartbeat, wakeup.notified()).await;
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        eprintln!("Quorum node {} failed to handle a response from {}: {}", self.config.node_id, peer_id, e);
+                        tokio::time::sleep(heartbeat).await;
+                    }
+                },
+                // an unreachable peer is retried at the heartbeat pace
+                Err(_) => tokio::time::sleep(heartbeat).await,
+            }
+        }
+    }
+
+    // what the peer needs next: a snapshot if its entries were compacted away,
+    // otherwise entries from its next offset, which may be none (a heartbeat)
+    async fn replication_request(&self, peer_id: i32) -> Option<(i32, RaftRequest)> {
+        let state = self.state.lock().await;
+        if state.role != Role::Leader {
+            return None;
+        }
+        let next_offset = state.next_offset.get(&peer_id).copied().unwrap_or(state.log.end_offset());
+        if let Some(snapshot) = state.snapshot.as_ref().filter(|snapshot| next_offset <= snapshot.last_offset) {
+            let request = InstallSnapshotRequest { term: state.term, leader_id: self.config.node_id, snapshot: snapshot.clone() };
+            return Some((state.term, RaftRequest::InstallSnapshot(request)));
+        }
+        let prev_log_offset = next_offset - 1;
+        let request = AppendEntriesRequest {
+            term: state.term,
+            leader_id: self.config.node_id,
+            prev_log_offset,
+            prev_log_term: state.log.term_at(prev_log_offset).unwrap_or(-1),
+            entries: state.log.batches_from(next_offset, QUORUM_MAX_BATCHES_PER_APPEND),
+            leader_commit: state.commit_offset,
+        };
+        Some((state.term, RaftRequest::AppendEntries(request)))
+    }
+
+    // true once the peer has everything the leader has
+    async fn handle_replication_response(
+        &self,
+        peer_id: i32,
+        term: i32,
+        request: &RaftRequest,
+        response: RaftResponse,
+    ) -> Result<bool, RaftError> {
+        let mut state = self.state.lock().await;
+        let response_term = match &response {
+            RaftResponse::Vote(response) => response.term,
+            RaftResponse::AppendEntries(response) => response.term,
+            RaftResponse::InstallSnapshot(response) => response.term,
+        };
+        if response_term > state.term {
+            self.become_follower(&mut state, response_term, None)?;
+            return Ok(true);
+        }
+        if state.role != Role::Leader || state.term != term {
+            return Ok(true);
+        }
+
+        match (request, response) {
+            (RaftRequest::AppendEntries(_), RaftResponse::AppendEntries(response)) if response.success => {
+                state.match_offset.insert(peer_id, response.last_log_offset);
+                state.next_offset.insert(peer_id, response.last_log_offset + 1);
+            }
+            (RaftRequest::AppendEntries(request), RaftResponse::AppendEntries(response)) => {
+                let retry_offset = (response.last_log_offset + 1).min(request.prev_log_offset).max(0);
+                let next_offset = state.log.batch_start(retry_offset);
+                state.next_offset.insert(peer_id, next_offset);
+                return Ok(false);
+            }
+            (RaftRequest::InstallSnapshot(request), RaftResponse::InstallSnapshot(_)) => {
+                state.match_offset.insert(peer_id, request.snapshot.last_offset);
+                state.next_offset.insert(peer_id, request.snapshot.last_offset + 1);
+            }
+            _ => return Ok(true),
+        }
+        self.advance_commit(&mut state).await?;
+        Ok(state.next_offset[&peer_id] >= state.log.end_offset())
+    }
+
+    // commits the highest offset a majority of voters has, once it's from this term
+    async fn advance_commit(&self, state: &mut RaftState) -> Result<(), RaftError> {
+        if state.role != Role::Leader {
+            return Ok(());
+        }
+        let (last_offset, _) = state.log.last_offset_and_term();
+        let mut matched: Vec<i64> = self
+            .config
+            .voters
+            .keys()
+            .map(|id| match *id == self.config.node_id {
+                true => last_offset,
+                false => state.match_offset.get(id).copied().unwrap_or(-1),
+            })
+            .collect();
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let quorum_offset = matched[self.config.majority() - 1];
+        if quorum_offset > state.commit_offset && state.log.term_at(quorum_offset) == Some(state.term) {
+            state.commit_offset = quorum_offset;
+            self.apply_committed(state).await?;
+        }
+        Ok(())
+    }
+
+    async fn apply_committed(&self, state: &mut RaftState) -> Result<(), RaftError> {
+        let batches = state.log.batches_between(state.applied_offset, state.commit_offset);
+        let Some(last_batch) = batches.last() else {
+            return Ok(());
+        };
+        state.applied_offset = last_batch.last_offset();
+        {
+            let mut image = self.image.write().await;
+            for record in batches.iter().flat_map(|batch| &batch.records) {
+                image.replay(record);
+            }
+        }
+        self.applied.send_replace(state.applied_offset);
+        if state.role == Role::Leader {
+            self.publish_status(state);
+        }
+
+        if state.applied_offset - state.log.snapshot_offset() >= self.config.snapshot_max_records {
+            self.take_snapshot(state).await?;
+        }
+        Ok(())
+    }
+
+    async fn take_snapshot(&self, state: &mut RaftState) -> Result<(), RaftError> {
+        let snapshot = Snapshot {
+            last_offset: state.applied_offset,
+            last_term: state.log.term_at(state.applied_offset).unwrap_or(state.term),
+            image: self.image.read().await.clone(),
+        };
+        write_json(&self.config.dir.join(SNAPSHOT_FILE), &snapshot)?;
+        state.log.compact_to(snapshot.last_offset, snapshot.last_term)?;
+        state.snapshot = Some(snapshot);
+        Ok(())
+    }
+
+    fn wake_peers(&self) {
+        for wakeup in self.peer_wakeups.values() {
+            wakeup.notify_one();
+        }
+    }
+
+    fn publish_status(&self, state: &RaftState) {
+        self.status.send_replace(RaftStatus {
+            role: state.role,
+            term: state.term,
+            leader_id: state.leader_id,
+            active: state.role == Role::Leader && state.applied_offset >= state.leader_start_offset,
+        });
+    }
+
+    fn persist_hard_state(&self, state: &RaftState) -> Result<(), RaftError> {
+        let hard_state = HardState { current_term: state.term, voted_for: state.voted_for };
+        write_json(&self.config.dir.join(QUORUM_STATE_FILE), &hard_state)
+    }
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, RaftError> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// written to a temp file and renamed over, so a crash leaves the old or new version.
+// The directory is synced too, or the rename itself could be lost
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), RaftError> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec(value)?)?;
+    fs::File::open(&tmp)?.sync_all()?;
+    fs::rename(&tmp, path)?;
+    if let Some(dir) = path.parent() {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(20);
+
+    // three voters on loopback, each started and crashed on demand
+    struct Quorum {
+        dir: PathBuf,
+        configs: BTreeMap<i32, RaftConfig>,
+        nodes: BTreeMap<i32, Arc<RaftNode>>,
+    }
+
+    impl Quorum {
+        fn new(snapshot_max_records: i64) -> Self {
+            let dir = std::env::temp_dir().join(format!("rafka-quorum-{}", Uuid::new_v4()));
+            let voters: BTreeMap<i32, String> = (1..=3)
+                .map(|id| {
+                    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+                    (id, listener.local_addr().unwrap().to_string())
+                })
+                .collect();
+            let configs = voters
+                .keys()
+                .map(|id| {
+                    let mut config = RaftConfig::new(*id, dir.join(id.to_string()), voters.clone());
+                    config.election_timeout_ms = 150;
+                    config.heartbeat_interval_ms = 30;
+                    config.snapshot_max_records = snapshot_max_records;
+                    config.log_segment_bytes = 300;
+                    (*id, config)
+                })
+                .collect();
+            Quorum { dir, configs, nodes: BTreeMap::new() }
+        }
+
+        async fn start(&mut self, id: i32) {
+            let node = RaftNode::start(self.configs[&id].clone()).await.unwrap();
+            self.nodes.insert(id, node);
+        }
+
+        async fn start_all(&mut self) {
+            for id in 1..=3 {
+                self.start(id).await;
+            }
+        }
+
+        // stops the node and waits until it's gone, so its log can be opened again
+        async fn crash(&mut self, id: i32) {
+            let node = self.nodes.remove(&id).unwrap();
+            node.stop();
+            wait_until(|| async { Arc::strong_count(&node) == 1 }).await;
+        }
+
+        async fn leader(&self) -> Arc<RaftNode> {
+            wait_until(|| async { self.nodes.values().any(|node| node.active_term().is_some()) }).await;
+            self.nodes.values().find(|node| node.active_term().is_some()).cloned().unwrap()
+        }
+
+        async fn propose(&self, name: &str) -> i64 {
+            let leader = self.leader().await;
+            let term = leader.active_term().unwrap();
+            leader.propose(term, vec![topic(name)]).await.unwrap()
+        }
+
+        async fn wait_for_topic(&self, id: i32, name: &str) {
+            let node = &self.nodes[&id];
+            wait_until(|| async { node.image().read().await.topics.contains_key(name) }).await;
+        }
+    }
+
+    impl Drop for Quorum {
+        fn drop(&mut self) {
+            for node in self.nodes.values() {
+                node.stop();
+            }
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn topic(name: &str) -> MetadataRecord {
+        MetadataRecord::Topic { name: name.to_string(), topic_id: Uuid::new_v4() }
+    }
+
+    async fn wait_until<F, Fut>(mut condition: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let deadline = Instant::now() + TIMEOUT;
+        while !condition().await {
+            assert!(Instant::now() < deadline, "condition not met in time");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn end_offset(node: &RaftNode) -> i64 {
+        node.state.lock().await.log.end_offset()
+    }
+
+    #[tokio::test]
+    async fn elects_a_leader_and_replicates_to_every_voter() {
+        let mut quorum = Quorum::new(METADATA_SNAPSHOT_MAX_RECORDS);
+        quorum.start_all().await;
+
+        let leader = quorum.leader().await;
+        quorum.propose("orders").await;
+        for id in 1..=3 {
+            quorum.wait_for_topic(id, "orders").await;
+        }
+        let term = leader.active_term().unwrap();
+        for node in quorum.nodes.values().filter(|node| node.node_id() != leader.node_id()) {
+            let status = node.status();
+            assert_eq!((status.role, status.term, status.leader_id), (Role::Follower, term, Some(leader.node_id())));
+        }
+    }
+
+    #[tokio::test]
+    async fn another_voter_takes_over_when_the_leader_crashes() {
+        let mut quorum = Quorum::new(METADATA_SNAPSHOT_MAX_RECORDS);
+        quorum.start_all().await;
+        let old_leader = quorum.leader().await;
+        let (old_id, old_term) = (old_leader.node_id(), old_leader.active_term().unwrap());
+        drop(old_leader);
+        quorum.propose("orders").await;
+
+        quorum.crash(old_id).await;
+        let leader = quorum.leader().await;
+        assert_ne!(leader.node_id(), old_id);
+        assert!(leader.active_term().unwrap() > old_term);
+        // committed entries survive the change of leader
+        assert!(leader.image().read().await.topics.contains_key("orders"));
+        drop(leader);
+        quorum.propose("payments").await;
+
+        // the old leader recovers its log from disk and catches up
+        quorum.start(old_id).await;
+        quorum.wait_for_topic(old_id, "payments").await;
+        assert!(quorum.nodes[&old_id].image().read().await.topics.contains_key("orders"));
+    }
+
+    #[tokio::test]
+    async fn follower_truncates_entries_the_quorum_never_committed() {
+        let mut quorum = Quorum::new(METADATA_SNAPSHOT_MAX_RECORDS);
+        quorum.start_all().await;
+        let leader = quorum.leader().await;
+        let (leader_id, term) = (leader.node_id(), leader.active_term().unwrap());
+        let followers: Vec<i32> = (1..=3).filter(|id| *id != leader_id).collect();
+        for id in &followers {
+            quorum.crash(*id).await;
+        }
+
+        // the leader appends an entry no other voter gets
+        let lost_offset = end_offset(&leader).await;
+        let proposal = tokio::spawn({
+            let leader = Arc::clone(&leader);
+            async move { leader.propose(term, vec![topic("lost")]).await }
+        });
+        wait_until(|| async { end_offset(&leader).await > lost_offset }).await;
+        drop(leader);
+        quorum.crash(leader_id).await;
+        assert!(matches!(proposal.await.unwrap(), Err(RaftError::NotLeader(_))));
+
+        // the other two move on without it and write something else at that offset
+        for id in &followers {
+            quorum.start(*id).await;
+        }
+        quorum.propose("kept").await;
+
+        quorum.start(leader_id).await;
+        quorum.wait_for_topic(leader_id, "kept").await;
+        let node = &quorum.nodes[&leader_id];
+        assert!(!node.image().read().await.topics.contains_key("lost"));
+        assert!(node.state.lock().await.log.term_at(lost_offset).unwrap() > term);
+    }
+
+    #[tokio::test]
+    async fn lagging_follower_installs_the_leaders_snapshot() {
+        let mut quorum = Quorum::new(4);
+        quorum.start_all().await;
+        let leader = quorum.leader().await;
+        let lagging = (1..=3).find(|id| *id != leader.node_id()).unwrap();
+        drop(leader);
+        let lagging_end = end_offset(&quorum.nodes[&lagging]).await;
+        quorum.crash(lagging).await;
+
+        let names: Vec<String> = (0..10).map(|i| format!("topic-{}", i)).collect();
+        for name in &names {
+            quorum.propose(name).await;
+        }
+        // the leader has compacted away the entries the follower is missing
+        let leader = quorum.leader().await;
+        assert!(leader.state.lock().await.log.snapshot_offset() >= lagging_end);
+        drop(leader);
+
+        quorum.start(lagging).await;
+        for name in &names {
+            quorum.wait_for_topic(lagging, name).await;
+        }
+        let state = quorum.nodes[&lagging].state.lock().await;
+        assert!(state.snapshot.is_some());
+    }
+}
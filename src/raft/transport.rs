@@ -0,0 +1,102 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{
+    constants::{MAX_MESSAGE_SIZE, QUORUM_REQUEST_TIMEOUT_MS},
+    error::ServerError,
+    network::handler::MessageParser,
+    raft::messages::{RaftRequest, RaftResponse},
+    raft::node::RaftNode,
+    raft::RaftError,
+};
+
+// quorum messages travel as a size-prefixed JSON document
+
+async fn write_message<T: Serialize>(stream: &mut TcpStream, message: &T) -> Result<(), RaftError> {
+    let body = serde_json::to_vec(message)?;
+    stream.write_all(&(body.len() as i32).to_be_bytes()).await?;
+    stream.write_all(&body).await?;
+    Ok(())
+}
+
+async fn read_message<T: DeserializeOwned>(stream: &mut TcpStream) -> Result<T, RaftError> {
+    let size = MessageParser::read_i32_async(stream).await?;
+    if size <= 0 || size as usize > MAX_MESSAGE_SIZE {
+        return Err(ServerError::InvalidMessageSize(size).into());
+    }
+    let body = MessageParser::read_exact_bytes_async(stream, size as usize).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// connection to another quorum node, opened on first use and dropped on errors
+pub struct RaftClient {
+    address: String,
+    stream: Option<TcpStream>,
+}
+
+impl RaftClient {
+    pub fn new(address: &str) -> Self {
+        RaftClient { address: address.to_string(), stream: None }
+    }
+
+    pub async fn send(&mut self, request: &RaftRequest) -> Result<RaftResponse, RaftError> {
+        let timeout = Duration::from_millis(QUORUM_REQUEST_TIMEOUT_MS);
+        let result = match tokio::time::timeout(timeout, self.round_trip(request)).await {
+            Ok(result) => result,
+            Err(_) => Err(RaftError::Timeout(self.address.clone())),
+        };
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+
+    async fn round_trip(&mut self, request: &RaftRequest) -> Result<RaftResponse, RaftError> {
+        if self.stream.is_none() {
+            let stream = TcpStream::connect(&self.address).await?;
+            stream.set_nodelay(true)?;
+            self.stream = Some(stream);
+        }
+        let stream = self.stream.as_mut().unwrap();
+        write_message(stream, request).await?;
+        read_message(stream).await
+    }
+}
+
+/// answers Vote, AppendEntries and InstallSnapshot from the other quorum nodes
+pub async fn serve(listener: TcpListener, node: Arc<RaftNode>) {
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Quorum listener error: {}", e);
+                continue;
+            }
+        };
+        let node = Arc::clone(&node);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, node).await {
+                if !matches!(e, RaftError::Transport(ServerError::IoError(_))) {
+                    eprintln!("Quorum connection error: {}", e);
+                }
+            }
+        });
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, node: Arc<RaftNode>) -> Result<(), RaftError> {
+    stream.set_nodelay(true)?;
+    loop {
+        let request: RaftRequest = read_message(&mut stream).await?;
+        // a stopped node goes silent, like a crashed one
+        let Some(response) = node.handle(request).await? else {
+            return Ok(());
+        };
+        write_message(&mut stream, &response).await?;
+    }
+}
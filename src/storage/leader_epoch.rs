@@ -0,0 +1,205 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub const LEADER_EPOCH_CHECKPOINT_FILE: &str = "leader-epoch-checkpoint";
+const CHECKPOINT_VERSION: i32 = 0;
+
+// what OffsetForLeaderEpoch returns when the requested epoch predates everything known
+pub const UNDEFINED_EPOCH: i32 = -1;
+pub const UNDEFINED_EPOCH_OFFSET: i64 = -1;
+
+// start offset of every leader epoch a partition's log has seen, oldest first, persisted
+// as Kafka's leader-epoch-checkpoint: a version line, a count, then "epoch start_offset" lines
+#[derive(Debug)]
+pub struct LeaderEpochCache {
+    path: PathBuf,
+    entries: Vec<(i32, i64)>,
+}
+
+impl LeaderEpochCache {
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(LEADER_EPOCH_CHECKPOINT_FILE);
+        let entries = match fs::read_to_string(&path) {
+            Ok(contents) => parse_checkpoint(&contents)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("malformed {}", path.display())))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path, entries })
+    }
+
+    pub fn latest_epoch(&self) -> Option<i32> {
+        self.entries.last().map(|(epoch, _)| *epoch)
+    }
+
+    /// records that `epoch` starts at `start_offset`; older or repeated epochs are ignored
+    pub fn assign(&mut self, epoch: i32, start_offset: i64) -> io::Result<()> {
+        if self.latest_epoch().is_some_and(|latest| epoch <= latest) {
+            return Ok(());
+        }
+        // an epoch that never got any records is superseded by this one
+        self.entries.retain(|(_, offset)| *offset < start_offset);
+        self.entries.push((epoch, start_offset));
+        self.flush()
+    }
+
+    /// the largest epoch <= `epoch` and the offset where it ended: the start of the
+    /// next epoch, or `log_end_offset` for the latest one
+    pub fn end_offset_for(&self, epoch: i32, log_end_offset: i64) -> (i32, i64) {
+        let Some(index) = self.entries.iter().rposition(|(e, _)| *e <= epoch) else {
+            return (UNDEFINED_EPOCH, UNDEFINED_EPOCH_OFFSET);
+        };
+        let end_offset = self.entries.get(index + 1).map_or(log_end_offset, |(_, start)| *start);
+        (self.entries[index].0, end_offset)
+    }
+
+    /// forgets epochs starting at or after `end_offset`, after the log was truncated there
+    pub fn truncate_from_end(&mut self, end_offset: i64) -> io::Result<()> {
+        let before = self.entries.len();
+        self.entries.retain(|(_, start)| *start < end_offset);
+        if self.entries.len() != before {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// forgets every epoch, for a log that starts over empty
+    pub fn clear(&mut self) -> io::Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        self.entries.clear();
+        self.flush()
+    }
+
+    // write-then-rename so a crash leaves either the old or the new checkpoint
+    fn flush(&self) -> io::Result<()> {
+        let mut contents = format!("{}\n{}\n", CHECKPOINT_VERSION, self.entries.len());
+        for (epoch, start_offset) in &self.entries {
+            contents.push_str(&format!("{} {}\n", epoch, start_offset));
+        }
+
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+fn parse_checkpoint(contents: &str) -> Option<Vec<(i32, i64)>> {
+    let mut lines = contents.lines();
+    if lines.next()?.trim().parse::<i32>().ok()? != CHECKPOINT_VERSION {
+        return None;
+    }
+    let count: usize = lines.next()?.trim().parse().ok()?;
+    // every entry takes a line, so a count past the lines left can't be right
+    let lines: Vec<&str> = lines.collect();
+    if count > lines.len() {
+        return None;
+    }
+    let mut entries = Vec::with_capacity(count);
+    for line in lines.into_iter().take(count) {
+        let mut fields = line.split_whitespace();
+        entries.push((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?));
+    }
+    (entries.len() == count).then_some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rafka-epochs-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn assign_keeps_epochs_in_order() {
+        let dir = temp_dir();
+        let mut cache = LeaderEpochCache::load(&dir).unwrap();
+        assert_eq!(cache.latest_epoch(), None);
+
+        cache.assign(0, 0).unwrap();
+        cache.assign(2, 10).unwrap();
+        // older or repeated epochs change nothing
+        cache.assign(1, 20).unwrap();
+        cache.assign(2, 30).unwrap();
+        assert_eq!(cache.entries, vec![(0, 0), (2, 10)]);
+
+        // epoch 3 never got a record before epoch 4 took over at the same offset
+        cache.assign(3, 15).unwrap();
+        cache.assign(4, 15).unwrap();
+        assert_eq!(cache.entries, vec![(0, 0), (2, 10), (4, 15)]);
+        assert_eq!(cache.latest_epoch(), Some(4));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn end_offset_for_finds_the_largest_epoch_at_or_below() {
+        let dir = temp_dir();
+        let mut cache = LeaderEpochCache::load(&dir).unwrap();
+        cache.assign(1, 5).unwrap();
+        cache.assign(3, 12).unwrap();
+
+        assert_eq!(cache.end_offset_for(0, 20), (UNDEFINED_EPOCH, UNDEFINED_EPOCH_OFFSET));
+        assert_eq!(cache.end_offset_for(1, 20), (1, 12));
+        // epoch 2 was never used, so the answer is about epoch 1
+        assert_eq!(cache.end_offset_for(2, 20), (1, 12));
+        assert_eq!(cache.end_offset_for(3, 20), (3, 20));
+        assert_eq!(cache.end_offset_for(9, 20), (3, 20));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncate_from_end_drops_epochs_starting_at_or_past_the_offset() {
+        let dir = temp_dir();
+        let mut cache = LeaderEpochCache::load(&dir).unwrap();
+        cache.assign(1, 0).unwrap();
+        cache.assign(2, 8).unwrap();
+        cache.assign(3, 12).unwrap();
+
+        cache.truncate_from_end(12).unwrap();
+        assert_eq!(cache.entries, vec![(1, 0), (2, 8)]);
+        cache.truncate_from_end(9).unwrap();
+        assert_eq!(cache.entries, vec![(1, 0), (2, 8)]);
+        cache.truncate_from_end(0).unwrap();
+        assert_eq!(cache.latest_epoch(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_is_reloaded() {
+        let dir = temp_dir();
+        let mut cache = LeaderEpochCache::load(&dir).unwrap();
+        cache.assign(1, 0).unwrap();
+        cache.assign(4, 7).unwrap();
+        cache.truncate_from_end(7).unwrap();
+        cache.assign(5, 7).unwrap();
+        drop(cache);
+
+        assert_eq!(fs::read_to_string(dir.join(LEADER_EPOCH_CHECKPOINT_FILE)).unwrap(), "0\n2\n1 0\n5 7\n");
+        let cache = LeaderEpochCache::load(&dir).unwrap();
+        assert_eq!(cache.entries, vec![(1, 0), (5, 7)]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn malformed_checkpoints_are_rejected() {
+        assert_eq!(parse_checkpoint("0\n1\n3 10\n"), Some(vec![(3, 10)]));
+        // a count no file could hold is refused before anything is allocated for it
+        assert_eq!(parse_checkpoint(&format!("0\n{}\n3 10\n", usize::MAX)), None);
+        assert_eq!(parse_checkpoint("0\n2\n3 10\n"), None);
+        assert_eq!(parse_checkpoint("1\n0\n"), None);
+        assert_eq!(parse_checkpoint("0\n1\n3\n"), None);
+
+        let dir = temp_dir();
+        fs::write(dir.join(LEADER_EPOCH_CHECKPOINT_FILE), "0\n5\n1 0\n").unwrap();
+        let error = LeaderEpochCache::load(&dir).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -2,6 +2,12 @@ use std::path::{PathBuf};
 use std::io::{self, Seek, SeekFrom, Write, Read};
 use std::fs::{File, OpenOptions, create_dir_all};
 use fs2::FileExt;
+use chrono::Utc;
+
+use crate::constants::{EARLIEST_TIMESTAMP, LATEST_TIMESTAMP, LOG_INDEX_INTERVAL_BYTES};
+use crate::storage::leader_epoch::LeaderEpochCache;
+use crate::storage::record_batch;
+use crate::storage::producer_state::{ProducerBatch, ProducerStateError, ProducerStateManager};
 
 // entire commit log for a single partition
 #[derive(Debug)]
@@ -11,6 +17,8 @@ pub struct Log {
     segments: Vec<LogSegment>,
     max_segment_size: u64,
     next_offset: i64, // gotta track next logical offset
+    leader_epoch_cache: LeaderEpochCache,
+    producer_state: Option<ProducerStateManager>, // only tracked for partition logs
 }
 
 // single file on disk storing a contiguous block of messages
@@ -21,6 +29,75 @@ pub struct LogSegment {
     path: PathBuf,
     position: u64,
     message_count: u64, // for tracking messages in this segment
+    next_offset: i64, // a record batch entry spans several offsets
+    time_index: File, // (timestamp, offset) pairs, one per new max timestamp
+    max_timestamp: i64,
+    txn_index: File, // aborted transactions whose ABORT marker is in this segment
+    offset_index: Vec<(i64, u64)>, // (offset, position) of an entry every LOG_INDEX_INTERVAL_BYTES, in memory
+    bytes_since_last_index_entry: u64,
+}
+
+/// a transaction an ABORT marker ended, as kept in the `.txnindex` of the segment
+/// holding the marker. Read_committed consumers skip its batches from `first_offset` on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AbortedTxn {
+    pub producer_id: i64,
+    pub first_offset: i64,
+    pub last_offset: i64, // the marker's
+    pub last_stable_offset: i64, // the partition's LSO once the marker was written
+}
+
+impl AbortedTxn {
+    // version(2) producerId(8) firstOffset(8) lastOffset(8) lastStableOffset(8)
+    fn encode(&self) -> [u8; TXN_INDEX_ENTRY_SIZE] {
+        let mut entry = [0u8; TXN_INDEX_ENTRY_SIZE];
+        entry[..2].copy_from_slice(&0i16.to_be_bytes());
+        entry[2..10].copy_from_slice(&self.producer_id.to_be_bytes());
+        entry[10..18].copy_from_slice(&self.first_offset.to_be_bytes());
+        entry[18..26].copy_from_slice(&self.last_offset.to_be_bytes());
+        entry[26..].copy_from_slice(&self.last_stable_offset.to_be_bytes());
+        entry
+    }
+
+    fn decode(entry: &[u8]) -> Self {
+        let read = |pos: usize| i64::from_be_bytes(entry[pos..pos + 8].try_into().unwrap());
+        AbortedTxn {
+            producer_id: read(2),
+            first_offset: read(10),
+            last_offset: read(18),
+            last_stable_offset: read(26),
+        }
+    }
+}
+
+const TIME_INDEX_ENTRY_SIZE: usize = 16;
+const TXN_INDEX_ENTRY_SIZE: usize = 34;
+
+// segments being removed are renamed to this first, so a crash can't leave a
+// half-deleted segment that still looks live
+const DELETED_SUFFIX: &str = "deleted";
+
+fn time_index_path(log_path: &std::path::Path) -> PathBuf {
+    log_path.with_extension("timeindex")
+}
+
+fn txn_index_path(log_path: &std::path::Path) -> PathBuf {
+    log_path.with_extension("txnindex")
+}
+
+// offsets an entry covers: a v2 record batch spans its records, anything else one
+fn entry_record_count(data: &[u8]) -> i64 {
+    match record_batch::magic(data) {
+        Some(2) => record_batch::record_count(data).unwrap_or(1),
+        _ => 1,
+    }
+}
+
+fn deleted_path(path: &std::path::Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(DELETED_SUFFIX);
+    PathBuf::from(name)
 }
 
 impl LogSegment {
@@ -35,16 +112,168 @@ impl LogSegment {
 
         let position = file.metadata()?.len();
 
+        let mut time_index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(time_index_path(&path))?;
+
+        // the last entry always holds the largest timestamp seen so far
+        let mut max_timestamp = i64::MIN;
+        let index_len = time_index.metadata()?.len();
+        if index_len >= TIME_INDEX_ENTRY_SIZE as u64 {
+            let last_entry = index_len - index_len % TIME_INDEX_ENTRY_SIZE as u64 - TIME_INDEX_ENTRY_SIZE as u64;
+            time_index.seek(SeekFrom::Start(last_entry))?;
+            let mut ts_buf = [0u8; 8];
+            time_index.read_exact(&mut ts_buf)?;
+            max_timestamp = i64::from_be_bytes(ts_buf);
+        }
+
+        let txn_index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(txn_index_path(&path))?;
+
         Ok(Self {
             base_offset,
             file,
             path,
             position,
             message_count: 0,
+            next_offset: base_offset,
+            time_index,
+            max_timestamp,
+            txn_index,
+            offset_index: Vec::new(),
+            bytes_since_last_index_entry: 0,
         })
     }
 
-    pub fn write_message(&mut self, offset: i64, data: &[u8]) -> io::Result<u64> {
+    /// reopens a segment written before a restart, finding where its entries end.
+    /// A torn entry at the tail, left by a crash mid-write, is cut off.
+    fn open(base_offset: i64, path: PathBuf) -> io::Result<Self> {
+        let mut segment = LogSegment::new(base_offset, path)?;
+        let mut data = Vec::new();
+        segment.file.seek(SeekFrom::Start(0))?;
+        segment.file.read_to_end(&mut data)?;
+
+        let mut position = 0usize;
+        while position + 12 <= data.len() {
+            let total_len = u32::from_be_bytes(data[position..position + 4].try_into().unwrap()) as usize;
+            if total_len < 8 || position + 4 + total_len > data.len() {
+                break;
+            }
+            let offset = i64::from_be_bytes(data[position + 4..position + 12].try_into().unwrap());
+            segment.next_offset = offset + entry_record_count(&data[position + 12..position + 4 + total_len]);
+            segment.message_count += 1;
+            segment.index_position(offset, position as u64, 4 + total_len as u64);
+            position += 4 + total_len;
+        }
+        if position as u64 != segment.position {
+            segment.file.set_len(position as u64)?;
+            segment.position = position as u64;
+        }
+        segment.truncate_txn_index(segment.next_offset)?;
+        Ok(segment)
+    }
+
+    // notes where the entry at `offset` starts once enough bytes were written since
+    // the last noted one; the first entry is always noted
+    fn index_position(&mut self, offset: i64, position: u64, entry_size: u64) {
+        if self.offset_index.is_empty() || self.bytes_since_last_index_entry >= LOG_INDEX_INTERVAL_BYTES {
+            self.offset_index.push((offset, position));
+            self.bytes_since_last_index_entry = 0;
+        }
+        self.bytes_since_last_index_entry += entry_size;
+    }
+
+    // position of the last indexed entry at or before `offset`, where a reader
+    // looking for it starts
+    fn lookup_position(&self, offset: i64) -> u64 {
+        let index = self.offset_index.partition_point(|(indexed, _)| *indexed <= offset);
+        index.checked_sub(1).map_or(0, |index| self.offset_index[index].1)
+    }
+
+    // forgets the positions at or past `position`, once the file was cut there
+    fn truncate_offset_index(&mut self, position: u64) {
+        self.offset_index.retain(|(_, indexed)| *indexed < position);
+        // the next entry written is indexed right away, the file end is not known to be covered
+        self.bytes_since_last_index_entry = LOG_INDEX_INTERVAL_BYTES;
+    }
+
+    fn index_timestamp(&mut self, offset: i64, timestamp: i64) -> io::Result<()> {
+        if timestamp <= self.max_timestamp {
+            return Ok(());
+        }
+        let mut entry = [0u8; TIME_INDEX_ENTRY_SIZE];
+        entry[..8].copy_from_slice(&timestamp.to_be_bytes());
+        entry[8..].copy_from_slice(&offset.to_be_bytes());
+        self.time_index.write_all(&entry)?;
+        self.max_timestamp = timestamp;
+        Ok(())
+    }
+
+    // largest timestamp indexed at or before `offset`. The first entry of a segment
+    // is always indexed, so any offset the segment holds has one
+    fn indexed_timestamp_at(&mut self, offset: i64) -> io::Result<Option<i64>> {
+        self.time_index.seek(SeekFrom::Start(0))?;
+        let mut index = Vec::new();
+        self.time_index.read_to_end(&mut index)?;
+        Ok(index
+            .chunks_exact(TIME_INDEX_ENTRY_SIZE)
+            .take_while(|entry| i64::from_be_bytes(entry[8..].try_into().unwrap()) <= offset)
+            .last()
+            .map(|entry| i64::from_be_bytes(entry[..8].try_into().unwrap())))
+    }
+
+    fn append_aborted_txn(&mut self, txn: &AbortedTxn) -> io::Result<()> {
+        self.txn_index.write_all(&txn.encode())
+    }
+
+    // every aborted transaction in the txn index, in marker order
+    fn aborted_txns(&mut self) -> io::Result<Vec<AbortedTxn>> {
+        self.txn_index.seek(SeekFrom::Start(0))?;
+        let mut index = Vec::new();
+        self.txn_index.read_to_end(&mut index)?;
+        Ok(index.chunks_exact(TXN_INDEX_ENTRY_SIZE).map(AbortedTxn::decode).collect())
+    }
+
+    // forgets aborted transactions whose marker is at or past `end_offset`
+    fn truncate_txn_index(&mut self, end_offset: i64) -> io::Result<()> {
+        let kept = self.aborted_txns()?.iter().take_while(|txn| txn.last_offset < end_offset).count();
+        let index_len = (kept * TXN_INDEX_ENTRY_SIZE) as u64;
+        if index_len != self.txn_index.metadata()?.len() {
+            self.txn_index.set_len(index_len)?;
+            self.txn_index.sync_all()?;
+        }
+        Ok(())
+    }
+
+    // first offset whose timestamp is >= the target, if this segment has one
+    pub fn offset_for_timestamp(&mut self, timestamp: i64) -> io::Result<Option<i64>> {
+        if self.max_timestamp < timestamp {
+            return Ok(None);
+        }
+
+        self.time_index.seek(SeekFrom::Start(0))?;
+        let mut reader = io::BufReader::new(&self.time_index);
+        let mut entry = [0u8; TIME_INDEX_ENTRY_SIZE];
+        loop {
+            match reader.read_exact(&mut entry) {
+                Ok(()) => {
+                    let entry_ts = i64::from_be_bytes(entry[..8].try_into().unwrap());
+                    if entry_ts >= timestamp {
+                        return Ok(Some(i64::from_be_bytes(entry[8..].try_into().unwrap())));
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn write_message(&mut self, offset: i64, record_count: i64, data: &[u8]) -> io::Result<u64> {
         let pos = self.position;
 
         let total_len = (8 + data.len() as u32).to_be_bytes();
@@ -55,6 +284,8 @@ impl LogSegment {
         self.file.write_all(data)?;
         self.position += 4 + 8 + data.len() as u64;
         self.message_count += 1;
+        self.index_position(offset, pos, 4 + 8 + data.len() as u64);
+        self.next_offset = offset + record_count;
 
         Ok(pos)
     }
@@ -90,6 +321,42 @@ impl LogSegment {
         Ok(messages)
     }
 
+    /// entries from the one containing `offset` on, starting at the indexed position
+    /// closest before it. Stops before an entry that would take the total past
+    /// `max_bytes`, unless `messages` is still empty.
+    fn read_from(&mut self, offset: i64, max_bytes: usize, messages: &mut Vec<(i64, Vec<u8>)>, bytes: &mut usize) -> io::Result<bool> {
+        let start = self.lookup_position(offset);
+        self.file.seek(SeekFrom::Start(start))?;
+        let mut reader = io::BufReader::new((&self.file).take(self.position - start));
+
+        loop {
+            let mut header = [0u8; 12];
+            match reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(e) => return Err(e),
+            }
+            let total_len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+            if total_len < 8 {
+                return Ok(false);
+            }
+            let msg_offset = i64::from_be_bytes(header[4..].try_into().unwrap());
+            let data_len = total_len - 8;
+            if !messages.is_empty() && *bytes + data_len > max_bytes {
+                return Ok(true);
+            }
+
+            let mut data = vec![0u8; data_len];
+            reader.read_exact(&mut data)?;
+            // entries between the indexed one and `offset`
+            if msg_offset + entry_record_count(&data) <= offset {
+                continue;
+            }
+            *bytes += data_len;
+            messages.push((msg_offset, data));
+        }
+    }
+
     pub fn truncate_before(&mut self, offset: i64) -> io::Result<()> {
         if offset <= self.base_offset {
             return Ok(());
@@ -132,11 +399,56 @@ impl LogSegment {
         // truncate file
         self.file.set_len(truncate_pos)?;
         self.position = truncate_pos;
+        self.truncate_offset_index(truncate_pos);
+        Ok(())
+    }
+
+    // drops the entry containing `offset` and everything after it. The index is cut
+    // before the log so it never points past the end of the data.
+    fn truncate_from(&mut self, offset: i64) -> io::Result<()> {
+        let entries = self.read_all()?;
+        let mut position = 0u64;
+        let mut kept = 0u64;
+        let mut next_offset = self.base_offset;
+        let ends: Vec<i64> = entries.iter().skip(1).map(|(o, _)| *o).chain([self.next_offset]).collect();
+        for ((entry_offset, data), end) in entries.iter().zip(ends) {
+            if end > offset {
+                next_offset = *entry_offset;
+                break;
+            }
+            position += 4 + 8 + data.len() as u64;
+            kept += 1;
+            next_offset = end;
+        }
+
+        // keep only index entries that still point into the segment
+        self.time_index.seek(SeekFrom::Start(0))?;
+        let mut index = Vec::new();
+        self.time_index.read_to_end(&mut index)?;
+        let mut index_len = 0;
+        self.max_timestamp = i64::MIN;
+        for entry in index.chunks_exact(TIME_INDEX_ENTRY_SIZE) {
+            if i64::from_be_bytes(entry[8..].try_into().unwrap()) >= next_offset {
+                break;
+            }
+            self.max_timestamp = i64::from_be_bytes(entry[..8].try_into().unwrap());
+            index_len += TIME_INDEX_ENTRY_SIZE as u64;
+        }
+        self.time_index.set_len(index_len)?;
+        self.time_index.sync_all()?;
+        self.truncate_txn_index(next_offset)?;
+
+        self.file.set_len(position)?;
+        self.file.sync_all()?;
+        self.position = position;
+        self.truncate_offset_index(position);
+        self.message_count = kept;
+        self.next_offset = next_offset;
         Ok(())
     }
 
     pub fn last_offset(&self) -> i64 {
-        self.base_offset + self.message_count as i64 - 1
+        self.next_offset - 1
     }
 }
 
@@ -147,32 +459,76 @@ impl Drop for LogSegment {
 }
 
 impl Log {
+    /// opens the log in `dir`, recovering the segments already in it. An empty
+    /// directory starts a log at `base_offset`.
     pub fn new(dir: PathBuf, base_offset: i64, max_segment_size: u64) -> io::Result<Self> {
         create_dir_all(&dir)?;
-        let path = dir.join(format!("{:020}.log", base_offset));
-        let active_segment = LogSegment::new(base_offset, path.clone())?;
+        // finish deletions a crash interrupted
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == DELETED_SUFFIX) {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        // segments left by an earlier run are picked up again, the newest stays active
+        let mut segment_paths = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "log") {
+                continue;
+            }
+            if let Some(offset) = path.file_stem().and_then(|stem| stem.to_str()?.parse::<i64>().ok()) {
+                segment_paths.push((offset, path));
+            }
+        }
+        segment_paths.sort_unstable();
+        let mut segments = Vec::with_capacity(segment_paths.len());
+        for (offset, path) in segment_paths {
+            segments.push(LogSegment::open(offset, path)?);
+        }
+        let active_segment = match segments.pop() {
+            Some(segment) => segment,
+            None => LogSegment::new(base_offset, dir.join(format!("{:020}.log", base_offset)))?,
+        };
+        let leader_epoch_cache = LeaderEpochCache::load(&dir)?;
 
         Ok(Self {
             dir,
+            next_offset: active_segment.next_offset,
             active_segment,
-            segments: vec![],
+            segments,
             max_segment_size,
-            next_offset: base_offset,
+            leader_epoch_cache,
+            producer_state: None,
         })
     }
 
     pub fn append(&mut self, data: &[u8]) -> io::Result<i64> {
+        self.append_with_timestamp(data, Utc::now().timestamp_millis())
+    }
+
+    pub fn append_with_timestamp(&mut self, data: &[u8], timestamp: i64) -> io::Result<i64> {
+        self.append_batch(data, 1, timestamp)
+    }
+
+    /// appends one entry covering `record_count` offsets, e.g. a whole record batch,
+    /// and returns its base offset
+    pub fn append_batch(&mut self, data: &[u8], record_count: i64, timestamp: i64) -> io::Result<i64> {
         if self.active_segment.position + 4 + 8 + data.len() as u64 > self.max_segment_size {
             // rotate segment - use proper next offset
             let next_base_offset = self.next_offset;
             let new_path = self.dir.join(format!("{:020}.log", next_base_offset));
             let new_segment = LogSegment::new(next_base_offset, new_path)?;
+            if let Some(producer_state) = &self.producer_state {
+                producer_state.take_snapshot(next_base_offset)?;
+            }
             self.segments.push(std::mem::replace(&mut self.active_segment, new_segment));
         }
 
         let offset = self.next_offset;
-        self.active_segment.write_message(offset, data)?;
-        self.next_offset += 1;
+        self.active_segment.write_message(offset, record_count.max(1), data)?;
+        self.active_segment.index_timestamp(offset, timestamp)?;
+        self.next_offset = self.active_segment.next_offset;
 
         // flush once on rotation, not on every message for performance
         if self.active_segment.message_count == 1 {
@@ -182,6 +538,82 @@ impl Log {
         Ok(offset)
     }
 
+    /// appends a v2 record batch whose base offset is already set to the log end,
+    /// keeping track of the producer that wrote it. An ABORT marker adds the
+    /// transaction it ends to the active segment's txn index.
+    pub fn append_record_batch(&mut self, batch: &[u8], timestamp: i64) -> io::Result<i64> {
+        let offset = self.append_batch(batch, record_batch::record_count(batch).unwrap_or(1), timestamp)?;
+        let (Some(producer_state), Some(producer_batch)) = (&mut self.producer_state, ProducerBatch::from_batch(batch)) else {
+            return Ok(offset);
+        };
+        if let Some(completed) = producer_state.update(&producer_batch).filter(|txn| txn.is_aborted) {
+            self.active_segment.append_aborted_txn(&AbortedTxn {
+                producer_id: completed.producer_id,
+                first_offset: completed.first_offset,
+                last_offset: completed.last_offset,
+                last_stable_offset: producer_state.first_unstable_offset().unwrap_or(completed.last_offset + 1),
+            })?;
+        }
+        Ok(offset)
+    }
+
+    /// starts tracking producers: loads the newest producer snapshot and replays the
+    /// batches written after it
+    pub fn load_producer_state(&mut self) -> io::Result<()> {
+        let (mut producer_state, replay_from) = ProducerStateManager::load(&self.dir, self.next_offset)?;
+        for (offset, batch) in self.read_from(replay_from.max(self.log_start_offset()), usize::MAX)? {
+            if offset < replay_from {
+                continue;
+            }
+            if let Some(producer_batch) = ProducerBatch::from_batch(&batch) {
+                producer_state.update(&producer_batch);
+            }
+        }
+        self.producer_state = Some(producer_state);
+        Ok(())
+    }
+
+    /// first offset of the oldest transaction without a marker yet, if any is open
+    pub fn first_unstable_offset(&self) -> Option<i64> {
+        self.producer_state.as_ref()?.first_unstable_offset()
+    }
+
+    /// aborted transactions that overlap the offsets from `fetch_offset` up to
+    /// `upper_bound`, for a read_committed fetch of that range. Their markers can be
+    /// in any later segment, so every segment from the one holding `fetch_offset` on
+    /// is searched.
+    pub fn collect_aborted_txns(&mut self, fetch_offset: i64, upper_bound: i64) -> io::Result<Vec<AbortedTxn>> {
+        let mut aborted = Vec::new();
+        for segment in self.segments.iter_mut().chain(std::iter::once(&mut self.active_segment)) {
+            if segment.next_offset <= fetch_offset {
+                continue;
+            }
+            aborted.extend(
+                segment
+                    .aborted_txns()?
+                    .into_iter()
+                    .filter(|txn| txn.last_offset >= fetch_offset && txn.first_offset < upper_bound),
+            );
+        }
+        Ok(aborted)
+    }
+
+    /// refuses duplicate, out of order or fenced batches from idempotent producers
+    pub fn check_producer_batches(&self, batches: &[ProducerBatch]) -> Result<(), ProducerStateError> {
+        match &self.producer_state {
+            Some(producer_state) => producer_state.check_all(batches),
+            None => Ok(()),
+        }
+    }
+
+    /// snapshots producer state as of the log end, e.g. before shutting down
+    pub fn take_producer_snapshot(&self) -> io::Result<()> {
+        match &self.producer_state {
+            Some(producer_state) => producer_state.take_snapshot(self.next_offset),
+            None => Ok(()),
+        }
+    }
+
     pub fn read_active_segment(&mut self) -> io::Result<Vec<(i64, Vec<u8>)>> {
         self.active_segment.read_all()
     }
@@ -212,13 +644,32 @@ impl Log {
         Ok(None)
     }
 
+    /// entries from the one containing `offset` on until `max_bytes` is reached; the
+    /// first one is always included so an oversized entry can't stall a reader
+    pub fn read_from(&mut self, offset: i64, max_bytes: usize) -> io::Result<Vec<(i64, Vec<u8>)>> {
+        let mut messages = Vec::new();
+        let mut bytes = 0;
+        let segments = self.segments.iter_mut().chain(std::iter::once(&mut self.active_segment));
+        for segment in segments {
+            if segment.next_offset <= offset {
+                continue;
+            }
+            if segment.read_from(offset, max_bytes, &mut messages, &mut bytes)? {
+                break;
+            }
+        }
+        Ok(messages)
+    }
+
     pub fn truncate_before(&mut self, offset: i64) -> io::Result<()> {
         // remove entire segments before offset
         while let Some(first) = self.segments.first() {
             if first.last_offset() < offset {
                 let path = first.path.clone();
                 let _ = self.segments.remove(0);
-                std::fs::remove_file(path)?;
+                std::fs::remove_file(&path)?;
+                std::fs::remove_file(time_index_path(&path))?;
+                std::fs::remove_file(txn_index_path(&path))?;
             } else {
                 break;
             }
@@ -242,7 +693,454 @@ impl Log {
         Ok(())
     }
 
+    /// removes every entry at or above `offset`, across segments and their indexes,
+    /// and moves the log end back. An entry spanning `offset` goes too, so the log
+    /// end lands on an entry boundary at or below it.
+    ///
+    /// Segments past the cut are renamed to `.deleted`, and the renames synced, before
+    /// anything else changes, and `Log::new` removes leftovers, so a crash midway never
+    /// brings them back.
+    pub fn truncate_to(&mut self, offset: i64) -> io::Result<()> {
+        if offset >= self.next_offset {
+            return Ok(());
+        }
+
+        // segments starting past the cut go; the last survivor becomes active
+        let mut removed = Vec::new();
+        while self.active_segment.base_offset > offset {
+            let Some(previous) = self.segments.pop() else {
+                break;
+            };
+            removed.push(std::mem::replace(&mut self.active_segment, previous));
+        }
+        let mut doomed = Vec::with_capacity(removed.len() * 3);
+        for segment in removed {
+            let path = segment.path.clone();
+            drop(segment);
+            for file in [time_index_path(&path), txn_index_path(&path), path] {
+                let deleted = deleted_path(&file);
+                std::fs::rename(&file, &deleted)?;
+                doomed.push(deleted);
+            }
+        }
+        if !doomed.is_empty() {
+            File::open(&self.dir)?.sync_all()?;
+        }
+
+        self.active_segment.truncate_from(offset)?;
+        self.next_offset = self.active_segment.next_offset;
+        self.leader_epoch_cache.truncate_from_end(self.next_offset)?;
+        // producer state may include batches that are gone now
+        if self.producer_state.is_some() {
+            self.load_producer_state()?;
+        }
+
+        for path in doomed {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// deletes every entry and starts the log over, empty, at `offset`, e.g. for a
+    /// follower whose whole log lies before its leader's log start. Epochs and
+    /// producer state of the old entries go with them.
+    pub fn truncate_fully_and_start_at(&mut self, offset: i64) -> io::Result<()> {
+        // renamed first, so the new segment can take the name of an old one
+        let mut doomed = Vec::new();
+        let active_path = self.active_segment.path.clone();
+        let paths = self.segments.iter().map(|segment| segment.path.clone()).chain([active_path]);
+        for path in paths.collect::<Vec<_>>() {
+            for file in [time_index_path(&path), txn_index_path(&path), path] {
+                let deleted = deleted_path(&file);
+                std::fs::rename(&file, &deleted)?;
+                doomed.push(deleted);
+            }
+        }
+        // the renames have to reach the disk before the new segment, or a crash could
+        // leave both behind
+        File::open(&self.dir)?.sync_all()?;
+
+        let segment = LogSegment::new(offset, self.dir.join(format!("{:020}.log", offset)))?;
+        self.segments.clear();
+        self.active_segment = segment;
+        self.next_offset = offset;
+        self.leader_epoch_cache.clear()?;
+        if let Some(producer_state) = &mut self.producer_state {
+            producer_state.truncate_fully()?;
+        }
+
+        for path in doomed {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// removes the closed segments that only hold entries below `offset`, e.g. once a
+    /// snapshot covers them. The active segment always stays.
+    pub fn delete_segments_before(&mut self, offset: i64) -> io::Result<()> {
+        let count = self.segments.iter().take_while(|segment| segment.next_offset <= offset).count();
+        for segment in self.segments.drain(..count) {
+            let path = segment.path.clone();
+            drop(segment);
+            // the log goes first, so a crash midway leaves no segment without its data
+            for file in [path.clone(), time_index_path(&path), txn_index_path(&path)] {
+                std::fs::remove_file(file)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// forces what was appended to the active segment onto disk, along with the
+    /// directory so a segment created by rotation survives a crash too
+    pub fn sync(&self) -> io::Result<()> {
+        self.active_segment.file.sync_data()?;
+        self.active_segment.time_index.sync_data()?;
+        File::open(&self.dir)?.sync_all()
+    }
+
+    /// when the entry at `offset` was written, as far as the time index tells: the
+    /// largest timestamp indexed at or before it. None outside the log.
+    pub fn timestamp_at(&mut self, offset: i64) -> io::Result<Option<i64>> {
+        if offset < self.log_start_offset() || offset >= self.next_offset {
+            return Ok(None);
+        }
+        let segment = self
+            .segments
+            .iter_mut()
+            .find(|segment| offset < segment.next_offset)
+            .unwrap_or(&mut self.active_segment);
+        segment.indexed_timestamp_at(offset)
+    }
+
+    pub fn latest_epoch(&self) -> Option<i32> {
+        self.leader_epoch_cache.latest_epoch()
+    }
+
+    /// remembers that `epoch` starts at `start_offset`
+    pub fn assign_epoch(&mut self, epoch: i32, start_offset: i64) -> io::Result<()> {
+        self.leader_epoch_cache.assign(epoch, start_offset)
+    }
+
+    /// the largest known epoch <= `epoch` and the offset it ended at
+    pub fn end_offset_for_epoch(&self, epoch: i32) -> (i32, i64) {
+        self.leader_epoch_cache.end_offset_for(epoch, self.next_offset)
+    }
+
     pub fn get_latest_offset(&self) -> i64 {
         self.next_offset - 1
     }
-}
\ No newline at end of file
+
+    pub fn log_start_offset(&self) -> i64 {
+        self.segments
+            .first()
+            .map(|segment| segment.base_offset)
+            .unwrap_or(self.active_segment.base_offset)
+    }
+
+    pub fn log_end_offset(&self) -> i64 {
+        self.next_offset
+    }
+
+    /// ListOffsets semantics: EARLIEST_TIMESTAMP gives the log start, LATEST_TIMESTAMP
+    /// the next offset to be written, anything else the first offset whose timestamp
+    /// is at or after it (None if every message is older).
+    pub fn list_offset(&mut self, timestamp: i64) -> io::Result<Option<i64>> {
+        match timestamp {
+            EARLIEST_TIMESTAMP => Ok(Some(self.log_start_offset())),
+            LATEST_TIMESTAMP => Ok(Some(self.log_end_offset())),
+            _ => {
+                for segment in self.segments.iter_mut() {
+                    if let Some(offset) = segment.offset_for_timestamp(timestamp)? {
+                        return Ok(Some(offset));
+                    }
+                }
+                self.active_segment.offset_for_timestamp(timestamp)
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 12 bytes of framing plus 20 of data: three entries fit in a 100 byte segment
+    const ENTRY: [u8; 20] = [7; 20];
+    const SEGMENT_BYTES: u64 = 100;
+
+    fn temp_log() -> (PathBuf, Log) {
+        let dir = std::env::temp_dir().join(format!("rafka-log-{}", uuid::Uuid::new_v4()));
+        let log = Log::new(dir.clone(), 0, SEGMENT_BYTES).unwrap();
+        (dir, log)
+    }
+
+    // offsets 0..9 over segments based at 0, 3, 6 and 9, with timestamp = 1000 + offset
+    fn fill(log: &mut Log) {
+        for offset in 0..10 {
+            log.append_with_timestamp(&ENTRY, 1000 + offset).unwrap();
+        }
+        assert_eq!(log.segments.len(), 3);
+    }
+
+    fn offsets(log: &mut Log) -> Vec<i64> {
+        log.read_from(0, usize::MAX).unwrap().into_iter().map(|(offset, _)| offset).collect()
+    }
+
+    fn segment_files(dir: &std::path::Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.contains(".log") || name.contains(".timeindex"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn truncate_to_middle_of_closed_segment() {
+        let (dir, mut log) = temp_log();
+        fill(&mut log);
+
+        log.truncate_to(4).unwrap();
+
+        assert_eq!(log.log_end_offset(), 4);
+        assert_eq!(offsets(&mut log), vec![0, 1, 2, 3]);
+        assert_eq!(log.segments.len(), 1);
+        assert_eq!(log.active_segment.base_offset, 3);
+        assert_eq!(
+            segment_files(&dir),
+            vec![
+                "00000000000000000000.log",
+                "00000000000000000000.timeindex",
+                "00000000000000000003.log",
+                "00000000000000000003.timeindex",
+            ]
+        );
+
+        // the truncated segment keeps taking appends where the log now ends
+        assert_eq!(log.append_with_timestamp(&ENTRY, 2000).unwrap(), 4);
+        assert_eq!(offsets(&mut log), vec![0, 1, 2, 3, 4]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncate_to_trims_time_index() {
+        let (dir, mut log) = temp_log();
+        fill(&mut log);
+
+        log.truncate_to(4).unwrap();
+
+        assert_eq!(log.list_offset(1003).unwrap(), Some(3));
+        assert_eq!(log.list_offset(1004).unwrap(), None);
+        log.append_with_timestamp(&ENTRY, 1004).unwrap();
+        assert_eq!(log.list_offset(1004).unwrap(), Some(4));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncate_to_drops_whole_spanning_batch() {
+        let (dir, mut log) = temp_log();
+        log.append_batch(&ENTRY, 5, 1000).unwrap();
+        log.append_batch(&ENTRY, 5, 1001).unwrap();
+
+        // offset 7 lies inside the batch covering 5..9
+        log.truncate_to(7).unwrap();
+
+        assert_eq!(log.log_end_offset(), 5);
+        assert_eq!(offsets(&mut log), vec![0]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncate_to_log_end_or_beyond_is_a_no_op() {
+        let (dir, mut log) = temp_log();
+        fill(&mut log);
+
+        log.truncate_to(10).unwrap();
+        log.truncate_to(50).unwrap();
+
+        assert_eq!(log.log_end_offset(), 10);
+        assert_eq!(offsets(&mut log).len(), 10);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncate_to_forgets_later_epochs() {
+        let (dir, mut log) = temp_log();
+        log.assign_epoch(0, 0).unwrap();
+        fill(&mut log);
+        log.assign_epoch(1, 10).unwrap();
+        log.append_with_timestamp(&ENTRY, 1010).unwrap();
+        log.assign_epoch(2, 11).unwrap();
+        log.append_with_timestamp(&ENTRY, 1011).unwrap();
+
+        log.truncate_to(11).unwrap();
+
+        assert_eq!(log.latest_epoch(), Some(1));
+        assert_eq!(log.end_offset_for_epoch(2), (1, 11));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn aborted_transactions_are_indexed_by_segment() {
+        let (dir, mut log) = temp_log();
+        log.load_producer_state().unwrap();
+        let append = |log: &mut Log, mut batch: Vec<u8>| {
+            record_batch::set_base_offset(&mut batch, log.log_end_offset());
+            log.append_record_batch(&batch, 1000).unwrap();
+        };
+        let values = vec![vec![1], vec![2]];
+
+        // producer 7 writes offsets 0..=1 and producer 8 offset 2, each into a segment of its own
+        append(&mut log, record_batch::build_producer_batch(0, 7, 0, 0, true, 1000, &values));
+        append(&mut log, record_batch::build_producer_batch(0, 8, 0, 0, true, 1000, &values[..1]));
+        assert_eq!(log.first_unstable_offset(), Some(0));
+
+        append(&mut log, record_batch::build_control_batch(7, 0, false, 0, 1000));
+        assert_eq!(log.first_unstable_offset(), Some(2));
+        append(&mut log, record_batch::build_control_batch(8, 0, true, 0, 1000));
+        assert_eq!(log.first_unstable_offset(), None);
+
+        let aborted = AbortedTxn { producer_id: 7, first_offset: 0, last_offset: 3, last_stable_offset: 2 };
+        assert_eq!(log.collect_aborted_txns(0, 5).unwrap(), vec![aborted]);
+        assert_eq!(log.collect_aborted_txns(1, 2).unwrap(), vec![aborted]);
+        assert!(log.collect_aborted_txns(4, 5).unwrap().is_empty());
+
+        // the index survives a restart, and goes with the marker on truncation
+        drop(log);
+        let mut log = Log::new(dir.clone(), 0, SEGMENT_BYTES).unwrap();
+        log.load_producer_state().unwrap();
+        assert_eq!(log.first_unstable_offset(), None);
+        assert_eq!(log.collect_aborted_txns(0, 5).unwrap(), vec![aborted]);
+
+        log.truncate_to(3).unwrap();
+        assert!(log.collect_aborted_txns(0, 3).unwrap().is_empty());
+        assert_eq!(log.first_unstable_offset(), Some(0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reopening_recovers_segments() {
+        let (dir, mut log) = temp_log();
+        fill(&mut log);
+        drop(log);
+        // a crash halfway through writing an entry
+        let active = dir.join("00000000000000000009.log");
+        let mut torn = std::fs::read(&active).unwrap();
+        torn.extend_from_slice(&32u32.to_be_bytes());
+        torn.extend_from_slice(&10i64.to_be_bytes());
+        std::fs::write(&active, torn).unwrap();
+
+        let mut log = Log::new(dir.clone(), 0, SEGMENT_BYTES).unwrap();
+
+        assert_eq!(log.log_end_offset(), 10);
+        assert_eq!(offsets(&mut log), (0..10).collect::<Vec<_>>());
+        assert_eq!(log.list_offset(1004).unwrap(), Some(4));
+        assert_eq!(log.append_with_timestamp(&ENTRY, 2000).unwrap(), 10);
+        assert_eq!(offsets(&mut log), (0..11).collect::<Vec<_>>());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reopening_removes_leftover_deleted_segments() {
+        let (dir, log) = temp_log();
+        drop(log);
+        let leftover = deleted_path(&dir.join("00000000000000000003.log"));
+        std::fs::write(&leftover, ENTRY).unwrap();
+
+        let _log = Log::new(dir.clone(), 0, SEGMENT_BYTES).unwrap();
+
+        assert!(!leftover.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn delete_segments_before_keeps_segments_holding_the_offset() {
+        let (dir, mut log) = temp_log();
+        fill(&mut log);
+
+        log.delete_segments_before(5).unwrap();
+
+        assert_eq!(log.log_start_offset(), 3);
+        assert_eq!(offsets(&mut log), (3..10).collect::<Vec<_>>());
+        assert_eq!(segment_files(&dir)[0], "00000000000000000003.log");
+
+        // the active segment stays even once everything is below the offset
+        log.delete_segments_before(100).unwrap();
+        assert_eq!(log.log_start_offset(), 9);
+        assert_eq!(log.log_end_offset(), 10);
+        drop(log);
+        let log = Log::new(dir.clone(), 0, SEGMENT_BYTES).unwrap();
+        assert_eq!(log.log_start_offset(), 9);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn timestamp_at_comes_from_the_time_index() {
+        let (dir, mut log) = temp_log();
+        fill(&mut log);
+        // an older timestamp isn't indexed, the entry keeps the max seen before it
+        log.append_with_timestamp(&ENTRY, 900).unwrap();
+
+        assert_eq!(log.timestamp_at(0).unwrap(), Some(1000));
+        assert_eq!(log.timestamp_at(4).unwrap(), Some(1004));
+        assert_eq!(log.timestamp_at(9).unwrap(), Some(1009));
+        assert_eq!(log.timestamp_at(10).unwrap(), Some(1009));
+        assert_eq!(log.timestamp_at(11).unwrap(), None);
+
+        log.delete_segments_before(5).unwrap();
+        assert_eq!(log.timestamp_at(2).unwrap(), None);
+        assert_eq!(log.timestamp_at(3).unwrap(), Some(1003));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn read_from_seeks_through_the_position_index() {
+        let dir = std::env::temp_dir().join(format!("rafka-log-{}", uuid::Uuid::new_v4()));
+        let mut log = Log::new(dir.clone(), 0, 1024 * 1024).unwrap();
+        // 1000 byte entries, so every fifth one gets a position index entry
+        let entry = [7u8; 1000];
+        for offset in 0..50 {
+            log.append_with_timestamp(&entry, 1000 + offset).unwrap();
+        }
+        assert_eq!(log.active_segment.offset_index.len(), 10);
+
+        let read = |log: &mut Log, offset: i64, max_bytes: usize| -> Vec<i64> {
+            log.read_from(offset, max_bytes).unwrap().into_iter().map(|(offset, _)| offset).collect()
+        };
+        assert_eq!(read(&mut log, 23, 3000), vec![23, 24, 25]);
+        assert_eq!(read(&mut log, 25, 2999), vec![25, 26]);
+        assert_eq!(read(&mut log, 48, usize::MAX), vec![48, 49]);
+        // the first entry comes back even when it alone is over max_bytes
+        assert_eq!(read(&mut log, 7, 10), vec![7]);
+
+        // cut back and reopened, the index still points at the entries left
+        log.truncate_to(32).unwrap();
+        drop(log);
+        let mut log = Log::new(dir.clone(), 0, 1024 * 1024).unwrap();
+        assert_eq!(log.active_segment.offset_index.len(), 7);
+        assert_eq!(read(&mut log, 31, usize::MAX), vec![31]);
+        assert_eq!(log.append_with_timestamp(&entry, 2000).unwrap(), 32);
+        assert_eq!(read(&mut log, 30, usize::MAX), vec![30, 31, 32]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncate_fully_starts_an_empty_log_at_the_offset() {
+        let (dir, mut log) = temp_log();
+        fill(&mut log);
+        log.assign_epoch(3, 4).unwrap();
+
+        log.truncate_fully_and_start_at(20).unwrap();
+
+        assert_eq!((log.log_start_offset(), log.log_end_offset()), (20, 20));
+        assert_eq!(offsets(&mut log), Vec::<i64>::new());
+        assert_eq!(log.latest_epoch(), None);
+        assert_eq!(segment_files(&dir), vec!["00000000000000000020.log", "00000000000000000020.timeindex"]);
+        assert_eq!(log.append_with_timestamp(&ENTRY, 2000).unwrap(), 20);
+        drop(log);
+
+        let mut log = Log::new(dir.clone(), 0, SEGMENT_BYTES).unwrap();
+        assert_eq!(offsets(&mut log), vec![20]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
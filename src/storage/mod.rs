@@ -1 +1,4 @@
-pub mod log;
\ No newline at end of file
+pub mod leader_epoch;
+pub mod log;
+pub mod producer_state;
+pub mod record_batch;
\ No newline at end of file
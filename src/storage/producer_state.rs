@@ -0,0 +1,389 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::storage::record_batch;
+
+pub const PRODUCER_SNAPSHOT_SUFFIX: &str = "snapshot";
+
+// how many of a producer's latest batches are remembered to catch retried duplicates,
+// the most an idempotent producer keeps in flight
+const NUM_BATCHES_TO_RETAIN: usize = 5;
+// snapshots kept around, the newest ones
+const NUM_SNAPSHOTS_TO_RETAIN: usize = 2;
+
+#[derive(Debug, Error)]
+pub enum ProducerStateError {
+    #[error("Epoch {1} of producer {0} is older than its current epoch {2}")]
+    InvalidProducerEpoch(i64, i16, i16),
+
+    #[error("Producer {0} sent sequence {1} where {2} was expected")]
+    OutOfOrderSequence(i64, i32, i32),
+
+    #[error("Sequences {1}..={2} of producer {0} were already written at offset {3}")]
+    DuplicateSequence(i64, i32, i32, i64),
+}
+
+/// the idempotence fields of one record batch and the offsets it landed at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProducerBatch {
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub first_sequence: i32,
+    pub last_sequence: i32,
+    pub first_offset: i64,
+    pub last_offset: i64,
+    pub is_transactional: bool,
+    pub is_control: bool, // a transaction marker, which has no sequence
+    pub is_commit: bool,  // for markers, a COMMIT rather than an ABORT
+}
+
+/// a transaction that a COMMIT or ABORT marker just ended in the partition
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompletedTxn {
+    pub producer_id: i64,
+    pub first_offset: i64,
+    pub last_offset: i64, // the marker's
+    pub is_aborted: bool,
+}
+
+impl ProducerBatch {
+    /// None for batches written without a producer id
+    pub fn from_batch(batch: &[u8]) -> Option<Self> {
+        let producer_id = record_batch::producer_id(batch)?;
+        if producer_id < 0 || record_batch::magic(batch)? != 2 {
+            return None;
+        }
+        let first_offset = record_batch::base_offset(batch)?;
+        let offset_delta = record_batch::record_count(batch)? - 1;
+        let first_sequence = record_batch::base_sequence(batch)?;
+        // sequences wrap around to 0 after i32::MAX
+        let last_sequence = ((first_sequence as i64 + offset_delta) % (i32::MAX as i64 + 1)) as i32;
+        Some(ProducerBatch {
+            producer_id,
+            producer_epoch: record_batch::producer_epoch(batch)?,
+            first_sequence,
+            last_sequence,
+            first_offset,
+            last_offset: first_offset + offset_delta,
+            is_transactional: record_batch::is_transactional(batch),
+            is_control: record_batch::is_control(batch),
+            is_commit: record_batch::is_commit_marker(batch).unwrap_or(false),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct BatchMetadata {
+    first_sequence: i32,
+    last_sequence: i32,
+    first_offset: i64,
+    last_offset: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ProducerStateEntry {
+    producer_id: i64,
+    producer_epoch: i16,
+    batches: VecDeque<BatchMetadata>, // oldest first
+    #[serde(default)]
+    current_txn_first_offset: Option<i64>, // first offset of its open transaction
+}
+
+// what a snapshot file holds: every producer's state as of `offset`
+#[derive(Serialize, Deserialize)]
+struct ProducerSnapshot {
+    offset: i64,
+    producers: Vec<ProducerStateEntry>,
+}
+
+/// latest batches of every producer that wrote to a partition, to refuse duplicates
+/// and sequence gaps from idempotent producers. Snapshotted next to the log's segments
+/// as `{offset}.snapshot`, the state as of that offset.
+#[derive(Debug)]
+pub struct ProducerStateManager {
+    dir: PathBuf,
+    producers: HashMap<i64, ProducerStateEntry>,
+}
+
+impl ProducerStateManager {
+    /// loads the newest snapshot at or below `log_end_offset`, dropping newer ones.
+    /// Returns the offset the log has to be replayed from to bring the state up to date.
+    pub fn load(dir: &Path, log_end_offset: i64) -> io::Result<(Self, i64)> {
+        let mut manager = ProducerStateManager { dir: dir.to_path_buf(), producers: HashMap::new() };
+        let mut replay_from = 0;
+        for (offset, path) in manager.snapshot_files()?.into_iter().rev() {
+            if offset > log_end_offset {
+                fs::remove_file(&path)?;
+                continue;
+            }
+            let snapshot: ProducerSnapshot = serde_json::from_slice(&fs::read(&path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("malformed {}: {}", path.display(), e)))?;
+            manager.producers = snapshot.producers.into_iter().map(|entry| (entry.producer_id, entry)).collect();
+            replay_from = snapshot.offset;
+            break;
+        }
+        Ok((manager, replay_from))
+    }
+
+    /// checks a batch from a leader append. A producer that isn't known yet can start
+    /// at any sequence, since its state may have been removed with old segments.
+    pub fn check(&self, batch: &ProducerBatch) -> Result<(), ProducerStateError> {
+        let Some(entry) = self.producers.get(&batch.producer_id) else {
+            return Ok(());
+        };
+        if batch.producer_epoch < entry.producer_epoch {
+            return Err(ProducerStateError::InvalidProducerEpoch(batch.producer_id, batch.producer_epoch, entry.producer_epoch));
+        }
+        if batch.is_control {
+            return Ok(());
+        }
+        // a bumped epoch starts its sequences over
+        if batch.producer_epoch > entry.producer_epoch {
+            return match batch.first_sequence {
+                0 => Ok(()),
+                other => Err(ProducerStateError::OutOfOrderSequence(batch.producer_id, other, 0)),
+            };
+        }
+
+        let duplicate = entry
+            .batches
+            .iter()
+            .find(|known| known.first_sequence == batch.first_sequence && known.last_sequence == batch.last_sequence);
+        if let Some(known) = duplicate {
+            return Err(ProducerStateError::DuplicateSequence(
+                batch.producer_id,
+                batch.first_sequence,
+                batch.last_sequence,
+                known.first_offset,
+            ));
+        }
+        let Some(last) = entry.batches.back() else {
+            return Ok(());
+        };
+        let expected = if last.last_sequence == i32::MAX { 0 } else { last.last_sequence + 1 };
+        if batch.first_sequence != expected {
+            return Err(ProducerStateError::OutOfOrderSequence(batch.producer_id, batch.first_sequence, expected));
+        }
+        Ok(())
+    }
+
+    /// checks the batches of one leader append in order, each against the state the
+    /// batches before it leave, so a producer can send several at once
+    pub fn check_all(&self, batches: &[ProducerBatch]) -> Result<(), ProducerStateError> {
+        let mut pending = ProducerStateManager { dir: self.dir.clone(), producers: HashMap::new() };
+        for batch in batches {
+            if let Some(entry) = self.producers.get(&batch.producer_id) {
+                pending.producers.entry(batch.producer_id).or_insert_with(|| entry.clone());
+            }
+            pending.check(batch)?;
+            pending.update(batch);
+        }
+        Ok(())
+    }
+
+    /// records a batch that was written to the log. Returns the transaction a
+    /// marker ended, if the producer had written any of it here.
+    pub fn update(&mut self, batch: &ProducerBatch) -> Option<CompletedTxn> {
+        let entry = self.producers.entry(batch.producer_id).or_insert_with(|| ProducerStateEntry {
+            producer_id: batch.producer_id,
+            producer_epoch: batch.producer_epoch,
+            batches: VecDeque::new(),
+            current_txn_first_offset: None,
+        });
+        // the open transaction survives a bump, its marker comes with the new epoch
+        if batch.producer_epoch != entry.producer_epoch {
+            entry.producer_epoch = batch.producer_epoch;
+            entry.batches.clear();
+        }
+        // a marker can fence older epochs but takes no sequence numbers
+        if batch.is_control {
+            return entry.current_txn_first_offset.take().map(|first_offset| CompletedTxn {
+                producer_id: batch.producer_id,
+                first_offset,
+                last_offset: batch.last_offset,
+                is_aborted: !batch.is_commit,
+            });
+        }
+        if batch.is_transactional && entry.current_txn_first_offset.is_none() {
+            entry.current_txn_first_offset = Some(batch.first_offset);
+        }
+        entry.batches.push_back(BatchMetadata {
+            first_sequence: batch.first_sequence,
+            last_sequence: batch.last_sequence,
+            first_offset: batch.first_offset,
+            last_offset: batch.last_offset,
+        });
+        if entry.batches.len() > NUM_BATCHES_TO_RETAIN {
+            entry.batches.pop_front();
+        }
+        None
+    }
+
+    /// first offset of the oldest transaction still waiting for its marker, which
+    /// read_committed consumers can't read past
+    pub fn first_unstable_offset(&self) -> Option<i64> {
+        self.producers.values().filter_map(|entry| entry.current_txn_first_offset).min()
+    }
+
+    /// writes the state as of `offset`, everything before it having been applied, and
+    /// removes all but the newest snapshots
+    pub fn take_snapshot(&self, offset: i64) -> io::Result<()> {
+        let snapshot = ProducerSnapshot { offset, producers: self.producers.values().cloned().collect() };
+        let path = self.dir.join(format!("{:020}.{}", offset, PRODUCER_SNAPSHOT_SUFFIX));
+        // write-then-rename so a crash never leaves half a snapshot
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(&snapshot)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+
+        let snapshots = self.snapshot_files()?;
+        for (_, old) in snapshots.iter().take(snapshots.len().saturating_sub(NUM_SNAPSHOTS_TO_RETAIN)) {
+            fs::remove_file(old)?;
+        }
+        Ok(())
+    }
+
+    /// forgets every producer and deletes their snapshots, for a log that starts over
+    pub fn truncate_fully(&mut self) -> io::Result<()> {
+        self.producers.clear();
+        for (_, path) in self.snapshot_files()? {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    // snapshot files in the log directory, oldest first
+    fn snapshot_files(&self) -> io::Result<Vec<(i64, PathBuf)>> {
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != PRODUCER_SNAPSHOT_SUFFIX) {
+                continue;
+            }
+            if let Some(offset) = path.file_stem().and_then(|stem| stem.to_str()?.parse::<i64>().ok()) {
+                snapshots.push((offset, path));
+            }
+        }
+        snapshots.sort_unstable();
+        Ok(snapshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn temp_manager() -> (PathBuf, ProducerStateManager) {
+        let dir = std::env::temp_dir().join(format!("rafka-producer-state-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let (manager, replay_from) = ProducerStateManager::load(&dir, 0).unwrap();
+        assert_eq!(replay_from, 0);
+        (dir, manager)
+    }
+
+    fn batch(producer_epoch: i16, first_sequence: i32, last_sequence: i32, first_offset: i64) -> ProducerBatch {
+        ProducerBatch {
+            producer_id: 7,
+            producer_epoch,
+            first_sequence,
+            last_sequence,
+            first_offset,
+            last_offset: first_offset + (last_sequence - first_sequence) as i64,
+            is_transactional: false,
+            is_control: false,
+            is_commit: false,
+        }
+    }
+
+    #[test]
+    fn retried_batches_are_duplicates() {
+        let (dir, mut manager) = temp_manager();
+        manager.update(&batch(0, 0, 2, 0));
+        manager.update(&batch(0, 3, 4, 3));
+
+        assert!(matches!(manager.check(&batch(0, 0, 2, 5)), Err(ProducerStateError::DuplicateSequence(7, 0, 2, 0))));
+        assert!(matches!(manager.check(&batch(0, 3, 4, 5)), Err(ProducerStateError::DuplicateSequence(7, 3, 4, 3))));
+        assert!(manager.check(&batch(0, 5, 5, 5)).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sequence_gaps_are_out_of_order() {
+        let (dir, mut manager) = temp_manager();
+        // an unknown producer may start anywhere
+        assert!(manager.check(&batch(0, 10, 10, 0)).is_ok());
+        manager.update(&batch(0, 10, 11, 0));
+
+        assert!(matches!(manager.check(&batch(0, 13, 13, 2)), Err(ProducerStateError::OutOfOrderSequence(7, 13, 12))));
+        assert!(manager.check(&batch(0, 12, 12, 2)).is_ok());
+        // a bumped epoch has to start over at 0
+        assert!(matches!(manager.check(&batch(1, 12, 12, 2)), Err(ProducerStateError::OutOfOrderSequence(7, 12, 0))));
+        assert!(manager.check(&batch(1, 0, 0, 2)).is_ok());
+        // sequences wrap around after i32::MAX
+        manager.update(&batch(0, 12, i32::MAX, 2));
+        assert!(manager.check(&batch(0, 0, 0, 3)).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn older_producer_epochs_are_fenced() {
+        let (dir, mut manager) = temp_manager();
+        manager.update(&batch(3, 0, 0, 0));
+
+        assert!(matches!(manager.check(&batch(2, 1, 1, 1)), Err(ProducerStateError::InvalidProducerEpoch(7, 2, 3))));
+        let marker = ProducerBatch { is_control: true, ..batch(2, -1, -1, 1) };
+        assert!(matches!(manager.check(&marker), Err(ProducerStateError::InvalidProducerEpoch(7, 2, 3))));
+        assert!(manager.check(&batch(3, 1, 1, 1)).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn check_all_applies_earlier_batches_of_the_append() {
+        let (dir, mut manager) = temp_manager();
+        manager.update(&batch(0, 0, 0, 0));
+
+        assert!(manager.check_all(&[batch(0, 1, 1, 1), batch(0, 2, 3, 2), batch(0, 4, 4, 4)]).is_ok());
+        assert!(matches!(
+            manager.check_all(&[batch(0, 1, 1, 1), batch(0, 1, 1, 2)]),
+            Err(ProducerStateError::DuplicateSequence(7, 1, 1, 1))
+        ));
+        assert!(matches!(
+            manager.check_all(&[batch(0, 1, 1, 1), batch(0, 3, 3, 2)]),
+            Err(ProducerStateError::OutOfOrderSequence(7, 3, 2))
+        ));
+        // checking leaves the state alone
+        assert!(manager.check(&batch(0, 1, 1, 1)).is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn snapshots_reload_and_only_the_newest_are_kept() {
+        let (dir, mut manager) = temp_manager();
+        for (offset, sequence) in [(1, 0), (2, 1), (3, 2)] {
+            manager.update(&batch(0, sequence, sequence, offset - 1));
+            manager.take_snapshot(offset).unwrap();
+        }
+        let offsets: Vec<i64> = manager.snapshot_files().unwrap().into_iter().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets, vec![2, 3]);
+
+        let (reloaded, replay_from) = ProducerStateManager::load(&dir, 3).unwrap();
+        assert_eq!(replay_from, 3);
+        assert!(matches!(reloaded.check(&batch(0, 2, 2, 3)), Err(ProducerStateError::DuplicateSequence(7, 2, 2, 2))));
+        assert!(reloaded.check(&batch(0, 3, 3, 3)).is_ok());
+
+        // a log that ends before the newest snapshot falls back to the one before
+        let (reloaded, replay_from) = ProducerStateManager::load(&dir, 2).unwrap();
+        assert_eq!(replay_from, 2);
+        assert!(reloaded.check(&batch(0, 2, 2, 2)).is_ok());
+        let offsets: Vec<i64> = reloaded.snapshot_files().unwrap().into_iter().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets, vec![2]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
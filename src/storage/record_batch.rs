@@ -0,0 +1,324 @@
+// helpers for the fixed-size header of a v2 RecordBatch:
+// baseOffset(8) batchLength(4) partitionLeaderEpoch(4) magic(1) crc(4) attributes(2)
+// lastOffsetDelta(4) baseTimestamp(8) maxTimestamp(8) producerId(8) producerEpoch(2)
+// baseSequence(4) recordCount(4)
+
+const BASE_OFFSET_POS: usize = 0;
+const BATCH_LENGTH_POS: usize = 8;
+const PARTITION_LEADER_EPOCH_POS: usize = 12;
+const MAGIC_POS: usize = 16;
+const CRC_POS: usize = 17;
+const ATTRIBUTES_POS: usize = 21;
+const LAST_OFFSET_DELTA_POS: usize = 23;
+const MAX_TIMESTAMP_POS: usize = 35;
+const PRODUCER_ID_POS: usize = 43;
+const PRODUCER_EPOCH_POS: usize = 51;
+const BASE_SEQUENCE_POS: usize = 53;
+const RECORDS_POS: usize = 61;
+
+// baseOffset and batchLength aren't counted by batchLength itself
+pub const LOG_OVERHEAD: usize = 12;
+
+// attribute bits
+const TRANSACTIONAL_FLAG: i16 = 0x10;
+const CONTROL_FLAG: i16 = 0x20;
+
+// key type of the single record in a control batch
+const ABORT_MARKER: i16 = 0;
+const COMMIT_MARKER: i16 = 1;
+
+// CRC-32C (Castagnoli) lookup table, the checksum v2 batches carry
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82F6_3B78 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| CRC32C_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+fn read_i32_at(batch: &[u8], pos: usize) -> Option<i32> {
+    batch.get(pos..pos + 4).map(|b| i32::from_be_bytes(b.try_into().unwrap()))
+}
+
+fn read_i16_at(batch: &[u8], pos: usize) -> Option<i16> {
+    batch.get(pos..pos + 2).map(|b| i16::from_be_bytes(b.try_into().unwrap()))
+}
+
+fn read_i64_at(batch: &[u8], pos: usize) -> Option<i64> {
+    batch.get(pos..pos + 8).map(|b| i64::from_be_bytes(b.try_into().unwrap()))
+}
+
+pub fn base_offset(batch: &[u8]) -> Option<i64> {
+    read_i64_at(batch, BASE_OFFSET_POS)
+}
+
+pub fn set_base_offset(batch: &mut [u8], offset: i64) {
+    if batch.len() >= BASE_OFFSET_POS + 8 {
+        batch[BASE_OFFSET_POS..BASE_OFFSET_POS + 8].copy_from_slice(&offset.to_be_bytes());
+    }
+}
+
+pub fn partition_leader_epoch(batch: &[u8]) -> Option<i32> {
+    read_i32_at(batch, PARTITION_LEADER_EPOCH_POS)
+}
+
+// not covered by the CRC, so the leader can stamp it on produced batches
+pub fn set_partition_leader_epoch(batch: &mut [u8], epoch: i32) {
+    if batch.len() >= PARTITION_LEADER_EPOCH_POS + 4 {
+        batch[PARTITION_LEADER_EPOCH_POS..PARTITION_LEADER_EPOCH_POS + 4].copy_from_slice(&epoch.to_be_bytes());
+    }
+}
+
+pub fn magic(batch: &[u8]) -> Option<i8> {
+    batch.get(MAGIC_POS).map(|b| *b as i8)
+}
+
+pub fn attributes(batch: &[u8]) -> Option<i16> {
+    read_i16_at(batch, ATTRIBUTES_POS)
+}
+
+/// written by a transactional producer, part of a transaction
+pub fn is_transactional(batch: &[u8]) -> bool {
+    attributes(batch).is_some_and(|attributes| attributes & TRANSACTIONAL_FLAG != 0)
+}
+
+/// holds a transaction marker rather than data
+pub fn is_control(batch: &[u8]) -> bool {
+    attributes(batch).is_some_and(|attributes| attributes & CONTROL_FLAG != 0)
+}
+
+/// number of offsets the batch covers
+pub fn record_count(batch: &[u8]) -> Option<i64> {
+    read_i32_at(batch, LAST_OFFSET_DELTA_POS).map(|delta| delta as i64 + 1)
+}
+
+pub fn max_timestamp(batch: &[u8]) -> Option<i64> {
+    read_i64_at(batch, MAX_TIMESTAMP_POS)
+}
+
+/// -1 unless the batch comes from an idempotent or transactional producer
+pub fn producer_id(batch: &[u8]) -> Option<i64> {
+    read_i64_at(batch, PRODUCER_ID_POS)
+}
+
+pub fn producer_epoch(batch: &[u8]) -> Option<i16> {
+    read_i16_at(batch, PRODUCER_EPOCH_POS)
+}
+
+/// sequence number of the first record, the others follow one per offset
+pub fn base_sequence(batch: &[u8]) -> Option<i32> {
+    read_i32_at(batch, BASE_SEQUENCE_POS)
+}
+
+/// splits the records field of a fetch response into whole batches,
+/// dropping a trailing partial batch cut off by max_bytes
+pub fn split_batches(records: &[u8]) -> Vec<&[u8]> {
+    let mut batches = Vec::new();
+    let mut pos = 0;
+    while let Some(batch_length) = read_i32_at(records, pos + BATCH_LENGTH_POS) {
+        let end = pos + LOG_OVERHEAD + batch_length.max(0) as usize;
+        if batch_length <= 0 || end > records.len() {
+            break;
+        }
+        batches.push(&records[pos..end]);
+        pos = end;
+    }
+    batches
+}
+
+// zigzag varint, as used for the lengths and deltas inside records
+fn put_varint(buf: &mut Vec<u8>, value: i64) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    while zigzag >= 0x80 {
+        buf.push((zigzag as u8 & 0x7f) | 0x80);
+        zigzag >>= 7;
+    }
+    buf.push(zigzag as u8);
+}
+
+// producer fields of a batch header; -1 everywhere for plain producers
+struct ProducerFields {
+    producer_id: i64,
+    producer_epoch: i16,
+    base_sequence: i32,
+}
+
+const NO_PRODUCER: ProducerFields = ProducerFields { producer_id: -1, producer_epoch: -1, base_sequence: -1 };
+
+// an uncompressed v2 batch with one record per (key, value), all at `timestamp`
+fn encode_batch(
+    base_offset: i64,
+    partition_leader_epoch: i32,
+    timestamp: i64,
+    attributes: i16,
+    producer: ProducerFields,
+    records: &[(Option<&[u8]>, &[u8])],
+) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for (offset_delta, (key, value)) in records.iter().enumerate() {
+        let mut record = vec![0x00]; // attributes
+        put_varint(&mut record, 0); // timestamp delta
+        put_varint(&mut record, offset_delta as i64);
+        match key {
+            Some(key) => {
+                put_varint(&mut record, key.len() as i64);
+                record.extend_from_slice(key);
+            }
+            None => put_varint(&mut record, -1),
+        }
+        put_varint(&mut record, value.len() as i64);
+        record.extend_from_slice(value);
+        put_varint(&mut record, 0); // headers
+        put_varint(&mut encoded, record.len() as i64);
+        encoded.extend(record);
+    }
+
+    let mut batch = Vec::with_capacity(61 + encoded.len());
+    batch.extend_from_slice(&base_offset.to_be_bytes());
+    batch.extend_from_slice(&0i32.to_be_bytes()); // batch length, filled in below
+    batch.extend_from_slice(&partition_leader_epoch.to_be_bytes());
+    batch.push(2); // magic
+    batch.extend_from_slice(&0u32.to_be_bytes()); // crc, filled in below
+    batch.extend_from_slice(&attributes.to_be_bytes());
+    batch.extend_from_slice(&(records.len() as i32 - 1).to_be_bytes());
+    batch.extend_from_slice(&timestamp.to_be_bytes()); // base timestamp
+    batch.extend_from_slice(&timestamp.to_be_bytes()); // max timestamp
+    batch.extend_from_slice(&producer.producer_id.to_be_bytes());
+    batch.extend_from_slice(&producer.producer_epoch.to_be_bytes());
+    batch.extend_from_slice(&producer.base_sequence.to_be_bytes());
+    batch.extend_from_slice(&(records.len() as i32).to_be_bytes());
+    batch.extend(encoded);
+
+    let batch_length = (batch.len() - LOG_OVERHEAD) as i32;
+    batch[BATCH_LENGTH_POS..BATCH_LENGTH_POS + 4].copy_from_slice(&batch_length.to_be_bytes());
+    // covers everything from the attributes on
+    let crc = crc32c(&batch[ATTRIBUTES_POS..]);
+    batch[CRC_POS..CRC_POS + 4].copy_from_slice(&crc.to_be_bytes());
+    batch
+}
+
+/// builds an uncompressed v2 batch with one keyless record per value
+pub fn build_batch(base_offset: i64, partition_leader_epoch: i32, timestamp: i64, values: &[Vec<u8>]) -> Vec<u8> {
+    let records: Vec<(Option<&[u8]>, &[u8])> = values.iter().map(|value| (None, value.as_slice())).collect();
+    encode_batch(base_offset, partition_leader_epoch, timestamp, 0, NO_PRODUCER, &records)
+}
+
+/// builds an uncompressed v2 batch from an idempotent producer, part of its open
+/// transaction when `transactional` is set
+pub fn build_producer_batch(
+    base_offset: i64,
+    producer_id: i64,
+    producer_epoch: i16,
+    base_sequence: i32,
+    transactional: bool,
+    timestamp: i64,
+    values: &[Vec<u8>],
+) -> Vec<u8> {
+    let records: Vec<(Option<&[u8]>, &[u8])> = values.iter().map(|value| (None, value.as_slice())).collect();
+    let attributes = if transactional { TRANSACTIONAL_FLAG } else { 0 };
+    let producer = ProducerFields { producer_id, producer_epoch, base_sequence };
+    encode_batch(base_offset, -1, timestamp, attributes, producer, &records)
+}
+
+/// builds the control batch ending a producer's transaction in a partition: a
+/// COMMIT or ABORT marker carrying the coordinator epoch
+pub fn build_control_batch(producer_id: i64, producer_epoch: i16, commit: bool, coordinator_epoch: i32, timestamp: i64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(4);
+    key.extend_from_slice(&0i16.to_be_bytes()); // version
+    key.extend_from_slice(&(if commit { COMMIT_MARKER } else { ABORT_MARKER }).to_be_bytes());
+    let mut value = Vec::with_capacity(6);
+    value.extend_from_slice(&0i16.to_be_bytes()); // version
+    value.extend_from_slice(&coordinator_epoch.to_be_bytes());
+
+    let producer = ProducerFields { producer_id, producer_epoch, base_sequence: -1 };
+    encode_batch(0, -1, timestamp, TRANSACTIONAL_FLAG | CONTROL_FLAG, producer, &[(Some(&key), &value)])
+}
+
+// zigzag varint at `pos`, advancing it
+fn read_varint(buf: &[u8], pos: &mut usize) -> Option<i64> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *buf.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some((value >> 1) as i64 ^ -((value & 1) as i64));
+        }
+    }
+    None
+}
+
+/// for a control batch, whether its marker is a COMMIT rather than an ABORT; None
+/// for data batches or a cut-short marker
+pub fn is_commit_marker(batch: &[u8]) -> Option<bool> {
+    if !is_control(batch) {
+        return None;
+    }
+    let mut pos = RECORDS_POS; // the single record
+    read_varint(batch, &mut pos)?; // length
+    pos += 1; // attributes
+    read_varint(batch, &mut pos)?; // timestamp delta
+    read_varint(batch, &mut pos)?; // offset delta
+    if read_varint(batch, &mut pos)? < 4 {
+        return None;
+    }
+    // the key is a version followed by the marker type
+    match read_i16_at(batch, pos + 2)? {
+        COMMIT_MARKER => Some(true),
+        ABORT_MARKER => Some(false),
+        _ => None,
+    }
+}
+
+/// values of the records in an uncompressed v2 batch, None for null values;
+/// None altogether if the batch is cut short
+pub fn record_values(batch: &[u8]) -> Option<Vec<Option<Vec<u8>>>> {
+    let count = read_i32_at(batch, RECORDS_POS - 4)?;
+    let mut pos = RECORDS_POS;
+    // the count is the batch's own claim, so it doesn't size anything up front
+    let mut values = Vec::new();
+    for _ in 0..count {
+        let length = read_varint(batch, &mut pos)?;
+        let end = pos.checked_add(usize::try_from(length).ok()?)?;
+        pos += 1; // attributes
+        read_varint(batch, &mut pos)?; // timestamp delta
+        read_varint(batch, &mut pos)?; // offset delta
+        let key_length = read_varint(batch, &mut pos)?;
+        pos += key_length.max(0) as usize;
+        let value_length = read_varint(batch, &mut pos)?;
+        values.push(match value_length {
+            -1 => None,
+            len => Some(batch.get(pos..pos + usize::try_from(len).ok()?)?.to_vec()),
+        });
+        // headers aren't needed, the record length skips them
+        pos = end;
+    }
+    (pos <= batch.len()).then_some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_values_stops_at_the_end_of_a_batch_claiming_more_records() {
+        let values = vec![b"a".to_vec(), b"bc".to_vec()];
+        let mut batch = build_batch(0, 0, 0, &values);
+        assert_eq!(record_values(&batch), Some(values.into_iter().map(Some).collect()));
+
+        batch[RECORDS_POS - 4..RECORDS_POS].copy_from_slice(&i32::MAX.to_be_bytes());
+        assert_eq!(record_values(&batch), None);
+    }
+}